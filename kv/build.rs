@@ -1,11 +1,20 @@
 use anyhow::Result;
 use std::{fs, process::Command};
 fn main() -> Result<()> {
+    println!("cargo:rerun-if-changed=protos/messages.proto");
+    // src/pb/abi.rs 已经提交到仓库中，没有 protoc 时直接使用，不重新生成
+    let protoc = prost_build::protoc_from_env();
+    if Command::new(&protoc).arg("--version").output().is_err() {
+        println!(
+            "cargo:warning=protoc not found ({}), using the checked-in src/pb/abi.rs",
+            protoc.display()
+        );
+        return Ok(());
+    }
     fs::create_dir_all("src/pb")?;
     prost_build::Config::new()
         .out_dir("src/pb")
         .compile_protos(&["protos/messages.proto"], &["."])?;
-    println!("cargo:rerun-if-changed=protos/messages.proto");
     let output = Command::new("cargo")
         .arg("fmt")
         .output()
@@ -1,10 +1,6 @@
 use anyhow::Result;
-use bytes::Bytes;
-use futures::{SinkExt, StreamExt};
-use kv::{CommandRequest, CommandResponse, TlsClientConnector};
-use prost::Message;
+use kv::{CommandRequest, KvClient, TlsClientConnector};
 use tokio::net::TcpStream;
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use tracing::info;
 const CA_CERT: &str = "fixtures/ca.cert";
 const CLIENT_CERT: &str = "fixtures/client.cert";
@@ -21,31 +17,24 @@ async fn main() -> Result<()> {
         Some(CA_CERT),
     )?;
     let stream = connector.connect(stream).await?;
-    let mut stream = Framed::new(stream, LengthDelimitedCodec::new());
-    let mut cmds = Vec::new();
-    // 创建命令
-    cmds.push(CommandRequest::new_hset("test", "key", "test".into()));
-    cmds.push(CommandRequest::new_hset("test", "key1", 10.into()));
-    cmds.push(CommandRequest::new_hset("test", "key3", 10.1.into()));
-    cmds.push(CommandRequest::new_hset("test", "key2", true.into()));
-    cmds.push(CommandRequest::new_hget("test", "key"));
-    cmds.push(CommandRequest::new_hget("test", "key1"));
-    cmds.push(CommandRequest::new_hget("test", "key2"));
-    cmds.push(CommandRequest::new_hget("test", "key3"));
-    cmds.push(CommandRequest::new_hgetall("test"));
-    for cmd in cmds {
-        // 序列化并发送命令
-        let data = cmd.encode_to_vec();
-        stream.send(Bytes::from(data)).await?;
+    let mut client = KvClient::new(stream);
 
-        // 接收响应info!("Sent command");
-
-        // 接收响应
-        if let Some(Ok(n)) = stream.next().await {
-            let response = CommandResponse::decode(&n[..])?;
-            info!("Got response: {:?}", response);
-        }
+    client.hset("test", "key", "test").await?;
+    client.hset("test", "key1", 10).await?;
+    client.hset("test", "key3", 10.1).await?;
+    client.hset("test", "key2", true).await?;
+    for key in ["key", "key1", "key2", "key3"] {
+        let value = client.hget("test", key).await?;
+        info!("Got {}: {:?}", key, value);
     }
+    let pairs = client.hgetall("test").await?;
+    info!("Got pairs: {:?}", pairs);
+
+    // 也可以直接发送 CommandRequest
+    let response = client
+        .execute(CommandRequest::new_hexists("test", "key"))
+        .await?;
+    info!("Got response: {:?}", response);
 
     Ok(())
 }
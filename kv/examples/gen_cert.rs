@@ -26,9 +26,9 @@ fn create_ca() -> Result<CertPem> {
 
 fn create_cert(ca: &CA, domains: &[&str], cn: &str, is_client: bool) -> Result<CertPem> {
     let (days, cert_type) = if is_client {
-        (365, CertType::Client)
+        (10 * 365, CertType::Client)
     } else {
-        (365, CertType::Server)
+        (10 * 365, CertType::Server)
     };
     let (cert, key) = generate_cert(
         ca,
@@ -0,0 +1,389 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use clap::{Parser, ValueEnum};
+use kv::{
+    AsyncStorage, BlockingStorage, ClientTlsConfig, DEFAULT_RESP_TABLE, GatewayConfig,
+    KvServerStream, MemTable, Metrics, MetricsConfig, ReplicationConfig, RespConfig,
+    RespServerStream, ServerConfig, ServerTlsConfig, Service, ServiceInner, StorageConfig,
+    TlsClientConnector, TlsServerAcceptor, WalOptions, bitcask::Bitcask, error::KvError,
+    is_multiplex_server, peer_identity, replicate_from, serve_gateway, serve_metrics, serve_yamux,
+    sleddb::SledDb,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
+
+// 清理过期 key 的间隔
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+// follower 与 leader 断开后重新连接的间隔
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Parser)]
+#[command(author, version, about = "kv server", long_about = None)]
+struct Args {
+    // toml 配置文件，不指定时使用默认配置
+    #[arg(short, long)]
+    config: Option<String>,
+    #[arg(short, long)]
+    addr: Option<String>,
+    // 非 TLS 连接开启 yamux 多路复用
+    #[arg(long)]
+    multiplex: bool,
+    #[arg(long, value_enum)]
+    storage: Option<StorageKind>,
+    // sleddb/bitcask 的数据目录，memtable 指定后开启持久化
+    #[arg(long, visible_alias = "sled-path")]
+    storage_path: Option<String>,
+    #[arg(long, requires = "key")]
+    cert: Option<String>,
+    #[arg(long, requires = "cert")]
+    key: Option<String>,
+    #[arg(long, requires = "cert")]
+    ca: Option<String>,
+    #[arg(long)]
+    log_level: Option<String>,
+    // 开启 Prometheus 的 /metrics，例如 127.0.0.1:9100
+    #[arg(long)]
+    metrics_addr: Option<String>,
+    // 作为 follower 从这个地址的 leader 同步数据
+    #[arg(long)]
+    leader: Option<String>,
+    // 开启 redis 协议的访问，例如 127.0.0.1:6379
+    #[arg(long)]
+    resp_addr: Option<String>,
+    // 开启 HTTP/JSON 网关，例如 127.0.0.1:3000
+    #[arg(long)]
+    gateway_addr: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum StorageKind {
+    Memtable,
+    Sleddb,
+    Bitcask,
+}
+
+impl Args {
+    // 命令行参数覆盖配置文件中的值
+    fn merge(self, mut config: ServerConfig) -> ServerConfig {
+        if let Some(addr) = self.addr {
+            config.general.addr = addr;
+        }
+        if self.multiplex {
+            config.general.multiplex = true;
+        }
+        let kind = match (self.storage, &config.storage) {
+            (Some(kind), _) => Some(kind),
+            (None, StorageConfig::Bitcask { .. }) if self.storage_path.is_some() => {
+                Some(StorageKind::Bitcask)
+            }
+            // 只指定路径时默认使用 sleddb
+            (None, _) if self.storage_path.is_some() => Some(StorageKind::Sleddb),
+            (None, _) => None,
+        };
+        if let Some(kind) = kind {
+            let path = self.storage_path;
+            config.storage = match (kind, config.storage) {
+                (
+                    StorageKind::Memtable,
+                    StorageConfig::MemTable {
+                        path: old,
+                        fsync,
+                        snapshot_interval,
+                    },
+                ) => StorageConfig::MemTable {
+                    path: path.or(old),
+                    fsync,
+                    snapshot_interval,
+                },
+                // memtable 不沿用其他存储的数据目录
+                (StorageKind::Memtable, _) => StorageConfig::memtable(path),
+                (kind, storage) => {
+                    let path = path
+                        .or_else(|| storage.path().map(String::from))
+                        .unwrap_or_default();
+                    match kind {
+                        StorageKind::Bitcask => StorageConfig::Bitcask { path },
+                        _ => StorageConfig::SledDb { path },
+                    }
+                }
+            };
+        }
+        if let (Some(cert), Some(key)) = (self.cert, self.key) {
+            config.tls = Some(ServerTlsConfig {
+                cert,
+                key,
+                ca: self.ca,
+            });
+        }
+        if let Some(level) = self.log_level {
+            config.log.level = level;
+        }
+        if let Some(addr) = self.metrics_addr {
+            config.metrics = Some(MetricsConfig { addr });
+        }
+        if let Some(leader) = self.leader {
+            // 沿用配置文件中连接 leader 的 TLS 配置
+            let tls = match config.replication {
+                Some(ReplicationConfig::Follower { tls, .. }) => tls,
+                _ => None,
+            };
+            config.replication = Some(ReplicationConfig::Follower { leader, tls });
+        }
+        if let Some(addr) = self.resp_addr {
+            // 沿用配置文件中的表和身份
+            config.resp = Some(match config.resp {
+                Some(resp) => RespConfig { addr, ..resp },
+                None => RespConfig {
+                    addr,
+                    table: DEFAULT_RESP_TABLE.into(),
+                    identity: None,
+                },
+            });
+        }
+        if let Some(addr) = self.gateway_addr {
+            config.gateway = Some(match config.gateway {
+                Some(gateway) => GatewayConfig { addr, ..gateway },
+                None => GatewayConfig {
+                    addr,
+                    identity: None,
+                    cors_origins: vec![],
+                },
+            });
+        }
+        config
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let config = match args.config.as_ref() {
+        Some(path) => ServerConfig::load(path)?,
+        None => ServerConfig::default(),
+    };
+    let config = args.merge(config);
+    config.validate()?;
+
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(&config.log.level))
+        .init();
+
+    match &config.storage {
+        StorageConfig::MemTable {
+            path,
+            fsync,
+            snapshot_interval,
+        } => {
+            let store = match path {
+                Some(path) => {
+                    let opts = WalOptions {
+                        fsync: *fsync,
+                        snapshot_interval: (*snapshot_interval > 0)
+                            .then(|| Duration::from_secs(*snapshot_interval)),
+                    };
+                    MemTable::open(path, opts)?
+                }
+                None => MemTable::new(),
+            };
+            run(ServiceInner::new(BlockingStorage::new(store)), &config).await
+        }
+        StorageConfig::SledDb { path } => {
+            let store = BlockingStorage::new(SledDb::try_new(path)?);
+            run(ServiceInner::new(store), &config).await
+        }
+        StorageConfig::Bitcask { path } => {
+            let store = BlockingStorage::new(Bitcask::try_new(path)?);
+            run(ServiceInner::new(store), &config).await
+        }
+    }
+}
+
+async fn run<S: AsyncStorage + Send + Sync + 'static>(
+    inner: ServiceInner<S>,
+    config: &ServerConfig,
+) -> Result<()> {
+    let inner = match config.acl.clone() {
+        Some(acl) => inner.with_acl(acl),
+        None => inner,
+    };
+    let inner = match config.replication.as_ref() {
+        Some(ReplicationConfig::Leader { followers }) => inner.with_change_log(followers.clone()),
+        Some(ReplicationConfig::Follower { leader, .. }) => inner.with_leader(leader.as_str()),
+        None => inner,
+    };
+    let service: Service<S> = match config.metrics.as_ref() {
+        Some(metrics) => {
+            let listener = TcpListener::bind(&metrics.addr).await?;
+            info!("Serving metrics on http://{}/metrics", metrics.addr);
+            let metrics = Arc::new(Metrics::new());
+            tokio::spawn(serve_metrics(listener, metrics.clone()));
+            inner.with_metrics(metrics)
+        }
+        None => inner,
+    }
+    .into();
+    let acceptor = match config.tls.as_ref() {
+        Some(tls) => Some(TlsServerAcceptor::new(
+            &tls.cert,
+            &tls.key,
+            tls.ca.as_deref(),
+        )?),
+        None => None,
+    };
+    service.start_reaper(REAP_INTERVAL);
+    if let Some(ReplicationConfig::Follower { leader, tls }) = config.replication.as_ref() {
+        let connector = tls.as_ref().map(connector).transpose()?;
+        tokio::spawn(follow(leader.clone(), connector, service.clone()));
+    }
+    if let Some(resp) = config.resp.as_ref() {
+        let listener = TcpListener::bind(&resp.addr).await?;
+        info!(
+            "Serving redis protocol on {} (table: {})",
+            resp.addr, resp.table
+        );
+        tokio::spawn(serve_resp(listener, service.clone(), resp.clone()));
+    }
+    if let Some(gateway) = config.gateway.as_ref() {
+        let listener = TcpListener::bind(&gateway.addr).await?;
+        info!("Serving http gateway on http://{}", gateway.addr);
+        let (service, gateway) = (service.clone(), gateway.clone());
+        tokio::spawn(async move {
+            if let Err(e) =
+                serve_gateway(listener, service, gateway.identity, &gateway.cors_origins).await
+            {
+                warn!("Http gateway stopped: {}", e);
+            }
+        });
+    }
+    let listener = TcpListener::bind(&config.general.addr).await?;
+    info!(
+        "Listening on {} (tls: {}, multiplex: {}, storage: {:?})",
+        config.general.addr,
+        acceptor.is_some(),
+        config.general.multiplex,
+        config.storage
+    );
+    let multiplex = config.general.multiplex;
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        info!("Client {} connected", addr);
+        let svc = service.clone();
+        // 在 accept 时统计连接，多路复用的逻辑流不会被当作单独的连接
+        let connection = service.connection();
+        match acceptor.clone() {
+            Some(acceptor) => {
+                tokio::spawn(async move {
+                    let _connection = connection;
+                    match acceptor.accept(stream).await {
+                        Ok(stream) => {
+                            let multiplex = is_multiplex_server(&stream);
+                            // 无法解析身份的客户端直接断开，不按默认权限处理
+                            let identity = match peer_identity(&stream) {
+                                Ok(identity) => identity,
+                                Err(e) => {
+                                    warn!("Rejecting client {}: {}", addr, e);
+                                    return;
+                                }
+                            };
+                            info!("Client {} identity: {:?}", addr, identity);
+                            handle(stream, svc, multiplex, identity).await
+                        }
+                        Err(e) => warn!("TLS handshake with {} failed: {}", addr, e),
+                    }
+                });
+            }
+            None => {
+                tokio::spawn(async move {
+                    let _connection = connection;
+                    handle(stream, svc, multiplex, None).await
+                });
+            }
+        }
+    }
+}
+
+async fn handle<T, S>(stream: T, service: Service<S>, multiplex: bool, identity: Option<String>)
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: AsyncStorage + Send + Sync + 'static,
+{
+    let result = if multiplex {
+        // 每个逻辑流各自运行一个处理循环
+        serve_yamux(stream, None, |stream| {
+            KvServerStream::new(stream, service.clone())
+                .with_identity(identity.clone())
+                .process()
+        })
+        .await
+    } else {
+        KvServerStream::new(stream, service)
+            .with_identity(identity)
+            .process()
+            .await
+    };
+    if let Err(e) = result {
+        warn!("Failed to process stream: {}", e);
+    }
+}
+
+// redis 协议的连接没有 TLS，命令以 resp.identity 的身份执行
+async fn serve_resp<S>(listener: TcpListener, service: Service<S>, config: RespConfig)
+where
+    S: AsyncStorage + Send + Sync + 'static,
+{
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept redis client: {}", e);
+                continue;
+            }
+        };
+        info!("Redis client {} connected", addr);
+        let stream = RespServerStream::new(stream, service.clone())
+            .with_table(config.table.as_str())
+            .with_identity(config.identity.clone());
+        let connection = service.connection();
+        tokio::spawn(async move {
+            let _connection = connection;
+            if let Err(e) = stream.process().await {
+                warn!("Failed to process redis stream: {}", e);
+            }
+        });
+    }
+}
+
+fn connector(tls: &ClientTlsConfig) -> Result<TlsClientConnector, KvError> {
+    let identity = tls.cert.as_deref().zip(tls.key.as_deref());
+    TlsClientConnector::new(&tls.domain, identity, tls.ca.as_deref())
+}
+
+// 连接断开或者同步出错后重新连接，每次连接都从快照开始同步
+async fn follow<S>(leader: String, connector: Option<TlsClientConnector>, service: Service<S>)
+where
+    S: AsyncStorage + Send + Sync + 'static,
+{
+    loop {
+        let result = async {
+            let stream = TcpStream::connect(&leader).await?;
+            match connector.as_ref() {
+                Some(connector) => {
+                    let stream = connector.connect(stream).await?;
+                    replicate_from(stream, service.clone()).await
+                }
+                None => replicate_from(stream, service.clone()).await,
+            }
+        }
+        .await;
+        match result {
+            Ok(()) => warn!("Replication from leader {} is closed", leader),
+            Err(e) => warn!("Failed to replicate from leader {}: {}", leader, e),
+        }
+        tokio::time::sleep(RECONNECT_INTERVAL).await;
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{CommandRequest, command_request::RequestData, error::KvError};
+
+// 表名为 "*" 时匹配所有的表
+const ANY_TABLE: &str = "*";
+
+// 按客户端证书的 CN 授权可以访问的表
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AclConfig {
+    // 没有匹配的身份（包括没有客户端证书的连接）使用的权限
+    pub default: TablePermissions,
+    pub identities: HashMap<String, TablePermissions>,
+}
+
+// 可以读、写的表，写权限同时包含读权限（写命令会返回旧值）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TablePermissions {
+    pub read: Vec<String>,
+    pub write: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+}
+
+impl AclConfig {
+    // 检查身份是否可以执行这个命令，事务检查其中的每个命令和 watch 的 key
+    pub fn check(&self, identity: Option<&str>, cmd: &CommandRequest) -> Result<(), KvError> {
+        let permissions = identity
+            .and_then(|identity| self.identities.get(identity))
+            .unwrap_or(&self.default);
+        permissions.check_command(cmd).map_err(|(table, access)| {
+            let action = match access {
+                Access::Read => "read",
+                Access::Write => "write",
+            };
+            KvError::PermissionDenied(format!(
+                "{} cannot {} table {}",
+                identity.unwrap_or("anonymous"),
+                action,
+                table
+            ))
+        })
+    }
+}
+
+impl TablePermissions {
+    fn allows(&self, table: &str, access: Access) -> bool {
+        let matches = |tables: &Vec<String>| tables.iter().any(|t| t == ANY_TABLE || t == table);
+        matches(&self.write) || (access == Access::Read && matches(&self.read))
+    }
+
+    fn check_command<'a>(&self, cmd: &'a CommandRequest) -> Result<(), (&'a str, Access)> {
+        let (table, access): (&str, Access) = match &cmd.request_data {
+            Some(RequestData::Hget(p)) => (&p.table, Access::Read),
+            Some(RequestData::Hgetall(p)) => (&p.table, Access::Read),
+            Some(RequestData::Hmget(p)) => (&p.table, Access::Read),
+            Some(RequestData::Hexists(p)) => (&p.table, Access::Read),
+            Some(RequestData::Hmexists(p)) => (&p.table, Access::Read),
+            Some(RequestData::Ttl(p)) => (&p.table, Access::Read),
+            Some(RequestData::Hscan(p)) => (&p.table, Access::Read),
+            Some(RequestData::TableInfo(p)) => (&p.table, Access::Read),
+            Some(RequestData::Lrange(p)) => (&p.table, Access::Read),
+            Some(RequestData::Smembers(p)) => (&p.table, Access::Read),
+            Some(RequestData::Sinter(p)) => (&p.table, Access::Read),
+            Some(RequestData::Zrange(p)) => (&p.table, Access::Read),
+            Some(RequestData::Zrangebyscore(p)) => (&p.table, Access::Read),
+            Some(RequestData::Zrank(p)) => (&p.table, Access::Read),
+            Some(RequestData::Hset(p)) => (&p.table, Access::Write),
+            Some(RequestData::Hmset(p)) => (&p.table, Access::Write),
+            Some(RequestData::Hdel(p)) => (&p.table, Access::Write),
+            Some(RequestData::Hmdel(p)) => (&p.table, Access::Write),
+            Some(RequestData::Expire(p)) => (&p.table, Access::Write),
+            Some(RequestData::Persist(p)) => (&p.table, Access::Write),
+            Some(RequestData::Hincrby(p)) => (&p.table, Access::Write),
+            Some(RequestData::Hincrbyfloat(p)) => (&p.table, Access::Write),
+            Some(RequestData::Hsetnx(p)) => (&p.table, Access::Write),
+            Some(RequestData::Cas(p)) => (&p.table, Access::Write),
+            Some(RequestData::DropTable(p)) => (&p.table, Access::Write),
+            Some(RequestData::Lpush(p)) => (&p.table, Access::Write),
+            Some(RequestData::Rpush(p)) => (&p.table, Access::Write),
+            Some(RequestData::Lpop(p)) => (&p.table, Access::Write),
+            Some(RequestData::Rpop(p)) => (&p.table, Access::Write),
+            Some(RequestData::Sadd(p)) => (&p.table, Access::Write),
+            Some(RequestData::Srem(p)) => (&p.table, Access::Write),
+            Some(RequestData::Zadd(p)) => (&p.table, Access::Write),
+            Some(RequestData::Zrem(p)) => (&p.table, Access::Write),
+            // 列出表名和复制需要所有表的读权限
+            Some(RequestData::ListTables(_) | RequestData::Replicate(_)) => {
+                (ANY_TABLE, Access::Read)
+            }
+            Some(RequestData::Transaction(tx)) => {
+                for watch in &tx.watches {
+                    if !self.allows(&watch.table, Access::Read) {
+                        return Err((&watch.table, Access::Read));
+                    }
+                }
+                return tx
+                    .commands
+                    .iter()
+                    .try_for_each(|cmd| self.check_command(cmd));
+            }
+            // 主题不属于任何表，无效的命令交给后面处理
+            Some(
+                RequestData::Subscribe(_) | RequestData::Unsubscribe(_) | RequestData::Publish(_),
+            )
+            | None => return Ok(()),
+        };
+        if self.allows(table, access) {
+            Ok(())
+        } else {
+            Err((table, access))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Kvpair;
+
+    use super::*;
+
+    #[test]
+    fn acl_should_work() {
+        let acl: AclConfig = toml::from_str(
+            r#"
+            [default]
+            read = ["public"]
+
+            [identities.admin]
+            write = ["*"]
+
+            [identities.awesome-client]
+            read = ["*"]
+            write = ["t1"]
+            "#,
+        )
+        .unwrap();
+
+        let hget = CommandRequest::new_hget("t2", "k1");
+        let hset = CommandRequest::new_hset("t2", "k1", "v1".into());
+        assert!(acl.check(Some("admin"), &hset).is_ok());
+        assert!(acl.check(Some("awesome-client"), &hget).is_ok());
+        assert_eq!(
+            acl.check(Some("awesome-client"), &hset),
+            Err(KvError::PermissionDenied(
+                "awesome-client cannot write table t2".into()
+            ))
+        );
+        let hset = CommandRequest::new_hset("t1", "k1", "v1".into());
+        assert!(acl.check(Some("awesome-client"), &hset).is_ok());
+
+        // 没有证书或者未知的身份使用默认权限
+        assert!(
+            acl.check(None, &CommandRequest::new_hgetall("public"))
+                .is_ok()
+        );
+        assert!(acl.check(Some("nobody"), &hget).is_err());
+        assert_eq!(
+            acl.check(None, &CommandRequest::new_list_tables()),
+            Err(KvError::PermissionDenied(
+                "anonymous cannot read table *".into()
+            ))
+        );
+        assert!(
+            acl.check(Some("awesome-client"), &CommandRequest::new_list_tables())
+                .is_ok()
+        );
+        assert!(
+            acl.check(None, &CommandRequest::new_subscribe("lobby"))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn acl_should_check_every_command_in_transaction() {
+        let mut acl = AclConfig::default();
+        acl.default.write = vec!["t1".into()];
+
+        let cmd = CommandRequest::new_transaction(
+            vec![CommandRequest::new_hmset(
+                "t1",
+                vec![Kvpair::new("k1", 1.into())],
+            )],
+            vec![],
+        );
+        assert!(acl.check(None, &cmd).is_ok());
+
+        let cmd = CommandRequest::new_transaction(
+            vec![
+                CommandRequest::new_hdel("t1", "k1"),
+                CommandRequest::new_hdel("t2", "k1"),
+            ],
+            vec![],
+        );
+        assert!(acl.check(None, &cmd).is_err());
+    }
+}
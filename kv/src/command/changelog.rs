@@ -0,0 +1,198 @@
+use std::{collections::HashSet, sync::Arc};
+
+use futures::StreamExt;
+use tokio::sync::{
+    Mutex, MutexGuard,
+    broadcast::{self, error::RecvError},
+    mpsc,
+};
+use tracing::{debug, warn};
+
+use crate::{CommandRequest, CommandResponse, error::KvError, now_ms, storage::AsyncStorage};
+
+// 修改日志的缓冲区大小，follower 落后超过这个数量时断开，由 follower 重新同步快照
+const CHANGE_LOG_CAPACITY: usize = 4096;
+// 每个复制响应最多包含的命令数量
+const REPLICATION_BATCH: usize = 256;
+
+// leader 上的修改日志：修改命令持有写锁执行，之后按执行的顺序把修改的结果广播给所有的 follower
+// 日志中是 key 最终的值（带绝对的过期时间）或者删除，follower 重复执行也得到相同的结果
+// None 表示读取修改的结果失败，日志已经不完整，follower 需要重新同步快照
+pub struct ChangeLog {
+    write_lock: Mutex<()>,
+    sender: broadcast::Sender<Option<Arc<CommandRequest>>>,
+    // 允许同步数据的 follower 的身份，即客户端证书的 CN
+    followers: Vec<String>,
+}
+
+impl ChangeLog {
+    pub fn new(followers: Vec<String>) -> Self {
+        let (sender, _) = broadcast::channel(CHANGE_LOG_CAPACITY);
+        Self {
+            write_lock: Mutex::new(()),
+            sender,
+            followers,
+        }
+    }
+
+    pub async fn lock(&self) -> MutexGuard<'_, ()> {
+        self.write_lock.lock().await
+    }
+
+    pub fn is_follower(&self, identity: Option<&str>) -> bool {
+        identity.is_some_and(|identity| self.followers.iter().any(|f| f == identity))
+    }
+
+    // 调用者需要持有写锁，命令失败时也可能已经修改了部分 key，同样要记录
+    // 没有 follower 时不需要记录，之后连上的 follower 会先同步快照
+    pub(crate) async fn append<S: AsyncStorage>(&self, store: &S, cmd: &CommandRequest) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        // 同一个 key 只记录最后一次修改，DropTable 之后再写入的 key 排在 DropTable 之后
+        let mut seen = HashSet::new();
+        let mut touched: Vec<_> = cmd
+            .touched()
+            .into_iter()
+            .rev()
+            .filter(|touched| seen.insert(*touched))
+            .collect();
+        touched.reverse();
+        for (table, key) in touched {
+            let change = match key {
+                Some(key) => change(store, table, key).await,
+                None => Ok(CommandRequest::new_drop_table(table)),
+            };
+            match change {
+                Ok(change) => {
+                    let _ = self.sender.send(Some(Arc::new(change)));
+                }
+                Err(e) => {
+                    warn!("Failed to read change of {}: {}", table, e);
+                    let _ = self.sender.send(None);
+                    return;
+                }
+            }
+        }
+    }
+
+    // 把快照和之后的修改发送给一个 follower，follower 断开或者落后太多时返回
+    pub(crate) async fn replicate<S: AsyncStorage>(
+        &self,
+        store: &S,
+        tx: mpsc::Sender<CommandResponse>,
+    ) -> Result<(), KvError> {
+        // 持有写锁订阅日志并读出快照，快照之后的修改都会出现在订阅的日志中
+        // 发送快照时已经释放写锁，follower 读取慢不会阻塞 leader 的写入
+        let (mut changes, snapshot) = {
+            let _guard = self.lock().await;
+            (self.sender.subscribe(), snapshot(store).await?)
+        };
+        // 快照前后各有一个空的响应，follower 据此开始和结束加载快照，之后的响应都不为空
+        if tx.send(batch(vec![])).await.is_err() {
+            return Ok(());
+        }
+        let mut snapshot = snapshot.into_iter();
+        loop {
+            let commands: Vec<_> = snapshot.by_ref().take(REPLICATION_BATCH).collect();
+            if commands.is_empty() {
+                break;
+            }
+            if tx.send(batch(commands)).await.is_err() {
+                return Ok(());
+            }
+        }
+        if tx.send(batch(vec![])).await.is_err() {
+            return Ok(());
+        }
+        debug!("Snapshot is sent, streaming changes");
+        loop {
+            let cmd = tokio::select! {
+                _ = tx.closed() => return Ok(()),
+                cmd = changes.recv() => cmd,
+            };
+            let mut commands = match cmd {
+                Ok(cmd) => vec![received(cmd)?],
+                Err(RecvError::Lagged(n)) => {
+                    warn!("Follower lagged {} changes behind", n);
+                    return Err(KvError::Internal(format!(
+                        "follower lagged {} changes behind, resync required",
+                        n
+                    )));
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            };
+            // 已经积压的修改合并到一个响应中发送
+            while commands.len() < REPLICATION_BATCH {
+                match changes.try_recv() {
+                    Ok(cmd) => commands.push(received(cmd)?),
+                    Err(_) => break,
+                }
+            }
+            if tx.send(batch(commands)).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn received(cmd: Option<Arc<CommandRequest>>) -> Result<CommandRequest, KvError> {
+    match cmd {
+        Some(cmd) => Ok((*cmd).clone()),
+        None => Err(KvError::Internal(
+            "change log is incomplete, resync required".into(),
+        )),
+    }
+}
+
+// 读取 key 当前的值和过期时间，不存在时记录为删除
+async fn change<S: AsyncStorage>(
+    store: &S,
+    table: &str,
+    key: &str,
+) -> Result<CommandRequest, KvError> {
+    let Some(value) = store.get(table, key).await? else {
+        return Ok(CommandRequest::new_hdel(table, key));
+    };
+    let expire_at = match store.ttl(table, key).await? {
+        Some(ttl) => now_ms() + (ttl.as_millis() as u64).max(1),
+        // 读取之后才过期的 key 记录为删除
+        None if !store.contains(table, key).await? => {
+            return Ok(CommandRequest::new_hdel(table, key));
+        }
+        None => 0,
+    };
+    Ok(CommandRequest::new_hset_with_deadline(
+        table, key, value, expire_at,
+    ))
+}
+
+// 调用者需要持有写锁，快照中的 key 带绝对的过期时间
+async fn snapshot<S: AsyncStorage>(store: &S) -> Result<Vec<CommandRequest>, KvError> {
+    let mut commands = Vec::new();
+    for table in store.list_tables().await? {
+        let mut pairs = store.get_iter(&table).await?;
+        while let Some(pair) = pairs.next().await {
+            let expire_at = match store.ttl(&table, &pair.key).await? {
+                Some(ttl) => now_ms() + (ttl.as_millis() as u64).max(1),
+                // 遍历之后才过期的 key 不再同步
+                None if !store.contains(&table, &pair.key).await? => continue,
+                None => 0,
+            };
+            let value = pair.value.unwrap_or_default();
+            commands.push(CommandRequest::new_hset_with_deadline(
+                &table, &pair.key, value, expire_at,
+            ));
+        }
+    }
+    Ok(commands)
+}
+
+fn batch(commands: Vec<CommandRequest>) -> CommandResponse {
+    CommandResponse {
+        status: 200,
+        message: "success".to_string(),
+        commands,
+        ..Default::default()
+    }
+}
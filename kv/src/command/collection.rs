@@ -0,0 +1,793 @@
+use std::ops::Range;
+
+use crate::{
+    CommandResponse, Kvpair, ListValue, Lpop, Lpush, Lrange, Rpop, Rpush, Sadd, ScoredMember,
+    SetValue, Sinter, Smembers, Srem, Value, Zadd, Zrange, Zrangebyscore, Zrank, Zrem, ZsetValue,
+    error::KvError, storage::AsyncStorage, value,
+};
+
+use super::commandservice::CommandService;
+
+// 列表、集合和有序集合整体保存在一个 Value 中，修改通过 update 原子地读取-修改-写入，
+// 所有的存储引擎都不需要额外的支持；弹出或移除最后一个元素后 key 保留空的集合
+// 代价是每次修改都重写整个集合：WAL、sled 和 bitcask 中每次写入 O(n) 字节，
+// 逐个添加 n 个元素一共写入 O(n²) 字节，因此限制集合最多 MAX_COLLECTION_LEN 个元素
+pub const MAX_COLLECTION_LEN: usize = 10_000;
+
+// key 不存在时视为空的集合，其它类型的值返回类型错误
+fn as_list(key: &str, value: Option<&Value>) -> Result<ListValue, KvError> {
+    match value.and_then(|v| v.value.as_ref()) {
+        None => Ok(ListValue::default()),
+        Some(value::Value::ListValue(list)) => Ok(list.clone()),
+        Some(_) => Err(wrong_type(key, value, "list")),
+    }
+}
+
+fn as_set(key: &str, value: Option<&Value>) -> Result<SetValue, KvError> {
+    match value.and_then(|v| v.value.as_ref()) {
+        None => Ok(SetValue::default()),
+        Some(value::Value::SetValue(set)) => Ok(set.clone()),
+        Some(_) => Err(wrong_type(key, value, "set")),
+    }
+}
+
+fn as_zset(key: &str, value: Option<&Value>) -> Result<ZsetValue, KvError> {
+    match value.and_then(|v| v.value.as_ref()) {
+        None => Ok(ZsetValue::default()),
+        Some(value::Value::ZsetValue(zset)) => Ok(zset.clone()),
+        Some(_) => Err(wrong_type(key, value, "zset")),
+    }
+}
+
+fn check_len(key: &str, len: usize) -> Result<(), KvError> {
+    if len > MAX_COLLECTION_LEN {
+        return Err(KvError::OutOfRange(format!(
+            "{} would hold {} elements, at most {} are allowed",
+            key, len, MAX_COLLECTION_LEN
+        )));
+    }
+    Ok(())
+}
+
+fn wrong_type(key: &str, value: Option<&Value>, expected: &'static str) -> KvError {
+    let actual = value.map_or("empty", Value::type_name);
+    KvError::WrongType(key.into(), actual, expected)
+}
+
+fn required<T>(items: &[T], name: &str) -> Result<(), KvError> {
+    if items.is_empty() {
+        return Err(KvError::InvalidArgument(format!("{} are required", name)));
+    }
+    Ok(())
+}
+
+// 与 redis 一致：start 和 stop 都包含在内，负数从尾部开始计算，超出范围的部分被忽略
+fn index_range(len: usize, start: i64, stop: i64) -> Range<usize> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop || start >= len {
+        return 0..0;
+    }
+    start as usize..stop as usize + 1
+}
+
+fn push(
+    key: &str,
+    old: Option<&Value>,
+    values: &[Value],
+    left: bool,
+) -> Result<ListValue, KvError> {
+    let mut list = as_list(key, old)?;
+    check_len(key, list.values.len() + values.len())?;
+    if left {
+        // 依次插入到头部，最后一个元素排在最前面
+        let mut values: Vec<Value> = values.iter().rev().cloned().collect();
+        values.append(&mut list.values);
+        list.values = values;
+    } else {
+        list.values.extend_from_slice(values);
+    }
+    Ok(list)
+}
+
+fn pop(
+    key: &str,
+    old: Option<&Value>,
+    count: u32,
+    left: bool,
+) -> Result<(ListValue, Vec<Value>), KvError> {
+    let mut list = as_list(key, old)?;
+    let count = (count.max(1) as usize).min(list.values.len());
+    let popped = if left {
+        list.values.drain(..count).collect()
+    } else {
+        let mut popped = list.values.split_off(list.values.len() - count);
+        popped.reverse();
+        popped
+    };
+    Ok((list, popped))
+}
+
+async fn push_list<S: AsyncStorage>(
+    storage: &S,
+    table: &str,
+    key: &str,
+    values: &[Value],
+    left: bool,
+) -> CommandResponse {
+    if let Err(e) = required(values, "values") {
+        return e.into();
+    }
+    let (k, v) = (key.to_owned(), values.to_vec());
+    let res = storage
+        .update(
+            table,
+            key,
+            Box::new(move |old| Ok(Some(push(&k, old, &v, left)?.into()))),
+        )
+        .await;
+    // update 的闭包需要 'static，列表的长度由返回的旧值重新计算得到
+    match res.and_then(|old| as_list(key, old.as_ref())) {
+        Ok(list) => Value::from((list.values.len() + values.len()) as i64).into(),
+        Err(e) => e.into(),
+    }
+}
+
+async fn pop_list<S: AsyncStorage>(
+    storage: &S,
+    table: &str,
+    key: &str,
+    count: u32,
+    left: bool,
+) -> CommandResponse {
+    let k = key.to_owned();
+    let res = storage
+        .update(
+            table,
+            key,
+            Box::new(move |old| {
+                let (list, popped) = pop(&k, old, count, left)?;
+                Ok((!popped.is_empty()).then(|| list.into()))
+            }),
+        )
+        .await;
+    match res.and_then(|old| pop(key, old.as_ref(), count, left)) {
+        Ok((_, popped)) => popped.into(),
+        Err(e) => e.into(),
+    }
+}
+
+impl CommandService for Lpush {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        push_list(storage, &self.table, &self.key, &self.values, true).await
+    }
+}
+
+impl CommandService for Rpush {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        push_list(storage, &self.table, &self.key, &self.values, false).await
+    }
+}
+
+impl CommandService for Lpop {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        pop_list(storage, &self.table, &self.key, self.count, true).await
+    }
+}
+
+impl CommandService for Rpop {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        pop_list(storage, &self.table, &self.key, self.count, false).await
+    }
+}
+
+impl CommandService for Lrange {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        let res = storage.get(&self.table, &self.key).await;
+        match res.and_then(|value| as_list(&self.key, value.as_ref())) {
+            Ok(mut list) => {
+                let range = index_range(list.values.len(), self.start, self.stop);
+                list.values.drain(range).collect::<Vec<_>>().into()
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+// 成员按字典序排列，返回新添加的数量
+fn add_members(
+    key: &str,
+    old: Option<&Value>,
+    members: &[String],
+) -> Result<(SetValue, usize), KvError> {
+    let mut set = as_set(key, old)?;
+    let mut added = 0;
+    for member in members {
+        if let Err(i) = set.members.binary_search(member) {
+            set.members.insert(i, member.clone());
+            added += 1;
+        }
+    }
+    check_len(key, set.members.len())?;
+    Ok((set, added))
+}
+
+fn remove_members(
+    key: &str,
+    old: Option<&Value>,
+    members: &[String],
+) -> Result<(SetValue, usize), KvError> {
+    let mut set = as_set(key, old)?;
+    let mut removed = 0;
+    for member in members {
+        if let Ok(i) = set.members.binary_search(member) {
+            set.members.remove(i);
+            removed += 1;
+        }
+    }
+    Ok((set, removed))
+}
+
+impl CommandService for Sadd {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        if let Err(e) = required(&self.members, "members") {
+            return e.into();
+        }
+        let (key, members) = (self.key.clone(), self.members.clone());
+        let res = storage
+            .update(
+                &self.table,
+                &self.key,
+                Box::new(move |old| {
+                    let (set, added) = add_members(&key, old, &members)?;
+                    Ok((added > 0).then(|| set.into()))
+                }),
+            )
+            .await;
+        match res.and_then(|old| add_members(&self.key, old.as_ref(), &self.members)) {
+            Ok((_, added)) => Value::from(added as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Srem {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        let (key, members) = (self.key.clone(), self.members.clone());
+        let res = storage
+            .update(
+                &self.table,
+                &self.key,
+                Box::new(move |old| {
+                    let (set, removed) = remove_members(&key, old, &members)?;
+                    Ok((removed > 0).then(|| set.into()))
+                }),
+            )
+            .await;
+        match res.and_then(|old| remove_members(&self.key, old.as_ref(), &self.members)) {
+            Ok((_, removed)) => Value::from(removed as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Smembers {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        let res = storage.get(&self.table, &self.key).await;
+        match res.and_then(|value| as_set(&self.key, value.as_ref())) {
+            Ok(set) => set
+                .members
+                .into_iter()
+                .map(Value::from)
+                .collect::<Vec<_>>()
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Sinter {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        let res = async {
+            required(&self.keys, "keys")?;
+            let mut result: Option<Vec<String>> = None;
+            // 所有的 key 都要读取，其中有错误类型的值时返回类型错误
+            for key in &self.keys {
+                let set = as_set(key, storage.get(&self.table, key).await?.as_ref())?;
+                result = Some(match result {
+                    None => set.members,
+                    Some(mut members) => {
+                        members.retain(|m| set.members.binary_search(m).is_ok());
+                        members
+                    }
+                });
+            }
+            Ok::<_, KvError>(result.unwrap_or_default())
+        }
+        .await;
+        match res {
+            Ok(members) => members
+                .into_iter()
+                .map(Value::from)
+                .collect::<Vec<_>>()
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+// 成员按 (score, member) 排列，排名就是成员在列表中的位置
+fn zadd(
+    key: &str,
+    old: Option<&Value>,
+    members: &[ScoredMember],
+) -> Result<(ZsetValue, usize), KvError> {
+    let mut zset = as_zset(key, old)?;
+    let mut added = 0;
+    for member in members {
+        match zset.members.iter().position(|m| m.member == member.member) {
+            Some(i) => {
+                zset.members.remove(i);
+            }
+            None => added += 1,
+        }
+        let i = zset
+            .members
+            .partition_point(|m| (m.score, &m.member) < (member.score, &member.member));
+        zset.members.insert(i, member.clone());
+    }
+    check_len(key, zset.members.len())?;
+    Ok((zset, added))
+}
+
+fn zrem(key: &str, old: Option<&Value>, members: &[String]) -> Result<(ZsetValue, usize), KvError> {
+    let mut zset = as_zset(key, old)?;
+    let len = zset.members.len();
+    zset.members.retain(|m| !members.contains(&m.member));
+    let removed = len - zset.members.len();
+    Ok((zset, removed))
+}
+
+fn scored_pairs(members: impl Iterator<Item = ScoredMember>) -> CommandResponse {
+    members
+        .map(|m| Kvpair::new(&m.member, m.score.into()))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+impl CommandService for Zadd {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        if let Err(e) = required(&self.members, "members") {
+            return e.into();
+        }
+        if self.members.iter().any(|m| m.score.is_nan()) {
+            return KvError::InvalidArgument("score must not be NaN".into()).into();
+        }
+        let (key, members) = (self.key.clone(), self.members.clone());
+        let res = storage
+            .update(
+                &self.table,
+                &self.key,
+                Box::new(move |old| Ok(Some(zadd(&key, old, &members)?.0.into()))),
+            )
+            .await;
+        match res.and_then(|old| zadd(&self.key, old.as_ref(), &self.members)) {
+            Ok((_, added)) => Value::from(added as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrem {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        let (key, members) = (self.key.clone(), self.members.clone());
+        let res = storage
+            .update(
+                &self.table,
+                &self.key,
+                Box::new(move |old| {
+                    let (zset, removed) = zrem(&key, old, &members)?;
+                    Ok((removed > 0).then(|| zset.into()))
+                }),
+            )
+            .await;
+        match res.and_then(|old| zrem(&self.key, old.as_ref(), &self.members)) {
+            Ok((_, removed)) => Value::from(removed as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrange {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        let res = storage.get(&self.table, &self.key).await;
+        match res.and_then(|value| as_zset(&self.key, value.as_ref())) {
+            Ok(mut zset) => {
+                let range = index_range(zset.members.len(), self.start, self.stop);
+                scored_pairs(zset.members.drain(range))
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrangebyscore {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        let limit = match self.limit {
+            0 => usize::MAX,
+            limit => limit as usize,
+        };
+        let res = storage.get(&self.table, &self.key).await;
+        match res.and_then(|value| as_zset(&self.key, value.as_ref())) {
+            Ok(zset) => scored_pairs(
+                zset.members
+                    .into_iter()
+                    .skip_while(|m| m.score < self.min)
+                    .take_while(|m| m.score <= self.max)
+                    .take(limit),
+            ),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrank {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        let res = storage.get(&self.table, &self.key).await;
+        match res.and_then(|value| as_zset(&self.key, value.as_ref())) {
+            Ok(zset) => match zset.members.iter().position(|m| m.member == self.member) {
+                Some(rank) => Value::from(rank as i64).into(),
+                None => KvError::KeyNotFound.into(),
+            },
+            Err(e) => e.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        CommandRequest, MemTable, command::commandservice::dispatch_sync, sleddb::SledDb,
+        storage::storage::Storage,
+    };
+
+    #[test]
+    fn index_range_should_work() {
+        assert_eq!(index_range(5, 0, -1), 0..5);
+        assert_eq!(index_range(5, 1, 2), 1..3);
+        assert_eq!(index_range(5, -2, -1), 3..5);
+        assert_eq!(index_range(5, -10, 10), 0..5);
+        assert_eq!(index_range(5, 3, 1), 0..0);
+        assert_eq!(index_range(5, 5, 10), 0..0);
+        assert_eq!(index_range(0, 0, -1), 0..0);
+    }
+
+    #[test]
+    fn memtable_list_should_work() {
+        test_list(&MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_list_should_work() {
+        let dir = tempdir().unwrap();
+        test_list(&SledDb::new(dir));
+    }
+
+    #[test]
+    fn memtable_set_should_work() {
+        test_set(&MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_set_should_work() {
+        let dir = tempdir().unwrap();
+        test_set(&SledDb::new(dir));
+    }
+
+    #[test]
+    fn memtable_zset_should_work() {
+        test_zset(&MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_zset_should_work() {
+        let dir = tempdir().unwrap();
+        test_zset(&SledDb::new(dir));
+    }
+
+    #[test]
+    fn memtable_wrong_type_should_fail() {
+        test_wrong_type(&MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_wrong_type_should_fail() {
+        let dir = tempdir().unwrap();
+        test_wrong_type(&SledDb::new(dir));
+    }
+
+    #[test]
+    fn sleddb_collections_should_survive_restart() {
+        let dir = tempdir().unwrap();
+        {
+            let store = SledDb::new(dir.path());
+            let cmd = CommandRequest::new_rpush("t1", "queue", vec!["a".into(), 1.into()]);
+            dispatch_sync(cmd, &store);
+            let cmd =
+                CommandRequest::new_zadd("t1", "board", vec![ScoredMember::new("alice", 1.5)]);
+            dispatch_sync(cmd, &store);
+            store
+                .expire("t1", "queue", Duration::from_secs(60))
+                .unwrap();
+        }
+        // 等待上一个 SledDb 释放目录的锁
+        thread::sleep(Duration::from_millis(80));
+        let store = SledDb::new(dir.path());
+        let res = dispatch_sync(CommandRequest::new_lpop("t1", "queue", 0), &store);
+        assert_eq!(res.values, vec![Value::from("a")]);
+        // 修改集合不会清除过期时间
+        assert!(store.ttl("t1", "queue").unwrap().is_some());
+        let res = dispatch_sync(CommandRequest::new_zrange("t1", "board", 0, -1), &store);
+        assert_eq!(res.pairs, vec![Kvpair::new("alice", 1.5.into())]);
+    }
+
+    fn test_list(store: &dyn Storage) {
+        let res = dispatch_sync(
+            CommandRequest::new_rpush("t1", "l1", vec!["b".into(), "c".into()]),
+            store,
+        );
+        assert_eq!(res.values, vec![Value::from(2)]);
+        let res = dispatch_sync(
+            CommandRequest::new_lpush("t1", "l1", vec!["a".into(), 0.into()]),
+            store,
+        );
+        assert_eq!(res.values, vec![Value::from(4)]);
+
+        let res = dispatch_sync(CommandRequest::new_lrange("t1", "l1", 0, -1), store);
+        let all: Vec<Value> = vec![0.into(), "a".into(), "b".into(), "c".into()];
+        assert_eq!(res.values, all);
+        let res = dispatch_sync(CommandRequest::new_lrange("t1", "l1", 1, -2), store);
+        assert_eq!(res.values, all[1..3]);
+        let res = dispatch_sync(CommandRequest::new_lrange("t1", "nope", 0, -1), store);
+        assert_eq!(res.status, 200);
+        assert!(res.values.is_empty());
+
+        let res = dispatch_sync(CommandRequest::new_lpop("t1", "l1", 0), store);
+        assert_eq!(res.values, vec![Value::from(0)]);
+        let res = dispatch_sync(CommandRequest::new_rpop("t1", "l1", 2), store);
+        assert_eq!(res.values, vec![Value::from("c"), "b".into()]);
+        let res = dispatch_sync(CommandRequest::new_rpop("t1", "l1", 10), store);
+        assert_eq!(res.values, vec![Value::from("a")]);
+        let res = dispatch_sync(CommandRequest::new_lpop("t1", "l1", 1), store);
+        assert!(res.values.is_empty());
+        // 弹出所有元素后保留空的列表
+        assert_eq!(store.get("t1", "l1"), Ok(Some(ListValue::default().into())));
+
+        let res = dispatch_sync(CommandRequest::new_lpop("t1", "nope", 1), store);
+        assert!(res.values.is_empty());
+        assert_eq!(store.contains("t1", "nope"), Ok(false));
+        let res = dispatch_sync(CommandRequest::new_lpush("t1", "l2", vec![]), store);
+        assert_eq!(res.status, 400);
+        assert_eq!(store.contains("t1", "l2"), Ok(false));
+
+        // 超过长度限制时返回 400，不修改原来的值
+        let values = vec![Value::from(1); MAX_COLLECTION_LEN];
+        let res = dispatch_sync(CommandRequest::new_rpush("t1", "l2", values), store);
+        assert_eq!(res.values, vec![Value::from(MAX_COLLECTION_LEN as i64)]);
+        let res = dispatch_sync(CommandRequest::new_lpush("t1", "l2", vec![2.into()]), store);
+        assert_eq!(res.status, 400);
+        let res = dispatch_sync(CommandRequest::new_lrange("t1", "l2", 0, 0), store);
+        assert_eq!(res.values, vec![Value::from(1)]);
+    }
+
+    fn test_set(store: &dyn Storage) {
+        let members = |members: &[&str]| members.iter().map(|m| m.to_string()).collect();
+        let res = dispatch_sync(
+            CommandRequest::new_sadd("t1", "s1", members(&["c", "a", "b", "a"])),
+            store,
+        );
+        assert_eq!(res.values, vec![Value::from(3)]);
+        let res = dispatch_sync(
+            CommandRequest::new_sadd("t1", "s1", members(&["b", "d"])),
+            store,
+        );
+        assert_eq!(res.values, vec![Value::from(1)]);
+        let res = dispatch_sync(CommandRequest::new_smembers("t1", "s1"), store);
+        assert_eq!(
+            res.values,
+            vec!["a".into(), "b".into(), "c".into(), Value::from("d")]
+        );
+
+        let res = dispatch_sync(
+            CommandRequest::new_srem("t1", "s1", members(&["a", "x"])),
+            store,
+        );
+        assert_eq!(res.values, vec![Value::from(1)]);
+
+        dispatch_sync(
+            CommandRequest::new_sadd("t1", "s2", members(&["b", "d", "e"])),
+            store,
+        );
+        dispatch_sync(
+            CommandRequest::new_sadd("t1", "s3", members(&["d", "b"])),
+            store,
+        );
+        let res = dispatch_sync(
+            CommandRequest::new_sinter("t1", members(&["s1", "s2", "s3"])),
+            store,
+        );
+        assert_eq!(res.values, vec![Value::from("b"), "d".into()]);
+        let res = dispatch_sync(
+            CommandRequest::new_sinter("t1", members(&["s1", "nope"])),
+            store,
+        );
+        assert_eq!(res.status, 200);
+        assert!(res.values.is_empty());
+        let res = dispatch_sync(CommandRequest::new_smembers("t1", "nope"), store);
+        assert!(res.values.is_empty());
+    }
+
+    fn test_zset(store: &dyn Storage) {
+        let members = vec![
+            ScoredMember::new("alice", 30.0),
+            ScoredMember::new("bob", 10.0),
+            ScoredMember::new("carol", 20.0),
+            ScoredMember::new("dave", 20.0),
+        ];
+        let res = dispatch_sync(CommandRequest::new_zadd("t1", "z1", members), store);
+        assert_eq!(res.values, vec![Value::from(4)]);
+        // 已经存在的成员更新分数
+        let members = vec![
+            ScoredMember::new("bob", 25.0),
+            ScoredMember::new("eve", 5.0),
+        ];
+        let res = dispatch_sync(CommandRequest::new_zadd("t1", "z1", members), store);
+        assert_eq!(res.values, vec![Value::from(1)]);
+
+        let res = dispatch_sync(CommandRequest::new_zrange("t1", "z1", 0, -1), store);
+        let keys: Vec<_> = res.pairs.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(keys, ["eve", "carol", "dave", "bob", "alice"]);
+        assert_eq!(res.pairs[3], Kvpair::new("bob", 25.0.into()));
+        let res = dispatch_sync(CommandRequest::new_zrange("t1", "z1", -2, -1), store);
+        assert_eq!(
+            res.pairs,
+            vec![
+                Kvpair::new("bob", 25.0.into()),
+                Kvpair::new("alice", 30.0.into())
+            ]
+        );
+
+        let res = dispatch_sync(
+            CommandRequest::new_zrangebyscore("t1", "z1", 20.0, 25.0, 0),
+            store,
+        );
+        let keys: Vec<_> = res.pairs.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(keys, ["carol", "dave", "bob"]);
+        let res = dispatch_sync(
+            CommandRequest::new_zrangebyscore("t1", "z1", 0.0, f64::INFINITY, 2),
+            store,
+        );
+        let keys: Vec<_> = res.pairs.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(keys, ["eve", "carol"]);
+
+        let res = dispatch_sync(CommandRequest::new_zrank("t1", "z1", "bob"), store);
+        assert_eq!(res.values, vec![Value::from(3)]);
+        let res = dispatch_sync(CommandRequest::new_zrank("t1", "z1", "nope"), store);
+        assert_eq!(res.status, 404);
+
+        let res = dispatch_sync(
+            CommandRequest::new_zrem("t1", "z1", vec!["eve".into(), "nope".into()]),
+            store,
+        );
+        assert_eq!(res.values, vec![Value::from(1)]);
+        let res = dispatch_sync(CommandRequest::new_zrank("t1", "z1", "carol"), store);
+        assert_eq!(res.values, vec![Value::from(0)]);
+
+        let members = vec![ScoredMember::new("nan", f64::NAN)];
+        let res = dispatch_sync(CommandRequest::new_zadd("t1", "z1", members), store);
+        assert_eq!(res.status, 400);
+    }
+
+    fn test_wrong_type(store: &dyn Storage) {
+        store.set("t1", "str", "v1".into()).unwrap();
+        dispatch_sync(
+            CommandRequest::new_rpush("t1", "list", vec!["a".into()]),
+            store,
+        );
+        dispatch_sync(
+            CommandRequest::new_sadd("t1", "set", vec!["a".into()]),
+            store,
+        );
+
+        let cmds = [
+            (
+                CommandRequest::new_lpush("t1", "str", vec!["a".into()]),
+                "str",
+                "string",
+                "list",
+            ),
+            (
+                CommandRequest::new_rpop("t1", "set", 1),
+                "set",
+                "set",
+                "list",
+            ),
+            (
+                CommandRequest::new_lrange("t1", "str", 0, -1),
+                "str",
+                "string",
+                "list",
+            ),
+            (
+                CommandRequest::new_sadd("t1", "list", vec!["a".into()]),
+                "list",
+                "list",
+                "set",
+            ),
+            (
+                CommandRequest::new_sinter("t1", vec!["set".into(), "str".into()]),
+                "str",
+                "string",
+                "set",
+            ),
+            (
+                CommandRequest::new_zadd("t1", "set", vec![ScoredMember::new("a", 1.0)]),
+                "set",
+                "set",
+                "zset",
+            ),
+            (
+                CommandRequest::new_zrank("t1", "list", "a"),
+                "list",
+                "list",
+                "zset",
+            ),
+            (
+                CommandRequest::new_hincrby("t1", "list", 1),
+                "list",
+                "list",
+                "int64",
+            ),
+            (
+                CommandRequest::new_hincrbyfloat("t1", "set", 1.0),
+                "set",
+                "set",
+                "double",
+            ),
+            (
+                CommandRequest::new_cas("t1", "list", None, "v".into()),
+                "list",
+                "list",
+                "scalar",
+            ),
+        ];
+        for (cmd, key, actual, expected) in cmds {
+            let res = dispatch_sync(cmd, store);
+            assert_eq!(res.status, 400);
+            assert_eq!(
+                res.message,
+                KvError::WrongType(key.into(), actual, expected).to_string()
+            );
+        }
+        // 类型错误时不修改原来的值
+        assert_eq!(store.get("t1", "str"), Ok(Some("v1".into())));
+        let res = dispatch_sync(CommandRequest::new_lrange("t1", "list", 0, -1), store);
+        assert_eq!(res.values, vec![Value::from("a")]);
+        let res = dispatch_sync(CommandRequest::new_smembers("t1", "set"), store);
+        assert_eq!(res.values, vec![Value::from("a")]);
+    }
+}
@@ -1,30 +1,87 @@
+use std::{
+    ops::Bound,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::FutureExt;
+
 use crate::{
-    CommandRequest, CommandResponse, Hget, Hgetall, Hset, Value, command_request::RequestData,
-    error::KvError, storage::storage::Storage,
+    Cas, CommandRequest, CommandResponse, DropTable, Expire, Hdel, Hexists, Hget, Hgetall, Hincrby,
+    Hincrbyfloat, Hmdel, Hmexists, Hmget, Hmset, Hscan, Hset, Hsetnx, Kvpair, ListTables, Persist,
+    TableInfo, Transaction, Ttl, Value,
+    command_request::RequestData,
+    error::KvError,
+    now_ms,
+    storage::{AsyncStorage, StorageView, storage::Storage},
 };
 
+// Hscan 没有指定 limit 时每页返回的数量，以及每页最多返回的数量
+pub(crate) const DEFAULT_SCAN_LIMIT: usize = 100;
+pub(crate) const MAX_SCAN_LIMIT: usize = 1000;
+
 pub trait CommandService {
-    fn exec(&self, storage: &dyn Storage) -> CommandResponse;
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse;
 }
-#[allow(unused)]
-pub fn dispatch(cmd: CommandRequest, storage: &dyn Storage) -> CommandResponse {
+
+pub async fn dispatch<S: AsyncStorage>(cmd: CommandRequest, storage: &S) -> CommandResponse {
     match cmd.request_data {
-        Some(RequestData::Hget(params)) => params.exec(storage),
-        Some(RequestData::Hgetall(params)) => params.exec(storage),
-        Some(RequestData::Hset(params)) => params.exec(storage),
-        // Some(RequestData::Hmget(params)) => params.exec(storage),
-        // Some(RequestData::Hmset(params)) => params.exec(storage),
-        // Some(RequestData::Hdel(params)) => params.exec(storage),
-        // Some(RequestData::Hmdel(params)) => params.exec(storage),
-        // Some(RequestData::Hexists(params)) => params.exec(storage),
-        // Some(RequestData::Hmexists(params)) => params.exec(storage),
-        _ => KvError::Internal("Not implemented".into()).into(),
+        Some(RequestData::Hget(params)) => params.exec(storage).await,
+        Some(RequestData::Hgetall(params)) => params.exec(storage).await,
+        Some(RequestData::Hset(params)) => params.exec(storage).await,
+        Some(RequestData::Hmget(params)) => params.exec(storage).await,
+        Some(RequestData::Hmset(params)) => params.exec(storage).await,
+        Some(RequestData::Hdel(params)) => params.exec(storage).await,
+        Some(RequestData::Hmdel(params)) => params.exec(storage).await,
+        Some(RequestData::Hexists(params)) => params.exec(storage).await,
+        Some(RequestData::Hmexists(params)) => params.exec(storage).await,
+        Some(RequestData::Expire(params)) => params.exec(storage).await,
+        Some(RequestData::Ttl(params)) => params.exec(storage).await,
+        Some(RequestData::Persist(params)) => params.exec(storage).await,
+        Some(RequestData::Transaction(params)) => params.exec(storage).await,
+        Some(RequestData::Hincrby(params)) => params.exec(storage).await,
+        Some(RequestData::Hincrbyfloat(params)) => params.exec(storage).await,
+        Some(RequestData::Hsetnx(params)) => params.exec(storage).await,
+        Some(RequestData::Cas(params)) => params.exec(storage).await,
+        Some(RequestData::Hscan(params)) => params.exec(storage).await,
+        Some(RequestData::ListTables(params)) => params.exec(storage).await,
+        Some(RequestData::DropTable(params)) => params.exec(storage).await,
+        Some(RequestData::TableInfo(params)) => params.exec(storage).await,
+        Some(RequestData::Lpush(params)) => params.exec(storage).await,
+        Some(RequestData::Rpush(params)) => params.exec(storage).await,
+        Some(RequestData::Lpop(params)) => params.exec(storage).await,
+        Some(RequestData::Rpop(params)) => params.exec(storage).await,
+        Some(RequestData::Lrange(params)) => params.exec(storage).await,
+        Some(RequestData::Sadd(params)) => params.exec(storage).await,
+        Some(RequestData::Srem(params)) => params.exec(storage).await,
+        Some(RequestData::Smembers(params)) => params.exec(storage).await,
+        Some(RequestData::Sinter(params)) => params.exec(storage).await,
+        Some(RequestData::Zadd(params)) => params.exec(storage).await,
+        Some(RequestData::Zrem(params)) => params.exec(storage).await,
+        Some(RequestData::Zrange(params)) => params.exec(storage).await,
+        Some(RequestData::Zrangebyscore(params)) => params.exec(storage).await,
+        Some(RequestData::Zrank(params)) => params.exec(storage).await,
+        // 主题相关的命令由 Service 中的 Broadcaster 处理
+        Some(RequestData::Subscribe(_) | RequestData::Unsubscribe(_) | RequestData::Publish(_)) => {
+            KvError::InvalidCommand("topic command is not a storage command".into()).into()
+        }
+        Some(RequestData::Replicate(_)) => {
+            KvError::InvalidCommand("replicate requires Service::execute".into()).into()
+        }
+        None => KvError::InvalidCommand("request data is required".into()).into(),
     }
 }
 
+// 直接在同步的存储上执行命令，事务中的命令通过它执行
+pub(super) fn dispatch_sync(cmd: CommandRequest, storage: &dyn Storage) -> CommandResponse {
+    dispatch(cmd, &StorageView(storage))
+        .now_or_never()
+        .expect("storage view should never be pending")
+}
+
 impl CommandService for Hget {
-    fn exec(&self, storage: &dyn Storage) -> CommandResponse {
-        match storage.get(&self.table, &self.key) {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        match storage.get(&self.table, &self.key).await {
             Ok(Some(value)) => value.into(),
             Ok(None) => KvError::KeyNotFound.into(),
             Err(e) => e.into(),
@@ -33,35 +90,435 @@ impl CommandService for Hget {
 }
 
 impl CommandService for Hset {
-    fn exec(&self, storage: &dyn Storage) -> CommandResponse {
-        if let Some(pair) = self.pair.as_ref() {
-            if pair.value.is_none() {
-                return KvError::Internal("value is required".into()).into();
-            } else {
-                match storage.set(&self.table, &pair.key, pair.value.clone().unwrap()) {
-                    Ok(Some(value)) => value.into(),
-                    Ok(None) => Value::default().into(),
-                    Err(e) => e.into(),
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        let Some(pair) = self.pair.as_ref() else {
+            return KvError::InvalidArgument("pair is required".into()).into();
+        };
+        let Some(value) = pair.value.clone() else {
+            return KvError::InvalidArgument("value is required".into()).into();
+        };
+        let ttl = match (self.expire_at, self.ttl) {
+            (0, ttl) => ttl,
+            // 同步过来时已经过期的 key 直接删除
+            (expire_at, _) => match expire_at.saturating_sub(now_ms()) {
+                0 => {
+                    return match storage.delete(&self.table, &pair.key).await {
+                        Ok(_) => Value::default().into(),
+                        Err(e) => e.into(),
+                    };
                 }
+                ttl => ttl,
+            },
+        };
+        let res = match ttl {
+            0 => storage.set(&self.table, &pair.key, value).await,
+            ttl => {
+                let ttl = Duration::from_millis(ttl);
+                storage
+                    .set_with_ttl(&self.table, &pair.key, value, ttl)
+                    .await
             }
-        } else {
-            KvError::Internal("pair is required".into()).into()
+        };
+        match res {
+            Ok(Some(value)) => value.into(),
+            Ok(None) => Value::default().into(),
+            Err(e) => e.into(),
         }
     }
 }
 
 impl CommandService for Hgetall {
-    fn exec(&self, storage: &dyn Storage) -> CommandResponse {
-        match storage.get_all(&self.table) {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        match storage.get_all(&self.table).await {
             Ok(pairs) => pairs.into(),
             Err(e) => e.into(),
         }
     }
 }
 
+impl CommandService for Hmget {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        let res = async {
+            let mut values = Vec::with_capacity(self.keys.len());
+            for key in &self.keys {
+                values.push(storage.get(&self.table, key).await?.unwrap_or_default());
+            }
+            Ok::<_, KvError>(values)
+        }
+        .await;
+        res.map_or_else(CommandResponse::from, CommandResponse::from)
+    }
+}
+
+impl CommandService for Hmset {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        if self.pairs.iter().any(|pair| pair.value.is_none()) {
+            return KvError::InvalidArgument("value is required".into()).into();
+        }
+        // 所有 key 的值和过期时间同时写入
+        let res = match self.ttl {
+            0 => storage.set_many(&self.table, self.pairs.clone()).await,
+            ttl => {
+                let ttl = Duration::from_millis(ttl);
+                storage
+                    .set_many_with_ttl(&self.table, self.pairs.clone(), ttl)
+                    .await
+            }
+        };
+        match res {
+            Ok(olds) => olds
+                .into_iter()
+                .map(Option::unwrap_or_default)
+                .collect::<Vec<Value>>()
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hdel {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        match storage.delete(&self.table, &self.key).await {
+            Ok(Some(value)) => value.into(),
+            Ok(None) => Value::default().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hmdel {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        match storage.delete_many(&self.table, &self.keys).await {
+            Ok(olds) => olds
+                .into_iter()
+                .map(Option::unwrap_or_default)
+                .collect::<Vec<Value>>()
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hexists {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        match storage.contains(&self.table, &self.key).await {
+            Ok(exists) => Value::from(exists).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Expire {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        match storage
+            .expire(&self.table, &self.key, Duration::from_millis(self.ttl))
+            .await
+        {
+            Ok(done) => Value::from(done).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Ttl {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        // 与 redis 一致：-2 表示 key 不存在，-1 表示没有过期时间
+        let res = async {
+            if !storage.contains(&self.table, &self.key).await? {
+                return Ok(-2);
+            }
+            let ttl = storage.ttl(&self.table, &self.key).await?;
+            Ok::<_, KvError>(ttl.map_or(-1, |ttl| ttl.as_millis() as i64))
+        }
+        .await;
+        match res {
+            Ok(ttl) => Value::from(ttl).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Persist {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        match storage.persist(&self.table, &self.key).await {
+            Ok(done) => Value::from(done).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+// update 的闭包需要 'static，结果由返回的旧值重新计算得到
+// 值的类型不对或者结果超出范围都是客户端的错误，返回 400
+fn incr_int(key: &str, old: Option<&Value>, delta: i64) -> Result<i64, KvError> {
+    let current = match old {
+        Some(value) => i64::try_from(value)
+            .map_err(|_| KvError::WrongType(key.into(), value.type_name(), "int64"))?,
+        None => 0,
+    };
+    current
+        .checked_add(delta)
+        .ok_or_else(|| KvError::OutOfRange("increment would overflow".into()))
+}
+
+fn incr_float(key: &str, old: Option<&Value>, delta: f64) -> Result<f64, KvError> {
+    let current = match old {
+        Some(value) => f64::try_from(value)
+            .map_err(|_| KvError::WrongType(key.into(), value.type_name(), "double"))?,
+        None => 0.0,
+    };
+    let result = current + delta;
+    if !result.is_finite() {
+        return Err(KvError::OutOfRange(
+            "increment would produce NaN or Infinity".into(),
+        ));
+    }
+    Ok(result)
+}
+
+impl CommandService for Hincrby {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        let (key, delta) = (self.key.clone(), self.delta);
+        let res = storage
+            .update(
+                &self.table,
+                &self.key,
+                Box::new(move |old| Ok(Some(incr_int(&key, old, delta)?.into()))),
+            )
+            .await;
+        match res.and_then(|old| incr_int(&self.key, old.as_ref(), delta)) {
+            Ok(result) => Value::from(result).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hincrbyfloat {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        let (key, delta) = (self.key.clone(), self.delta);
+        let res = storage
+            .update(
+                &self.table,
+                &self.key,
+                Box::new(move |old| Ok(Some(incr_float(&key, old, delta)?.into()))),
+            )
+            .await;
+        match res.and_then(|old| incr_float(&self.key, old.as_ref(), delta)) {
+            Ok(result) => Value::from(result).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hsetnx {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        let Some(Kvpair {
+            key,
+            value: Some(value),
+        }) = self.pair.as_ref()
+        else {
+            return KvError::InvalidArgument("value is required".into()).into();
+        };
+        let value = value.clone();
+        let res = storage
+            .update(
+                &self.table,
+                key,
+                Box::new(move |old| Ok(old.is_none().then(|| value.clone()))),
+            )
+            .await;
+        match res {
+            Ok(old) => Value::from(old.is_none()).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Cas {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        let Some(value) = self.value.clone() else {
+            return KvError::InvalidArgument("value is required".into()).into();
+        };
+        let (key, expected, new) = (self.key.clone(), self.expected.clone(), value.clone());
+        let res = storage
+            .update(
+                &self.table,
+                &self.key,
+                Box::new(move |old| {
+                    // 集合整体比较没有意义，与 Hincrby 一样按类型错误处理
+                    if let Some(old) = old.filter(|old| old.is_collection()) {
+                        return Err(KvError::WrongType(key.clone(), old.type_name(), "scalar"));
+                    }
+                    Ok((old == expected.as_ref()).then(|| new.clone()))
+                }),
+            )
+            .await;
+        match res {
+            Ok(old) if old == self.expected => vec![true.into(), value].into(),
+            Ok(old) => vec![false.into(), old.unwrap_or_default()].into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hscan {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        let limit = match self.limit as usize {
+            0 => DEFAULT_SCAN_LIMIT,
+            limit => limit.min(MAX_SCAN_LIMIT),
+        };
+        // cursor 是上一页最后一个 key，下一页从它之后开始
+        let start = match (self.cursor.as_str(), self.start.as_str()) {
+            (cursor, start) if !cursor.is_empty() && cursor >= start => Bound::Excluded(cursor),
+            ("", "") => Bound::Unbounded,
+            (_, start) => Bound::Included(start),
+        };
+        let end = match self.end.as_str() {
+            "" => Bound::Unbounded,
+            end => Bound::Excluded(end),
+        };
+        // 多取一个用来判断是否还有下一页
+        match storage
+            .scan(&self.table, &self.prefix, (start, end), limit + 1)
+            .await
+        {
+            Ok(mut pairs) => {
+                let cursor = if pairs.len() > limit {
+                    pairs.truncate(limit);
+                    pairs
+                        .last()
+                        .map(|pair| pair.key.clone())
+                        .unwrap_or_default()
+                } else {
+                    String::new()
+                };
+                CommandResponse {
+                    cursor,
+                    ..pairs.into()
+                }
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for ListTables {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        match storage.list_tables().await {
+            Ok(tables) => tables
+                .into_iter()
+                .map(Value::from)
+                .collect::<Vec<_>>()
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for DropTable {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        match storage.drop_table(&self.table).await {
+            Ok(count) => Value::from(count as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for TableInfo {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        match storage.table_info(&self.table).await {
+            Ok(Some(stats)) => vec![
+                Kvpair::new("keys", (stats.keys as i64).into()),
+                Kvpair::new("bytes", (stats.bytes as i64).into()),
+            ]
+            .into(),
+            Ok(None) => KvError::TableNotFound(self.table.clone()).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Transaction {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        // 事务中只能包含存储命令，不能嵌套事务
+        let valid = self.commands.iter().all(|cmd| {
+            !matches!(
+                cmd.request_data,
+                None | Some(
+                    RequestData::Subscribe(_)
+                        | RequestData::Unsubscribe(_)
+                        | RequestData::Publish(_)
+                        | RequestData::Transaction(_)
+                )
+            )
+        });
+        if !valid {
+            return KvError::InvalidCommand("transaction only accepts storage commands".into())
+                .into();
+        }
+
+        // 事务可能在其它线程中执行，命令的响应通过共享的 Vec 带回来
+        let responses = Arc::new(Mutex::new(Vec::with_capacity(self.commands.len())));
+        let collected = Arc::clone(&responses);
+        let Transaction { commands, watches } = self.clone();
+        let res = storage
+            .transaction(Box::new(move |tx| {
+                let mut responses = collected.lock().unwrap();
+                // 冲突重试时重新执行
+                responses.clear();
+                for watch in &watches {
+                    if tx.get(&watch.table, &watch.key)? != watch.value {
+                        return Err(KvError::TransactionAborted(format!(
+                            "watched key {}:{} has changed",
+                            watch.table, watch.key
+                        )));
+                    }
+                }
+                for (i, cmd) in commands.iter().enumerate() {
+                    let res = dispatch_sync(cmd.clone(), tx);
+                    // 404 是正常的查询结果，其它错误（包括类型错误、参数错误等 4xx）回滚整个事务
+                    if res.status != 200 && res.status != 404 {
+                        return Err(KvError::TransactionAborted(format!(
+                            "command {} failed: {}",
+                            i, res.message
+                        )));
+                    }
+                    responses.push(res);
+                }
+                Ok(())
+            }))
+            .await;
+        match res {
+            Ok(()) => CommandResponse {
+                status: 200,
+                message: "success".to_string(),
+                responses: std::mem::take(&mut *responses.lock().unwrap()),
+                ..Default::default()
+            },
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hmexists {
+    async fn exec<S: AsyncStorage>(&self, storage: &S) -> CommandResponse {
+        let res = async {
+            let mut values = Vec::with_capacity(self.keys.len());
+            for key in &self.keys {
+                values.push(Value::from(storage.contains(&self.table, key).await?));
+            }
+            Ok::<_, KvError>(values)
+        }
+        .await;
+        res.map_or_else(CommandResponse::from, CommandResponse::from)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{Kvpair, MemTable, Value};
+    use prost::Message;
+    use tempfile::tempdir;
+
+    use crate::{MemTable, Value, Watch, sleddb::SledDb};
 
     use super::*;
 
@@ -71,11 +528,11 @@ mod tests {
         let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
         let _cmd2 = CommandRequest::new_hset("t1", "k2", "v2".into());
 
-        let resp = dispatch(cmd.clone(), &table);
+        let resp = dispatch_sync(cmd.clone(), &table);
 
         assert_res_ok(resp, &[Value::default()], &[]);
 
-        let resp = dispatch(cmd, &table);
+        let resp = dispatch_sync(cmd, &table);
         assert_res_ok(resp, &["v1".into()], &[]);
     }
 
@@ -84,14 +541,14 @@ mod tests {
         let table = MemTable::new();
         let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
 
-        dispatch(cmd.clone(), &table);
+        dispatch_sync(cmd.clone(), &table);
 
         let cmd = CommandRequest::new_hget("t1", "k1");
-        let resp = dispatch(cmd, &table);
+        let resp = dispatch_sync(cmd, &table);
         assert_res_ok(resp, &["v1".into()], &[]);
 
         let cmd = CommandRequest::new_hget("t1", "k2");
-        let resp = dispatch(cmd, &table);
+        let resp = dispatch_sync(cmd, &table);
         assert_res_error(resp, 404, "not found");
     }
 
@@ -104,10 +561,10 @@ mod tests {
             CommandRequest::new_hset("t1", "k3", "v3".into()),
         ];
         for cmd in cmds {
-            dispatch(cmd.clone(), &table);
+            dispatch_sync(cmd.clone(), &table);
         }
         let cmd = CommandRequest::new_hgetall("t1");
-        let resp = dispatch(cmd, &table);
+        let resp = dispatch_sync(cmd, &table);
         assert_res_ok(
             resp,
             &[],
@@ -119,6 +576,418 @@ mod tests {
         );
     }
 
+    #[test]
+    fn hmget_should_work() {
+        let table = MemTable::new();
+        dispatch_sync(CommandRequest::new_hset("t1", "k1", "v1".into()), &table);
+        dispatch_sync(CommandRequest::new_hset("t1", "k2", 2.into()), &table);
+
+        let cmd = CommandRequest::new_hmget("t1", vec!["k1".into(), "k3".into(), "k2".into()]);
+        let resp = dispatch_sync(cmd, &table);
+        assert_res_ok(resp, &["v1".into(), Value::default(), 2.into()], &[]);
+    }
+
+    #[test]
+    fn hmset_should_work() {
+        let table = MemTable::new();
+        dispatch_sync(CommandRequest::new_hset("t1", "k1", "v1".into()), &table);
+
+        let pairs = vec![
+            Kvpair::new("k1", "v11".into()),
+            Kvpair::new("k2", "v2".into()),
+        ];
+        let resp = dispatch_sync(CommandRequest::new_hmset("t1", pairs), &table);
+        assert_res_ok(resp, &["v1".into(), Value::default()], &[]);
+
+        let resp = dispatch_sync(CommandRequest::new_hgetall("t1"), &table);
+        assert_res_ok(
+            resp,
+            &[],
+            &[
+                Kvpair::new("k1", "v11".into()),
+                Kvpair::new("k2", "v2".into()),
+            ],
+        );
+
+        // 缺少值是客户端的错误
+        let resp = dispatch_sync(
+            CommandRequest::new_hmset("t1", vec![Kvpair::default()]),
+            &table,
+        );
+        assert_res_error(resp, 400, "invalid argument: value is required");
+        let mut cmd = CommandRequest::new_cas("t1", "k1", None, "v1".into());
+        if let Some(RequestData::Cas(cas)) = cmd.request_data.as_mut() {
+            cas.value = None;
+        }
+        assert_res_error(
+            dispatch_sync(cmd, &table),
+            400,
+            "invalid argument: value is required",
+        );
+    }
+
+    #[test]
+    fn hdel_should_work() {
+        let table = MemTable::new();
+        dispatch_sync(CommandRequest::new_hset("t1", "k1", "v1".into()), &table);
+
+        let resp = dispatch_sync(CommandRequest::new_hdel("t1", "k1"), &table);
+        assert_res_ok(resp, &["v1".into()], &[]);
+
+        let resp = dispatch_sync(CommandRequest::new_hdel("t1", "k1"), &table);
+        assert_res_ok(resp, &[Value::default()], &[]);
+    }
+
+    #[test]
+    fn hmdel_should_work() {
+        let table = MemTable::new();
+        dispatch_sync(CommandRequest::new_hset("t1", "k1", "v1".into()), &table);
+        dispatch_sync(CommandRequest::new_hset("t1", "k2", "v2".into()), &table);
+
+        let cmd = CommandRequest::new_hmdel("t1", vec!["k1".into(), "k3".into()]);
+        let resp = dispatch_sync(cmd, &table);
+        assert_res_ok(resp, &["v1".into(), Value::default()], &[]);
+
+        let resp = dispatch_sync(CommandRequest::new_hgetall("t1"), &table);
+        assert_res_ok(resp, &[], &[Kvpair::new("k2", "v2".into())]);
+    }
+
+    #[test]
+    fn hexists_should_work() {
+        let table = MemTable::new();
+        dispatch_sync(CommandRequest::new_hset("t1", "k1", "v1".into()), &table);
+
+        let resp = dispatch_sync(CommandRequest::new_hexists("t1", "k1"), &table);
+        assert_res_ok(resp, &[true.into()], &[]);
+
+        let resp = dispatch_sync(CommandRequest::new_hexists("t1", "k2"), &table);
+        assert_res_ok(resp, &[false.into()], &[]);
+    }
+
+    #[test]
+    fn hmexists_should_work() {
+        let table = MemTable::new();
+        dispatch_sync(CommandRequest::new_hset("t1", "k1", "v1".into()), &table);
+
+        let cmd = CommandRequest::new_hmexists("t1", vec!["k1".into(), "k2".into()]);
+        let resp = dispatch_sync(cmd, &table);
+        assert_res_ok(resp, &[true.into(), false.into()], &[]);
+    }
+
+    #[test]
+    fn ttl_commands_should_work() {
+        let table = MemTable::new();
+        let cmd = CommandRequest::new_hset_with_ttl("t1", "k1", "v1".into(), 50);
+        dispatch_sync(cmd, &table);
+        let pairs = vec![
+            Kvpair::new("k2", "v2".into()),
+            Kvpair::new("k3", "v3".into()),
+        ];
+        dispatch_sync(CommandRequest::new_hmset_with_ttl("t1", pairs, 50), &table);
+        dispatch_sync(CommandRequest::new_hset("t1", "k4", "v4".into()), &table);
+
+        let resp = dispatch_sync(CommandRequest::new_ttl("t1", "k4"), &table);
+        assert_res_ok(resp, &[(-1).into()], &[]);
+        let resp = dispatch_sync(CommandRequest::new_ttl("t1", "nope"), &table);
+        assert_res_ok(resp, &[(-2).into()], &[]);
+
+        let resp = dispatch_sync(CommandRequest::new_persist("t1", "k3"), &table);
+        assert_res_ok(resp, &[true.into()], &[]);
+        let resp = dispatch_sync(CommandRequest::new_expire("t1", "k4", 60_000), &table);
+        assert_res_ok(resp, &[true.into()], &[]);
+        let resp = dispatch_sync(CommandRequest::new_expire("t1", "nope", 60_000), &table);
+        assert_res_ok(resp, &[false.into()], &[]);
+
+        std::thread::sleep(Duration::from_millis(80));
+
+        let resp = dispatch_sync(CommandRequest::new_hget("t1", "k1"), &table);
+        assert_res_error(resp, 404, "not found");
+        let resp = dispatch_sync(CommandRequest::new_hgetall("t1"), &table);
+        assert_res_ok(
+            resp,
+            &[],
+            &[
+                Kvpair::new("k3", "v3".into()),
+                Kvpair::new("k4", "v4".into()),
+            ],
+        );
+    }
+
+    #[test]
+    fn hincrby_should_work() {
+        let table = MemTable::new();
+        let resp = dispatch_sync(CommandRequest::new_hincrby("t1", "n", 10), &table);
+        assert_res_ok(resp, &[10.into()], &[]);
+        let resp = dispatch_sync(CommandRequest::new_hincrby("t1", "n", -3), &table);
+        assert_res_ok(resp, &[7.into()], &[]);
+
+        let resp = dispatch_sync(CommandRequest::new_hincrbyfloat("t1", "n", 0.5), &table);
+        assert_res_ok(resp, &[7.5.into()], &[]);
+        // 浮点数不能再按整数递增
+        let resp = dispatch_sync(CommandRequest::new_hincrby("t1", "n", 1), &table);
+        assert_eq!(resp.status, 400);
+        assert_eq!(
+            resp.message,
+            KvError::WrongType("n".into(), "double", "int64").to_string()
+        );
+        assert_eq!(table.get("t1", "n"), Ok(Some(7.5.into())));
+
+        dispatch_sync(CommandRequest::new_hset("t1", "s", "v1".into()), &table);
+        let resp = dispatch_sync(CommandRequest::new_hincrbyfloat("t1", "s", 1.0), &table);
+        assert_eq!(resp.status, 400);
+        assert_eq!(
+            resp.message,
+            KvError::WrongType("s".into(), "string", "double").to_string()
+        );
+
+        dispatch_sync(
+            CommandRequest::new_hset("t1", "max", i64::MAX.into()),
+            &table,
+        );
+        let resp = dispatch_sync(CommandRequest::new_hincrby("t1", "max", 1), &table);
+        assert_eq!(resp.status, 400);
+        assert_eq!(table.get("t1", "max"), Ok(Some(i64::MAX.into())));
+        let resp = dispatch_sync(
+            CommandRequest::new_hincrbyfloat("t1", "n", f64::INFINITY),
+            &table,
+        );
+        assert_eq!(resp.status, 400);
+        let resp = dispatch_sync(
+            CommandRequest::new_hincrbyfloat("t1", "n", f64::NAN),
+            &table,
+        );
+        assert_eq!(resp.status, 400);
+        assert_eq!(table.get("t1", "n"), Ok(Some(7.5.into())));
+    }
+
+    #[test]
+    fn hincrby_should_keep_ttl() {
+        let table = MemTable::new();
+        dispatch_sync(
+            CommandRequest::new_hset_with_ttl("t1", "n", 1.into(), 60_000),
+            &table,
+        );
+        let resp = dispatch_sync(CommandRequest::new_hincrby("t1", "n", 1), &table);
+        assert_res_ok(resp, &[2.into()], &[]);
+        assert!(table.ttl("t1", "n").unwrap().is_some());
+
+        // 过期的值视为不存在
+        dispatch_sync(
+            CommandRequest::new_hset_with_ttl("t1", "m", 5.into(), 10),
+            &table,
+        );
+        std::thread::sleep(Duration::from_millis(20));
+        let resp = dispatch_sync(CommandRequest::new_hincrby("t1", "m", 1), &table);
+        assert_res_ok(resp, &[1.into()], &[]);
+        assert_eq!(table.ttl("t1", "m"), Ok(None));
+    }
+
+    #[test]
+    fn hsetnx_should_work() {
+        let table = MemTable::new();
+        let resp = dispatch_sync(CommandRequest::new_hsetnx("t1", "k1", "v1".into()), &table);
+        assert_res_ok(resp, &[true.into()], &[]);
+        let resp = dispatch_sync(CommandRequest::new_hsetnx("t1", "k1", "v2".into()), &table);
+        assert_res_ok(resp, &[false.into()], &[]);
+        assert_eq!(table.get("t1", "k1"), Ok(Some("v1".into())));
+    }
+
+    #[test]
+    fn cas_should_work() {
+        let table = MemTable::new();
+        let cmd = CommandRequest::new_cas("t1", "k1", None, "v1".into());
+        let resp = dispatch_sync(cmd.clone(), &table);
+        assert_res_ok(resp, &[true.into(), "v1".into()], &[]);
+        let resp = dispatch_sync(cmd, &table);
+        assert_res_ok(resp, &[false.into(), "v1".into()], &[]);
+
+        let cmd = CommandRequest::new_cas("t1", "k1", Some("v1".into()), "v2".into());
+        let resp = dispatch_sync(cmd, &table);
+        assert_res_ok(resp, &[true.into(), "v2".into()], &[]);
+        assert_eq!(table.get("t1", "k1"), Ok(Some("v2".into())));
+    }
+
+    #[test]
+    fn transaction_should_work() {
+        test_transaction_command(&MemTable::new());
+        let dir = tempdir().unwrap();
+        test_transaction_command(&SledDb::new(dir));
+    }
+
+    #[test]
+    fn hscan_should_work() {
+        let dir = tempdir().unwrap();
+        test_hscan_command(&MemTable::new());
+        test_hscan_command(&SledDb::new(dir.path()));
+    }
+
+    #[test]
+    fn table_commands_should_work() {
+        let dir = tempdir().unwrap();
+        test_table_commands(&MemTable::new());
+        test_table_commands(&SledDb::new(dir.path()));
+    }
+
+    #[test]
+    fn transaction_should_reject_non_storage_commands() {
+        let table = MemTable::new();
+        let cmd = CommandRequest::new_transaction(
+            vec![
+                CommandRequest::new_hset("t1", "k1", "v1".into()),
+                CommandRequest::new_publish("lobby", vec![]),
+            ],
+            vec![],
+        );
+        let resp = dispatch_sync(cmd, &table);
+        assert_eq!(resp.status, 500);
+        assert_eq!(table.get("t1", "k1"), Ok(None));
+    }
+
+    fn test_hscan_command(table: &dyn Storage) {
+        for i in 0..5 {
+            let key = format!("user:{}", i);
+            dispatch_sync(CommandRequest::new_hset("t1", &key, i.into()), table);
+        }
+        dispatch_sync(CommandRequest::new_hset("t1", "item", "book".into()), table);
+
+        // 按页遍历，最后一页的游标为空
+        let mut keys = vec![];
+        let mut cursor = String::new();
+        loop {
+            let res = dispatch_sync(CommandRequest::new_hscan("t1", &cursor, 2), table);
+            assert_eq!(res.status, 200);
+            assert!(res.pairs.len() <= 2);
+            keys.extend(res.pairs.into_iter().map(|p| p.key));
+            if res.cursor.is_empty() {
+                break;
+            }
+            cursor = res.cursor;
+        }
+        assert_eq!(
+            keys,
+            ["item", "user:0", "user:1", "user:2", "user:3", "user:4"]
+        );
+
+        let res = dispatch_sync(
+            CommandRequest::new_hscan_prefix("t1", "user:", "", 3),
+            table,
+        );
+        assert_eq!(res.cursor, "user:2");
+        let res = dispatch_sync(
+            CommandRequest::new_hscan_prefix("t1", "user:", &res.cursor, 3),
+            table,
+        );
+        let pairs = vec![
+            Kvpair::new("user:3", 3.into()),
+            Kvpair::new("user:4", 4.into()),
+        ];
+        assert_res_ok(res, &[], &pairs);
+
+        let res = dispatch_sync(
+            CommandRequest::new_hscan_range("t1", "user:1", "user:3", "", 0),
+            table,
+        );
+        assert!(res.cursor.is_empty());
+        let pairs = vec![
+            Kvpair::new("user:1", 1.into()),
+            Kvpair::new("user:2", 2.into()),
+        ];
+        assert_res_ok(res, &[], &pairs);
+
+        // 游标已经超出范围时返回空
+        let res = dispatch_sync(
+            CommandRequest::new_hscan_range("t1", "user:1", "user:3", "user:4", 0),
+            table,
+        );
+        assert_res_ok(res, &[], &[]);
+    }
+
+    fn test_table_commands(table: &dyn Storage) {
+        dispatch_sync(CommandRequest::new_hset("t2", "k1", "v1".into()), table);
+        dispatch_sync(CommandRequest::new_hset("t1", "k1", 1.into()), table);
+        dispatch_sync(CommandRequest::new_hset("t1", "k2", 2.into()), table);
+
+        let res = dispatch_sync(CommandRequest::new_list_tables(), table);
+        assert_res_ok(res, &["t1".into(), "t2".into()], &[]);
+
+        let res = dispatch_sync(CommandRequest::new_table_info("t1"), table);
+        let bytes = 2 * ("k1".len() + Value::from(1).encoded_len()) as i64;
+        let pairs = vec![
+            Kvpair::new("bytes", bytes.into()),
+            Kvpair::new("keys", 2.into()),
+        ];
+        assert_res_ok(res, &[], &pairs);
+
+        let res = dispatch_sync(CommandRequest::new_drop_table("t1"), table);
+        assert_res_ok(res, &[2.into()], &[]);
+
+        let res = dispatch_sync(CommandRequest::new_table_info("t1"), table);
+        assert_res_error(res, 404, "table not found: t1");
+        let res = dispatch_sync(CommandRequest::new_list_tables(), table);
+        assert_res_ok(res, &["t2".into()], &[]);
+    }
+
+    fn test_transaction_command(table: &dyn Storage) {
+        dispatch_sync(CommandRequest::new_hset("t1", "item", "book".into()), table);
+
+        // 把 item 从 t1 移动到 t2
+        let watches = vec![Watch::new("t1", "item", Some("book".into()))];
+        let cmd = CommandRequest::new_transaction(
+            vec![
+                CommandRequest::new_hdel("t1", "item"),
+                CommandRequest::new_hset("t2", "item", "book".into()),
+                CommandRequest::new_hget("t1", "item"),
+            ],
+            watches.clone(),
+        );
+        let resp = dispatch_sync(cmd.clone(), table);
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.responses.len(), 3);
+        assert_eq!(resp.responses[0].values, &["book".into()]);
+        assert_eq!(resp.responses[2].status, 404);
+        assert_eq!(table.get("t2", "item"), Ok(Some("book".into())));
+
+        // watch 的值已经变化，事务不会执行
+        let resp = dispatch_sync(cmd, table);
+        assert_eq!(resp.status, 409);
+        assert!(resp.responses.is_empty());
+        assert_eq!(table.get("t2", "item"), Ok(Some("book".into())));
+
+        // watch 要求 key 不存在
+        let cmd = CommandRequest::new_transaction(
+            vec![CommandRequest::new_hset("t1", "item", "pen".into())],
+            vec![Watch::new("t1", "item", None)],
+        );
+        assert_eq!(dispatch_sync(cmd, table).status, 200);
+
+        // 任何一个命令失败时全部回滚
+        let cmd = CommandRequest::new_transaction(
+            vec![
+                CommandRequest::new_hset("t1", "item", "cup".into()),
+                CommandRequest::new_hset("t1", "other", "v".into()),
+                CommandRequest::new_hmset("t1", vec![Kvpair::default()]),
+            ],
+            vec![],
+        );
+        let resp = dispatch_sync(cmd, table);
+        assert_eq!(resp.status, 409);
+        assert_eq!(table.get("t1", "item"), Ok(Some("pen".into())));
+        assert_eq!(table.get("t1", "other"), Ok(None));
+
+        // 4xx 的错误同样回滚
+        let cmd = CommandRequest::new_transaction(
+            vec![
+                CommandRequest::new_hset("t1", "a", "x".into()),
+                CommandRequest::new_hincrby("t1", "item", 1),
+            ],
+            vec![],
+        );
+        let resp = dispatch_sync(cmd, table);
+        assert_eq!(resp.status, 409);
+        assert_eq!(table.get("t1", "a"), Ok(None));
+        assert_eq!(table.get("t1", "item"), Ok(Some("pen".into())));
+    }
+
     fn assert_res_ok(mut res: CommandResponse, values: &[Value], pairs: &[Kvpair]) {
         res.pairs.sort_by(|a, b| a.key.cmp(&b.key));
         assert_eq!(res.status, 200);
@@ -1,3 +1,12 @@
+mod acl;
+mod changelog;
+mod collection;
 mod commandservice;
 mod service;
+mod topic;
+pub use acl::{AclConfig, TablePermissions};
+pub(crate) use changelog::ChangeLog;
+pub use collection::MAX_COLLECTION_LEN;
+pub(crate) use commandservice::{DEFAULT_SCAN_LIMIT, MAX_SCAN_LIMIT, dispatch};
 pub use service::*;
+pub use topic::*;
@@ -1,32 +1,235 @@
-use std::sync::Arc;
+use std::{
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use tracing::{debug, info};
+use futures::{Stream, StreamExt, future::BoxFuture, stream};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::{debug, warn};
 
 use crate::{
-    CommandRequest, CommandResponse, MemTable, command::commandservice::dispatch,
-    storage::storage::Storage,
+    AclConfig, BlockingStorage, Broadcaster, ChangeLog, CommandRequest, CommandResponse,
+    ConnectionGuard, MemTable, Metrics, StreamingService, TopicService, command::dispatch,
+    command_request::RequestData, error::KvError, storage::AsyncStorage,
 };
 
-pub struct Service<S = MemTable> {
+// 复制流缓冲的响应数量，follower 读取慢时暂停发送
+const REPLICATION_BUFFER: usize = 16;
+
+// 流式响应，普通命令只有一个响应，Subscribe 会持续返回数据直到取消订阅
+pub type StreamingResponse = Pin<Box<dyn Stream<Item = CommandResponse> + Send>>;
+
+pub struct Service<S = BlockingStorage<MemTable>> {
     inner: Arc<ServiceInner<S>>,
 }
 
-impl<S: Storage> Service<S> {
-    pub fn exec(&self, cmd: CommandRequest) -> CommandResponse {
+impl<S: AsyncStorage + Send + Sync + 'static> Service<S> {
+    pub async fn exec(&self, cmd: CommandRequest) -> CommandResponse {
+        self.exec_as(cmd, None).await
+    }
+
+    // 以客户端的身份执行命令，配置了 ACL 时先检查权限
+    pub async fn exec_as(&self, cmd: CommandRequest, identity: Option<&str>) -> CommandResponse {
         debug!("Got request: {:?}", cmd);
-        self.inner.on_received.notify(&cmd);
-        let mut res = dispatch(cmd, &self.inner.store);
-        debug!("Exec result: {:?}", res);
-        self.inner.on_executed.notify(&res);
-        self.inner.on_berfore_send.notify(&mut res);
-        if !self.inner.on_after_send.is_empty() {
-            info!("modify response: {:?}", res);
-        }
+        let id = cmd.id;
+        let name = cmd.command_name();
+        let start = Instant::now();
+        // 被拒绝或者 on_received 钩子返回响应时不再执行命令，也不触发 on_executed
+        let mut res = match self.inner.intercept(&cmd, identity).await {
+            Some(res) => res,
+            None => {
+                let res = match cmd.request_data {
+                    Some(RequestData::Publish(params)) => params.exec(&self.inner.broadcaster),
+                    Some(RequestData::Unsubscribe(params)) => params.exec(&self.inner.broadcaster),
+                    Some(RequestData::Subscribe(_) | RequestData::Replicate(_)) => {
+                        KvError::InvalidCommand(format!("{} requires Service::execute", name))
+                            .into()
+                    }
+                    _ => self.inner.exec_storage(cmd).await,
+                };
+                debug!("Exec result: {:?}", res);
+                self.inner.executed(&res).await;
+                res
+            }
+        };
+        // 响应带上请求 id，客户端据此匹配乱序返回的响应
+        res.id = id;
+        self.inner.before_send(&mut res).await;
+        self.inner.record(name, res.status, start);
         res
     }
+
+    // 流式执行命令，Subscribe、Replicate 返回持续的数据流，其它命令返回只有一个响应的流
+    pub fn execute(&self, cmd: CommandRequest) -> StreamingResponse {
+        self.execute_as(cmd, None)
+    }
+
+    pub fn execute_as(&self, cmd: CommandRequest, identity: Option<Arc<str>>) -> StreamingResponse {
+        let service = self.clone();
+        match &cmd.request_data {
+            Some(RequestData::Subscribe(_) | RequestData::Replicate(_)) => Box::pin(
+                stream::once(async move { service.stream(cmd, identity.as_deref()).await })
+                    .flatten(),
+            ),
+            _ => Box::pin(stream::once(async move {
+                service.exec_as(cmd, identity.as_deref()).await
+            })),
+        }
+    }
+
+    async fn stream(&self, cmd: CommandRequest, identity: Option<&str>) -> StreamingResponse {
+        debug!("Got request: {:?}", cmd);
+        let id = cmd.id;
+        let name = cmd.command_name();
+        let start = Instant::now();
+        let stream = match self.inner.intercept(&cmd, identity).await {
+            Some(res) => Err(res),
+            None => match &cmd.request_data {
+                Some(RequestData::Subscribe(params)) => Ok(params.execute(&self.inner.broadcaster)),
+                Some(RequestData::Replicate(_)) => self.replicate(identity).map_err(Into::into),
+                _ => unreachable!("streaming command is checked by Service::execute"),
+            },
+        };
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(mut res) => {
+                res.id = id;
+                self.inner.before_send(&mut res).await;
+                self.inner.record(name, res.status, start);
+                return Box::pin(stream::once(std::future::ready(res)));
+            }
+        };
+        // 流式命令只统计建立的耗时，之后推送的数据不算在命令里
+        self.inner.record(name, 200, start);
+        let inner = Arc::clone(&self.inner);
+        Box::pin(stream.then(move |mut res| {
+            let inner = Arc::clone(&inner);
+            async move {
+                res.id = id;
+                inner.executed(&res).await;
+                inner.before_send(&mut res).await;
+                res
+            }
+        }))
+    }
+
+    // 在后台把快照和修改日志发送给 follower，follower 断开后随之结束
+    fn replicate(&self, identity: Option<&str>) -> Result<StreamingResponse, KvError> {
+        let Some(log) = self.inner.change_log.as_ref() else {
+            return Err(KvError::InvalidCommand(
+                "replication is not enabled on this server".into(),
+            ));
+        };
+        if !log.is_follower(identity) {
+            return Err(KvError::PermissionDenied(format!(
+                "{} is not an allowed follower",
+                identity.unwrap_or("anonymous")
+            )));
+        }
+        let (tx, mut rx) = mpsc::channel(REPLICATION_BUFFER);
+        let inner = Arc::clone(&self.inner);
+        tokio::spawn(async move {
+            let Some(log) = inner.change_log.as_ref() else {
+                return;
+            };
+            if let Err(e) = log.replicate(&inner.store, tx.clone()).await {
+                warn!("Replication stopped: {}", e);
+                let _ = tx.send(e.into()).await;
+            }
+        });
+        Ok(Box::pin(stream::poll_fn(move |cx| rx.poll_recv(cx))))
+    }
+
+    // follower 执行从 leader 同步的命令，不经过钩子和只读检查
+    pub(crate) async fn replay(&self, cmd: CommandRequest) -> CommandResponse {
+        dispatch(cmd, &self.inner.store).await
+    }
+
+    // follower 加载完整的快照后换掉本地数据：删除快照中没有的表和 key，再写入快照中的数据
+    // 加载期间本地数据一直可读，不会出现清空后还没有同步完的状态
+    pub(crate) async fn load_snapshot<T: AsyncStorage>(
+        &self,
+        snapshot: &T,
+    ) -> Result<usize, KvError> {
+        let store = &self.inner.store;
+        let tables = snapshot.list_tables().await?;
+        for table in store.list_tables().await? {
+            if !tables.contains(&table) {
+                store.drop_table(&table).await?;
+                continue;
+            }
+            let keys: Vec<_> = store
+                .get_iter(&table)
+                .await?
+                .map(|pair| pair.key)
+                .collect()
+                .await;
+            for key in keys {
+                if !snapshot.contains(&table, &key).await? {
+                    store.delete(&table, &key).await?;
+                }
+            }
+        }
+        let mut count = 0;
+        for table in &tables {
+            let mut pairs = snapshot.get_iter(table).await?;
+            while let Some(pair) = pairs.next().await {
+                let value = pair.value.unwrap_or_default();
+                match snapshot.ttl(table, &pair.key).await? {
+                    Some(ttl) => store.set_with_ttl(table, &pair.key, value, ttl).await?,
+                    None => store.set(table, &pair.key, value).await?,
+                };
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    pub fn metrics(&self) -> Option<Arc<Metrics>> {
+        self.inner.metrics.clone()
+    }
+
+    // accept 连接时调用，返回值被释放时活跃连接数减一
+    // 一个 yamux 连接上的多个逻辑流只算一个连接
+    pub fn connection(&self) -> Option<ConnectionGuard> {
+        self.inner
+            .metrics
+            .as_ref()
+            .map(|metrics| metrics.connection())
+    }
+
+    // 响应真正写到连接上之后由网络层调用
+    pub async fn after_send(&self) {
+        for hook in &self.inner.on_after_send {
+            match hook {
+                Hook::Sync(f) => f(),
+                Hook::Async(f) => f().await,
+            }
+        }
+    }
+
+    // 后台定期清理过期的 key，Service 被释放后自动退出
+    pub fn start_reaper(&self, interval: Duration) -> JoinHandle<()> {
+        let inner = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let Some(inner) = inner.upgrade() else {
+                    break;
+                };
+                match inner.store.reap_expired().await {
+                    Ok(0) => {}
+                    Ok(n) => debug!("Reaped {} expired keys", n),
+                    Err(e) => warn!("Failed to reap expired keys: {}", e),
+                }
+            }
+        })
+    }
 }
 
-impl<S: Storage> Clone for Service<S> {
+impl<S> Clone for Service<S> {
     fn clone(&self) -> Self {
         Self {
             inner: Arc::clone(&self.inner),
@@ -34,18 +237,50 @@ impl<S: Storage> Clone for Service<S> {
     }
 }
 
+// 钩子可以是同步的闭包，也可以是返回 future 的异步闭包，同一类钩子按注册的顺序执行
+enum Hook<F: ?Sized, A: ?Sized> {
+    Sync(Box<F>),
+    Async(Box<A>),
+}
+
+type ReceivedHook = Hook<
+    dyn Fn(&CommandRequest) -> Option<CommandResponse> + Send + Sync,
+    dyn for<'a> Fn(&'a CommandRequest) -> BoxFuture<'a, Option<CommandResponse>> + Send + Sync,
+>;
+type ExecutedHook = Hook<
+    dyn Fn(&CommandResponse) + Send + Sync,
+    dyn for<'a> Fn(&'a CommandResponse) -> BoxFuture<'a, ()> + Send + Sync,
+>;
+type BeforeSendHook = Hook<
+    dyn Fn(&mut CommandResponse) + Send + Sync,
+    dyn for<'a> Fn(&'a mut CommandResponse) -> BoxFuture<'a, ()> + Send + Sync,
+>;
+type AfterSendHook = Hook<dyn Fn() + Send + Sync, dyn Fn() -> BoxFuture<'static, ()> + Send + Sync>;
+
 pub struct ServiceInner<S> {
     store: S,
-    on_received: Vec<fn(&CommandRequest)>,
-    on_executed: Vec<fn(&CommandResponse)>,
-    on_berfore_send: Vec<fn(&mut CommandResponse)>,
-    on_after_send: Vec<fn()>,
+    broadcaster: Arc<Broadcaster>,
+    acl: Option<AclConfig>,
+    metrics: Option<Arc<Metrics>>,
+    // leader 记录修改日志供 follower 同步
+    change_log: Option<ChangeLog>,
+    // follower 只读，修改命令返回 leader 的地址
+    leader: Option<String>,
+    on_received: Vec<ReceivedHook>,
+    on_executed: Vec<ExecutedHook>,
+    on_berfore_send: Vec<BeforeSendHook>,
+    on_after_send: Vec<AfterSendHook>,
 }
 
-impl<S: Storage> ServiceInner<S> {
+impl<S: AsyncStorage> ServiceInner<S> {
     pub fn new(store: S) -> Self {
         Self {
             store,
+            broadcaster: Arc::new(Broadcaster::default()),
+            acl: None,
+            metrics: None,
+            change_log: None,
+            leader: None,
             on_received: Vec::new(),
             on_executed: Vec::new(),
             on_berfore_send: Vec::new(),
@@ -53,70 +288,195 @@ impl<S: Storage> ServiceInner<S> {
         }
     }
 
-    pub fn fn_received(mut self, f: fn(&CommandRequest)) -> Self {
-        self.on_received.push(f);
+    // 按客户端的身份授权，拒绝的命令返回 403
+    pub fn with_acl(mut self, acl: AclConfig) -> Self {
+        self.acl = Some(acl);
         self
     }
 
-    pub fn fn_executed(mut self, f: fn(&CommandResponse)) -> Self {
-        self.on_executed.push(f);
+    // 统计命令数、错误数和耗时，网络层也会用它统计连接和流量
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
         self
     }
 
-    pub fn fn_berfore_send(mut self, f: fn(&mut CommandResponse)) -> Self {
-        self.on_berfore_send.push(f);
+    // 作为 leader，followers 中的客户端可以通过 Replicate 命令同步数据
+    pub fn with_change_log(mut self, followers: Vec<String>) -> Self {
+        self.change_log = Some(ChangeLog::new(followers));
         self
     }
 
-    pub fn fn_after_send(mut self, f: fn()) -> Self {
-        self.on_after_send.push(f);
+    // 作为 follower，修改命令返回 307 和 leader 的地址
+    pub fn with_leader(mut self, leader: impl Into<String>) -> Self {
+        self.leader = Some(leader.into());
+        self
+    }
+
+    pub fn fn_received(self, f: impl Fn(&CommandRequest) + Send + Sync + 'static) -> Self {
+        self.fn_intercept(move |cmd| {
+            f(cmd);
+            None
+        })
+    }
+
+    // 返回 Some 时拒绝或者直接响应这个请求，后面的 on_received 钩子和命令都不再执行
+    pub fn fn_intercept(
+        mut self,
+        f: impl Fn(&CommandRequest) -> Option<CommandResponse> + Send + Sync + 'static,
+    ) -> Self {
+        self.on_received.push(Hook::Sync(Box::new(f)));
+        self
+    }
+
+    pub fn fn_intercept_async<F>(mut self, f: F) -> Self
+    where
+        F: for<'a> Fn(&'a CommandRequest) -> BoxFuture<'a, Option<CommandResponse>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.on_received.push(Hook::Async(Box::new(f)));
+        self
+    }
+
+    pub fn fn_executed(mut self, f: impl Fn(&CommandResponse) + Send + Sync + 'static) -> Self {
+        self.on_executed.push(Hook::Sync(Box::new(f)));
+        self
+    }
+
+    pub fn fn_executed_async<F>(mut self, f: F) -> Self
+    where
+        F: for<'a> Fn(&'a CommandResponse) -> BoxFuture<'a, ()> + Send + Sync + 'static,
+    {
+        self.on_executed.push(Hook::Async(Box::new(f)));
+        self
+    }
+
+    pub fn fn_berfore_send(
+        mut self,
+        f: impl Fn(&mut CommandResponse) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_berfore_send.push(Hook::Sync(Box::new(f)));
+        self
+    }
+
+    pub fn fn_berfore_send_async<F>(mut self, f: F) -> Self
+    where
+        F: for<'a> Fn(&'a mut CommandResponse) -> BoxFuture<'a, ()> + Send + Sync + 'static,
+    {
+        self.on_berfore_send.push(Hook::Async(Box::new(f)));
+        self
+    }
+
+    pub fn fn_after_send(mut self, f: impl Fn() + Send + Sync + 'static) -> Self {
+        self.on_after_send.push(Hook::Sync(Box::new(f)));
+        self
+    }
+
+    pub fn fn_after_send_async<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> BoxFuture<'static, ()> + Send + Sync + 'static,
+    {
+        self.on_after_send.push(Hook::Async(Box::new(f)));
         self
     }
 }
 
-impl<S: Storage> From<ServiceInner<S>> for Service<S> {
-    fn from(inner: ServiceInner<S>) -> Self {
-        Self {
-            inner: Arc::new(inner),
+impl<S: AsyncStorage> ServiceInner<S> {
+    // leader 的修改命令持有写锁执行，再把修改的结果写入修改日志，保证 follower 按相同的顺序执行
+    async fn exec_storage(&self, cmd: CommandRequest) -> CommandResponse {
+        if !cmd.is_write() {
+            return dispatch(cmd, &self.store).await;
+        }
+        if let Some(leader) = self.leader.as_ref() {
+            return KvError::Redirect(leader.clone()).into();
         }
+        let Some(log) = self.change_log.as_ref() else {
+            return dispatch(cmd, &self.store).await;
+        };
+        let _guard = log.lock().await;
+        let res = dispatch(cmd.clone(), &self.store).await;
+        log.append(&self.store, &cmd).await;
+        res
     }
-}
 
-pub trait Nofiy<Arg> {
-    fn notify(&self, arg: &Arg);
-}
+    async fn intercept(
+        &self,
+        cmd: &CommandRequest,
+        identity: Option<&str>,
+    ) -> Option<CommandResponse> {
+        if let Some(acl) = self.acl.as_ref()
+            && let Err(e) = acl.check(identity, cmd)
+        {
+            return Some(e.into());
+        }
+        self.received(cmd).await
+    }
 
-pub trait NotifyMut<Arg> {
-    fn notify(&self, arg: &mut Arg);
-}
+    fn record(&self, name: &'static str, status: u32, start: Instant) {
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.record_command(name, status, start.elapsed());
+        }
+    }
 
-impl<Arg> Nofiy<Arg> for Vec<fn(&Arg)> {
-    fn notify(&self, arg: &Arg) {
-        for f in self {
-            f(&arg);
+    async fn received(&self, cmd: &CommandRequest) -> Option<CommandResponse> {
+        for hook in &self.on_received {
+            let res = match hook {
+                Hook::Sync(f) => f(cmd),
+                Hook::Async(f) => f(cmd).await,
+            };
+            if res.is_some() {
+                return res;
+            }
+        }
+        None
+    }
+
+    async fn executed(&self, res: &CommandResponse) {
+        for hook in &self.on_executed {
+            match hook {
+                Hook::Sync(f) => f(res),
+                Hook::Async(f) => f(res).await,
+            }
+        }
+    }
+
+    async fn before_send(&self, res: &mut CommandResponse) {
+        for hook in &self.on_berfore_send {
+            match hook {
+                Hook::Sync(f) => f(res),
+                Hook::Async(f) => f(res).await,
+            }
         }
     }
 }
 
-impl<Arg> NotifyMut<Arg> for Vec<fn(&mut Arg)> {
-    fn notify(&self, arg: &mut Arg) {
-        for f in self {
-            f(arg);
+impl<S: AsyncStorage> From<ServiceInner<S>> for Service<S> {
+    fn from(inner: ServiceInner<S>) -> Self {
+        Self {
+            inner: Arc::new(inner),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::stream::BoxStream;
     use http::StatusCode;
     use tracing::info;
 
-    use crate::{MemTable, Value};
+    use crate::{
+        BoxedTxFn, BoxedUpdateFn, Kvpair, MemTable, Value,
+        storage::storage::{ScanRange, Storage, TableStats},
+        value,
+    };
 
     use super::*;
 
-    #[test]
-    fn event_registration_should_work() {
+    #[tokio::test]
+    async fn event_registration_should_work() {
         fn b(cmd: &CommandRequest) {
             info!("on_received: {:?}", cmd);
         }
@@ -130,16 +490,332 @@ mod tests {
             info!("on_after_send");
         }
 
-        let service: Service = ServiceInner::new(MemTable::default())
+        let service: Service = ServiceInner::new(BlockingStorage::new(MemTable::default()))
             .fn_received(b)
             .fn_executed(c)
             .fn_berfore_send(d)
             .fn_after_send(e)
             .into();
 
-        let res = service.exec(CommandRequest::new_hset("t1", "k1", "v1".into()));
-        assert_eq!(res.status, StatusCode::CREATED.as_u16() as _);
+        let res = service
+            .exec(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await;
+        assert_eq!(res.status, StatusCode::CREATED.as_u16() as u32);
         assert_eq!(res.message, "success");
         assert_eq!(res.values, vec![Value::default()]);
     }
+
+    #[tokio::test]
+    async fn hooks_should_capture_state_and_intercept() {
+        let executed = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&executed);
+        let service: Service = ServiceInner::new(BlockingStorage::new(MemTable::default()))
+            // 拒绝访问 secret 表
+            .fn_intercept(|cmd| {
+                let denied = match &cmd.request_data {
+                    Some(RequestData::Hget(hget)) => hget.table == "secret",
+                    Some(RequestData::Subscribe(sub)) => sub.topic == "secret",
+                    _ => false,
+                };
+                denied.then(|| CommandResponse {
+                    status: StatusCode::FORBIDDEN.as_u16() as _,
+                    message: "forbidden".into(),
+                    ..Default::default()
+                })
+            })
+            // 直接返回缓存的结果
+            .fn_intercept_async(|cmd| {
+                Box::pin(async move {
+                    tokio::task::yield_now().await;
+                    match &cmd.request_data {
+                        Some(RequestData::Hget(hget)) if hget.table == "cache" => {
+                            Some(Value::from("cached").into())
+                        }
+                        _ => None,
+                    }
+                })
+            })
+            .fn_executed(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .fn_berfore_send_async(|res| {
+                Box::pin(async move {
+                    res.message = format!("{}:{}", res.message, res.status);
+                })
+            })
+            .into();
+
+        let mut cmd = CommandRequest::new_hget("secret", "k1");
+        cmd.id = 3;
+        let res = service.exec(cmd).await;
+        assert_eq!(res.status, StatusCode::FORBIDDEN.as_u16() as u32);
+        assert_eq!(res.message, "forbidden:403");
+        assert_eq!(res.id, 3);
+
+        let res = service.exec(CommandRequest::new_hget("cache", "k1")).await;
+        assert_eq!(res.values, vec!["cached".into()]);
+        assert_eq!(executed.load(Ordering::SeqCst), 0);
+
+        // 拒绝订阅时返回只有一个响应的流
+        let mut stream = service.execute(CommandRequest::new_subscribe("secret"));
+        assert_eq!(stream.next().await.unwrap().status, 403);
+        assert_eq!(stream.next().await, None);
+
+        let res = service.exec(CommandRequest::new_hget("t1", "k1")).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND.as_u16() as u32);
+        assert_eq!(executed.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn service_pub_sub_should_work() {
+        let service: Service = ServiceInner::new(BlockingStorage::new(MemTable::default())).into();
+
+        let mut cmd = CommandRequest::new_subscribe("lobby");
+        cmd.id = 9;
+        let mut stream = service.execute(cmd);
+        let res = stream.next().await.unwrap();
+        assert_eq!(res.status, 200);
+        assert_eq!(res.id, 9);
+        let id = match res.values[0].value {
+            Some(value::Value::Int64Value(id)) => id as u32,
+            _ => panic!("subscription id expected"),
+        };
+
+        let data: Vec<Value> = vec!["hello".into(), 1.into()];
+        let res = service
+            .exec(CommandRequest::new_publish("lobby", data.clone()))
+            .await;
+        assert_eq!(res.values, vec![1.into()]);
+        let res = stream.next().await.unwrap();
+        assert_eq!(res.values, data);
+        assert_eq!(res.id, 9);
+
+        let res = service
+            .exec(CommandRequest::new_unsubscribe("lobby", id))
+            .await;
+        assert_eq!(res.values, vec![(id as i64).into()]);
+        assert_eq!(stream.next().await, None);
+
+        let res = service
+            .exec(CommandRequest::new_unsubscribe("lobby", id))
+            .await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND.as_u16() as u32);
+    }
+
+    #[tokio::test]
+    async fn reaper_should_remove_expired_keys() {
+        let service: Service = ServiceInner::new(BlockingStorage::new(MemTable::default())).into();
+        let cmd = CommandRequest::new_hset_with_ttl("t1", "k1", "v1".into(), 10);
+        service.exec(cmd).await;
+
+        let handle = service.start_reaper(Duration::from_millis(20));
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(service.inner.store.reap_expired().await, Ok(0));
+
+        // Service 释放后 reaper 退出
+        drop(service);
+        tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn subscribe_should_require_streaming() {
+        let service: Service = ServiceInner::new(BlockingStorage::new(MemTable::default())).into();
+        let res = service.exec(CommandRequest::new_subscribe("lobby")).await;
+        assert_eq!(
+            res.status,
+            StatusCode::INTERNAL_SERVER_ERROR.as_u16() as u32
+        );
+
+        let mut stream = service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        assert_eq!(stream.next().await.unwrap().values, vec![Value::default()]);
+        assert_eq!(stream.next().await, None);
+    }
+
+    // 模拟对象存储之类的异步后端：每个操作都先让出执行权，再访问本地的 MemTable
+    #[derive(Default)]
+    struct RemoteStore {
+        inner: MemTable,
+    }
+
+    impl AsyncStorage for RemoteStore {
+        async fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.get(table, key)
+        }
+
+        async fn set(
+            &self,
+            table: &str,
+            key: &str,
+            value: Value,
+        ) -> Result<Option<Value>, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.set(table, key, value)
+        }
+
+        async fn delete(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.delete(table, key)
+        }
+
+        async fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.contains(table, key)
+        }
+
+        async fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.get_all(table)
+        }
+
+        async fn get_iter(&self, table: &str) -> Result<BoxStream<'static, Kvpair>, KvError> {
+            tokio::task::yield_now().await;
+            let pairs: Vec<_> = self.inner.get_iter(table)?.collect();
+            Ok(stream::iter(pairs).boxed())
+        }
+
+        async fn set_many(
+            &self,
+            table: &str,
+            pairs: Vec<Kvpair>,
+        ) -> Result<Vec<Option<Value>>, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.set_many(table, pairs)
+        }
+
+        async fn delete_many(
+            &self,
+            table: &str,
+            keys: &[String],
+        ) -> Result<Vec<Option<Value>>, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.delete_many(table, keys)
+        }
+
+        async fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.expire(table, key, ttl)
+        }
+
+        async fn set_with_ttl(
+            &self,
+            table: &str,
+            key: &str,
+            value: Value,
+            ttl: Duration,
+        ) -> Result<Option<Value>, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.set_with_ttl(table, key, value, ttl)
+        }
+
+        async fn set_many_with_ttl(
+            &self,
+            table: &str,
+            pairs: Vec<Kvpair>,
+            ttl: Duration,
+        ) -> Result<Vec<Option<Value>>, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.set_many_with_ttl(table, pairs, ttl)
+        }
+
+        async fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.ttl(table, key)
+        }
+
+        async fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.persist(table, key)
+        }
+
+        async fn reap_expired(&self) -> Result<usize, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.reap_expired()
+        }
+
+        async fn update(
+            &self,
+            table: &str,
+            key: &str,
+            mut f: BoxedUpdateFn,
+        ) -> Result<Option<Value>, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.update(table, key, &mut *f)
+        }
+
+        async fn scan(
+            &self,
+            table: &str,
+            prefix: &str,
+            range: ScanRange<'_>,
+            limit: usize,
+        ) -> Result<Vec<Kvpair>, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.scan(table, prefix, range, limit)
+        }
+
+        async fn list_tables(&self) -> Result<Vec<String>, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.list_tables()
+        }
+
+        async fn drop_table(&self, table: &str) -> Result<usize, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.drop_table(table)
+        }
+
+        async fn table_info(&self, table: &str) -> Result<Option<TableStats>, KvError> {
+            tokio::task::yield_now().await;
+            self.inner.table_info(table)
+        }
+
+        async fn transaction(&self, mut f: BoxedTxFn) -> Result<(), KvError> {
+            tokio::task::yield_now().await;
+            self.inner.transaction(&mut *f)
+        }
+    }
+
+    #[tokio::test]
+    async fn service_should_work_with_async_storage() {
+        let service: Service<RemoteStore> = ServiceInner::new(RemoteStore::default()).into();
+
+        let res = service
+            .exec(CommandRequest::new_hset_with_ttl(
+                "t1",
+                "k1",
+                "v1".into(),
+                60_000,
+            ))
+            .await;
+        assert_eq!(res.values, vec![Value::default()]);
+        let res = service
+            .exec(CommandRequest::new_hincrby("t1", "n", 2))
+            .await;
+        assert_eq!(res.values, vec![2.into()]);
+
+        // 请求在其它任务中执行
+        let handle = tokio::spawn({
+            let service = service.clone();
+            async move { service.exec(CommandRequest::new_hget("t1", "k1")).await }
+        });
+        assert_eq!(handle.await.unwrap().values, vec!["v1".into()]);
+
+        let mut stream = service.execute(CommandRequest::new_hgetall("t1"));
+        let res = stream.next().await.unwrap();
+        assert_eq!(res.pairs.len(), 2);
+
+        let cmd = CommandRequest::new_transaction(
+            vec![
+                CommandRequest::new_hdel("t1", "k1"),
+                CommandRequest::new_hset("t2", "k1", "v1".into()),
+            ],
+            vec![],
+        );
+        assert_eq!(service.exec(cmd).await.status, 200);
+        let res = service.exec(CommandRequest::new_list_tables()).await;
+        assert_eq!(res.values, vec!["t1".into(), "t2".into()]);
+    }
 }
@@ -0,0 +1,231 @@
+use std::{
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+    task::{Context, Poll},
+};
+
+use dashmap::{DashMap, DashSet};
+use futures::{
+    Stream, StreamExt,
+    channel::mpsc::{self, Receiver, Sender},
+    stream,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    CommandResponse, Publish, StreamingResponse, Subscribe, Unsubscribe, Value, error::KvError,
+};
+
+// 每个订阅者的缓冲区大小，慢速订阅者超出后被移除，订阅的 stream 随之结束
+const BROADCAST_CAPACITY: usize = 128;
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+fn get_next_subscription_id() -> u32 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// 主题广播器：topic -> 订阅 id 集合，订阅 id -> 发送端
+#[derive(Default)]
+pub struct Broadcaster {
+    topics: DashMap<String, DashSet<u32>>,
+    subscriptions: DashMap<u32, Sender<CommandResponse>>,
+}
+
+impl Broadcaster {
+    // 订阅主题，返回订阅 id 和用于接收数据的 stream
+    pub fn subscribe(&self, topic: &str) -> (u32, mpsc::Receiver<CommandResponse>) {
+        let id = get_next_subscription_id();
+        let (tx, rx) = mpsc::channel(BROADCAST_CAPACITY);
+        self.subscriptions.insert(id, tx);
+        self.topics.entry(topic.into()).or_default().insert(id);
+        debug!("Subscription {} is added to topic {}", id, topic);
+        (id, rx)
+    }
+
+    // 取消订阅后发送端被释放，对应的 stream 随之结束
+    pub fn unsubscribe(&self, topic: &str, id: u32) -> Result<u32, KvError> {
+        let removed = match self.topics.get(topic) {
+            Some(ids) => ids.remove(&id).is_some(),
+            None => false,
+        };
+        if !removed {
+            return Err(KvError::NotFound(topic.into(), id.to_string()));
+        }
+        self.topics.remove_if(topic, |_, ids| ids.is_empty());
+        self.subscriptions.remove(&id);
+        info!("Subscription {} is removed from topic {}", id, topic);
+        Ok(id)
+    }
+
+    // 发布数据到主题，返回收到数据的订阅者数量
+    pub fn publish(&self, topic: &str, res: CommandResponse) -> usize {
+        let Some(ids) = self.topics.get(topic).map(|ids| ids.clone()) else {
+            return 0;
+        };
+        let mut count = 0;
+        for id in ids.iter() {
+            // 用保存的发送端发送，克隆的发送端各自多占一个缓冲位置，缓冲区永远不会满
+            let Some(result) = self
+                .subscriptions
+                .get_mut(&*id)
+                .map(|mut tx| tx.try_send(res.clone()).map_err(|e| e.is_full()))
+            else {
+                continue;
+            };
+            match result {
+                Ok(()) => count += 1,
+                // 订阅端已经断开或者跟不上发布的速度，移除订阅，不静默丢弃消息
+                Err(full) => {
+                    let reason = if full { "full" } else { "closed" };
+                    warn!("Subscription {} is {}, removing it", *id, reason);
+                    let _ = self.unsubscribe(topic, *id);
+                }
+            }
+        }
+        count
+    }
+}
+
+// 订阅的数据流，连接断开时 stream 被释放，自动取消订阅
+pub struct SubscriptionStream {
+    id: u32,
+    topic: String,
+    rx: Receiver<CommandResponse>,
+    broadcaster: Arc<Broadcaster>,
+}
+
+impl Stream for SubscriptionStream {
+    type Item = CommandResponse;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_next_unpin(cx)
+    }
+}
+
+impl Drop for SubscriptionStream {
+    fn drop(&mut self) {
+        // 已经取消的订阅返回 NotFound，忽略即可
+        let _ = self.broadcaster.unsubscribe(&self.topic, self.id);
+    }
+}
+
+// 只有一个响应的主题命令
+pub trait TopicService {
+    fn exec(&self, topic: &Broadcaster) -> CommandResponse;
+}
+
+// 返回多个响应的主题命令
+pub trait StreamingService {
+    fn execute(&self, topic: &Arc<Broadcaster>) -> StreamingResponse;
+}
+
+impl StreamingService for Subscribe {
+    fn execute(&self, topic: &Arc<Broadcaster>) -> StreamingResponse {
+        let (id, rx) = topic.subscribe(&self.topic);
+        let rx = SubscriptionStream {
+            id,
+            topic: self.topic.clone(),
+            rx,
+            broadcaster: Arc::clone(topic),
+        };
+        // 第一个响应返回订阅 id，客户端用它来取消订阅
+        let first = CommandResponse::from(Value::from(id as i64));
+        Box::pin(stream::once(async move { first }).chain(rx))
+    }
+}
+
+impl TopicService for Unsubscribe {
+    fn exec(&self, topic: &Broadcaster) -> CommandResponse {
+        match topic.unsubscribe(&self.topic, self.id) {
+            Ok(id) => Value::from(id as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl TopicService for Publish {
+    fn exec(&self, topic: &Broadcaster) -> CommandResponse {
+        let count = topic.publish(&self.topic, self.data.clone().into());
+        Value::from(count as i64).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pub_sub_should_work() {
+        let b = Broadcaster::default();
+        let topic = "lobby";
+
+        let (id1, mut rx1) = b.subscribe(topic);
+        let (id2, mut rx2) = b.subscribe(topic);
+
+        let v: Value = "hello".into();
+        assert_eq!(b.publish(topic, vec![v.clone()].into()), 2);
+
+        assert_eq!(rx1.next().await.unwrap().values, vec![v.clone()]);
+        assert_eq!(rx2.next().await.unwrap().values, vec![v.clone()]);
+
+        assert_eq!(b.unsubscribe(topic, id1), Ok(id1));
+        assert_eq!(rx1.next().await, None);
+
+        assert_eq!(b.publish(topic, vec![v.clone()].into()), 1);
+        assert_eq!(rx2.next().await.unwrap().values, vec![v]);
+
+        assert!(matches!(
+            b.unsubscribe(topic, id1),
+            Err(KvError::NotFound(_, _))
+        ));
+        assert_eq!(b.unsubscribe(topic, id2), Ok(id2));
+        assert!(b.topics.is_empty());
+    }
+
+    #[tokio::test]
+    async fn publish_to_dropped_subscriber_should_clean_up() {
+        let b = Broadcaster::default();
+        let (id, rx) = b.subscribe("t");
+        drop(rx);
+
+        assert_eq!(b.publish("t", Value::from(1).into()), 0);
+        assert!(b.subscriptions.get(&id).is_none());
+        assert!(b.topics.is_empty());
+    }
+
+    #[tokio::test]
+    async fn publish_to_full_subscriber_should_remove_it() {
+        let b = Broadcaster::default();
+        let (id, mut rx) = b.subscribe("t");
+        let mut sent = 0;
+        while b.publish("t", Value::from(sent as i64).into()) == 1 {
+            sent += 1;
+            assert!(sent <= BROADCAST_CAPACITY + 1);
+        }
+        // 缓冲区满后订阅被移除，已经缓冲的消息读完后 stream 结束
+        assert!(b.subscriptions.get(&id).is_none());
+        assert!(b.topics.is_empty());
+        let mut received = 0;
+        while rx.next().await.is_some() {
+            received += 1;
+        }
+        assert_eq!(received, sent);
+    }
+
+    #[tokio::test]
+    async fn dropped_subscription_stream_should_unsubscribe() {
+        let b = Arc::new(Broadcaster::default());
+        let cmd = Subscribe { topic: "t".into() };
+        let mut stream = cmd.execute(&b);
+        let id = i64::try_from(&stream.next().await.unwrap().values[0]).unwrap();
+        assert!(b.subscriptions.get(&(id as u32)).is_some());
+
+        drop(stream);
+        assert!(b.subscriptions.is_empty());
+        assert!(b.topics.is_empty());
+    }
+}
@@ -0,0 +1,569 @@
+use std::{fs, net::SocketAddr, path::Path};
+
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::EnvFilter;
+
+use crate::{AclConfig, DEFAULT_RESP_TABLE, FsyncPolicy, error::KvError};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub general: GeneralConfig,
+    pub tls: Option<ServerTlsConfig>,
+    pub storage: StorageConfig,
+    pub log: LogConfig,
+    // 配置后按客户端证书的 CN 授权访问的表
+    pub acl: Option<AclConfig>,
+    // 配置后在单独的端口上提供 Prometheus 的 /metrics
+    pub metrics: Option<MetricsConfig>,
+    pub replication: Option<ReplicationConfig>,
+    // 配置后在单独的端口上提供 redis 协议的访问
+    pub resp: Option<RespConfig>,
+    // 配置后在单独的端口上提供 HTTP/JSON 的访问
+    pub gateway: Option<GatewayConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeneralConfig {
+    pub addr: String,
+    // 非 TLS 连接是否使用 yamux 多路复用，TLS 连接由 ALPN 协商
+    #[serde(default)]
+    pub multiplex: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerTlsConfig {
+    pub cert: String,
+    pub key: String,
+    //配置后开启双向认证，应该是签发客户端证书的根证书 ca.cert
+    pub ca: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StorageConfig {
+    MemTable {
+        // 配置后开启持久化：修改写入 WAL 并定期生成快照
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+        #[serde(default)]
+        fsync: FsyncPolicy,
+        // 快照间隔，单位秒，0 表示不生成快照
+        #[serde(default = "default_snapshot_interval")]
+        snapshot_interval: u64,
+    },
+    SledDb {
+        path: String,
+    },
+    Bitcask {
+        path: String,
+    },
+}
+
+impl StorageConfig {
+    pub fn memtable(path: Option<String>) -> Self {
+        StorageConfig::MemTable {
+            path,
+            fsync: FsyncPolicy::default(),
+            snapshot_interval: default_snapshot_interval(),
+        }
+    }
+
+    // 磁盘存储的数据目录
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            StorageConfig::MemTable { path, .. } => path.as_deref(),
+            StorageConfig::SledDb { path } | StorageConfig::Bitcask { path } => Some(path),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "role", rename_all = "lowercase")]
+pub enum ReplicationConfig {
+    // 记录修改日志，follower 通过 Replicate 命令同步
+    Leader {
+        // 允许同步数据的 follower 客户端证书的 CN，需要开启 TLS 双向认证
+        #[serde(default)]
+        followers: Vec<String>,
+    },
+    // 只读，从 leader 同步数据，修改命令返回 307 和 leader 的地址
+    Follower {
+        leader: String,
+        // 不配置时使用明文 TCP 连接 leader
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tls: Option<ClientTlsConfig>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClientTlsConfig {
+    pub domain: String,
+    //签发 leader 证书的根证书
+    pub ca: Option<String>,
+    // leader 开启双向认证时使用的客户端证书
+    pub cert: Option<String>,
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    pub addr: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RespConfig {
+    pub addr: String,
+    // GET/SET 等没有表名的 redis 命令使用的表
+    #[serde(default = "default_resp_table")]
+    pub table: String,
+    // 连接没有 TLS，命令以这个身份经过 ACL 授权；配置了 tls 或 acl 时必须显式设置
+    #[serde(default)]
+    pub identity: Option<String>,
+}
+
+fn default_resp_table() -> String {
+    DEFAULT_RESP_TABLE.into()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GatewayConfig {
+    pub addr: String,
+    // 同 resp.identity
+    #[serde(default)]
+    pub identity: Option<String>,
+    // 允许跨域访问的 Origin，"*" 表示任意 Origin，默认不允许跨域
+    #[serde(default)]
+    pub cors_origins: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogConfig {
+    pub level: String,
+}
+
+impl Default for GeneralConfig {
+    fn default() -> Self {
+        Self {
+            addr: "127.0.0.1:8080".into(),
+            multiplex: false,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig::memtable(None)
+    }
+}
+
+fn default_snapshot_interval() -> u64 {
+    60
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".into(),
+        }
+    }
+}
+
+impl ServerConfig {
+    // 从 toml 文件加载配置
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, KvError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|e| {
+            KvError::ConfigError(format!("failed to read {}: {}", path.display(), e))
+        })?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, KvError> {
+        toml::from_str(content).map_err(|e| KvError::ConfigError(e.to_string()))
+    }
+
+    // 启动前校验配置，尽早给出明确的错误信息
+    pub fn validate(&self) -> Result<(), KvError> {
+        self.general.addr.parse::<SocketAddr>().map_err(|e| {
+            KvError::ConfigError(format!("invalid addr {}: {}", self.general.addr, e))
+        })?;
+
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.addr.parse::<SocketAddr>().map_err(|e| {
+                KvError::ConfigError(format!("invalid metrics addr {}: {}", metrics.addr, e))
+            })?;
+        }
+
+        if let Some(resp) = self.resp.as_ref() {
+            resp.addr.parse::<SocketAddr>().map_err(|e| {
+                KvError::ConfigError(format!("invalid resp addr {}: {}", resp.addr, e))
+            })?;
+            if resp.table.is_empty() {
+                return Err(KvError::ConfigError("resp.table must not be empty".into()));
+            }
+            self.check_plain_identity("resp", &resp.identity)?;
+        }
+
+        if let Some(gateway) = self.gateway.as_ref() {
+            gateway.addr.parse::<SocketAddr>().map_err(|e| {
+                KvError::ConfigError(format!("invalid gateway addr {}: {}", gateway.addr, e))
+            })?;
+            self.check_plain_identity("gateway", &gateway.identity)?;
+            for origin in gateway.cors_origins.iter() {
+                if origin != "*" && http::HeaderValue::from_str(origin).is_err() {
+                    return Err(KvError::ConfigError(format!(
+                        "invalid gateway cors origin {}",
+                        origin
+                    )));
+                }
+            }
+        }
+
+        if let Some(ReplicationConfig::Leader { followers }) = self.replication.as_ref() {
+            if followers.is_empty() {
+                return Err(KvError::ConfigError(
+                    "replication.followers must not be empty".into(),
+                ));
+            }
+            if self.tls.as_ref().is_none_or(|tls| tls.ca.is_none()) {
+                return Err(KvError::ConfigError(
+                    "replication leader requires tls.ca to authenticate followers".into(),
+                ));
+            }
+        }
+
+        if let Some(ReplicationConfig::Follower { leader, tls }) = self.replication.as_ref() {
+            if leader.is_empty() {
+                return Err(KvError::ConfigError(
+                    "replication.leader must not be empty".into(),
+                ));
+            }
+            if let Some(tls) = tls.as_ref() {
+                if tls.cert.is_some() != tls.key.is_some() {
+                    return Err(KvError::ConfigError(
+                        "replication.tls.cert and replication.tls.key must be set together".into(),
+                    ));
+                }
+                for (name, path) in [
+                    ("replication.tls.ca", &tls.ca),
+                    ("replication.tls.cert", &tls.cert),
+                    ("replication.tls.key", &tls.key),
+                ] {
+                    if let Some(path) = path.as_ref() {
+                        check_file(name, path)?;
+                    }
+                }
+            }
+        }
+
+        if let Some(tls) = self.tls.as_ref() {
+            check_file("tls.cert", &tls.cert)?;
+            check_file("tls.key", &tls.key)?;
+            if let Some(ca) = tls.ca.as_ref() {
+                check_file("tls.ca", ca)?;
+            }
+        }
+
+        if self.storage.path().is_some_and(str::is_empty) {
+            return Err(KvError::ConfigError(
+                "storage.path must not be empty".into(),
+            ));
+        }
+        if let StorageConfig::MemTable {
+            fsync: FsyncPolicy::Interval(0),
+            ..
+        } = self.storage
+        {
+            return Err(KvError::ConfigError(
+                "storage.fsync interval must be at least 1ms".into(),
+            ));
+        }
+
+        // 与 kvs 一样按 EnvFilter 解析，支持 kv=debug,yamux=warn 这样的写法
+        EnvFilter::try_new(&self.log.level).map_err(|e| {
+            KvError::ConfigError(format!("invalid log level {}: {}", self.log.level, e))
+        })?;
+        Ok(())
+    }
+
+    // resp 和 gateway 的连接没有 TLS，开启了 tls 或 acl 时必须显式指定以哪个身份访问，
+    // 避免无意中绕过客户端证书的认证
+    fn check_plain_identity(&self, name: &str, identity: &Option<String>) -> Result<(), KvError> {
+        match identity.as_deref() {
+            Some("") => Err(KvError::ConfigError(format!(
+                "{}.identity must not be empty",
+                name
+            ))),
+            None if self.tls.is_some() || self.acl.is_some() => Err(KvError::ConfigError(format!(
+                "{} listener is not encrypted, set {}.identity to serve it with tls or acl",
+                name, name
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn check_file(name: &str, path: &str) -> Result<(), KvError> {
+    if Path::new(path).is_file() {
+        Ok(())
+    } else {
+        Err(KvError::ConfigError(format!(
+            "{} file not found: {}",
+            name, path
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_config_should_load() {
+        let config = ServerConfig::load("fixtures/kvs.toml").unwrap();
+        assert_eq!(config.general.addr, "127.0.0.1:8080");
+        assert!(!config.general.multiplex);
+        assert_eq!(
+            config.storage,
+            StorageConfig::SledDb {
+                path: "tmp/kvserver".into()
+            }
+        );
+        assert_eq!(
+            config.tls.as_ref().unwrap().ca.as_deref(),
+            Some("fixtures/ca.cert")
+        );
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn server_config_should_use_default() {
+        let config = ServerConfig::parse("").unwrap();
+        assert_eq!(config, ServerConfig::default());
+        assert_eq!(config.storage, StorageConfig::default());
+        assert_eq!(config.storage.path(), None);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_server_config_should_be_rejected() {
+        let config = ServerConfig::parse("[general]\naddr = \"localhost\"").unwrap();
+        assert!(matches!(config.validate(), Err(KvError::ConfigError(_))));
+
+        let config = ServerConfig::parse("[storage]\ntype = \"sleddb\"\npath = \"\"").unwrap();
+        assert!(matches!(config.validate(), Err(KvError::ConfigError(_))));
+
+        let config =
+            ServerConfig::parse("[tls]\ncert = \"nope.cert\"\nkey = \"nope.key\"").unwrap();
+        assert!(matches!(config.validate(), Err(KvError::ConfigError(_))));
+
+        let config = ServerConfig::parse("[log]\nlevel = \"kv=loud\"").unwrap();
+        assert!(matches!(config.validate(), Err(KvError::ConfigError(_))));
+
+        let config = ServerConfig::parse("[log]\nlevel = \"kv=debug,yamux=warn\"").unwrap();
+        assert!(config.validate().is_ok());
+
+        let config = ServerConfig::parse("[storage]\ntype = \"bitcask\"\npath = \"\"").unwrap();
+        assert!(matches!(config.validate(), Err(KvError::ConfigError(_))));
+
+        assert!(ServerConfig::parse("[storage]\ntype = \"rocksdb\"").is_err());
+
+        let config = ServerConfig::parse(
+            "[storage]\ntype = \"memtable\"\npath = \"tmp/memtable\"\nfsync = { interval = 0 }",
+        )
+        .unwrap();
+        assert!(matches!(config.validate(), Err(KvError::ConfigError(_))));
+    }
+
+    #[test]
+    fn bitcask_storage_should_parse() {
+        let config =
+            ServerConfig::parse("[storage]\ntype = \"bitcask\"\npath = \"tmp/bitcask\"").unwrap();
+        assert_eq!(
+            config.storage,
+            StorageConfig::Bitcask {
+                path: "tmp/bitcask".into()
+            }
+        );
+        assert_eq!(config.storage.path(), Some("tmp/bitcask"));
+    }
+
+    #[test]
+    fn memtable_persistence_should_parse() {
+        let config = ServerConfig::parse(
+            "[storage]\ntype = \"memtable\"\npath = \"tmp/memtable\"\nfsync = \"always\"",
+        )
+        .unwrap();
+        assert_eq!(
+            config.storage,
+            StorageConfig::MemTable {
+                path: Some("tmp/memtable".into()),
+                fsync: FsyncPolicy::Always,
+                snapshot_interval: 60,
+            }
+        );
+
+        let config = ServerConfig::parse(
+            "[storage]\ntype = \"memtable\"\npath = \"tmp/memtable\"\nfsync = { interval = 100 }\nsnapshot_interval = 0",
+        )
+        .unwrap();
+        assert!(matches!(
+            config.storage,
+            StorageConfig::MemTable {
+                fsync: FsyncPolicy::Interval(100),
+                snapshot_interval: 0,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn acl_should_parse() {
+        let config = ServerConfig::parse(
+            "[acl.default]\nread = [\"public\"]\n[acl.identities.awesome-client]\nwrite = [\"*\"]",
+        )
+        .unwrap();
+        let acl = config.acl.unwrap();
+        assert_eq!(acl.default.read, vec!["public".to_string()]);
+        assert!(acl.default.write.is_empty());
+        assert_eq!(
+            acl.identities["awesome-client"].write,
+            vec!["*".to_string()]
+        );
+    }
+
+    #[test]
+    fn metrics_should_parse() {
+        let config = ServerConfig::parse("[metrics]\naddr = \"127.0.0.1:9100\"").unwrap();
+        assert_eq!(
+            config.metrics,
+            Some(MetricsConfig {
+                addr: "127.0.0.1:9100".into()
+            })
+        );
+        assert!(config.validate().is_ok());
+
+        let config = ServerConfig::parse("[metrics]\naddr = \"localhost\"").unwrap();
+        assert!(matches!(config.validate(), Err(KvError::ConfigError(_))));
+    }
+
+    #[test]
+    fn resp_should_parse() {
+        let config = ServerConfig::parse("[resp]\naddr = \"127.0.0.1:6379\"").unwrap();
+        assert_eq!(
+            config.resp,
+            Some(RespConfig {
+                addr: "127.0.0.1:6379".into(),
+                table: "default".into(),
+                identity: None,
+            })
+        );
+        assert!(config.validate().is_ok());
+
+        let config =
+            ServerConfig::parse("[resp]\naddr = \"127.0.0.1:6379\"\ntable = \"redis\"").unwrap();
+        assert_eq!(config.resp.unwrap().table, "redis");
+
+        let config =
+            ServerConfig::parse("[resp]\naddr = \"127.0.0.1:6379\"\ntable = \"\"").unwrap();
+        assert!(matches!(config.validate(), Err(KvError::ConfigError(_))));
+
+        // 配置了 acl 时必须显式指定身份
+        let config =
+            ServerConfig::parse("[acl.default]\nread = [\"*\"]\n[resp]\naddr = \"127.0.0.1:6379\"")
+                .unwrap();
+        assert!(matches!(config.validate(), Err(KvError::ConfigError(_))));
+        let config = ServerConfig::parse(
+            "[acl.default]\nread = [\"*\"]\n[resp]\naddr = \"127.0.0.1:6379\"\nidentity = \"redis\"",
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn gateway_should_parse() {
+        let config = ServerConfig::parse("[gateway]\naddr = \"127.0.0.1:3000\"").unwrap();
+        assert_eq!(
+            config.gateway,
+            Some(GatewayConfig {
+                addr: "127.0.0.1:3000".into(),
+                identity: None,
+                cors_origins: vec![],
+            })
+        );
+        assert!(config.validate().is_ok());
+
+        let config = ServerConfig::parse(
+            "[tls]\ncert = \"fixtures/server.cert\"\nkey = \"fixtures/server.key\"\n[gateway]\naddr = \"127.0.0.1:3000\"",
+        )
+        .unwrap();
+        assert!(matches!(config.validate(), Err(KvError::ConfigError(_))));
+        let config =
+            ServerConfig::parse("[gateway]\naddr = \"127.0.0.1:3000\"\nidentity = \"\"").unwrap();
+        assert!(matches!(config.validate(), Err(KvError::ConfigError(_))));
+
+        let config = ServerConfig::parse(
+            "[gateway]\naddr = \"127.0.0.1:3000\"\ncors_origins = [\"http://localhost:8000\", \"*\"]",
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let config = ServerConfig::parse(
+            "[gateway]\naddr = \"127.0.0.1:3000\"\ncors_origins = [\"bad\\norigin\"]",
+        )
+        .unwrap();
+        assert!(matches!(config.validate(), Err(KvError::ConfigError(_))));
+
+        let config = ServerConfig::parse("[gateway]\naddr = \"localhost\"").unwrap();
+        assert!(matches!(config.validate(), Err(KvError::ConfigError(_))));
+    }
+
+    #[test]
+    fn replication_should_parse() {
+        let config = ServerConfig::parse(
+            "[tls]\ncert = \"fixtures/server.cert\"\nkey = \"fixtures/server.key\"\nca = \"fixtures/ca.cert\"\n[replication]\nrole = \"leader\"\nfollowers = [\"awesome-client\"]",
+        )
+        .unwrap();
+        assert_eq!(
+            config.replication,
+            Some(ReplicationConfig::Leader {
+                followers: vec!["awesome-client".into()]
+            })
+        );
+        assert!(config.validate().is_ok());
+
+        // leader 需要配置 follower，并且开启双向认证
+        let config = ServerConfig::parse("[replication]\nrole = \"leader\"").unwrap();
+        assert!(matches!(config.validate(), Err(KvError::ConfigError(_))));
+        let config = ServerConfig::parse(
+            "[tls]\ncert = \"fixtures/server.cert\"\nkey = \"fixtures/server.key\"\n[replication]\nrole = \"leader\"\nfollowers = [\"awesome-client\"]",
+        )
+        .unwrap();
+        assert!(matches!(config.validate(), Err(KvError::ConfigError(_))));
+
+        let config = ServerConfig::parse(
+            "[replication]\nrole = \"follower\"\nleader = \"127.0.0.1:8080\"\n[replication.tls]\ndomain = \"kvserver.kevin.inc\"\nca = \"fixtures/ca.cert\"\ncert = \"fixtures/client.cert\"\nkey = \"fixtures/client.key\"",
+        )
+        .unwrap();
+        assert!(matches!(
+            config.replication,
+            Some(ReplicationConfig::Follower { ref leader, tls: Some(_) }) if leader == "127.0.0.1:8080"
+        ));
+        assert!(config.validate().is_ok());
+
+        let config = ServerConfig::parse(
+            "[replication]\nrole = \"follower\"\nleader = \"127.0.0.1:8080\"\n[replication.tls]\ndomain = \"kvserver.kevin.inc\"\ncert = \"fixtures/client.cert\"",
+        )
+        .unwrap();
+        assert!(matches!(config.validate(), Err(KvError::ConfigError(_))));
+        assert!(ServerConfig::parse("[replication]\nrole = \"follower\"").is_err());
+    }
+
+    #[test]
+    fn multiplex_should_be_configurable() {
+        let config =
+            ServerConfig::parse("[general]\naddr = \"0.0.0.0:9527\"\nmultiplex = true").unwrap();
+        assert!(config.general.multiplex);
+        assert!(config.validate().is_ok());
+    }
+}
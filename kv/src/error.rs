@@ -24,10 +24,34 @@ pub enum KvError {
     IoError(#[from] std::io::Error),
     #[error("frame error")]
     FrameError,
+    #[error("frame too large: {0} bytes")]
+    FrameTooLarge(usize),
+    #[error("invalid frame: {0}")]
+    InvalidFrame(String),
     #[error("CertifcateParse error: {0} {1}")]
     CertParseError(String, String),
     #[error("rustls error: {0}")]
     RustlsError(#[from] tokio_rustls::rustls::Error),
+    #[error("yamux error: {0}")]
+    YamuxError(#[from] yamux::ConnectionError),
+    #[error("config error: {0}")]
+    ConfigError(String),
+    #[error("server error: status {0}, {1}")]
+    ServerError(u32, String),
+    #[error("transaction aborted: {0}")]
+    TransactionAborted(String),
+    #[error("table not found: {0}")]
+    TableNotFound(String),
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("read only follower, redirect to leader: {0}")]
+    Redirect(String),
+    #[error("wrong type: key {0} holds a {1} value, expected {2}")]
+    WrongType(String, &'static str, &'static str),
+    #[error("value out of range: {0}")]
+    OutOfRange(String),
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
 }
 
 impl PartialEq for KvError {
@@ -46,6 +70,20 @@ impl PartialEq for KvError {
             (KvError::ProstEncodeError(_), KvError::ProstEncodeError(_)) => false, // 无法比较 prost::EncodeError
             (KvError::IoError(_), KvError::IoError(_)) => false, // 无法比较 std::io::Error
             (KvError::FrameError, KvError::FrameError) => true,
+            (KvError::YamuxError(_), KvError::YamuxError(_)) => false, // 无法比较 yamux::ConnectionError
+            (KvError::FrameTooLarge(l1), KvError::FrameTooLarge(l2)) => l1 == l2,
+            (KvError::InvalidFrame(s1), KvError::InvalidFrame(s2)) => s1 == s2,
+            (KvError::ConfigError(s1), KvError::ConfigError(s2)) => s1 == s2,
+            (KvError::ServerError(c1, m1), KvError::ServerError(c2, m2)) => c1 == c2 && m1 == m2,
+            (KvError::TransactionAborted(s1), KvError::TransactionAborted(s2)) => s1 == s2,
+            (KvError::TableNotFound(s1), KvError::TableNotFound(s2)) => s1 == s2,
+            (KvError::PermissionDenied(s1), KvError::PermissionDenied(s2)) => s1 == s2,
+            (KvError::Redirect(s1), KvError::Redirect(s2)) => s1 == s2,
+            (KvError::WrongType(k1, a1, e1), KvError::WrongType(k2, a2, e2)) => {
+                k1 == k2 && a1 == a2 && e1 == e2
+            }
+            (KvError::OutOfRange(s1), KvError::OutOfRange(s2)) => s1 == s2,
+            (KvError::InvalidArgument(s1), KvError::InvalidArgument(s2)) => s1 == s2,
             _ => false,
         }
     }
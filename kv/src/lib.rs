@@ -1,6 +1,10 @@
 mod pb;
 pub use pb::abi::*;
+mod config;
 pub mod error;
+pub use config::*;
+mod metrics;
+pub use metrics::*;
 pub mod storage;
 pub use storage::*;
 mod command;
@@ -0,0 +1,410 @@
+use std::{
+    fmt::Write,
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicI64, AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    Router, extract::State, http::header, response::IntoResponse, routing::get, serve::Listener,
+};
+use dashmap::DashMap;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    time::{self, Sleep},
+};
+use tracing::debug;
+
+use crate::error::KvError;
+
+// 命令耗时直方图的桶，单位秒
+const LATENCY_BUCKETS: [f64; 10] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+// /metrics 的一个连接最多保持的时间
+const METRICS_CONN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// 服务端的运行指标，以 Prometheus 文本格式输出
+#[derive(Debug, Default)]
+pub struct Metrics {
+    commands: DashMap<&'static str, CommandStats>,
+    // 按命令和状态码统计的错误数（status >= 400）
+    errors: DashMap<(&'static str, u32), AtomicU64>,
+    active_connections: AtomicI64,
+    connections_total: AtomicU64,
+    // 下标 0 为未压缩的 frame，1 为压缩的 frame
+    received_bytes: [AtomicU64; 2],
+    sent_bytes: [AtomicU64; 2],
+}
+
+#[derive(Debug, Default)]
+struct CommandStats {
+    count: AtomicU64,
+    // 每个桶只统计落在自己区间内的次数，输出时再累加
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    sum_micros: AtomicU64,
+}
+
+// 连接关闭（guard 被释放）时活跃连接数减一
+pub struct ConnectionGuard(Arc<Metrics>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_command(&self, command: &'static str, status: u32, elapsed: Duration) {
+        let stats = self.commands.entry(command).or_default();
+        stats.count.fetch_add(1, Ordering::Relaxed);
+        stats
+            .sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        let secs = elapsed.as_secs_f64();
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|le| secs <= *le) {
+            stats.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        drop(stats);
+        if status >= 400 {
+            self.errors
+                .entry((command, status))
+                .or_default()
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn connection(self: &Arc<Self>) -> ConnectionGuard {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        self.connections_total.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(Arc::clone(self))
+    }
+
+    pub fn record_received(&self, bytes: usize, compressed: bool) {
+        self.received_bytes[compressed as usize].fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_sent(&self, bytes: usize, compressed: bool) {
+        self.sent_bytes[compressed as usize].fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn active_connections(&self) -> i64 {
+        self.active_connections.load(Ordering::Relaxed)
+    }
+
+    // 输出 Prometheus 文本格式，按命令名排序保证输出稳定
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut commands: Vec<_> = self
+            .commands
+            .iter()
+            .map(|entry| {
+                let stats = entry.value();
+                let buckets: Vec<u64> = stats
+                    .buckets
+                    .iter()
+                    .map(|b| b.load(Ordering::Relaxed))
+                    .collect();
+                (
+                    *entry.key(),
+                    stats.count.load(Ordering::Relaxed),
+                    buckets,
+                    stats.sum_micros.load(Ordering::Relaxed),
+                )
+            })
+            .collect();
+        commands.sort_by_key(|(name, ..)| *name);
+
+        out.push_str("# HELP kv_commands_total Total number of commands executed.\n");
+        out.push_str("# TYPE kv_commands_total counter\n");
+        for (name, count, ..) in &commands {
+            let _ = writeln!(out, "kv_commands_total{{command=\"{}\"}} {}", name, count);
+        }
+
+        let mut errors: Vec<_> = self
+            .errors
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().load(Ordering::Relaxed)))
+            .collect();
+        errors.sort();
+        out.push_str("# HELP kv_command_errors_total Total number of failed commands by status.\n");
+        out.push_str("# TYPE kv_command_errors_total counter\n");
+        for ((name, status), count) in &errors {
+            let _ = writeln!(
+                out,
+                "kv_command_errors_total{{command=\"{}\",status=\"{}\"}} {}",
+                name, status, count
+            );
+        }
+
+        out.push_str("# HELP kv_command_duration_seconds Command execution latency.\n");
+        out.push_str("# TYPE kv_command_duration_seconds histogram\n");
+        for (name, count, buckets, sum_micros) in &commands {
+            let mut cumulative = 0;
+            for (le, n) in LATENCY_BUCKETS.iter().zip(buckets) {
+                cumulative += n;
+                let _ = writeln!(
+                    out,
+                    "kv_command_duration_seconds_bucket{{command=\"{}\",le=\"{}\"}} {}",
+                    name, le, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "kv_command_duration_seconds_bucket{{command=\"{}\",le=\"+Inf\"}} {}",
+                name, count
+            );
+            let _ = writeln!(
+                out,
+                "kv_command_duration_seconds_sum{{command=\"{}\"}} {}",
+                name,
+                *sum_micros as f64 / 1_000_000.0
+            );
+            let _ = writeln!(
+                out,
+                "kv_command_duration_seconds_count{{command=\"{}\"}} {}",
+                name, count
+            );
+        }
+
+        out.push_str("# HELP kv_active_connections Number of open connections.\n");
+        out.push_str("# TYPE kv_active_connections gauge\n");
+        let _ = writeln!(out, "kv_active_connections {}", self.active_connections());
+        out.push_str("# HELP kv_connections_total Total number of accepted connections.\n");
+        out.push_str("# TYPE kv_connections_total counter\n");
+        let _ = writeln!(
+            out,
+            "kv_connections_total {}",
+            self.connections_total.load(Ordering::Relaxed)
+        );
+
+        for (name, help, bytes) in [
+            (
+                "kv_received_bytes_total",
+                "Bytes of frames received.",
+                &self.received_bytes,
+            ),
+            (
+                "kv_sent_bytes_total",
+                "Bytes of frames sent.",
+                &self.sent_bytes,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for (compressed, n) in [false, true].iter().zip(bytes) {
+                let _ = writeln!(
+                    out,
+                    "{}{{compressed=\"{}\"}} {}",
+                    name,
+                    compressed,
+                    n.load(Ordering::Relaxed)
+                );
+            }
+        }
+        out
+    }
+}
+
+// 用 axum 提供 GET /metrics，请求头的大小由 hyper 限制
+// 每个连接最多保持 METRICS_CONN_TIMEOUT，慢速发送请求的客户端不会一直占用连接
+pub async fn serve_metrics(listener: TcpListener, metrics: Arc<Metrics>) -> Result<(), KvError> {
+    serve(listener, metrics, METRICS_CONN_TIMEOUT).await
+}
+
+async fn serve(
+    listener: TcpListener,
+    metrics: Arc<Metrics>,
+    timeout: Duration,
+) -> Result<(), KvError> {
+    let router = Router::new()
+        .route("/metrics", get(render_metrics))
+        .with_state(metrics);
+    let listener = TimeoutListener {
+        inner: listener,
+        timeout,
+    };
+    axum::serve(listener, router.into_make_service()).await?;
+    Ok(())
+}
+
+async fn render_metrics(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
+
+struct TimeoutListener {
+    inner: TcpListener,
+    timeout: Duration,
+}
+
+impl Listener for TimeoutListener {
+    type Io = TimeoutStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        let (stream, addr) = Listener::accept(&mut self.inner).await;
+        debug!("Metrics client {} connected", addr);
+        let stream = TimeoutStream {
+            inner: stream,
+            deadline: Box::pin(time::sleep(self.timeout)),
+        };
+        (stream, addr)
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.inner.local_addr()
+    }
+}
+
+// 超过截止时间后读写都返回 TimedOut，hyper 随之关闭连接
+struct TimeoutStream {
+    inner: TcpStream,
+    deadline: Pin<Box<Sleep>>,
+}
+
+impl TimeoutStream {
+    fn check(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        match self.deadline.as_mut().poll(cx) {
+            Poll::Ready(()) => Err(io::ErrorKind::TimedOut.into()),
+            Poll::Pending => Ok(()),
+        }
+    }
+}
+
+impl AsyncRead for TimeoutStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.check(cx)?;
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TimeoutStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.check(cx)?;
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.check(cx)?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[test]
+    fn metrics_should_render_histogram() {
+        let metrics = Arc::new(Metrics::new());
+        metrics.record_command("hget", 200, Duration::from_micros(50));
+        metrics.record_command("hget", 404, Duration::from_millis(3));
+        metrics.record_command("hset", 200, Duration::from_secs(10));
+        metrics.record_received(10, false);
+        metrics.record_sent(100, true);
+        let guard = metrics.connection();
+        let _guard2 = metrics.connection();
+        drop(guard);
+
+        let out = metrics.render();
+        assert!(out.contains("kv_commands_total{command=\"hget\"} 2\n"));
+        assert!(out.contains("kv_command_errors_total{command=\"hget\",status=\"404\"} 1\n"));
+        assert!(!out.contains("kv_command_errors_total{command=\"hset\""));
+        // 桶是累加的
+        assert!(
+            out.contains("kv_command_duration_seconds_bucket{command=\"hget\",le=\"0.0001\"} 1\n")
+        );
+        assert!(
+            out.contains("kv_command_duration_seconds_bucket{command=\"hget\",le=\"0.001\"} 1\n")
+        );
+        assert!(
+            out.contains("kv_command_duration_seconds_bucket{command=\"hget\",le=\"0.005\"} 2\n")
+        );
+        assert!(out.contains("kv_command_duration_seconds_bucket{command=\"hset\",le=\"5\"} 0\n"));
+        assert!(
+            out.contains("kv_command_duration_seconds_bucket{command=\"hset\",le=\"+Inf\"} 1\n")
+        );
+        assert!(out.contains("kv_command_duration_seconds_sum{command=\"hset\"} 10\n"));
+        assert!(out.contains("kv_command_duration_seconds_count{command=\"hget\"} 2\n"));
+        assert!(out.contains("kv_active_connections 1\n"));
+        assert!(out.contains("kv_connections_total 2\n"));
+        assert!(out.contains("kv_received_bytes_total{compressed=\"false\"} 10\n"));
+        assert!(out.contains("kv_sent_bytes_total{compressed=\"true\"} 100\n"));
+        // hget 排在 hset 前面
+        assert!(out.find("command=\"hget\"").unwrap() < out.find("command=\"hset\"").unwrap());
+    }
+
+    #[tokio::test]
+    async fn metrics_endpoint_should_work() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let metrics = Arc::new(Metrics::new());
+        metrics.record_command("hget", 200, Duration::from_micros(50));
+        tokio::spawn(serve_metrics(listener, metrics));
+
+        let get = |request: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut res = String::new();
+            stream.read_to_string(&mut res).await.unwrap();
+            res
+        };
+
+        let res =
+            get("GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(res.contains("content-type: text/plain; version=0.0.4\r\n"));
+        assert!(res.contains("kv_commands_total{command=\"hget\"} 1\n"));
+
+        let res = get("GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        assert!(res.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let res =
+            get("POST /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        assert!(res.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+
+    #[tokio::test]
+    async fn metrics_endpoint_should_close_slow_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let timeout = Duration::from_millis(100);
+        tokio::spawn(serve(listener, Arc::new(Metrics::new()), timeout));
+
+        // 请求头一直没有发完，超时后服务端关闭连接
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\n")
+            .await
+            .unwrap();
+        let mut res = Vec::new();
+        let read = time::timeout(timeout * 10, stream.read_to_end(&mut res)).await;
+        assert!(read.is_ok());
+    }
+}
@@ -0,0 +1,981 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{SinkExt, Stream, StreamExt};
+use http::StatusCode;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
+use tracing::debug;
+
+use crate::{
+    CommandRequest, CommandResponse, KvFrameCodec, Kvpair, ScoredMember, Value, Watch,
+    command_request::RequestData, error::KvError, storage::storage::TableStats, value,
+};
+
+// 可以运行在任意 AsyncRead + AsyncWrite 之上，如 TcpStream 或 TlsClientConnector::connect 的结果
+pub struct KvClient<S> {
+    inner: Framed<S, KvFrameCodec<CommandResponse>>,
+    next_id: u32,
+}
+
+impl<S> KvClient<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    pub fn new(stream: S) -> Self {
+        Self {
+            inner: Framed::new(stream, KvFrameCodec::new()),
+            next_id: 0,
+        }
+    }
+
+    // 发送一个请求并等待对应的响应
+    pub async fn execute(&mut self, mut cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        let id = self.next_id();
+        cmd.id = id;
+        self.inner.send(cmd).await?;
+        loop {
+            let res = self.recv().await?;
+            if res.id == id {
+                return Ok(res);
+            }
+            // 之前被取消的请求的响应，直接丢弃
+            debug!("Drop stale response {}", res.id);
+        }
+    }
+
+    // 流水线：一次发送所有请求而不等待响应，按请求的顺序返回响应
+    pub async fn pipeline(
+        &mut self,
+        cmds: Vec<CommandRequest>,
+    ) -> Result<Vec<CommandResponse>, KvError> {
+        if cmds
+            .iter()
+            .any(|cmd| matches!(cmd.request_data, Some(RequestData::Subscribe(_))))
+        {
+            return Err(KvError::InvalidCommand(
+                "subscribe can not be pipelined".into(),
+            ));
+        }
+
+        let mut ids = Vec::with_capacity(cmds.len());
+        for mut cmd in cmds {
+            cmd.id = self.next_id();
+            ids.push(cmd.id);
+            self.inner.feed(cmd).await?;
+        }
+        SinkExt::<CommandRequest>::flush(&mut self.inner).await?;
+
+        // 服务端并发执行，响应可能乱序到达
+        let mut responses = HashMap::with_capacity(ids.len());
+        while responses.len() < ids.len() {
+            let res = self.recv().await?;
+            if ids.contains(&res.id) {
+                responses.insert(res.id, res);
+            } else {
+                debug!("Drop stale response {}", res.id);
+            }
+        }
+        Ok(ids.iter().filter_map(|id| responses.remove(id)).collect())
+    }
+
+    pub async fn hget(&mut self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let res = self.execute(CommandRequest::new_hget(table, key)).await?;
+        if res.status == StatusCode::NOT_FOUND.as_u16() as u32 {
+            return Ok(None);
+        }
+        Ok(check(res)?.values.into_iter().next())
+    }
+
+    pub async fn hset(
+        &mut self,
+        table: &str,
+        key: &str,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, KvError> {
+        let res = self
+            .execute(CommandRequest::new_hset(table, key, value.into()))
+            .await?;
+        Ok(first_value(check(res)?))
+    }
+
+    pub async fn hgetall(&mut self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        let res = self.execute(CommandRequest::new_hgetall(table)).await?;
+        Ok(check(res)?.pairs)
+    }
+
+    pub async fn hmget(&mut self, table: &str, keys: Vec<String>) -> Result<Vec<Value>, KvError> {
+        let res = self.execute(CommandRequest::new_hmget(table, keys)).await?;
+        Ok(check(res)?.values)
+    }
+
+    pub async fn hmset(&mut self, table: &str, pairs: Vec<Kvpair>) -> Result<Vec<Value>, KvError> {
+        let res = self
+            .execute(CommandRequest::new_hmset(table, pairs))
+            .await?;
+        Ok(check(res)?.values)
+    }
+
+    pub async fn hdel(&mut self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let res = self.execute(CommandRequest::new_hdel(table, key)).await?;
+        Ok(first_value(check(res)?))
+    }
+
+    pub async fn hexists(&mut self, table: &str, key: &str) -> Result<bool, KvError> {
+        let res = self
+            .execute(CommandRequest::new_hexists(table, key))
+            .await?;
+        Ok(first_value(check(res)?) == Some(true.into()))
+    }
+
+    // 原子地加上 delta，返回新值
+    pub async fn hincrby(&mut self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        let res = self
+            .execute(CommandRequest::new_hincrby(table, key, delta))
+            .await?;
+        first_id(check(res)?)
+    }
+
+    pub async fn hincrbyfloat(
+        &mut self,
+        table: &str,
+        key: &str,
+        delta: f64,
+    ) -> Result<f64, KvError> {
+        let res = self
+            .execute(CommandRequest::new_hincrbyfloat(table, key, delta))
+            .await?;
+        match first_value(check(res)?) {
+            Some(v) => f64::try_from(&v),
+            None => Err(KvError::ConvertError("None".into(), "DoubleValue")),
+        }
+    }
+
+    // key 不存在时才写入，返回是否写入
+    pub async fn hsetnx(
+        &mut self,
+        table: &str,
+        key: &str,
+        value: impl Into<Value>,
+    ) -> Result<bool, KvError> {
+        let res = self
+            .execute(CommandRequest::new_hsetnx(table, key, value.into()))
+            .await?;
+        Ok(first_value(check(res)?) == Some(true.into()))
+    }
+
+    // 返回是否写入以及 key 当前的值
+    pub async fn cas(
+        &mut self,
+        table: &str,
+        key: &str,
+        expected: Option<Value>,
+        value: impl Into<Value>,
+    ) -> Result<(bool, Option<Value>), KvError> {
+        let res = self
+            .execute(CommandRequest::new_cas(table, key, expected, value.into()))
+            .await?;
+        let mut values = check(res)?.values.into_iter();
+        let swapped = values.next() == Some(true.into());
+        Ok((swapped, values.next().filter(|v| v.value.is_some())))
+    }
+
+    // 按 key 的顺序返回一页数据以及下一页的游标，游标为 None 表示已经遍历完
+    pub async fn hscan(
+        &mut self,
+        table: &str,
+        cursor: &str,
+        limit: u32,
+    ) -> Result<(Vec<Kvpair>, Option<String>), KvError> {
+        self.hscan_prefix(table, "", cursor, limit).await
+    }
+
+    pub async fn hscan_prefix(
+        &mut self,
+        table: &str,
+        prefix: &str,
+        cursor: &str,
+        limit: u32,
+    ) -> Result<(Vec<Kvpair>, Option<String>), KvError> {
+        let res = self
+            .execute(CommandRequest::new_hscan_prefix(
+                table, prefix, cursor, limit,
+            ))
+            .await?;
+        let res = check(res)?;
+        Ok((res.pairs, Some(res.cursor).filter(|c| !c.is_empty())))
+    }
+
+    pub async fn list_tables(&mut self) -> Result<Vec<String>, KvError> {
+        let res = self.execute(CommandRequest::new_list_tables()).await?;
+        strings(check(res)?)
+    }
+
+    // 返回删除的 key 的数量
+    pub async fn drop_table(&mut self, table: &str) -> Result<i64, KvError> {
+        let res = self.execute(CommandRequest::new_drop_table(table)).await?;
+        first_id(check(res)?)
+    }
+
+    // 表不存在时返回 None
+    pub async fn table_info(&mut self, table: &str) -> Result<Option<TableStats>, KvError> {
+        let res = self.execute(CommandRequest::new_table_info(table)).await?;
+        if res.status == StatusCode::NOT_FOUND.as_u16() as u32 {
+            return Ok(None);
+        }
+        let mut stats = TableStats::default();
+        for pair in check(res)?.pairs {
+            let n = pair.value.as_ref().map_or(Ok(0), i64::try_from)? as u64;
+            match pair.key.as_str() {
+                "keys" => stats.keys = n,
+                "bytes" => stats.bytes = n,
+                _ => {}
+            }
+        }
+        Ok(Some(stats))
+    }
+
+    // 原子执行多个命令，返回每个命令的响应；watch 条件不满足或命令失败时返回 409
+    pub async fn transaction(
+        &mut self,
+        commands: Vec<CommandRequest>,
+        watches: Vec<Watch>,
+    ) -> Result<Vec<CommandResponse>, KvError> {
+        let res = self
+            .execute(CommandRequest::new_transaction(commands, watches))
+            .await?;
+        Ok(check(res)?.responses)
+    }
+
+    // 插入到列表的头部，返回列表的长度
+    pub async fn lpush(
+        &mut self,
+        table: &str,
+        key: &str,
+        values: Vec<Value>,
+    ) -> Result<i64, KvError> {
+        let res = self
+            .execute(CommandRequest::new_lpush(table, key, values))
+            .await?;
+        first_id(check(res)?)
+    }
+
+    pub async fn rpush(
+        &mut self,
+        table: &str,
+        key: &str,
+        values: Vec<Value>,
+    ) -> Result<i64, KvError> {
+        let res = self
+            .execute(CommandRequest::new_rpush(table, key, values))
+            .await?;
+        first_id(check(res)?)
+    }
+
+    // 弹出最多 count 个元素，列表为空时返回空的 Vec
+    pub async fn lpop(
+        &mut self,
+        table: &str,
+        key: &str,
+        count: u32,
+    ) -> Result<Vec<Value>, KvError> {
+        let res = self
+            .execute(CommandRequest::new_lpop(table, key, count))
+            .await?;
+        Ok(check(res)?.values)
+    }
+
+    pub async fn rpop(
+        &mut self,
+        table: &str,
+        key: &str,
+        count: u32,
+    ) -> Result<Vec<Value>, KvError> {
+        let res = self
+            .execute(CommandRequest::new_rpop(table, key, count))
+            .await?;
+        Ok(check(res)?.values)
+    }
+
+    pub async fn lrange(
+        &mut self,
+        table: &str,
+        key: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<Value>, KvError> {
+        let res = self
+            .execute(CommandRequest::new_lrange(table, key, start, stop))
+            .await?;
+        Ok(check(res)?.values)
+    }
+
+    // 返回新添加的成员数量
+    pub async fn sadd(
+        &mut self,
+        table: &str,
+        key: &str,
+        members: Vec<String>,
+    ) -> Result<i64, KvError> {
+        let res = self
+            .execute(CommandRequest::new_sadd(table, key, members))
+            .await?;
+        first_id(check(res)?)
+    }
+
+    pub async fn srem(
+        &mut self,
+        table: &str,
+        key: &str,
+        members: Vec<String>,
+    ) -> Result<i64, KvError> {
+        let res = self
+            .execute(CommandRequest::new_srem(table, key, members))
+            .await?;
+        first_id(check(res)?)
+    }
+
+    pub async fn smembers(&mut self, table: &str, key: &str) -> Result<Vec<String>, KvError> {
+        let res = self
+            .execute(CommandRequest::new_smembers(table, key))
+            .await?;
+        strings(check(res)?)
+    }
+
+    pub async fn sinter(&mut self, table: &str, keys: Vec<String>) -> Result<Vec<String>, KvError> {
+        let res = self
+            .execute(CommandRequest::new_sinter(table, keys))
+            .await?;
+        strings(check(res)?)
+    }
+
+    // 返回新添加的成员数量，已经存在的成员只更新分数
+    pub async fn zadd(
+        &mut self,
+        table: &str,
+        key: &str,
+        members: Vec<ScoredMember>,
+    ) -> Result<i64, KvError> {
+        let res = self
+            .execute(CommandRequest::new_zadd(table, key, members))
+            .await?;
+        first_id(check(res)?)
+    }
+
+    pub async fn zrem(
+        &mut self,
+        table: &str,
+        key: &str,
+        members: Vec<String>,
+    ) -> Result<i64, KvError> {
+        let res = self
+            .execute(CommandRequest::new_zrem(table, key, members))
+            .await?;
+        first_id(check(res)?)
+    }
+
+    pub async fn zrange(
+        &mut self,
+        table: &str,
+        key: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<ScoredMember>, KvError> {
+        let res = self
+            .execute(CommandRequest::new_zrange(table, key, start, stop))
+            .await?;
+        scored_members(check(res)?)
+    }
+
+    pub async fn zrangebyscore(
+        &mut self,
+        table: &str,
+        key: &str,
+        min: f64,
+        max: f64,
+        limit: u32,
+    ) -> Result<Vec<ScoredMember>, KvError> {
+        let res = self
+            .execute(CommandRequest::new_zrangebyscore(
+                table, key, min, max, limit,
+            ))
+            .await?;
+        scored_members(check(res)?)
+    }
+
+    // 成员不存在时返回 None
+    pub async fn zrank(
+        &mut self,
+        table: &str,
+        key: &str,
+        member: &str,
+    ) -> Result<Option<i64>, KvError> {
+        let res = self
+            .execute(CommandRequest::new_zrank(table, key, member))
+            .await?;
+        if res.status == StatusCode::NOT_FOUND.as_u16() as u32 {
+            return Ok(None);
+        }
+        first_id(check(res)?).map(Some)
+    }
+
+    // 发布数据到主题，返回收到数据的订阅者数量
+    pub async fn publish(&mut self, topic: &str, data: Vec<Value>) -> Result<i64, KvError> {
+        let res = self
+            .execute(CommandRequest::new_publish(topic, data))
+            .await?;
+        first_id(check(res)?)
+    }
+
+    pub async fn unsubscribe(&mut self, topic: &str, id: u32) -> Result<(), KvError> {
+        let res = self
+            .execute(CommandRequest::new_unsubscribe(topic, id))
+            .await?;
+        check(res).map(|_| ())
+    }
+
+    // id 0 表示未设置，跳过
+    fn next_id(&mut self) -> u32 {
+        self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+        self.next_id
+    }
+
+    async fn recv(&mut self) -> Result<CommandResponse, KvError> {
+        match self.inner.next().await {
+            Some(res) => res,
+            None => Err(KvError::Internal("connection closed".into())),
+        }
+    }
+
+    // 订阅会独占这个连接，直到在其它连接上取消订阅
+    pub async fn subscribe(mut self, topic: &str) -> Result<Subscription<S>, KvError> {
+        let res = self.execute(CommandRequest::new_subscribe(topic)).await?;
+        let id = first_id(check(res)?)? as u32;
+        Ok(Subscription {
+            id,
+            inner: self.inner,
+        })
+    }
+}
+
+// 订阅返回的数据流，每一项是一次 Publish 的数据
+pub struct Subscription<S> {
+    id: u32,
+    inner: Framed<S, KvFrameCodec<CommandResponse>>,
+}
+
+impl<S> Subscription<S> {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl<S> Stream for Subscription<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    type Item = Result<Vec<Value>, KvError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner
+            .poll_next_unpin(cx)
+            .map(|res| res.map(|res| res.and_then(check).map(|res| res.values)))
+    }
+}
+
+// 非 200 的响应转换成错误
+pub(crate) fn check(res: CommandResponse) -> Result<CommandResponse, KvError> {
+    if res.status == StatusCode::OK.as_u16() as u32 {
+        Ok(res)
+    } else {
+        Err(KvError::ServerError(res.status, res.message))
+    }
+}
+
+// 服务端用 Value::default() 表示不存在的旧值
+pub(crate) fn first_value(res: CommandResponse) -> Option<Value> {
+    res.values.into_iter().next().filter(|v| v.value.is_some())
+}
+
+fn strings(res: CommandResponse) -> Result<Vec<String>, KvError> {
+    res.values
+        .into_iter()
+        .map(|v| match v.value {
+            Some(value::Value::StringValue(s)) => Ok(s),
+            _ => Err(KvError::ConvertError(format!("{:?}", v), "StringValue")),
+        })
+        .collect()
+}
+
+// 有序集合的成员和分数以 pair 返回
+fn scored_members(res: CommandResponse) -> Result<Vec<ScoredMember>, KvError> {
+    res.pairs
+        .into_iter()
+        .map(|pair| {
+            let score = pair.value.as_ref().map_or(Ok(0.0), f64::try_from)?;
+            Ok(ScoredMember::new(&pair.key, score))
+        })
+        .collect()
+}
+
+fn first_id(res: CommandResponse) -> Result<i64, KvError> {
+    match first_value(res).and_then(|v| v.value) {
+        Some(value::Value::Int64Value(id)) => Ok(id),
+        v => Err(KvError::ConvertError(format!("{:?}", v), "Int64Value")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+    use anyhow::Result;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::{
+        AclConfig, BlockingStorage, KvServerStream, MemTable, Metrics, Service, ServiceInner,
+        TablePermissions,
+    };
+
+    #[tokio::test]
+    async fn client_should_work() -> Result<()> {
+        let addr = start_server().await?;
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = KvClient::new(stream);
+
+        assert_eq!(client.hset("t1", "k1", "v1").await?, None);
+        assert_eq!(client.hset("t1", "k1", "v11").await?, Some("v1".into()));
+        assert_eq!(client.hget("t1", "k1").await?, Some("v11".into()));
+        assert_eq!(client.hget("t1", "k2").await?, None);
+
+        client
+            .hmset(
+                "t1",
+                vec![Kvpair::new("k2", 2.into()), Kvpair::new("k3", true.into())],
+            )
+            .await?;
+        let values = client
+            .hmget("t1", vec!["k2".into(), "k3".into(), "k4".into()])
+            .await?;
+        assert_eq!(values, vec![2.into(), true.into(), Value::default()]);
+
+        let mut pairs = client.hgetall("t1").await?;
+        pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(
+            pairs,
+            vec![
+                Kvpair::new("k1", "v11".into()),
+                Kvpair::new("k2", 2.into()),
+                Kvpair::new("k3", true.into()),
+            ]
+        );
+
+        assert!(client.hexists("t1", "k3").await?);
+        assert_eq!(client.hdel("t1", "k3").await?, Some(true.into()));
+        assert!(!client.hexists("t1", "k3").await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_should_return_server_error() -> Result<()> {
+        let addr = start_server().await?;
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = KvClient::new(stream);
+
+        let res = client.execute(CommandRequest::default()).await?;
+        assert_eq!(
+            res.status,
+            StatusCode::INTERNAL_SERVER_ERROR.as_u16() as u32
+        );
+        let res = client.hmset("t1", vec![Kvpair::default()]).await;
+        assert!(matches!(res, Err(KvError::ServerError(400, _))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_pub_sub_should_work() -> Result<()> {
+        let addr = start_server().await?;
+        let mut client = KvClient::new(TcpStream::connect(addr).await?);
+        let subscriber = KvClient::new(TcpStream::connect(addr).await?);
+
+        let mut subscription = subscriber.subscribe("lobby").await?;
+        let id = subscription.id();
+
+        let data: Vec<Value> = vec!["hello".into(), 42.into()];
+        assert_eq!(client.publish("lobby", data.clone()).await?, 1);
+        assert_eq!(subscription.next().await.unwrap()?, data);
+
+        client.unsubscribe("lobby", id).await?;
+        assert!(subscription.next().await.is_none());
+
+        let res = client.unsubscribe("lobby", id).await;
+        assert!(matches!(res, Err(KvError::ServerError(404, _))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn disconnected_subscriber_should_be_removed() -> Result<()> {
+        let addr = start_server().await?;
+        let mut client = KvClient::new(TcpStream::connect(addr).await?);
+        let subscriber = KvClient::new(TcpStream::connect(addr).await?);
+
+        let subscription = subscriber.subscribe("lobby").await?;
+        let id = subscription.id();
+        drop(subscription);
+
+        // 服务端发现连接断开后取消订阅，不需要等到下一次发布失败
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(client.publish("lobby", vec![1.into()]).await?, 0);
+        let res = client.unsubscribe("lobby", id).await;
+        assert!(matches!(res, Err(KvError::ServerError(404, _))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_pipeline_should_work() -> Result<()> {
+        let addr = start_server().await?;
+        let mut client = KvClient::new(TcpStream::connect(addr).await?);
+
+        let mut cmds: Vec<_> = (0..50)
+            .map(|i| CommandRequest::new_hset("t1", &format!("k{}", i), i.into()))
+            .collect();
+        cmds.push(CommandRequest::new_hgetall("t1"));
+        cmds.push(CommandRequest::default());
+        let res = client.pipeline(cmds).await?;
+
+        assert_eq!(res.len(), 52);
+        assert!(res[..50].iter().all(|res| res.status == 200));
+        assert_eq!(res[50].id + 1, res[51].id);
+        assert_eq!(res[51].status, 500);
+        // 后续请求不受影响
+        assert_eq!(client.hget("t1", "k7").await?, Some(7.into()));
+
+        let res = client
+            .pipeline(vec![CommandRequest::new_subscribe("lobby")])
+            .await;
+        assert!(matches!(res, Err(KvError::InvalidCommand(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_atomic_commands_should_work() -> Result<()> {
+        let addr = start_server().await?;
+        let mut client = KvClient::new(TcpStream::connect(addr).await?);
+
+        assert_eq!(client.hincrby("t1", "n", 5).await?, 5);
+        assert_eq!(client.hincrby("t1", "n", -2).await?, 3);
+        assert_eq!(client.hincrbyfloat("t1", "n", 0.5).await?, 3.5);
+        assert!(client.hsetnx("t1", "k1", "v1").await?);
+        assert!(!client.hsetnx("t1", "k1", "v2").await?);
+        let res = client.hincrby("t1", "k1", 1).await;
+        assert!(matches!(res, Err(KvError::ServerError(400, _))));
+
+        let (swapped, current) = client.cas("t1", "k1", Some("v0".into()), "v2").await?;
+        assert!(!swapped);
+        assert_eq!(current, Some("v1".into()));
+        let (swapped, _) = client.cas("t1", "k1", current, "v2").await?;
+        assert!(swapped);
+        assert_eq!(client.hget("t1", "k1").await?, Some("v2".into()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_hscan_should_work() -> Result<()> {
+        let addr = start_server().await?;
+        let mut client = KvClient::new(TcpStream::connect(addr).await?);
+        for key in ["k3", "k1", "k2", "other"] {
+            client.hset("t1", key, key).await?;
+        }
+
+        let (pairs, cursor) = client.hscan_prefix("t1", "k", "", 2).await?;
+        assert_eq!(
+            pairs,
+            [
+                Kvpair::new("k1", "k1".into()),
+                Kvpair::new("k2", "k2".into())
+            ]
+        );
+        assert_eq!(cursor.as_deref(), Some("k2"));
+        let (pairs, cursor) = client.hscan_prefix("t1", "k", "k2", 2).await?;
+        assert_eq!(pairs, [Kvpair::new("k3", "k3".into())]);
+        assert_eq!(cursor, None);
+
+        let (pairs, cursor) = client.hscan("t1", "", 0).await?;
+        assert_eq!(pairs.len(), 4);
+        assert_eq!(cursor, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_table_commands_should_work() -> Result<()> {
+        let addr = start_server().await?;
+        let mut client = KvClient::new(TcpStream::connect(addr).await?);
+        client.hset("t1", "k1", "v1").await?;
+        client.hset("t2", "k1", "v1").await?;
+        client.hset("t2", "k2", "v2").await?;
+
+        assert_eq!(client.list_tables().await?, ["t1", "t2"]);
+        let stats = client.table_info("t2").await?.unwrap();
+        assert_eq!(stats.keys, 2);
+        assert!(stats.bytes > 0);
+        assert_eq!(client.drop_table("t2").await?, 2);
+        assert_eq!(client.table_info("t2").await?, None);
+        assert_eq!(client.list_tables().await?, ["t1"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_transaction_should_work() -> Result<()> {
+        let addr = start_server().await?;
+        let mut client = KvClient::new(TcpStream::connect(addr).await?);
+        client.hset("t1", "k1", 1).await?;
+
+        let cmds = vec![
+            CommandRequest::new_hset("t1", "k1", 2.into()),
+            CommandRequest::new_hset("t2", "k1", 1.into()),
+        ];
+        let watches = vec![Watch::new("t1", "k1", Some(1.into()))];
+        let res = client.transaction(cmds.clone(), watches.clone()).await?;
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].values, &[1.into()]);
+        assert_eq!(client.hget("t2", "k1").await?, Some(1.into()));
+
+        let res = client.transaction(cmds, watches).await;
+        assert!(matches!(res, Err(KvError::ServerError(409, _))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_should_tag_responses_with_request_id() -> Result<()> {
+        let addr = start_server().await?;
+        let mut client = KvClient::new(TcpStream::connect(addr).await?);
+        for (key, value) in [("a", 1), ("b", 2), ("c", 3)] {
+            client.hset("t1", key, value).await?;
+        }
+        let stream = TcpStream::connect(addr).await?;
+        let mut framed = Framed::new(stream, KvFrameCodec::<CommandResponse>::new());
+
+        let expected = HashMap::from([(7, 1.into()), (3, 2.into()), (42, 3.into())]);
+        for (id, key) in [(7, "a"), (3, "b"), (42, "c")] {
+            let mut cmd = CommandRequest::new_hget("t1", key);
+            cmd.id = id;
+            framed.feed(cmd).await?;
+        }
+        SinkExt::<CommandRequest>::flush(&mut framed).await?;
+
+        // 每个响应的 id 对应各自请求的结果
+        let mut got = HashMap::new();
+        for _ in 0..expected.len() {
+            let res = framed.next().await.unwrap()?;
+            got.insert(res.id, res.values[0].clone());
+        }
+        assert_eq!(got, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn slow_request_should_not_block_later_requests() -> Result<()> {
+        // slow 表的读取在返回前等待一段时间
+        let service: Service = ServiceInner::new(BlockingStorage::new(MemTable::new()))
+            .fn_intercept_async(|cmd| {
+                Box::pin(async move {
+                    if matches!(&cmd.request_data, Some(RequestData::Hget(p)) if p.table == "slow")
+                    {
+                        tokio::time::sleep(Duration::from_millis(300)).await;
+                    }
+                    None
+                })
+            })
+            .into();
+        let addr = start_server_with(service).await?;
+        let stream = TcpStream::connect(addr).await?;
+        let mut framed = Framed::new(stream, KvFrameCodec::<CommandResponse>::new());
+
+        for (id, table) in [(1, "slow"), (2, "fast")] {
+            let mut cmd = CommandRequest::new_hget(table, "k1");
+            cmd.id = id;
+            framed.feed(cmd).await?;
+        }
+        SinkExt::<CommandRequest>::flush(&mut framed).await?;
+
+        // 后发送的快请求先返回
+        let first = framed.next().await.unwrap()?;
+        assert_eq!(first.id, 2);
+        let second = framed.next().await.unwrap()?;
+        assert_eq!(second.id, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_collections_should_work() -> Result<()> {
+        let addr = start_server().await?;
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = KvClient::new(stream);
+
+        assert_eq!(
+            client
+                .rpush("t1", "queue", vec!["a".into(), "b".into()])
+                .await?,
+            2
+        );
+        assert_eq!(client.lpush("t1", "queue", vec!["z".into()]).await?, 3);
+        assert_eq!(
+            client.lrange("t1", "queue", 0, -1).await?,
+            vec!["z".into(), "a".into(), Value::from("b")]
+        );
+        assert_eq!(
+            client.lpop("t1", "queue", 2).await?,
+            vec!["z".into(), Value::from("a")]
+        );
+        assert_eq!(client.rpop("t1", "queue", 1).await?, vec![Value::from("b")]);
+
+        let members = vec!["x".to_string(), "y".to_string()];
+        assert_eq!(client.sadd("t1", "s1", members).await?, 2);
+        assert_eq!(client.sadd("t1", "s2", vec!["y".into()]).await?, 1);
+        assert_eq!(
+            client.sinter("t1", vec!["s1".into(), "s2".into()]).await?,
+            ["y"]
+        );
+        assert_eq!(client.srem("t1", "s1", vec!["y".into()]).await?, 1);
+        assert_eq!(client.smembers("t1", "s1").await?, ["x"]);
+
+        let members = vec![
+            ScoredMember::new("alice", 2.0),
+            ScoredMember::new("bob", 1.0),
+        ];
+        assert_eq!(client.zadd("t1", "board", members).await?, 2);
+        assert_eq!(
+            client.zrange("t1", "board", 0, -1).await?,
+            vec![
+                ScoredMember::new("bob", 1.0),
+                ScoredMember::new("alice", 2.0)
+            ]
+        );
+        assert_eq!(
+            client.zrangebyscore("t1", "board", 1.5, 10.0, 0).await?,
+            vec![ScoredMember::new("alice", 2.0)]
+        );
+        assert_eq!(client.zrank("t1", "board", "alice").await?, Some(1));
+        assert_eq!(client.zrank("t1", "board", "carol").await?, None);
+        assert_eq!(client.zrem("t1", "board", vec!["bob".into()]).await?, 1);
+
+        // 对错误类型的 key 执行命令返回 400
+        let err = client
+            .lpush("t1", "s1", vec!["a".into()])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, KvError::ServerError(400, _)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_should_fire_after_send_once_written() -> Result<()> {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let service: Service = ServiceInner::new(BlockingStorage::new(MemTable::new()))
+            .fn_after_send(move || {
+                let _ = tx.send(());
+            })
+            .into();
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(KvServerStream::new(server, service).process());
+
+        let mut client = KvClient::new(client);
+        client.hset("t1", "k1", "v1").await?;
+        client.hget("t1", "k1").await?;
+        // 每写出一个响应触发一次
+        for _ in 0..2 {
+            tokio::time::timeout(Duration::from_secs(1), rx.recv())
+                .await?
+                .unwrap();
+        }
+        assert!(rx.try_recv().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_should_record_metrics() -> Result<()> {
+        let metrics = Arc::new(Metrics::new());
+        let service: Service = ServiceInner::new(BlockingStorage::new(MemTable::new()))
+            .with_metrics(metrics.clone())
+            .into();
+        let (client, server) = tokio::io::duplex(4096);
+        let connection = service.connection();
+        let handle = tokio::spawn(async move {
+            let _connection = connection;
+            KvServerStream::new(server, service).process().await
+        });
+
+        let mut client = KvClient::new(client);
+        client.hset("t1", "k1", "v1").await?;
+        client.hget("t1", "k2").await?;
+        assert_eq!(metrics.active_connections(), 1);
+        let out = metrics.render();
+        assert!(out.contains("kv_commands_total{command=\"hget\"} 1\n"));
+        assert!(out.contains("kv_command_errors_total{command=\"hget\",status=\"404\"} 1\n"));
+        assert!(out.contains("kv_command_duration_seconds_count{command=\"hset\"} 1\n"));
+        assert!(!out.contains("kv_received_bytes_total{compressed=\"false\"} 0\n"));
+
+        // 连接关闭后活跃连接数归零
+        drop(client);
+        handle.await??;
+        assert_eq!(metrics.active_connections(), 0);
+        assert!(metrics.render().contains("kv_connections_total 1\n"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_should_authorize_by_identity() -> Result<()> {
+        let mut acl = AclConfig::default();
+        acl.identities.insert(
+            "awesome-client".into(),
+            TablePermissions {
+                read: vec!["*".into()],
+                write: vec!["t1".into()],
+            },
+        );
+        let service: Service = ServiceInner::new(BlockingStorage::new(MemTable::new()))
+            .with_acl(acl)
+            .into();
+
+        let (client, server) = tokio::io::duplex(4096);
+        let stream = KvServerStream::new(server, service.clone())
+            .with_identity(Some("awesome-client".into()));
+        tokio::spawn(stream.process());
+        let mut client = KvClient::new(client);
+        assert_eq!(client.hset("t1", "k1", "v1").await?, None);
+        assert_eq!(client.hget("t2", "k1").await?, None);
+        let err = client.hset("t2", "k1", "v1").await.unwrap_err();
+        assert!(matches!(err, KvError::ServerError(403, _)));
+
+        // 没有客户端证书的连接使用默认权限
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(KvServerStream::new(server, service).process());
+        let mut client = KvClient::new(client);
+        let err = client.hget("t1", "k1").await.unwrap_err();
+        assert!(matches!(err, KvError::ServerError(403, _)));
+        Ok(())
+    }
+
+    async fn start_server() -> Result<SocketAddr> {
+        start_server_with(ServiceInner::new(BlockingStorage::new(MemTable::new())).into()).await
+    }
+
+    async fn start_server_with(service: Service) -> Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let stream = KvServerStream::new(stream, service.clone());
+                tokio::spawn(stream.process());
+            }
+        });
+
+        Ok(addr)
+    }
+}
@@ -1,14 +1,20 @@
-use std::io::{Read, Write};
+use std::{
+    io::{Read, Write},
+    marker::PhantomData,
+    sync::Arc,
+};
 
 use bytes::{Buf, BufMut, BytesMut};
 use flate2::{Compression, read::GzDecoder, write::GzEncoder};
 use prost::Message;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_util::codec::{Decoder, Encoder};
 
-use crate::{CommandRequest, CommandResponse, error::KvError};
+use crate::{CommandRequest, CommandResponse, Metrics, error::KvError};
 //长度占用4个字节
 pub const LEN_LEN: usize = 4;
-//长度占31bit，最大frame为2GB
-const MAX_FRAME: usize = 2 * 1024 * 1024 * 1024;
+//长度占31bit，但单个frame（包括解压后的数据）最大限制为16MB，避免恶意数据耗尽内存
+pub const MAX_FRAME: usize = 16 * 1024 * 1024;
 //payload超过1436字节时，进行压缩
 const COMPRESSION_LIMIT: usize = 1436;
 
@@ -21,15 +27,17 @@ where
 {
     fn encode_frame(&self, buf: &mut BytesMut) -> Result<(), KvError> {
         let size: usize = self.encoded_len();
-        if size >= MAX_FRAME {
-            return Err(KvError::FrameError);
+        if size > MAX_FRAME {
+            return Err(KvError::FrameTooLarge(size));
         }
+        // buf 中可能已经有之前写入的 frame，只处理本次写入的部分
+        let start = buf.len();
         buf.put_u32(size as _);
         if size > COMPRESSION_LIMIT {
             let mut buf1 = Vec::with_capacity(size);
             self.encode(&mut buf1)?;
-            let palyload = buf.split_off(LEN_LEN);
-            buf.clear();
+            let palyload = buf.split_off(start + LEN_LEN);
+            buf.truncate(start);
             let mut encoder = GzEncoder::new(palyload.writer(), Compression::default());
             encoder.write_all(&buf1[..])?;
             let payload = encoder.finish()?.into_inner();
@@ -41,32 +49,131 @@ where
         Ok(())
     }
 
-    fn decode_frame(buf: &mut BytesMut) -> Result<Self, KvError> {
-        let header = buf.get_u32() as usize;
-        let (len, compressed) = decode_header(header);
-        if compressed {
-            let mut decoder = GzDecoder::new(&buf[..len]);
-            let mut buf1 = Vec::with_capacity(len * 2);
-            decoder.read_to_end(&mut buf1)?;
-            buf.advance(len);
-            Ok(Self::decode(&buf1[..buf1.len()])?)
-        } else {
-            let msg = Self::decode(&buf[..len])?;
-            buf.advance(len);
-            Ok(msg)
+    // 数据不足一个完整 frame 时返回 Ok(None)，并且不消耗 buf 中的数据
+    fn decode_frame(buf: &mut BytesMut) -> Result<Option<Self>, KvError> {
+        if buf.len() < LEN_LEN {
+            return Ok(None);
+        }
+        let header = (&buf[..LEN_LEN]).get_u32() as usize;
+        let (len, compressed) = decode_header(header)?;
+        if buf.len() < LEN_LEN + len {
+            return Ok(None);
         }
+        buf.advance(LEN_LEN);
+        let payload = buf.split_to(len);
+        let msg = if compressed {
+            let data = decompress(&payload, MAX_FRAME)?;
+            Self::decode(&data[..])
+        } else {
+            Self::decode(payload)
+        };
+        msg.map(Some)
+            .map_err(|e| KvError::InvalidFrame(e.to_string()))
     }
 }
 
-#[allow(unused)]
-fn decode_header(header: usize) -> (usize, bool) {
+// 解析长度头，长度超过 MAX_FRAME 时直接报错，不再等待后续数据
+fn decode_header(header: usize) -> Result<(usize, bool), KvError> {
     let len = header & !COMPRESSION_BIT;
     let compressed = header & COMPRESSION_BIT == COMPRESSION_BIT;
-    (len, compressed)
+    if len > MAX_FRAME {
+        return Err(KvError::FrameTooLarge(len));
+    }
+    Ok((len, compressed))
+}
+
+// 解压时限制输出大小，防止压缩炸弹
+fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, KvError> {
+    let mut decoder = GzDecoder::new(data).take(limit as u64 + 1);
+    let mut buf = Vec::with_capacity(data.len() * 2);
+    decoder
+        .read_to_end(&mut buf)
+        .map_err(|e| KvError::InvalidFrame(e.to_string()))?;
+    if buf.len() > limit {
+        return Err(KvError::FrameTooLarge(buf.len()));
+    }
+    Ok(buf)
 }
 
 impl FrameCoder for CommandRequest {}
 impl FrameCoder for CommandResponse {}
+
+// 基于 FrameCoder 的 tokio-util 编解码器，D 为解码出的消息类型
+// 服务端使用 KvFrameCodec<CommandRequest>，客户端使用 KvFrameCodec<CommandResponse>
+pub struct KvFrameCodec<D> {
+    // 配置后统计收发的字节数，区分压缩和未压缩的 frame
+    metrics: Option<Arc<Metrics>>,
+    _marker: PhantomData<D>,
+}
+
+impl<D> KvFrameCodec<D> {
+    pub fn new() -> Self {
+        Self {
+            metrics: None,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn with_metrics(mut self, metrics: Option<Arc<Metrics>>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+}
+
+impl<D> Default for KvFrameCodec<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: FrameCoder> Decoder for KvFrameCodec<D> {
+    type Item = D;
+    type Error = KvError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let header = (self.metrics.is_some() && src.len() >= LEN_LEN)
+            .then(|| (&src[..LEN_LEN]).get_u32() as usize);
+        let msg = D::decode_frame(src)?;
+        // 解码出完整的 frame 后才统计
+        if let Some(metrics) = self.metrics.as_ref()
+            && let Some(header) = header
+            && msg.is_some()
+        {
+            let (len, compressed) = decode_header(header)?;
+            metrics.record_received(LEN_LEN + len, compressed);
+        }
+        Ok(msg)
+    }
+}
+
+impl<D, E: FrameCoder> Encoder<E> for KvFrameCodec<D> {
+    type Error = KvError;
+
+    fn encode(&mut self, item: E, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let start = dst.len();
+        item.encode_frame(dst)?;
+        if let Some(metrics) = self.metrics.as_ref() {
+            let header = (&dst[start..start + LEN_LEN]).get_u32() as usize;
+            metrics.record_sent(dst.len() - start, header & COMPRESSION_BIT != 0);
+        }
+        Ok(())
+    }
+}
+
+// 从 AsyncRead 中读取一个完整的 frame（包含长度头）放入 buf
+pub async fn read_frame<S>(stream: &mut S, buf: &mut BytesMut) -> Result<(), KvError>
+where
+    S: AsyncRead + Unpin + Send,
+{
+    let header = stream.read_u32().await? as usize;
+    let (len, _) = decode_header(header)?;
+    buf.reserve(LEN_LEN + len);
+    buf.put_u32(header as _);
+    let start = buf.len();
+    buf.resize(start + len, 0);
+    stream.read_exact(&mut buf[start..]).await?;
+    Ok(())
+}
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -83,7 +190,7 @@ mod tests {
         // 最高位没设置
         assert_eq!(is_compressed(&buf), false);
 
-        let cmd1 = CommandRequest::decode_frame(&mut buf).unwrap();
+        let cmd1 = CommandRequest::decode_frame(&mut buf).unwrap().unwrap();
         assert_eq!(cmd, cmd1);
     }
 
@@ -98,7 +205,7 @@ mod tests {
         // 最高位没设置
         assert_eq!(is_compressed(&buf), false);
 
-        let res1 = CommandResponse::decode_frame(&mut buf).unwrap();
+        let res1 = CommandResponse::decode_frame(&mut buf).unwrap().unwrap();
         assert_eq!(res, res1);
     }
 
@@ -113,10 +220,183 @@ mod tests {
         // 最高位设置了
         assert_eq!(is_compressed(&buf), true);
 
-        let res1 = CommandResponse::decode_frame(&mut buf).unwrap();
+        let res1 = CommandResponse::decode_frame(&mut buf).unwrap().unwrap();
         assert_eq!(res, res1);
     }
 
+    #[test]
+    fn codec_should_handle_partial_and_multiple_frames() {
+        let mut codec = KvFrameCodec::<CommandResponse>::new();
+        let mut buf = BytesMut::new();
+
+        let small: CommandResponse = Value::from("hello").into();
+        let large: CommandResponse =
+            Value::from(Bytes::from(vec![0u8; COMPRESSION_LIMIT + 1])).into();
+        codec.encode(small.clone(), &mut buf).unwrap();
+        codec.encode(large.clone(), &mut buf).unwrap();
+
+        let mut partial = buf.split_to(LEN_LEN + 2);
+        assert_eq!(codec.decode(&mut partial).unwrap(), None);
+        partial.unsplit(buf);
+
+        assert_eq!(codec.decode(&mut partial).unwrap(), Some(small));
+        assert_eq!(codec.decode(&mut partial).unwrap(), Some(large));
+        assert_eq!(codec.decode(&mut partial).unwrap(), None);
+    }
+
+    #[test]
+    fn codec_should_record_bytes() {
+        let metrics = Arc::new(Metrics::new());
+        let mut codec = KvFrameCodec::<CommandResponse>::new().with_metrics(Some(metrics.clone()));
+        let mut buf = BytesMut::new();
+
+        let small: CommandResponse = Value::from("hello").into();
+        let large: CommandResponse =
+            Value::from(Bytes::from(vec![0u8; COMPRESSION_LIMIT + 1])).into();
+        codec.encode(small, &mut buf).unwrap();
+        let small_len = buf.len();
+        codec.encode(large, &mut buf).unwrap();
+        let large_len = buf.len() - small_len;
+
+        // 不完整的 frame 不统计
+        let mut partial = buf.split_to(LEN_LEN + 2);
+        codec.decode(&mut partial).unwrap();
+        partial.unsplit(buf);
+        while codec.decode(&mut partial).unwrap().is_some() {}
+
+        let out = metrics.render();
+        for name in ["kv_sent_bytes_total", "kv_received_bytes_total"] {
+            assert!(out.contains(&format!("{}{{compressed=\"false\"}} {}\n", name, small_len)));
+            assert!(out.contains(&format!("{}{{compressed=\"true\"}} {}\n", name, large_len)));
+        }
+    }
+
+    #[tokio::test]
+    async fn read_frame_should_work() {
+        let mut buf = BytesMut::new();
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        cmd.encode_frame(&mut buf).unwrap();
+
+        let mut stream = &buf[..];
+        let mut data = BytesMut::new();
+        read_frame(&mut stream, &mut data).await.unwrap();
+
+        let cmd1 = CommandRequest::decode_frame(&mut data).unwrap().unwrap();
+        assert_eq!(cmd, cmd1);
+    }
+
+    #[test]
+    fn truncated_frame_should_need_more_data() {
+        let mut buf = BytesMut::new();
+        let res: CommandResponse =
+            Value::from(Bytes::from(vec![1u8; COMPRESSION_LIMIT * 2])).into();
+        res.encode_frame(&mut buf).unwrap();
+        res.encode_frame(&mut buf).unwrap();
+        let frame_len = buf.len() / 2;
+
+        // 任意前缀都不应该 panic，也不应该消耗数据
+        for i in 0..frame_len {
+            let mut partial = BytesMut::from(&buf[..i]);
+            assert_eq!(CommandResponse::decode_frame(&mut partial).unwrap(), None);
+            assert_eq!(partial.len(), i);
+        }
+
+        let mut data = buf.clone();
+        assert_eq!(
+            CommandResponse::decode_frame(&mut data).unwrap(),
+            Some(res.clone())
+        );
+        assert_eq!(data.len(), frame_len);
+        assert_eq!(CommandResponse::decode_frame(&mut data).unwrap(), Some(res));
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn oversize_frame_should_be_rejected() {
+        let mut buf = BytesMut::new();
+        buf.put_u32((MAX_FRAME + 1) as _);
+        assert_eq!(
+            CommandRequest::decode_frame(&mut buf),
+            Err(KvError::FrameTooLarge(MAX_FRAME + 1))
+        );
+
+        let mut buf = BytesMut::new();
+        buf.put_u32(u32::MAX);
+        assert!(matches!(
+            CommandRequest::decode_frame(&mut buf),
+            Err(KvError::FrameTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn corrupt_frame_should_be_rejected() {
+        // 设置了压缩位，但 payload 不是 gzip 数据
+        let mut buf = BytesMut::new();
+        buf.put_u32((4 | COMPRESSION_BIT) as _);
+        buf.put_slice(b"oops");
+        assert!(matches!(
+            CommandRequest::decode_frame(&mut buf),
+            Err(KvError::InvalidFrame(_))
+        ));
+
+        // 未压缩，但 payload 不是合法的 prost 数据
+        let mut buf = BytesMut::new();
+        buf.put_u32(2);
+        buf.put_slice(&[0xff, 0xff]);
+        assert!(matches!(
+            CommandRequest::decode_frame(&mut buf),
+            Err(KvError::InvalidFrame(_))
+        ));
+    }
+
+    #[test]
+    fn decompress_should_respect_limit() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&vec![0u8; 4096]).unwrap();
+        let data = encoder.finish().unwrap();
+
+        assert_eq!(decompress(&data, 4096).unwrap().len(), 4096);
+        assert_eq!(decompress(&data, 1024), Err(KvError::FrameTooLarge(1025)));
+    }
+
+    #[test]
+    fn random_bytes_should_not_panic() {
+        // 简单的 xorshift 伪随机数，保证测试可复现
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let mut codec = KvFrameCodec::<CommandRequest>::new();
+        for _ in 0..1000 {
+            let len = (next() % 64) as usize;
+            let mut buf: BytesMut = (0..len).map(|_| next() as u8).collect();
+            // 一半的样本使用合理的长度头，让 payload 真正进入解码流程
+            if len >= LEN_LEN && next() % 2 == 0 {
+                let mut header = (len - LEN_LEN) as u32;
+                if next() % 2 == 0 {
+                    header |= COMPRESSION_BIT as u32;
+                }
+                buf[..LEN_LEN].copy_from_slice(&header.to_be_bytes());
+            }
+            while let Ok(Some(_)) = codec.decode(&mut buf) {}
+        }
+    }
+
+    #[tokio::test]
+    async fn read_frame_should_reject_oversize_header() {
+        let header = ((MAX_FRAME + 1) as u32).to_be_bytes();
+        let mut stream = &header[..];
+        let mut data = BytesMut::new();
+        assert!(matches!(
+            read_frame(&mut stream, &mut data).await,
+            Err(KvError::FrameTooLarge(_))
+        ));
+        assert!(data.is_empty());
+    }
+
     fn is_compressed(data: &[u8]) -> bool {
         if let &[v] = &data[..1] {
             v >> 7 == 1
@@ -0,0 +1,447 @@
+use std::sync::Arc;
+
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{HeaderName, HeaderValue, Method},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use http::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::net::TcpListener;
+use tower::ServiceBuilder;
+use tower_http::{
+    LatencyUnit,
+    compression::CompressionLayer,
+    cors::{AllowOrigin, CorsLayer},
+    trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer},
+};
+use tracing::Level;
+
+use crate::{
+    CommandRequest, CommandResponse, ListValue, Service, Value, error::KvError,
+    storage::AsyncStorage, value,
+};
+
+#[derive(Debug, Deserialize)]
+struct PutParams {
+    // 过期时间，单位毫秒
+    ttl: Option<u64>,
+}
+
+// 网关的连接没有 TLS，请求以配置的身份经过 ACL 授权
+struct Gateway<S> {
+    service: Service<S>,
+    identity: Option<Arc<str>>,
+}
+
+impl<S> Clone for Gateway<S> {
+    fn clone(&self) -> Self {
+        Self {
+            service: self.service.clone(),
+            identity: self.identity.clone(),
+        }
+    }
+}
+
+// HTTP/JSON 网关，请求转换成 CommandRequest 后经过同一个 Service 执行
+// 响应的 status 作为 HTTP 的状态码，出错时 body 为 {"error": message}
+// cors_origins 为空时不允许跨域访问，"*" 表示任意 Origin
+pub fn gateway_router<S>(
+    service: Service<S>,
+    identity: Option<String>,
+    cors_origins: &[String],
+) -> Router
+where
+    S: AsyncStorage + Send + Sync + 'static,
+{
+    let state = Gateway {
+        service,
+        identity: identity.map(Into::into),
+    };
+    let router = Router::new()
+        .route("/tables", get(list_tables::<S>))
+        .route("/tables/{table}", get(get_table::<S>))
+        // key 中可以包含 /
+        .route(
+            "/tables/{table}/keys/{*key}",
+            get(get_key::<S>).put(put_key::<S>).delete(delete_key::<S>),
+        )
+        .with_state(state);
+    set_layer(router, cors_origins)
+}
+
+pub async fn serve_gateway<S>(
+    listener: TcpListener,
+    service: Service<S>,
+    identity: Option<String>,
+    cors_origins: &[String],
+) -> Result<(), KvError>
+where
+    S: AsyncStorage + Send + Sync + 'static,
+{
+    let router = gateway_router(service, identity, cors_origins);
+    axum::serve(listener, router.into_make_service()).await?;
+    Ok(())
+}
+
+fn set_layer(app: Router, cors_origins: &[String]) -> Router {
+    let app = app.layer(
+        ServiceBuilder::new()
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(DefaultMakeSpan::new().include_headers(true))
+                    .on_request(DefaultOnRequest::new().level(Level::INFO))
+                    .on_response(
+                        DefaultOnResponse::new()
+                            .level(Level::INFO)
+                            .latency_unit(LatencyUnit::Micros),
+                    ),
+            )
+            .layer(CompressionLayer::new().gzip(true).br(true).deflate(true)),
+    );
+    if cors_origins.is_empty() {
+        return app;
+    }
+    // 配置的 Origin 中的前端可以直接访问，无效的 Origin 已在配置校验时拒绝
+    let origins = if cors_origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            cors_origins
+                .iter()
+                .filter_map(|origin| HeaderValue::from_str(origin).ok()),
+        )
+    };
+    app.layer(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods([Method::GET, Method::PUT, Method::DELETE])
+            .allow_headers([HeaderName::from_static("content-type")]),
+    )
+}
+
+async fn list_tables<S>(State(gateway): State<Gateway<S>>) -> Response
+where
+    S: AsyncStorage + Send + Sync + 'static,
+{
+    respond(&gateway, CommandRequest::new_list_tables(), |res| {
+        json!(res.values.into_iter().map(to_json).collect::<Vec<_>>())
+    })
+    .await
+}
+
+async fn get_table<S>(State(gateway): State<Gateway<S>>, Path(table): Path<String>) -> Response
+where
+    S: AsyncStorage + Send + Sync + 'static,
+{
+    respond(&gateway, CommandRequest::new_hgetall(&table), |res| {
+        res.pairs
+            .into_iter()
+            .map(|pair| {
+                (
+                    pair.key,
+                    pair.value.map_or(serde_json::Value::Null, to_json),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    })
+    .await
+}
+
+async fn get_key<S>(
+    State(gateway): State<Gateway<S>>,
+    Path((table, key)): Path<(String, String)>,
+) -> Response
+where
+    S: AsyncStorage + Send + Sync + 'static,
+{
+    respond(
+        &gateway,
+        CommandRequest::new_hget(&table, &key),
+        first_value,
+    )
+    .await
+}
+
+// body 是 JSON 的值，返回原来的值，不存在时为 null
+async fn put_key<S>(
+    State(gateway): State<Gateway<S>>,
+    Path((table, key)): Path<(String, String)>,
+    Query(params): Query<PutParams>,
+    body: Bytes,
+) -> Response
+where
+    S: AsyncStorage + Send + Sync + 'static,
+{
+    let value = match serde_json::from_slice(&body)
+        .map_err(|e| e.to_string())
+        .and_then(from_json)
+    {
+        Ok(value) => value,
+        Err(e) => return error(StatusCode::BAD_REQUEST, e),
+    };
+    let cmd = match params.ttl {
+        Some(ttl) if ttl > 0 => CommandRequest::new_hset_with_ttl(&table, &key, value, ttl),
+        _ => CommandRequest::new_hset(&table, &key, value),
+    };
+    respond(&gateway, cmd, first_value).await
+}
+
+// 返回删除的值，不存在时为 null
+async fn delete_key<S>(
+    State(gateway): State<Gateway<S>>,
+    Path((table, key)): Path<(String, String)>,
+) -> Response
+where
+    S: AsyncStorage + Send + Sync + 'static,
+{
+    respond(
+        &gateway,
+        CommandRequest::new_hdel(&table, &key),
+        first_value,
+    )
+    .await
+}
+
+async fn respond<S>(
+    gateway: &Gateway<S>,
+    cmd: CommandRequest,
+    body: impl FnOnce(CommandResponse) -> serde_json::Value,
+) -> Response
+where
+    S: AsyncStorage + Send + Sync + 'static,
+{
+    let res = gateway
+        .service
+        .exec_as(cmd, gateway.identity.as_deref())
+        .await;
+    let status =
+        StatusCode::from_u16(res.status as u16).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let response = if status.is_success() {
+        (status, Json(body(res))).into_response()
+    } else {
+        error(status, res.message)
+    };
+    // axum 写出响应的时机无法得知，响应生成后即调用
+    gateway.service.after_send().await;
+    response
+}
+
+fn error(status: StatusCode, message: String) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+fn first_value(res: CommandResponse) -> serde_json::Value {
+    res.values
+        .into_iter()
+        .next()
+        .map_or(serde_json::Value::Null, to_json)
+}
+
+// 整数保存为 Int64Value，其它数字保存为 DoubleValue，数组保存为列表
+fn from_json(json: serde_json::Value) -> Result<Value, String> {
+    match json {
+        serde_json::Value::Null => Err("value must not be null".into()),
+        serde_json::Value::Bool(b) => Ok(b.into()),
+        serde_json::Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(n), _) => Ok(n.into()),
+            (None, Some(n)) => Ok(n.into()),
+            _ => Err(format!("number {} is out of range", n)),
+        },
+        serde_json::Value::String(s) => Ok(s.into()),
+        serde_json::Value::Array(values) => Ok(ListValue {
+            values: values
+                .into_iter()
+                .map(from_json)
+                .collect::<Result<_, _>>()?,
+        }
+        .into()),
+        serde_json::Value::Object(_) => Err("object value is not supported".into()),
+    }
+}
+
+// bytes 转换成字节的数组，有序集合转换成 {"member", "score"} 的数组
+fn to_json(value: Value) -> serde_json::Value {
+    match value.value {
+        None => serde_json::Value::Null,
+        Some(value::Value::StringValue(s)) => s.into(),
+        Some(value::Value::BytesValue(b)) => b.into(),
+        Some(value::Value::Int64Value(n)) => n.into(),
+        // NaN 和无穷大在 JSON 中为 null
+        Some(value::Value::DoubleValue(n)) => n.into(),
+        Some(value::Value::BoolValue(b)) => b.into(),
+        Some(value::Value::ListValue(list)) => list.values.into_iter().map(to_json).collect(),
+        Some(value::Value::SetValue(set)) => set.members.into(),
+        Some(value::Value::ZsetValue(zset)) => zset
+            .members
+            .into_iter()
+            .map(|m| json!({ "member": m.member, "score": m.score }))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::*;
+    use crate::{BlockingStorage, MemTable, ServiceInner, command_request::RequestData};
+
+    // 返回完整的 HTTP 响应
+    async fn send(
+        addr: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        headers: &str,
+        body: &str,
+    ) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let req = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            headers,
+            body.len(),
+            body
+        );
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await.unwrap();
+        res
+    }
+
+    async fn request(
+        addr: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        body: &str,
+    ) -> (u16, String) {
+        let res = send(addr, method, path, "", body).await;
+        let status = res[9..12].parse().unwrap();
+        let body = res.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn gateway_should_work() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service: Service = ServiceInner::new(BlockingStorage::new(MemTable::new())).into();
+        tokio::spawn(serve_gateway(listener, service.clone(), None, &[]));
+
+        let res = request(addr, "PUT", "/tables/t1/keys/hello", "\"world\"").await;
+        assert_eq!(res, (200, "null".into()));
+        let res = request(
+            addr,
+            "PUT",
+            "/tables/t1/keys/a/b?ttl=60000",
+            "[1, 2.5, true]",
+        )
+        .await;
+        assert_eq!(res, (200, "null".into()));
+        let res = request(addr, "PUT", "/tables/t1/keys/hello", "42").await;
+        assert_eq!(res, (200, "\"world\"".into()));
+
+        let res = request(addr, "GET", "/tables/t1/keys/hello", "").await;
+        assert_eq!(res, (200, "42".into()));
+        let res = request(addr, "GET", "/tables/t1/keys/a/b", "").await;
+        assert_eq!(res, (200, "[1,2.5,true]".into()));
+        let ttl = service.exec(CommandRequest::new_ttl("t1", "a/b")).await;
+        assert!(i64::try_from(&ttl.values[0]).unwrap() > 0);
+
+        let res = request(addr, "GET", "/tables/t1", "").await;
+        assert_eq!(res, (200, r#"{"a/b":[1,2.5,true],"hello":42}"#.into()));
+        let res = request(addr, "GET", "/tables", "").await;
+        assert_eq!(res, (200, r#"["t1"]"#.into()));
+
+        let res = request(addr, "DELETE", "/tables/t1/keys/hello", "").await;
+        assert_eq!(res, (200, "42".into()));
+        let res = request(addr, "GET", "/tables/t1/keys/hello", "").await;
+        assert_eq!(res, (404, r#"{"error":"not found"}"#.into()));
+    }
+
+    #[tokio::test]
+    async fn gateway_should_return_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service: Service = ServiceInner::new(BlockingStorage::new(MemTable::new()))
+            .fn_intercept(|cmd| match &cmd.request_data {
+                Some(RequestData::Hget(params)) if params.table == "secret" => {
+                    Some(KvError::PermissionDenied("secret is not readable".into()).into())
+                }
+                _ => None,
+            })
+            .into();
+        tokio::spawn(serve_gateway(listener, service, None, &[]));
+
+        // Service 钩子返回的状态码作为 HTTP 的状态码
+        let res = request(addr, "GET", "/tables/secret/keys/k", "").await;
+        assert_eq!(
+            res,
+            (
+                403,
+                r#"{"error":"permission denied: secret is not readable"}"#.into()
+            )
+        );
+
+        for body in ["{\"a\": 1}", "null", "[1, null]", "not json"] {
+            let (status, _) = request(addr, "PUT", "/tables/t1/keys/k", body).await;
+            assert_eq!(status, 400, "{}", body);
+        }
+        let (status, _) = request(addr, "PATCH", "/tables/t1/keys/k", "1").await;
+        assert_eq!(status, 405);
+    }
+
+    #[tokio::test]
+    async fn gateway_should_exec_as_identity() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acl = toml::from_str("[identities.gateway]\nwrite = [\"t1\"]").unwrap();
+        let service: Service = ServiceInner::new(BlockingStorage::new(MemTable::new()))
+            .with_acl(acl)
+            .into();
+        tokio::spawn(serve_gateway(
+            listener,
+            service,
+            Some("gateway".into()),
+            &[],
+        ));
+
+        let res = request(addr, "PUT", "/tables/t1/keys/k", "1").await;
+        assert_eq!(res, (200, "null".into()));
+        let (status, _) = request(addr, "PUT", "/tables/t2/keys/k", "1").await;
+        assert_eq!(status, 403);
+    }
+
+    #[tokio::test]
+    async fn gateway_should_only_allow_configured_cors_origins() {
+        let service: Service = ServiceInner::new(BlockingStorage::new(MemTable::new())).into();
+        let origin = "Origin: http://localhost:8000\r\n";
+
+        // 默认不允许跨域访问
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_gateway(listener, service.clone(), None, &[]));
+        let res = send(addr, "GET", "/tables", origin, "").await;
+        assert!(!res.to_lowercase().contains("access-control-allow-origin"));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let origins = vec!["http://localhost:8000".to_string()];
+        tokio::spawn(async move { serve_gateway(listener, service, None, &origins).await });
+        let res = send(addr, "GET", "/tables", origin, "")
+            .await
+            .to_lowercase();
+        assert!(res.contains("access-control-allow-origin: http://localhost:8000"));
+        let res = send(addr, "GET", "/tables", "Origin: http://evil.com\r\n", "").await;
+        assert!(!res.to_lowercase().contains("access-control-allow-origin"));
+    }
+}
@@ -1,3 +1,18 @@
+mod client;
 mod frame;
+mod gateway;
+mod multiplex;
+mod replication;
+mod resp;
+mod server;
+mod sharded;
 mod tls;
+pub use client::*;
+pub use frame::{FrameCoder, KvFrameCodec, read_frame};
+pub use gateway::*;
+pub use multiplex::*;
+pub use replication::*;
+pub use resp::*;
+pub use server::*;
+pub use sharded::*;
 pub use tls::*;
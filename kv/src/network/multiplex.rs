@@ -0,0 +1,228 @@
+use std::{collections::VecDeque, future::poll_fn, task::Poll};
+
+use futures::{
+    StreamExt,
+    channel::{mpsc, oneshot},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::compat::{Compat, FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt};
+use tracing::{debug, warn};
+use yamux::{Config, Connection, ConnectionError, Mode};
+
+use crate::error::KvError;
+
+// 多路复用后的逻辑流，可以直接交给 KvClient 或 KvServerStream 使用
+pub type YamuxStream = Compat<yamux::Stream>;
+
+// 客户端：在一个 TCP/TLS 连接上打开多个逻辑流
+// 所有 YamuxCtrl 都释放后，底层连接会被关闭
+#[derive(Clone)]
+pub struct YamuxCtrl {
+    tx: mpsc::UnboundedSender<oneshot::Sender<yamux::Stream>>,
+}
+
+impl YamuxCtrl {
+    pub fn new_client<S>(stream: S, config: Option<Config>) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let conn = Connection::new(stream.compat(), config.unwrap_or_default(), Mode::Client);
+        let (tx, rx) = mpsc::unbounded();
+        tokio::spawn(async move {
+            if let Err(e) = drive_client(conn, rx).await {
+                warn!("Yamux connection closed with error: {}", e);
+            }
+        });
+        Self { tx }
+    }
+
+    // 打开一个新的逻辑流
+    pub async fn open_stream(&self) -> Result<YamuxStream, KvError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(tx)
+            .map_err(|_| KvError::Internal("yamux connection closed".into()))?;
+        let stream = rx
+            .await
+            .map_err(|_| KvError::Internal("yamux connection closed".into()))?;
+        Ok(stream.compat())
+    }
+}
+
+// yamux 的连接需要一直被 poll 才能收发数据，由一个单独的任务驱动
+async fn drive_client<T>(
+    mut conn: Connection<T>,
+    mut rx: mpsc::UnboundedReceiver<oneshot::Sender<yamux::Stream>>,
+) -> Result<(), ConnectionError>
+where
+    T: futures::AsyncRead + futures::AsyncWrite + Unpin,
+{
+    let mut pending = VecDeque::new();
+    let mut closing = false;
+    poll_fn(|cx| {
+        while !closing {
+            match rx.poll_next_unpin(cx) {
+                Poll::Ready(Some(req)) => pending.push_back(req),
+                Poll::Ready(None) => closing = true,
+                Poll::Pending => break,
+            }
+        }
+
+        while let Some(req) = pending.pop_front() {
+            match conn.poll_new_outbound(cx) {
+                Poll::Ready(Ok(stream)) => {
+                    debug!("Yamux stream {} is opened", stream.id());
+                    // 请求方已经放弃时直接丢弃这个流
+                    let _ = req.send(stream);
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => {
+                    pending.push_front(req);
+                    break;
+                }
+            }
+        }
+
+        // 客户端不接受服务端主动打开的流
+        loop {
+            match conn.poll_next_inbound(cx) {
+                Poll::Ready(Some(Ok(stream))) => {
+                    warn!("Unexpected inbound yamux stream {}", stream.id())
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => break,
+            }
+        }
+
+        if closing && pending.is_empty() {
+            return conn.poll_close(cx);
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+// 服务端：对端打开的每个逻辑流都交给 f 处理，连接关闭时返回
+pub async fn serve_yamux<T, F, Fut>(stream: T, config: Option<Config>, f: F) -> Result<(), KvError>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    F: Fn(YamuxStream) -> Fut,
+    Fut: Future<Output = Result<(), KvError>> + Send + 'static,
+{
+    let mut conn = Connection::new(stream.compat(), config.unwrap_or_default(), Mode::Server);
+    while let Some(stream) = poll_fn(|cx| conn.poll_next_inbound(cx)).await {
+        let stream = stream?;
+        debug!("Yamux stream {} is accepted", stream.id());
+        let fut = f(stream.compat());
+        tokio::spawn(async move {
+            if let Err(e) = fut.await {
+                warn!("Failed to process yamux stream: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use anyhow::Result;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::{
+        BlockingStorage, KvClient, KvServerStream, MemTable, Metrics, Service, ServiceInner, Value,
+    };
+
+    #[tokio::test]
+    async fn yamux_streams_should_share_one_connection() -> Result<()> {
+        let addr = start_server().await?;
+        let ctrl = YamuxCtrl::new_client(TcpStream::connect(addr).await?, None);
+
+        let mut tasks = Vec::new();
+        for i in 0..8 {
+            let stream = ctrl.open_stream().await?;
+            tasks.push(tokio::spawn(async move {
+                let mut client = KvClient::new(stream);
+                let key = format!("k{}", i);
+                for j in 0..10 {
+                    client.hset("t1", &key, j).await?;
+                }
+                client.hget("t1", &key).await
+            }));
+        }
+        for task in tasks {
+            assert_eq!(task.await??, Some(Value::from(9)));
+        }
+
+        let mut client = KvClient::new(ctrl.open_stream().await?);
+        assert_eq!(client.hgetall("t1").await?.len(), 8);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn yamux_subscription_should_not_block_other_streams() -> Result<()> {
+        let addr = start_server().await?;
+        let ctrl = YamuxCtrl::new_client(TcpStream::connect(addr).await?, None);
+
+        let subscriber = KvClient::new(ctrl.open_stream().await?);
+        let mut subscription = subscriber.subscribe("lobby").await?;
+        let mut client = KvClient::new(ctrl.open_stream().await?);
+
+        assert_eq!(client.publish("lobby", vec!["hi".into()]).await?, 1);
+        assert_eq!(subscription.next().await.unwrap()?, vec!["hi".into()]);
+
+        client.unsubscribe("lobby", subscription.id()).await?;
+        assert!(subscription.next().await.is_none());
+        assert_eq!(client.hset("t1", "k1", "v1").await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn yamux_streams_should_count_as_one_connection() -> Result<()> {
+        let metrics = Arc::new(Metrics::new());
+        let service = ServiceInner::new(BlockingStorage::new(MemTable::new()))
+            .with_metrics(metrics.clone())
+            .into();
+        let addr = start_server_with(service).await?;
+        let ctrl = YamuxCtrl::new_client(TcpStream::connect(addr).await?, None);
+
+        let mut clients = Vec::new();
+        for _ in 0..3 {
+            let mut client = KvClient::new(ctrl.open_stream().await?);
+            client.hget("t1", "k1").await?;
+            clients.push(client);
+        }
+        assert_eq!(metrics.active_connections(), 1);
+        assert!(metrics.render().contains("kv_connections_total 1\n"));
+        Ok(())
+    }
+
+    async fn start_server() -> Result<SocketAddr> {
+        start_server_with(ServiceInner::new(BlockingStorage::new(MemTable::new())).into()).await
+    }
+
+    async fn start_server_with(service: Service) -> Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let svc = service.clone();
+                let connection = service.connection();
+                tokio::spawn(async move {
+                    let _connection = connection;
+                    serve_yamux(stream, None, move |stream| {
+                        KvServerStream::new(stream, svc.clone()).process()
+                    })
+                    .await
+                });
+            }
+        });
+
+        Ok(addr)
+    }
+}
@@ -0,0 +1,355 @@
+use futures::{SinkExt, StreamExt};
+use http::StatusCode;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
+use tracing::{debug, info};
+
+use crate::{
+    BlockingStorage, CommandRequest, CommandResponse, KvFrameCodec, MemTable, Service,
+    command::dispatch, error::KvError, storage::AsyncStorage,
+};
+
+// follower 从 leader 同步数据：先把完整的快照加载到临时的 MemTable，再换掉本地数据，之后依次执行修改
+// 执行失败时返回错误，由调用者重新连接并同步快照，连接断开时返回
+// stream 可以是 TLS 连接，leader 只允许配置的 follower 身份同步数据
+pub async fn replicate_from<T, S>(stream: T, service: Service<S>) -> Result<(), KvError>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+    S: AsyncStorage + Send + Sync + 'static,
+{
+    let mut framed = Framed::new(stream, KvFrameCodec::<CommandResponse>::new());
+    framed.send(CommandRequest::new_replicate()).await?;
+    // 第一个空的响应表示快照开始，第二个空的响应表示快照结束
+    let mut snapshot = None;
+    let mut synced = false;
+    while let Some(res) = framed.next().await {
+        let res = res?;
+        if res.status != StatusCode::OK.as_u16() as u32 {
+            return Err(KvError::ServerError(res.status, res.message));
+        }
+        if synced {
+            debug!("Replaying {} commands", res.commands.len());
+            for cmd in res.commands {
+                check(service.replay(cmd).await)?;
+            }
+            continue;
+        }
+        match snapshot.as_ref() {
+            None if res.commands.is_empty() => {
+                snapshot = Some(BlockingStorage::new(MemTable::new()));
+            }
+            None => {
+                return Err(KvError::Internal(
+                    "replication should start with a snapshot".into(),
+                ));
+            }
+            Some(staging) if res.commands.is_empty() => {
+                let keys = service.load_snapshot(staging).await?;
+                info!("Loaded {} keys from leader snapshot", keys);
+                snapshot = None;
+                synced = true;
+            }
+            Some(staging) => {
+                for cmd in res.commands {
+                    check(dispatch(cmd, staging).await)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check(res: CommandResponse) -> Result<(), KvError> {
+    if res.status != StatusCode::OK.as_u16() as u32 {
+        return Err(KvError::Internal(format!(
+            "failed to replay command: {}",
+            res.message
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use anyhow::Result;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::{
+        AclConfig, KvClient, KvServerStream, ServiceInner, TablePermissions, TlsClientConnector,
+        TlsServerAcceptor, Value, peer_identity,
+    };
+
+    const FOLLOWER: &str = "follower";
+
+    #[tokio::test]
+    async fn follower_should_replicate_from_leader() -> Result<()> {
+        let leader: Service = ServiceInner::new(BlockingStorage::new(MemTable::new()))
+            .with_change_log(vec![FOLLOWER.into()])
+            .into();
+        let leader_addr = start_server(leader.clone(), Some(FOLLOWER)).await?;
+        let mut client = KvClient::new(TcpStream::connect(leader_addr).await?);
+        // 快照中的数据
+        client.hset("t1", "k1", "v1").await?;
+        client.hincrby("t1", "counter", 5).await?;
+        client
+            .execute(CommandRequest::new_hset_with_ttl(
+                "t1",
+                "session",
+                "s1".into(),
+                60_000,
+            ))
+            .await?;
+
+        let follower: Service = ServiceInner::new(BlockingStorage::new(MemTable::new()))
+            .with_leader(leader_addr.to_string())
+            .into();
+        // 同步前已有的数据会被清空
+        follower
+            .replay(CommandRequest::new_hset("stale", "k1", "v1".into()))
+            .await;
+        let follower_addr = start_server(follower.clone(), None).await?;
+        let stream = TcpStream::connect(leader_addr).await?;
+        tokio::spawn(replicate_from(stream, follower.clone()));
+
+        // 修改日志中的数据，并发的修改在 follower 上按相同的顺序执行
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let leader = leader.clone();
+                tokio::spawn(async move {
+                    for _ in 0..25 {
+                        let cmd = CommandRequest::new_hincrby("t1", "counter", 1);
+                        assert_eq!(leader.exec(cmd).await.status, 200);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await?;
+        }
+        client.hdel("t1", "k1").await?;
+        client.hset("t2", "done", true).await?;
+
+        let mut reader = KvClient::new(TcpStream::connect(follower_addr).await?);
+        let mut synced = false;
+        for _ in 0..200 {
+            if reader.hget("t2", "done").await?.is_some() {
+                synced = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(synced);
+        assert_eq!(reader.hget("t1", "counter").await?, Some(Value::from(105)));
+        assert_eq!(reader.hget("t1", "k1").await?, None);
+        assert_eq!(reader.list_tables().await?, vec!["t1", "t2"]);
+        let res = reader
+            .execute(CommandRequest::new_ttl("t1", "session"))
+            .await?;
+        assert!(res.values[0].value.is_some());
+
+        // follower 拒绝写入，返回 leader 的地址
+        let err = reader.hset("t1", "k1", "v2").await.unwrap_err();
+        assert!(
+            matches!(err, KvError::ServerError(307, msg) if msg.contains(&leader_addr.to_string()))
+        );
+        assert_eq!(client.hget("t1", "k1").await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replicate_should_require_change_log() -> Result<()> {
+        let service: Service = ServiceInner::new(BlockingStorage::new(MemTable::new())).into();
+        let addr = start_server(service, Some(FOLLOWER)).await?;
+        let follower: Service = ServiceInner::new(BlockingStorage::new(MemTable::new()))
+            .with_leader(addr.to_string())
+            .into();
+        follower
+            .replay(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await;
+
+        let stream = TcpStream::connect(addr).await?;
+        let err = replicate_from(stream, follower.clone()).await.unwrap_err();
+        assert!(matches!(err, KvError::ServerError(400.., _)));
+        // 没有开始同步时保留本地数据
+        let res = follower.exec(CommandRequest::new_hget("t1", "k1")).await;
+        assert_eq!(res.values, vec![Value::from("v1")]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replicate_should_require_allowed_follower() -> Result<()> {
+        let leader: Service = ServiceInner::new(BlockingStorage::new(MemTable::new()))
+            .with_change_log(vec![FOLLOWER.into()])
+            .into();
+        leader
+            .exec(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await;
+        let follower: Service = ServiceInner::new(BlockingStorage::new(MemTable::new())).into();
+
+        for identity in [None, Some("intruder")] {
+            let addr = start_server(leader.clone(), identity).await?;
+            let stream = TcpStream::connect(addr).await?;
+            let err = replicate_from(stream, follower.clone()).await.unwrap_err();
+            assert!(matches!(err, KvError::ServerError(403, _)));
+        }
+        let res = follower.exec(CommandRequest::new_list_tables()).await;
+        assert!(res.values.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn follower_should_keep_data_until_snapshot_is_loaded() -> Result<()> {
+        let leader: Service = ServiceInner::new(BlockingStorage::new(MemTable::new()))
+            .with_change_log(vec![FOLLOWER.into()])
+            .into();
+        leader
+            .exec(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await;
+        let addr = start_server(leader.clone(), Some(FOLLOWER)).await?;
+
+        // 快照没有发送完时断开，本地数据保持不变
+        let follower: Service = ServiceInner::new(BlockingStorage::new(MemTable::new())).into();
+        follower
+            .replay(CommandRequest::new_hset("t1", "k1", "old".into()))
+            .await;
+        let mut framed = Framed::new(
+            TcpStream::connect(addr).await?,
+            KvFrameCodec::<CommandResponse>::new(),
+        );
+        framed.send(CommandRequest::new_replicate()).await?;
+        let res = framed.next().await.unwrap()?;
+        assert!(res.commands.is_empty());
+        let res = framed.next().await.unwrap()?;
+        assert_eq!(res.commands.len(), 1);
+        drop(framed);
+        let res = follower.exec(CommandRequest::new_hget("t1", "k1")).await;
+        assert_eq!(res.values, vec![Value::from("old")]);
+
+        // 执行失败的修改会中断复制，由调用者重新同步
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(async move {
+            let mut framed = Framed::new(server, KvFrameCodec::<CommandRequest>::new());
+            framed.next().await;
+            let mut res = CommandResponse {
+                status: 200,
+                ..Default::default()
+            };
+            for commands in [
+                vec![],
+                vec![CommandRequest::new_hset("t2", "k1", "v1".into())],
+                vec![],
+                vec![CommandRequest::new_replicate()],
+            ] {
+                res.commands = commands;
+                framed.send(res.clone()).await.unwrap();
+            }
+        });
+        let err = replicate_from(client, follower.clone()).await.unwrap_err();
+        assert!(matches!(err, KvError::Internal(_)));
+        let res = follower.exec(CommandRequest::new_list_tables()).await;
+        assert_eq!(res.values, vec![Value::from("t2")]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn follower_should_replicate_over_tls_with_acl() -> Result<()> {
+        // 客户端证书的 CN 是 awesome-client
+        let mut acl = AclConfig::default();
+        acl.identities.insert(
+            "awesome-client".into(),
+            TablePermissions {
+                read: vec!["*".into()],
+                write: vec!["t1".into()],
+            },
+        );
+        let connector = TlsClientConnector::new(
+            "kvserver.kevin.inc",
+            Some(("fixtures/client.cert", "fixtures/client.key")),
+            Some("fixtures/ca.cert"),
+        )?;
+
+        for (followers, allowed) in [(vec!["awesome-client"], true), (vec!["other"], false)] {
+            let leader: Service = ServiceInner::new(BlockingStorage::new(MemTable::new()))
+                .with_acl(acl.clone())
+                .with_change_log(followers.into_iter().map(Into::into).collect())
+                .into();
+            let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+            assert_eq!(
+                leader.exec_as(cmd, Some("awesome-client")).await.status,
+                200
+            );
+            let addr = start_tls_server(leader.clone()).await?;
+
+            let follower: Service = ServiceInner::new(BlockingStorage::new(MemTable::new())).into();
+            let stream = connector.connect(TcpStream::connect(addr).await?).await?;
+            let handle = tokio::spawn(replicate_from(stream, follower.clone()));
+            if !allowed {
+                let err = handle.await?.unwrap_err();
+                assert!(matches!(err, KvError::ServerError(403, _)));
+                continue;
+            }
+            let cmd = CommandRequest::new_hset("t1", "k2", "v2".into());
+            assert_eq!(
+                leader.exec_as(cmd, Some("awesome-client")).await.status,
+                200
+            );
+            let mut synced = false;
+            for _ in 0..200 {
+                let res = follower.exec(CommandRequest::new_hget("t1", "k2")).await;
+                if res.status == 200 {
+                    synced = true;
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            assert!(synced);
+            let res = follower.exec(CommandRequest::new_hget("t1", "k1")).await;
+            assert_eq!(res.values, vec![Value::from("v1")]);
+            handle.abort();
+        }
+        Ok(())
+    }
+
+    async fn start_server(service: Service, identity: Option<&str>) -> Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let identity = identity.map(String::from);
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let stream =
+                    KvServerStream::new(stream, service.clone()).with_identity(identity.clone());
+                tokio::spawn(stream.process());
+            }
+        });
+
+        Ok(addr)
+    }
+
+    // 与 kvs 一样开启双向认证，用客户端证书的 CN 作为身份
+    async fn start_tls_server(service: Service) -> Result<SocketAddr> {
+        let acceptor = TlsServerAcceptor::new(
+            "fixtures/server.cert",
+            "fixtures/server.key",
+            Some("fixtures/ca.cert"),
+        )?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let stream = acceptor.accept(stream).await.unwrap();
+                let identity = peer_identity(&stream).unwrap();
+                let stream = KvServerStream::new(stream, service.clone()).with_identity(identity);
+                tokio::spawn(stream.process());
+            }
+        });
+
+        Ok(addr)
+    }
+}
@@ -0,0 +1,669 @@
+use bytes::Bytes;
+use http::StatusCode;
+
+use super::frame::{RespFrame, format_double};
+use crate::{CommandRequest, CommandResponse, Kvpair, ScoredMember, Value, value};
+
+// 一个 redis 请求解析后的结果
+pub(crate) enum Parsed {
+    // 空的请求，例如 telnet 中的空行，不需要回复
+    Skip,
+    // 不需要访问存储的命令或者参数错误，直接回复
+    Reply(RespFrame),
+    // HELLO 命令，带有协议版本时切换协议
+    Hello(Option<u8>),
+    Quit,
+    Command(CommandRequest, Reply),
+    // 依次执行多个命令，回复整数之和，有命令失败时回复第一个错误
+    Sum(Vec<(CommandRequest, Reply)>),
+}
+
+// CommandResponse 转换成 redis 回复的方式，与对应的 redis 命令的回复一致
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Reply {
+    Ok,
+    // 第一个值，key 不存在时为 null
+    Data,
+    // 第一个值作为整数，key 不存在时为 null
+    Integer,
+    // 第一个 bool 值作为 1 或 0
+    Bool,
+    List,
+    Set,
+    // Hmset 返回的旧值中原来不存在的数量
+    Added,
+    // Hmdel 返回的旧值中原来存在的数量
+    Removed,
+    // Hmexists 返回的值中为 true 的数量
+    Trues,
+    Map,
+    Keys,
+    Values,
+    // TableInfo 中 key 的数量，表不存在时为 0
+    Len,
+    // DropTable 删除的或者 TableInfo 中 key 的数量大于 0 时为 1，表不存在时为 0
+    Found,
+    // Ttl 返回的毫秒数换算成的单位
+    Ttl(i64),
+    // 带有 count 参数时返回数组
+    Pop(bool),
+    // 有序集合的成员，跳过前 offset 个
+    Scores { withscores: bool, offset: usize },
+}
+
+type ParseResult = Result<Parsed, String>;
+
+// table 是 GET/SET 等没有表名的命令使用的表，hash 命令使用 redis 的 key 作为表名
+pub(crate) fn parse(frame: RespFrame, table: &str) -> Parsed {
+    let args = match frame {
+        RespFrame::Array(items) => items
+            .into_iter()
+            .map(|item| match item {
+                RespFrame::Bulk(data) => Ok(data),
+                _ => Err("ERR Protocol error: expected bulk string".to_string()),
+            })
+            .collect::<Result<Vec<_>, _>>(),
+        _ => Err("ERR Protocol error: expected array of bulk strings".to_string()),
+    };
+    match args.and_then(|args| match args.split_first() {
+        Some((name, args)) => parse_command(name, args, table),
+        None => Ok(Parsed::Skip),
+    }) {
+        Ok(parsed) => parsed,
+        Err(msg) => Parsed::Reply(RespFrame::Error(msg)),
+    }
+}
+
+fn parse_command(name: &Bytes, args: &[Bytes], table: &str) -> ParseResult {
+    let name = String::from_utf8_lossy(name).to_ascii_uppercase();
+    let arity = |ok: bool| {
+        if ok {
+            Ok(())
+        } else {
+            Err(format!(
+                "ERR wrong number of arguments for '{}' command",
+                name.to_ascii_lowercase()
+            ))
+        }
+    };
+    let n = args.len();
+    let cmd = |cmd: CommandRequest, reply: Reply| Ok(Parsed::Command(cmd, reply));
+    match name.as_str() {
+        // hash 命令
+        "HGET" => {
+            arity(n == 2)?;
+            cmd(
+                CommandRequest::new_hget(&key(&args[0])?, &key(&args[1])?),
+                Reply::Data,
+            )
+        }
+        "HSET" | "HMSET" => {
+            arity(n >= 3 && n % 2 == 1)?;
+            let pairs = args[1..]
+                .chunks(2)
+                .map(|pair| Ok(Kvpair::new(&key(&pair[0])?, to_value(&pair[1]))))
+                .collect::<Result<Vec<_>, String>>()?;
+            let reply = if name == "HSET" {
+                Reply::Added
+            } else {
+                Reply::Ok
+            };
+            cmd(CommandRequest::new_hmset(&key(&args[0])?, pairs), reply)
+        }
+        "HSETNX" => {
+            arity(n == 3)?;
+            cmd(
+                CommandRequest::new_hsetnx(&key(&args[0])?, &key(&args[1])?, to_value(&args[2])),
+                Reply::Bool,
+            )
+        }
+        "HGETALL" | "HKEYS" | "HVALS" => {
+            arity(n == 1)?;
+            let reply = match name.as_str() {
+                "HGETALL" => Reply::Map,
+                "HKEYS" => Reply::Keys,
+                _ => Reply::Values,
+            };
+            cmd(CommandRequest::new_hgetall(&key(&args[0])?), reply)
+        }
+        "HLEN" => {
+            arity(n == 1)?;
+            cmd(CommandRequest::new_table_info(&key(&args[0])?), Reply::Len)
+        }
+        "HDEL" => {
+            arity(n >= 2)?;
+            cmd(
+                CommandRequest::new_hmdel(&key(&args[0])?, keys(&args[1..])?),
+                Reply::Removed,
+            )
+        }
+        "HEXISTS" => {
+            arity(n == 2)?;
+            cmd(
+                CommandRequest::new_hexists(&key(&args[0])?, &key(&args[1])?),
+                Reply::Bool,
+            )
+        }
+        "HMGET" => {
+            arity(n >= 2)?;
+            cmd(
+                CommandRequest::new_hmget(&key(&args[0])?, keys(&args[1..])?),
+                Reply::List,
+            )
+        }
+        "HINCRBY" => {
+            arity(n == 3)?;
+            cmd(
+                CommandRequest::new_hincrby(&key(&args[0])?, &key(&args[1])?, int(&args[2])?),
+                Reply::Integer,
+            )
+        }
+        "HINCRBYFLOAT" => {
+            arity(n == 3)?;
+            cmd(
+                CommandRequest::new_hincrbyfloat(
+                    &key(&args[0])?,
+                    &key(&args[1])?,
+                    float(&args[2])?,
+                ),
+                Reply::Data,
+            )
+        }
+
+        // 字符串和 key 的命令，在 table 中执行
+        "GET" => {
+            arity(n == 1)?;
+            cmd(
+                CommandRequest::new_hget(table, &key(&args[0])?),
+                Reply::Data,
+            )
+        }
+        "SET" => {
+            arity(n == 2 || n == 4)?;
+            let (k, v) = (key(&args[0])?, to_value(&args[1]));
+            if n == 2 {
+                return cmd(CommandRequest::new_hset(table, &k, v), Reply::Ok);
+            }
+            let ttl = match String::from_utf8_lossy(&args[2])
+                .to_ascii_uppercase()
+                .as_str()
+            {
+                "EX" => ttl(&args[3])?.checked_mul(1000),
+                "PX" => Some(ttl(&args[3])?),
+                _ => return Err("ERR syntax error".into()),
+            }
+            .ok_or("ERR invalid expire time in 'set' command")?;
+            cmd(
+                CommandRequest::new_hset_with_ttl(table, &k, v, ttl),
+                Reply::Ok,
+            )
+        }
+        // hash 保存在以 key 为名的表中，DEL 和 EXISTS 同时处理 table 中的 key 和这些表，
+        // 不是原子的操作；与 table 同名的 key 不当作 hash，避免删除整个 table
+        "DEL" => {
+            arity(n >= 1)?;
+            let keys = keys(args)?;
+            let mut cmds = vec![(
+                CommandRequest::new_hmdel(table, keys.clone()),
+                Reply::Removed,
+            )];
+            cmds.extend(
+                keys.iter()
+                    .filter(|key| *key != table)
+                    .map(|key| (CommandRequest::new_drop_table(key), Reply::Found)),
+            );
+            Ok(Parsed::Sum(cmds))
+        }
+        "EXISTS" => {
+            arity(n >= 1)?;
+            let keys = keys(args)?;
+            let mut cmds = vec![(
+                CommandRequest::new_hmexists(table, keys.clone()),
+                Reply::Trues,
+            )];
+            cmds.extend(
+                keys.iter()
+                    .filter(|key| *key != table)
+                    .map(|key| (CommandRequest::new_table_info(key), Reply::Found)),
+            );
+            Ok(Parsed::Sum(cmds))
+        }
+        "INCR" | "DECR" => {
+            arity(n == 1)?;
+            let delta = if name == "INCR" { 1 } else { -1 };
+            cmd(
+                CommandRequest::new_hincrby(table, &key(&args[0])?, delta),
+                Reply::Integer,
+            )
+        }
+        "INCRBY" | "DECRBY" => {
+            arity(n == 2)?;
+            let delta = int(&args[1])?;
+            let delta = if name == "INCRBY" {
+                delta
+            } else {
+                delta.checked_neg().ok_or("ERR decrement would overflow")?
+            };
+            cmd(
+                CommandRequest::new_hincrby(table, &key(&args[0])?, delta),
+                Reply::Integer,
+            )
+        }
+        "INCRBYFLOAT" => {
+            arity(n == 2)?;
+            cmd(
+                CommandRequest::new_hincrbyfloat(table, &key(&args[0])?, float(&args[1])?),
+                Reply::Data,
+            )
+        }
+        "EXPIRE" | "PEXPIRE" => {
+            arity(n == 2)?;
+            let ttl = ttl(&args[1])?;
+            let ttl = if name == "EXPIRE" {
+                ttl.checked_mul(1000)
+                    .ok_or("ERR invalid expire time in 'expire' command")?
+            } else {
+                ttl
+            };
+            cmd(
+                CommandRequest::new_expire(table, &key(&args[0])?, ttl),
+                Reply::Bool,
+            )
+        }
+        "PERSIST" => {
+            arity(n == 1)?;
+            cmd(
+                CommandRequest::new_persist(table, &key(&args[0])?),
+                Reply::Bool,
+            )
+        }
+        "TTL" | "PTTL" => {
+            arity(n == 1)?;
+            let unit = if name == "TTL" { 1000 } else { 1 };
+            cmd(
+                CommandRequest::new_ttl(table, &key(&args[0])?),
+                Reply::Ttl(unit),
+            )
+        }
+
+        // 列表、集合和有序集合的命令，在 table 中执行
+        "LPUSH" | "RPUSH" => {
+            arity(n >= 2)?;
+            let (k, values) = (key(&args[0])?, args[1..].iter().map(to_value).collect());
+            let cmd_req = if name == "LPUSH" {
+                CommandRequest::new_lpush(table, &k, values)
+            } else {
+                CommandRequest::new_rpush(table, &k, values)
+            };
+            cmd(cmd_req, Reply::Integer)
+        }
+        "LPOP" | "RPOP" => {
+            arity(n == 1 || n == 2)?;
+            let count = match args.get(1) {
+                Some(arg) => match int(arg)? {
+                    count @ 1..=0xffff_ffff => count as u32,
+                    _ => return Err("ERR value is out of range, must be positive".into()),
+                },
+                None => 1,
+            };
+            let k = key(&args[0])?;
+            let cmd_req = if name == "LPOP" {
+                CommandRequest::new_lpop(table, &k, count)
+            } else {
+                CommandRequest::new_rpop(table, &k, count)
+            };
+            cmd(cmd_req, Reply::Pop(n == 2))
+        }
+        "LRANGE" => {
+            arity(n == 3)?;
+            cmd(
+                CommandRequest::new_lrange(table, &key(&args[0])?, int(&args[1])?, int(&args[2])?),
+                Reply::List,
+            )
+        }
+        "SADD" | "SREM" => {
+            arity(n >= 2)?;
+            let (k, members) = (key(&args[0])?, keys(&args[1..])?);
+            let cmd_req = if name == "SADD" {
+                CommandRequest::new_sadd(table, &k, members)
+            } else {
+                CommandRequest::new_srem(table, &k, members)
+            };
+            cmd(cmd_req, Reply::Integer)
+        }
+        "SMEMBERS" => {
+            arity(n == 1)?;
+            cmd(
+                CommandRequest::new_smembers(table, &key(&args[0])?),
+                Reply::Set,
+            )
+        }
+        "SINTER" => {
+            arity(n >= 1)?;
+            cmd(CommandRequest::new_sinter(table, keys(args)?), Reply::Set)
+        }
+        "ZADD" => {
+            arity(n >= 3 && n % 2 == 1)?;
+            let members = args[1..]
+                .chunks(2)
+                .map(|pair| Ok(ScoredMember::new(&key(&pair[1])?, float(&pair[0])?)))
+                .collect::<Result<Vec<_>, String>>()?;
+            cmd(
+                CommandRequest::new_zadd(table, &key(&args[0])?, members),
+                Reply::Integer,
+            )
+        }
+        "ZREM" => {
+            arity(n >= 2)?;
+            cmd(
+                CommandRequest::new_zrem(table, &key(&args[0])?, keys(&args[1..])?),
+                Reply::Integer,
+            )
+        }
+        "ZRANGE" => {
+            arity(n == 3 || n == 4)?;
+            let withscores = match args.get(3) {
+                Some(arg) if is_option(arg, "WITHSCORES") => true,
+                Some(_) => return Err("ERR syntax error".into()),
+                None => false,
+            };
+            cmd(
+                CommandRequest::new_zrange(table, &key(&args[0])?, int(&args[1])?, int(&args[2])?),
+                Reply::Scores {
+                    withscores,
+                    offset: 0,
+                },
+            )
+        }
+        "ZRANGEBYSCORE" => {
+            arity(n >= 3)?;
+            let (mut withscores, mut offset, mut limit, mut empty) = (false, 0, 0, false);
+            let mut rest = args[3..].iter();
+            while let Some(arg) = rest.next() {
+                if is_option(arg, "WITHSCORES") {
+                    withscores = true;
+                } else if is_option(arg, "LIMIT") {
+                    let (Some(o), Some(c)) = (rest.next(), rest.next()) else {
+                        return Err("ERR syntax error".into());
+                    };
+                    let (o, c) = (int(o)?, int(c)?);
+                    // 与 redis 一致：offset 为负数或者 count 为 0 时返回空的结果，count 为负数表示不限制数量
+                    empty = o < 0 || c == 0;
+                    offset = o.max(0) as usize;
+                    limit = match c {
+                        c if c < 0 => 0,
+                        c => (offset as u64 + c as u64).clamp(1, u32::MAX as u64) as u32,
+                    };
+                } else {
+                    return Err("ERR syntax error".into());
+                }
+            }
+            let (key, min, max) = (key(&args[0])?, float(&args[1])?, float(&args[2])?);
+            if empty {
+                return Ok(Parsed::Reply(RespFrame::Array(vec![])));
+            }
+            cmd(
+                CommandRequest::new_zrangebyscore(table, &key, min, max, limit),
+                Reply::Scores { withscores, offset },
+            )
+        }
+        "ZRANK" => {
+            arity(n == 2)?;
+            cmd(
+                CommandRequest::new_zrank(table, &key(&args[0])?, &key(&args[1])?),
+                Reply::Integer,
+            )
+        }
+        "PUBLISH" => {
+            arity(n == 2)?;
+            cmd(
+                CommandRequest::new_publish(&key(&args[0])?, vec![to_value(&args[1])]),
+                Reply::Integer,
+            )
+        }
+
+        // 连接相关的命令，不访问存储
+        "PING" => {
+            arity(n <= 1)?;
+            Ok(Parsed::Reply(match args.first() {
+                Some(msg) => RespFrame::Bulk(msg.clone()),
+                None => RespFrame::Simple("PONG".into()),
+            }))
+        }
+        "ECHO" => {
+            arity(n == 1)?;
+            Ok(Parsed::Reply(RespFrame::Bulk(args[0].clone())))
+        }
+        // 只支持协议版本，AUTH 和 SETNAME 等选项被忽略
+        "HELLO" => match args.first() {
+            None => Ok(Parsed::Hello(None)),
+            Some(arg) => match int(arg) {
+                Ok(version @ 2..=3) => Ok(Parsed::Hello(Some(version as u8))),
+                _ => Err("NOPROTO unsupported protocol version".into()),
+            },
+        },
+        "SELECT" => {
+            arity(n == 1)?;
+            match int(&args[0])? {
+                0 => Ok(Parsed::Reply(RespFrame::ok())),
+                _ => Err("ERR DB index is out of range".into()),
+            }
+        }
+        // 客户端库连接时会发送这些命令，返回空的结果即可
+        "COMMAND" => Ok(Parsed::Reply(RespFrame::Array(vec![]))),
+        "CLIENT" => Ok(Parsed::Reply(RespFrame::ok())),
+        "QUIT" => Ok(Parsed::Quit),
+        _ => Err(format!(
+            "ERR unknown command '{}'",
+            name.to_ascii_lowercase()
+        )),
+    }
+}
+
+// HELLO 的回复，proto 是当前连接使用的协议版本
+pub(crate) fn hello(resp3: bool) -> RespFrame {
+    let field = |k: &str, v: RespFrame| (RespFrame::bulk(k.to_owned()), v);
+    RespFrame::Map(vec![
+        field("server", RespFrame::bulk("kv")),
+        field("version", RespFrame::bulk(env!("CARGO_PKG_VERSION"))),
+        field("proto", RespFrame::Integer(if resp3 { 3 } else { 2 })),
+        field("mode", RespFrame::bulk("standalone")),
+        field("role", RespFrame::bulk("master")),
+        field("modules", RespFrame::Array(vec![])),
+    ])
+}
+
+// 把 Service 的响应按照命令对应的方式转换成 redis 的回复
+pub(crate) fn reply(kind: Reply, res: CommandResponse) -> RespFrame {
+    if res.status != StatusCode::OK.as_u16() as u32 {
+        return error(kind, res);
+    }
+    let CommandResponse { values, pairs, .. } = res;
+    let mut values = values.into_iter();
+    match kind {
+        Reply::Ok => RespFrame::ok(),
+        Reply::Data => values.next().map_or(RespFrame::Null, value_frame),
+        Reply::Integer => values
+            .next()
+            .and_then(|v| integer(&v))
+            .map_or(RespFrame::Null, RespFrame::Integer),
+        Reply::Bool => RespFrame::Integer(values.next().and_then(|v| integer(&v)).unwrap_or(0)),
+        Reply::List => RespFrame::Array(values.map(value_frame).collect()),
+        Reply::Set => RespFrame::Set(values.map(value_frame).collect()),
+        Reply::Added => RespFrame::Integer(values.filter(|v| v.value.is_none()).count() as i64),
+        Reply::Removed => RespFrame::Integer(values.filter(|v| v.value.is_some()).count() as i64),
+        Reply::Trues => RespFrame::Integer(
+            values
+                .filter(|v| v.value == Some(value::Value::BoolValue(true)))
+                .count() as i64,
+        ),
+        Reply::Map => RespFrame::Map(
+            pairs
+                .into_iter()
+                .map(|pair| {
+                    (
+                        RespFrame::bulk(pair.key),
+                        pair.value.map_or(RespFrame::Null, value_frame),
+                    )
+                })
+                .collect(),
+        ),
+        Reply::Keys => RespFrame::Array(
+            pairs
+                .into_iter()
+                .map(|pair| RespFrame::bulk(pair.key))
+                .collect(),
+        ),
+        Reply::Values => RespFrame::Array(
+            pairs
+                .into_iter()
+                .map(|pair| pair.value.map_or(RespFrame::Null, value_frame))
+                .collect(),
+        ),
+        Reply::Len => RespFrame::Integer(
+            pairs
+                .iter()
+                .find(|pair| pair.key == "keys")
+                .and_then(|pair| pair.value.as_ref())
+                .and_then(integer)
+                .unwrap_or(0),
+        ),
+        Reply::Found => {
+            let count = pairs
+                .iter()
+                .find(|pair| pair.key == "keys")
+                .and_then(|pair| pair.value.as_ref())
+                .or(values.next().as_ref())
+                .and_then(integer)
+                .unwrap_or(0);
+            RespFrame::Integer((count > 0) as i64)
+        }
+        // 与 redis 一致：-2 和 -1 原样返回，其它的四舍五入到对应的单位
+        Reply::Ttl(unit) => match values.next().and_then(|v| integer(&v)) {
+            Some(ttl) if ttl >= 0 => RespFrame::Integer((ttl + unit / 2) / unit),
+            Some(ttl) => RespFrame::Integer(ttl),
+            None => RespFrame::Null,
+        },
+        Reply::Pop(with_count) => {
+            let popped: Vec<_> = values.map(value_frame).collect();
+            match (popped.is_empty(), with_count) {
+                (true, _) => RespFrame::Null,
+                (false, true) => RespFrame::Array(popped),
+                (false, false) => popped.into_iter().next().unwrap_or(RespFrame::Null),
+            }
+        }
+        Reply::Scores { withscores, offset } => RespFrame::Array(
+            pairs
+                .into_iter()
+                .skip(offset)
+                .flat_map(|pair| {
+                    let score = pair.value.as_ref().and_then(|v| f64::try_from(v).ok());
+                    let member = RespFrame::bulk(pair.key);
+                    match (withscores, score) {
+                        (true, Some(score)) => vec![member, RespFrame::Double(score)],
+                        _ => vec![member],
+                    }
+                })
+                .collect(),
+        ),
+    }
+}
+
+fn error(kind: Reply, res: CommandResponse) -> RespFrame {
+    let status = StatusCode::from_u16(res.status as u16).unwrap_or(StatusCode::OK);
+    match status {
+        StatusCode::NOT_FOUND if matches!(kind, Reply::Data | Reply::Integer) => RespFrame::Null,
+        StatusCode::NOT_FOUND if matches!(kind, Reply::Len | Reply::Found) => RespFrame::Integer(0),
+        // 响应中只有状态码和消息，按 KvError::WrongType 的消息区分类型错误和其它的 400
+        StatusCode::BAD_REQUEST if res.message.starts_with("wrong type:") => {
+            RespFrame::Error(format!("WRONGTYPE {}", res.message))
+        }
+        StatusCode::FORBIDDEN => RespFrame::Error(format!("NOPERM {}", res.message)),
+        // follower 只读，消息中带有 leader 的地址
+        StatusCode::TEMPORARY_REDIRECT => RespFrame::Error(format!("READONLY {}", res.message)),
+        _ => RespFrame::Error(format!("ERR {}", res.message)),
+    }
+}
+
+fn value_frame(value: Value) -> RespFrame {
+    match value.value {
+        None => RespFrame::Null,
+        Some(value::Value::StringValue(s)) => RespFrame::bulk(s),
+        Some(value::Value::BytesValue(b)) => RespFrame::bulk(b),
+        Some(value::Value::Int64Value(n)) => RespFrame::bulk(n.to_string()),
+        Some(value::Value::DoubleValue(n)) => RespFrame::bulk(format_double(n)),
+        Some(value::Value::BoolValue(b)) => RespFrame::Boolean(b),
+        Some(value::Value::ListValue(list)) => {
+            RespFrame::Array(list.values.into_iter().map(value_frame).collect())
+        }
+        Some(value::Value::SetValue(set)) => {
+            RespFrame::Set(set.members.into_iter().map(RespFrame::bulk).collect())
+        }
+        Some(value::Value::ZsetValue(zset)) => RespFrame::Array(
+            zset.members
+                .into_iter()
+                .flat_map(|m| [RespFrame::bulk(m.member), RespFrame::Double(m.score)])
+                .collect(),
+        ),
+    }
+}
+
+fn integer(value: &Value) -> Option<i64> {
+    match value.value {
+        Some(value::Value::Int64Value(n)) => Some(n),
+        Some(value::Value::BoolValue(b)) => Some(b as i64),
+        _ => None,
+    }
+}
+
+// redis 的参数都是字符串，能够原样还原的整数和浮点数按数值保存，这样 INCR 等命令可以使用
+fn to_value(arg: &Bytes) -> Value {
+    match std::str::from_utf8(arg) {
+        Ok(s) => match (s.parse::<i64>(), s.parse::<f64>()) {
+            (Ok(n), _) if n.to_string() == s => n.into(),
+            (_, Ok(n)) if n.is_finite() && format_double(n) == s => n.into(),
+            _ => s.into(),
+        },
+        Err(_) => arg.as_ref().into(),
+    }
+}
+
+fn key(arg: &Bytes) -> Result<String, String> {
+    String::from_utf8(arg.to_vec()).map_err(|_| "ERR key must be valid utf-8".to_string())
+}
+
+fn keys(args: &[Bytes]) -> Result<Vec<String>, String> {
+    args.iter().map(key).collect()
+}
+
+fn int(arg: &Bytes) -> Result<i64, String> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| "ERR value is not an integer or out of range".to_string())
+}
+
+fn float(arg: &Bytes) -> Result<f64, String> {
+    let n = match std::str::from_utf8(arg)
+        .map(str::to_ascii_lowercase)
+        .as_deref()
+    {
+        Ok("inf" | "+inf") => Some(f64::INFINITY),
+        Ok("-inf") => Some(f64::NEG_INFINITY),
+        Ok(s) => s.parse::<f64>().ok().filter(|n| !n.is_nan()),
+        Err(_) => None,
+    };
+    n.ok_or_else(|| "ERR value is not a valid float".to_string())
+}
+
+// 过期时间必须是正数
+fn ttl(arg: &Bytes) -> Result<u64, String> {
+    match int(arg)? {
+        ttl if ttl > 0 => Ok(ttl as u64),
+        _ => Err("ERR invalid expire time".into()),
+    }
+}
+
+fn is_option(arg: &Bytes, name: &str) -> bool {
+    arg.eq_ignore_ascii_case(name.as_bytes())
+}
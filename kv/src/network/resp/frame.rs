@@ -0,0 +1,529 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{error::KvError, network::frame::MAX_FRAME};
+
+// 单个 bulk string 和一行的最大长度，整个请求（包括所有元素的类型和长度行）不超过 MAX_FRAME
+const MAX_BULK: usize = 16 * 1024 * 1024;
+const MAX_LINE: usize = 64 * 1024;
+// 数组最多的元素数量和嵌套层数，避免恶意的请求耗尽内存或栈
+const MAX_ELEMENTS: usize = 1024 * 1024;
+const MAX_DEPTH: usize = 32;
+
+// RESP2 和 RESP3 的数据类型，客户端的请求通常是 bulk string 的数组
+#[derive(Debug, Clone, PartialEq)]
+pub enum RespFrame {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Bytes),
+    Array(Vec<RespFrame>),
+    // RESP3 的类型，RESP2 的连接上编码时转换成对应的 RESP2 类型
+    Null,
+    Double(f64),
+    Boolean(bool),
+    Map(Vec<(RespFrame, RespFrame)>),
+    Set(Vec<RespFrame>),
+}
+
+impl RespFrame {
+    pub fn ok() -> Self {
+        RespFrame::Simple("OK".into())
+    }
+
+    pub fn error(msg: impl Into<String>) -> Self {
+        RespFrame::Error(msg.into())
+    }
+
+    pub fn bulk(data: impl Into<Bytes>) -> Self {
+        RespFrame::Bulk(data.into())
+    }
+}
+
+// 默认使用 RESP2，客户端发送 HELLO 3 后切换到 RESP3
+#[derive(Debug, Clone, Default)]
+pub struct RespCodec {
+    resp3: bool,
+    // 还没有收完的请求已经检查过的部分，下次从这里继续
+    scan: Scan,
+}
+
+// 只计算请求的长度，不复制数据：pos 之前的元素都已经完整，
+// remaining 是每一层还没有收到的元素数量，收完整个请求后再一次解析
+#[derive(Debug, Clone, Default)]
+struct Scan {
+    pos: usize,
+    remaining: Vec<usize>,
+}
+
+impl Scan {
+    // 请求完整时返回它的长度，数据不足时记录进度并返回 None
+    fn advance(&mut self, buf: &[u8]) -> Result<Option<usize>, KvError> {
+        loop {
+            let Some(&kind) = buf.get(self.pos) else {
+                return Ok(None);
+            };
+            let rest = &buf[self.pos..];
+            let Some(end) = rest.windows(2).position(|w| w == b"\r\n") else {
+                if rest.len() > MAX_LINE {
+                    return Err(KvError::FrameTooLarge(rest.len()));
+                }
+                return Ok(None);
+            };
+            let mut next = self.pos + end + 2;
+            match kind {
+                b'$' => {
+                    let len = number(&rest[1..end])?;
+                    if len >= 0 {
+                        if len as usize > MAX_BULK {
+                            return Err(KvError::FrameTooLarge(len as usize));
+                        }
+                        next += len as usize + 2;
+                    }
+                }
+                b'*' | b'~' | b'%' => {
+                    let count = number(&rest[1..end])?;
+                    let count = count.max(0) as usize * if kind == b'%' { 2 } else { 1 };
+                    if count > MAX_ELEMENTS {
+                        return Err(KvError::FrameTooLarge(count));
+                    }
+                    if count > 0 {
+                        if self.remaining.len() >= MAX_DEPTH {
+                            return Err(KvError::InvalidFrame(
+                                "resp frame is nested too deep".into(),
+                            ));
+                        }
+                        self.remaining.push(count);
+                        self.pos = next;
+                        continue;
+                    }
+                }
+                // 其它类型只有一行，非法的类型由 parse 报错
+                _ => {}
+            }
+            if next > MAX_FRAME {
+                return Err(KvError::FrameTooLarge(next));
+            }
+            if buf.len() < next {
+                return Ok(None);
+            }
+            self.pos = next;
+            // 一个元素完整之后，所在的数组可能也完整了
+            loop {
+                match self.remaining.last_mut() {
+                    None => return Ok(Some(self.pos)),
+                    Some(1) => {
+                        self.remaining.pop();
+                    }
+                    Some(n) => {
+                        *n -= 1;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl RespCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn resp3(&self) -> bool {
+        self.resp3
+    }
+
+    pub fn set_resp3(&mut self, resp3: bool) {
+        self.resp3 = resp3;
+    }
+}
+
+impl Decoder for RespCodec {
+    type Item = RespFrame;
+    type Error = KvError;
+
+    // 数据不足一个完整的请求时返回 Ok(None)，不消耗数据，已经收到的元素不会重复检查
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let Some(len) = self.scan.advance(src)? else {
+            return Ok(None);
+        };
+        self.scan = Scan::default();
+        match parse(&src[..len], 0)? {
+            Some((frame, parsed)) if parsed == len => {
+                src.advance(len);
+                Ok(Some(frame))
+            }
+            _ => Err(KvError::InvalidFrame("incomplete resp frame".into())),
+        }
+    }
+}
+
+impl Encoder<RespFrame> for RespCodec {
+    type Error = KvError;
+
+    fn encode(&mut self, item: RespFrame, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        encode(&item, self.resp3, dst);
+        Ok(())
+    }
+}
+
+fn parse(buf: &[u8], depth: usize) -> Result<Option<(RespFrame, usize)>, KvError> {
+    if depth > MAX_DEPTH {
+        return Err(KvError::InvalidFrame(
+            "resp frame is nested too deep".into(),
+        ));
+    }
+    let Some(end) = buf.windows(2).position(|w| w == b"\r\n") else {
+        if buf.len() > MAX_LINE {
+            return Err(KvError::FrameTooLarge(buf.len()));
+        }
+        return Ok(None);
+    };
+    let Some(&kind) = buf.first() else {
+        return Ok(None);
+    };
+    // 空行没有类型前缀，按 inline 命令处理
+    let line = buf.get(1..end).unwrap_or_default();
+    let mut pos = end + 2;
+    let frame = match kind {
+        b'+' => RespFrame::Simple(text(line)?.into()),
+        b'-' => RespFrame::Error(text(line)?.into()),
+        b':' => RespFrame::Integer(number(line)?),
+        b'$' => {
+            let len = number(line)?;
+            if len < 0 {
+                return Ok(Some((RespFrame::Null, pos)));
+            }
+            let len = len as usize;
+            if len > MAX_BULK {
+                return Err(KvError::FrameTooLarge(len));
+            }
+            if buf.len() < pos + len + 2 {
+                return Ok(None);
+            }
+            if &buf[pos + len..pos + len + 2] != b"\r\n" {
+                return Err(KvError::InvalidFrame(
+                    "bulk string is not terminated by CRLF".into(),
+                ));
+            }
+            let data = Bytes::copy_from_slice(&buf[pos..pos + len]);
+            pos += len + 2;
+            RespFrame::Bulk(data)
+        }
+        b'*' | b'~' | b'%' => {
+            let count = number(line)?;
+            if count < 0 {
+                return Ok(Some((RespFrame::Null, pos)));
+            }
+            // map 的每一项包含 key 和 value 两个元素
+            let count = count as usize * if kind == b'%' { 2 } else { 1 };
+            if count > MAX_ELEMENTS {
+                return Err(KvError::FrameTooLarge(count));
+            }
+            let mut items = Vec::with_capacity(count.min(1024));
+            for _ in 0..count {
+                match parse(&buf[pos..], depth + 1)? {
+                    Some((item, len)) => {
+                        items.push(item);
+                        pos += len;
+                    }
+                    None => return Ok(None),
+                }
+            }
+            match kind {
+                b'*' => RespFrame::Array(items),
+                b'~' => RespFrame::Set(items),
+                _ => {
+                    let mut items = items.into_iter();
+                    let mut pairs = Vec::with_capacity(count / 2);
+                    while let (Some(k), Some(v)) = (items.next(), items.next()) {
+                        pairs.push((k, v));
+                    }
+                    RespFrame::Map(pairs)
+                }
+            }
+        }
+        b'_' => RespFrame::Null,
+        b',' => RespFrame::Double(double(line)?),
+        b'#' => match line {
+            b"t" => RespFrame::Boolean(true),
+            b"f" => RespFrame::Boolean(false),
+            _ => return Err(KvError::InvalidFrame("invalid resp boolean".into())),
+        },
+        // 其它的类型只在服务端推送时使用，客户端的请求中不会出现
+        b'(' | b'=' | b'>' | b'|' | b'!' => {
+            return Err(KvError::InvalidFrame(format!(
+                "unsupported resp type {}",
+                kind as char
+            )));
+        }
+        // 嵌套在数组中的元素必须有类型前缀
+        _ if depth > 0 => {
+            return Err(KvError::InvalidFrame(format!(
+                "invalid resp type {}",
+                kind as char
+            )));
+        }
+        // inline 命令，例如 telnet 中输入的 PING，参数以空白分隔
+        _ => RespFrame::Array(
+            buf[..end]
+                .split(|b| b.is_ascii_whitespace())
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| RespFrame::Bulk(Bytes::copy_from_slice(arg)))
+                .collect(),
+        ),
+    };
+    Ok(Some((frame, pos)))
+}
+
+fn text(line: &[u8]) -> Result<&str, KvError> {
+    std::str::from_utf8(line).map_err(|e| KvError::InvalidFrame(e.to_string()))
+}
+
+fn number(line: &[u8]) -> Result<i64, KvError> {
+    text(line)?.parse().map_err(|_| {
+        KvError::InvalidFrame(format!(
+            "invalid resp number {}",
+            String::from_utf8_lossy(line)
+        ))
+    })
+}
+
+fn double(line: &[u8]) -> Result<f64, KvError> {
+    match text(line)? {
+        "inf" => Ok(f64::INFINITY),
+        "-inf" => Ok(f64::NEG_INFINITY),
+        s => s
+            .parse()
+            .map_err(|_| KvError::InvalidFrame(format!("invalid resp double {:?}", s))),
+    }
+}
+
+// 与 redis 一致：整数不带小数点，无穷大为 inf/-inf
+pub(crate) fn format_double(value: f64) -> String {
+    if value.is_nan() {
+        "nan".into()
+    } else if value.is_infinite() {
+        if value > 0.0 { "inf" } else { "-inf" }.into()
+    } else {
+        value.to_string()
+    }
+}
+
+fn encode(frame: &RespFrame, resp3: bool, dst: &mut BytesMut) {
+    match frame {
+        // simple string 和 error 中不能包含换行
+        RespFrame::Simple(s) => line(dst, b'+', &s.replace(['\r', '\n'], " ")),
+        RespFrame::Error(s) => line(dst, b'-', &s.replace(['\r', '\n'], " ")),
+        RespFrame::Integer(n) => line(dst, b':', &n.to_string()),
+        RespFrame::Bulk(data) => bulk(dst, data),
+        RespFrame::Array(items) => {
+            line(dst, b'*', &items.len().to_string());
+            items.iter().for_each(|item| encode(item, resp3, dst));
+        }
+        RespFrame::Null if resp3 => dst.put_slice(b"_\r\n"),
+        RespFrame::Null => dst.put_slice(b"$-1\r\n"),
+        RespFrame::Double(n) if resp3 => line(dst, b',', &format_double(*n)),
+        RespFrame::Double(n) => bulk(dst, format_double(*n).as_bytes()),
+        RespFrame::Boolean(b) if resp3 => line(dst, b'#', if *b { "t" } else { "f" }),
+        RespFrame::Boolean(b) => line(dst, b':', if *b { "1" } else { "0" }),
+        // RESP2 中 map 展开成 key、value 交替的数组
+        RespFrame::Map(pairs) => {
+            if resp3 {
+                line(dst, b'%', &pairs.len().to_string());
+            } else {
+                line(dst, b'*', &(pairs.len() * 2).to_string());
+            }
+            for (k, v) in pairs {
+                encode(k, resp3, dst);
+                encode(v, resp3, dst);
+            }
+        }
+        RespFrame::Set(items) => {
+            line(
+                dst,
+                if resp3 { b'~' } else { b'*' },
+                &items.len().to_string(),
+            );
+            items.iter().for_each(|item| encode(item, resp3, dst));
+        }
+    }
+}
+
+fn line(dst: &mut BytesMut, kind: u8, s: &str) {
+    dst.reserve(s.len() + 3);
+    dst.put_u8(kind);
+    dst.put_slice(s.as_bytes());
+    dst.put_slice(b"\r\n");
+}
+
+fn bulk(dst: &mut BytesMut, data: &[u8]) {
+    line(dst, b'$', &data.len().to_string());
+    dst.reserve(data.len() + 2);
+    dst.put_slice(data);
+    dst.put_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resp_decode_should_work() {
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::from(
+            &b"*3\r\n$4\r\nHGET\r\n$0\r\n\r\n$-1\r\n:-42\r\n+OK\r\n-ERR bad\r\n_\r\n,1.5\r\n#t\r\n%1\r\n+k\r\n~1\r\n:1\r\n"[..],
+        );
+        let frames: Vec<_> = std::iter::from_fn(|| codec.decode(&mut buf).unwrap()).collect();
+        assert_eq!(
+            frames,
+            vec![
+                RespFrame::Array(vec![
+                    RespFrame::bulk("HGET"),
+                    RespFrame::bulk(""),
+                    RespFrame::Null,
+                ]),
+                RespFrame::Integer(-42),
+                RespFrame::ok(),
+                RespFrame::error("ERR bad"),
+                RespFrame::Null,
+                RespFrame::Double(1.5),
+                RespFrame::Boolean(true),
+                RespFrame::Map(vec![(
+                    RespFrame::Simple("k".into()),
+                    RespFrame::Set(vec![RespFrame::Integer(1)])
+                )]),
+            ]
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn resp_decode_should_wait_for_complete_frame() {
+        let mut codec = RespCodec::new();
+        let data = b"*2\r\n$4\r\nPING\r\n$5\r\nhello\r\n";
+        let mut buf = BytesMut::new();
+        for (i, b) in data.iter().enumerate() {
+            buf.put_u8(*b);
+            let frame = codec.decode(&mut buf).unwrap();
+            // 只有最后一个字节到达后才能解析出完整的请求
+            assert_eq!(frame.is_some(), i == data.len() - 1);
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn resp_decode_should_resume_from_last_element() {
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::from(&b"*3\r\n$4\r\nPING\r\n$5\r\nhel"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        // 已经完整的元素不再检查
+        assert_eq!(codec.scan.pos, 14);
+        assert_eq!(codec.scan.remaining, vec![2]);
+        buf.put_slice(b"lo\r\n*1\r\n:1\r\n");
+        assert_eq!(codec.scan.advance(&buf).unwrap(), Some(buf.len()));
+
+        // 大量元素逐块到达时也能正确解析
+        let mut codec = RespCodec::new();
+        let count = 100_000;
+        let mut data = format!("*{}\r\n", count).into_bytes();
+        for i in 0..count {
+            data.extend_from_slice(format!("${}\r\n{}\r\n", i.to_string().len(), i).as_bytes());
+        }
+        let mut buf = BytesMut::new();
+        let mut frames = Vec::new();
+        for chunk in data.chunks(7) {
+            buf.put_slice(chunk);
+            frames.extend(codec.decode(&mut buf).unwrap());
+        }
+        assert_eq!(frames.len(), 1);
+        let RespFrame::Array(items) = &frames[0] else {
+            panic!("array expected");
+        };
+        assert_eq!(items.len(), count);
+        assert_eq!(items[count - 1], RespFrame::bulk((count - 1).to_string()));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn resp_decode_should_limit_frame_size() {
+        // 每个 bulk string 都没有超过限制，但是整个请求超过了 MAX_FRAME，不等数据到达就报错
+        let mut codec = RespCodec::new();
+        let half = MAX_FRAME / 2;
+        let mut buf = BytesMut::from(format!("*2\r\n${}\r\n", half).as_bytes());
+        buf.put_bytes(b'x', half);
+        buf.put_slice(format!("\r\n${}\r\n", half).as_bytes());
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(KvError::FrameTooLarge(len)) if len > MAX_FRAME
+        ));
+    }
+
+    #[test]
+    fn resp_decode_should_parse_inline_command() {
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::from(&b"HGET  user name\r\n\r\n"[..]);
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(RespFrame::Array(vec![
+                RespFrame::bulk("HGET"),
+                RespFrame::bulk("user"),
+                RespFrame::bulk("name"),
+            ]))
+        );
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(RespFrame::Array(vec![]))
+        );
+    }
+
+    #[test]
+    fn resp_decode_should_reject_invalid_frame() {
+        let mut codec = RespCodec::new();
+        for data in [
+            &b"$3\r\nabcd\r\n"[..],
+            b"*1\r\nPING\r\n",
+            b":abc\r\n",
+            b"$99999999999\r\n",
+            b">1\r\n:1\r\n",
+        ] {
+            let mut buf = BytesMut::from(data);
+            assert!(codec.decode(&mut buf).is_err(), "{:?}", data);
+        }
+        let mut buf = BytesMut::from(&vec![b'x'; MAX_LINE + 1][..]);
+        assert_eq!(
+            codec.decode(&mut buf),
+            Err(KvError::FrameTooLarge(MAX_LINE + 1))
+        );
+        let mut buf = BytesMut::from(&b"*1\r\n".repeat(MAX_DEPTH + 2)[..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn resp_encode_should_downgrade_for_resp2() {
+        let frame = RespFrame::Array(vec![
+            RespFrame::Null,
+            RespFrame::Double(3.0),
+            RespFrame::Boolean(true),
+            RespFrame::Map(vec![(RespFrame::bulk("k"), RespFrame::Integer(1))]),
+            RespFrame::Set(vec![RespFrame::Double(f64::NEG_INFINITY)]),
+            RespFrame::error("ERR a\r\nb"),
+        ]);
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode(frame.clone(), &mut buf).unwrap();
+        assert_eq!(
+            &buf[..],
+            b"*6\r\n$-1\r\n$1\r\n3\r\n:1\r\n*2\r\n$1\r\nk\r\n:1\r\n*1\r\n$4\r\n-inf\r\n-ERR a  b\r\n"
+        );
+
+        codec.set_resp3(true);
+        let mut buf = BytesMut::new();
+        codec.encode(frame, &mut buf).unwrap();
+        assert_eq!(
+            &buf[..],
+            b"*6\r\n_\r\n,3\r\n#t\r\n%1\r\n$1\r\nk\r\n:1\r\n~1\r\n,-inf\r\n-ERR a  b\r\n"
+        );
+    }
+}
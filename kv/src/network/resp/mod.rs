@@ -0,0 +1,241 @@
+mod command;
+mod frame;
+
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
+use tracing::debug;
+
+use crate::{Service, error::KvError, storage::AsyncStorage};
+use command::Parsed;
+pub use frame::{RespCodec, RespFrame};
+
+// GET/SET 等没有表名的命令默认使用的表
+pub const DEFAULT_RESP_TABLE: &str = "default";
+
+// redis 协议的一个连接，redis-cli 和 redis 的客户端库可以直接访问 kv
+// 请求按顺序执行，和 KvServerStream 一样经过 Service 的钩子、授权和统计
+pub struct RespServerStream<T, S> {
+    inner: Framed<T, RespCodec>,
+    service: Service<S>,
+    table: String,
+    identity: Option<String>,
+}
+
+impl<T, S> RespServerStream<T, S>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+    S: AsyncStorage + Send + Sync + 'static,
+{
+    pub fn new(stream: T, service: Service<S>) -> Self {
+        Self {
+            inner: Framed::new(stream, RespCodec::new()),
+            service,
+            table: DEFAULT_RESP_TABLE.into(),
+            identity: None,
+        }
+    }
+
+    // GET/SET、列表、集合等命令所在的表，hash 命令使用 redis 的 key 作为表名
+    pub fn with_table(mut self, table: impl Into<String>) -> Self {
+        self.table = table.into();
+        self
+    }
+
+    // 命令经过 ACL 授权时使用的身份，没有时作为匿名的客户端
+    pub fn with_identity(mut self, identity: Option<String>) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    pub async fn process(mut self) -> Result<(), KvError> {
+        while let Some(frame) = self.inner.next().await {
+            let frame = match frame {
+                Ok(frame) => frame,
+                // 协议错误后无法继续解析，回复错误后关闭连接
+                Err(e) => {
+                    let msg = format!("ERR Protocol error: {}", e);
+                    self.inner.send(RespFrame::Error(msg)).await?;
+                    return Err(e);
+                }
+            };
+            debug!("Got a new resp request: {:?}", frame);
+            match command::parse(frame, &self.table) {
+                Parsed::Skip => {}
+                Parsed::Reply(reply) => self.inner.send(reply).await?,
+                Parsed::Hello(version) => {
+                    if let Some(version) = version {
+                        self.inner.codec_mut().set_resp3(version == 3);
+                    }
+                    let reply = command::hello(self.inner.codec().resp3());
+                    self.inner.send(reply).await?;
+                }
+                Parsed::Quit => {
+                    self.inner.send(RespFrame::ok()).await?;
+                    return Ok(());
+                }
+                Parsed::Command(cmd, kind) => {
+                    let res = self.service.exec_as(cmd, self.identity.as_deref()).await;
+                    self.inner.send(command::reply(kind, res)).await?;
+                    self.service.after_send().await;
+                }
+                Parsed::Sum(cmds) => {
+                    let mut sum = 0;
+                    let mut reply = None;
+                    for (cmd, kind) in cmds {
+                        match command::reply(
+                            kind,
+                            self.service.exec_as(cmd, self.identity.as_deref()).await,
+                        ) {
+                            RespFrame::Integer(n) => sum += n,
+                            frame => {
+                                reply = Some(frame);
+                                break;
+                            }
+                        }
+                    }
+                    let reply = reply.unwrap_or(RespFrame::Integer(sum));
+                    self.inner.send(reply).await?;
+                    self.service.after_send().await;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::{
+        BlockingStorage, CommandRequest, MemTable, ServiceInner, command_request::RequestData,
+    };
+
+    // 把请求原样写到连接上，读取服务端的全部回复
+    async fn run(service: Service, input: &[u8]) -> Vec<u8> {
+        let (client, server) = tokio::io::duplex(4096);
+        let handle = tokio::spawn(RespServerStream::new(server, service).process());
+        let (mut reader, mut writer) = tokio::io::split(client);
+        writer.write_all(input).await.unwrap();
+        writer.shutdown().await.unwrap();
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).await.unwrap();
+        let _ = handle.await.unwrap();
+        output
+    }
+
+    fn service() -> Service {
+        ServiceInner::new(BlockingStorage::new(MemTable::new())).into()
+    }
+
+    async fn assert_fixture(input: &[u8], expected: &[u8]) {
+        let output = run(service(), input).await;
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            String::from_utf8_lossy(expected)
+        );
+    }
+
+    #[tokio::test]
+    async fn resp2_hash_commands_should_work() {
+        assert_fixture(
+            include_bytes!("../../../fixtures/resp/hash.in"),
+            include_bytes!("../../../fixtures/resp/hash.out"),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn resp3_hash_commands_should_work() {
+        assert_fixture(
+            include_bytes!("../../../fixtures/resp/hash_resp3.in"),
+            include_bytes!("../../../fixtures/resp/hash_resp3.out"),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn resp_keyspace_commands_should_work() {
+        assert_fixture(
+            include_bytes!("../../../fixtures/resp/keyspace.in"),
+            include_bytes!("../../../fixtures/resp/keyspace.out"),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn resp_collection_commands_should_work() {
+        assert_fixture(
+            include_bytes!("../../../fixtures/resp/collection.in"),
+            include_bytes!("../../../fixtures/resp/collection.out"),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn resp_should_use_configured_table() {
+        let service = service();
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(
+            RespServerStream::new(server, service.clone())
+                .with_table("redis")
+                .process(),
+        );
+        let (mut reader, mut writer) = tokio::io::split(client);
+        writer
+            .write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n")
+            .await
+            .unwrap();
+        let mut buf = [0u8; 5];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"+OK\r\n");
+
+        let res = service.exec(CommandRequest::new_hget("redis", "k")).await;
+        assert_eq!(res.values, vec!["v".into()]);
+    }
+
+    #[tokio::test]
+    async fn resp_should_run_service_hooks() {
+        let after_send = Arc::new(AtomicUsize::new(0));
+        let counter = after_send.clone();
+        let service: Service = ServiceInner::new(BlockingStorage::new(MemTable::new()))
+            .fn_intercept(|cmd| match &cmd.request_data {
+                Some(RequestData::Hget(params)) if params.table == "secret" => {
+                    Some(KvError::PermissionDenied("secret is not readable".into()).into())
+                }
+                _ => None,
+            })
+            .fn_after_send(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .into();
+
+        // PING 不经过 Service，不触发 after_send
+        let output = run(
+            service,
+            b"*3\r\n$4\r\nHGET\r\n$6\r\nsecret\r\n$1\r\nk\r\n*3\r\n$4\r\nHGET\r\n$6\r\npublic\r\n$1\r\nk\r\nPING\r\n",
+        )
+        .await;
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "-NOPERM permission denied: secret is not readable\r\n$-1\r\n+PONG\r\n"
+        );
+        assert_eq!(after_send.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn resp_should_close_on_protocol_error() {
+        let output = run(service(), b"PING\r\n*1\r\n+PING\r\n$x\r\nPING\r\n").await;
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "+PONG\r\n-ERR Protocol error: expected bulk string\r\n-ERR Protocol error: invalid frame: invalid resp number x\r\n"
+        );
+    }
+}
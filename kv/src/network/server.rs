@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::{Semaphore, mpsc},
+};
+use tokio_util::codec::Framed;
+use tracing::{debug, info};
+
+use crate::{
+    CommandRequest, CommandResponse, KvFrameCodec, Service, command_request::RequestData,
+    error::KvError, storage::AsyncStorage,
+};
+
+// 每个连接同时处理的请求上限，超出后暂停读取新的请求
+const MAX_IN_FLIGHT: usize = 128;
+
+// 服务端的一个连接，请求可以流水线发送，每个请求并发执行，响应带上请求 id 写回
+pub struct KvServerStream<T, S> {
+    inner: Framed<T, KvFrameCodec<CommandRequest>>,
+    service: Service<S>,
+    // 客户端证书中的身份，用于授权
+    identity: Option<Arc<str>>,
+}
+
+impl<T, S> KvServerStream<T, S>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+    S: AsyncStorage + Send + Sync + 'static,
+{
+    pub fn new(stream: T, service: Service<S>) -> Self {
+        let codec = KvFrameCodec::new().with_metrics(service.metrics());
+        Self {
+            inner: Framed::new(stream, codec),
+            service,
+            identity: None,
+        }
+    }
+
+    pub fn with_identity(mut self, identity: Option<String>) -> Self {
+        self.identity = identity.map(Arc::from);
+        self
+    }
+
+    pub async fn process(self) -> Result<(), KvError> {
+        let (mut sink, mut stream) = self.inner.split();
+        let (tx, mut rx) = mpsc::channel::<CommandResponse>(MAX_IN_FLIGHT);
+        let service = self.service;
+        let identity = self.identity;
+        let notifier = service.clone();
+
+        let read = async move {
+            let permits = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+            while let Some(cmd) = stream.next().await {
+                let cmd = cmd?;
+                debug!("Got a new command: {:?}", cmd);
+                let name = cmd.command_name();
+                let exclusive = matches!(
+                    cmd.request_data,
+                    Some(RequestData::Subscribe(_) | RequestData::Replicate(_))
+                );
+                let permit = permits.clone().acquire_owned().await.unwrap();
+                let mut responses = service.execute_as(cmd, identity.clone());
+                let tx = tx.clone();
+                let mut handle = tokio::spawn(async move {
+                    while let Some(res) = responses.next().await {
+                        if tx.send(res).await.is_err() {
+                            break;
+                        }
+                    }
+                    drop(permit);
+                });
+                // 订阅和复制独占连接，不再读取新的请求，取消订阅后连接随之关闭
+                if exclusive {
+                    info!("Connection is used by {}", name);
+                    // 客户端断开时结束数据流，释放订阅
+                    let closed = async { while let Some(Ok(_)) = stream.next().await {} };
+                    tokio::select! {
+                        _ = &mut handle => {}
+                        _ = closed => {
+                            info!("Connection used by {} is closed", name);
+                            handle.abort();
+                        }
+                    }
+                    break;
+                }
+            }
+            Ok::<_, KvError>(())
+        };
+
+        // 所有请求的响应都写完后（发送端全部释放）结束
+        let write = async move {
+            while let Some(res) = rx.recv().await {
+                sink.send(res).await?;
+                // send 会 flush，此时响应已经写到连接上
+                notifier.after_send().await;
+            }
+            Ok::<_, KvError>(())
+        };
+
+        tokio::try_join!(read, write)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,746 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use futures::future::join_all;
+use http::StatusCode;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{
+    CommandRequest, CommandResponse, KvClient, Kvpair, Value,
+    command::{DEFAULT_SCAN_LIMIT, MAX_SCAN_LIMIT},
+    command_request::RequestData,
+    error::KvError,
+    network::client::{check, first_value},
+    value,
+};
+
+// 每个节点在环上的虚拟节点数量，越多 key 分布越均匀
+const VIRTUAL_NODES: usize = 160;
+
+// 一致性哈希环：key 顺时针找到的第一个虚拟节点所属的节点负责这个 key
+// 增加或删除节点时，只有新节点接管的、或者被删除节点负责的 key 会改变归属
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    ring: BTreeMap<u64, String>,
+    vnodes: usize,
+}
+
+impl Default for HashRing {
+    fn default() -> Self {
+        Self::new(VIRTUAL_NODES)
+    }
+}
+
+impl HashRing {
+    pub fn new(vnodes: usize) -> Self {
+        Self {
+            ring: BTreeMap::new(),
+            vnodes: vnodes.max(1),
+        }
+    }
+
+    // 重复添加同一个节点不会改变环
+    pub fn add(&mut self, node: &str) {
+        for i in 0..self.vnodes {
+            let hash = hash(format!("{}#{}", node, i).as_bytes());
+            self.ring.insert(hash, node.into());
+        }
+    }
+
+    pub fn remove(&mut self, node: &str) {
+        self.ring.retain(|_, n| n != node);
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&str> {
+        let hash = hash(key);
+        self.ring
+            .range(hash..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, node)| node.as_str())
+    }
+
+    pub fn nodes(&self) -> Vec<&str> {
+        let nodes: BTreeSet<&str> = self.ring.values().map(String::as_str).collect();
+        nodes.into_iter().collect()
+    }
+}
+
+// FNV-1a 之后再做一次 splitmix64 的混合，相近的 key 也能均匀地分布在环上
+fn hash(data: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in data {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h ^ (h >> 31)
+}
+
+// 表名和 key 一起决定归属，同一个表的 key 分布在所有节点上
+fn shard_key(table: &str, key: &str) -> Vec<u8> {
+    let mut data = Vec::with_capacity(table.len() + key.len() + 1);
+    data.extend_from_slice(table.as_bytes());
+    data.push(0);
+    data.extend_from_slice(key.as_bytes());
+    data
+}
+
+// 分片的客户端：单 key 的命令发给负责它的节点，多 key 的命令按节点拆分后并发执行再合并，
+// Hgetall、Hscan 以及表管理命令发给所有节点后合并
+pub struct ShardedClient<S> {
+    ring: HashRing,
+    nodes: HashMap<String, KvClient<S>>,
+}
+
+impl<S> Default for ShardedClient<S> {
+    fn default() -> Self {
+        Self {
+            ring: HashRing::default(),
+            nodes: HashMap::new(),
+        }
+    }
+}
+
+impl<S> ShardedClient<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_virtual_nodes(vnodes: usize) -> Self {
+        Self {
+            ring: HashRing::new(vnodes),
+            nodes: HashMap::new(),
+        }
+    }
+
+    // name 通常是服务器的地址，同名的节点会被替换；已有的数据不会自动迁移
+    pub fn add_node(&mut self, name: impl Into<String>, client: KvClient<S>) {
+        let name = name.into();
+        self.ring.add(&name);
+        self.nodes.insert(name, client);
+    }
+
+    pub fn remove_node(&mut self, name: &str) -> Option<KvClient<S>> {
+        self.ring.remove(name);
+        self.nodes.remove(name)
+    }
+
+    pub fn nodes(&self) -> Vec<&str> {
+        self.ring.nodes()
+    }
+
+    // 负责这个 key 的节点
+    pub fn node_for(&self, table: &str, key: &str) -> Option<&str> {
+        self.ring.get(&shard_key(table, key))
+    }
+
+    // 负责这个主题的节点，订阅需要直接连接这个节点
+    pub fn node_for_topic(&self, topic: &str) -> Option<&str> {
+        self.ring.get(topic.as_bytes())
+    }
+
+    pub async fn execute(&mut self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        match &cmd.request_data {
+            Some(
+                RequestData::Hmget(_)
+                | RequestData::Hmset(_)
+                | RequestData::Hmdel(_)
+                | RequestData::Hmexists(_),
+            ) => self.split(cmd).await,
+            Some(
+                RequestData::Hgetall(_)
+                | RequestData::Hscan(_)
+                | RequestData::ListTables(_)
+                | RequestData::DropTable(_)
+                | RequestData::TableInfo(_),
+            ) => {
+                let responses = self.fan_out(&cmd).await?;
+                Ok(merge(&cmd, responses))
+            }
+            Some(RequestData::Publish(p)) => {
+                let node = self.node_for_topic(&p.topic).map(String::from);
+                self.send(node, cmd).await
+            }
+            Some(RequestData::Unsubscribe(p)) => {
+                let node = self.node_for_topic(&p.topic).map(String::from);
+                self.send(node, cmd).await
+            }
+            Some(RequestData::Subscribe(_) | RequestData::Replicate(_)) => {
+                Err(KvError::InvalidCommand(format!(
+                    "{} is not supported by sharded client, connect to the node directly",
+                    cmd.command_name()
+                )))
+            }
+            _ => {
+                let keys = keys(&cmd).unwrap_or_default();
+                let nodes: BTreeSet<&str> = keys
+                    .iter()
+                    .filter_map(|(table, key)| self.node_for(table, key))
+                    .collect();
+                // 事务只能在一个节点上原子地执行
+                if nodes.len() > 1 {
+                    return Err(KvError::InvalidCommand(format!(
+                        "{} touches keys on {} nodes",
+                        cmd.command_name(),
+                        nodes.len()
+                    )));
+                }
+                let node = nodes
+                    .into_iter()
+                    .next()
+                    .or_else(|| self.ring.nodes().into_iter().next())
+                    .map(String::from);
+                self.send(node, cmd).await
+            }
+        }
+    }
+
+    pub async fn hget(&mut self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let res = self.execute(CommandRequest::new_hget(table, key)).await?;
+        if res.status == StatusCode::NOT_FOUND.as_u16() as u32 {
+            return Ok(None);
+        }
+        Ok(check(res)?.values.into_iter().next())
+    }
+
+    pub async fn hset(
+        &mut self,
+        table: &str,
+        key: &str,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, KvError> {
+        let res = self
+            .execute(CommandRequest::new_hset(table, key, value.into()))
+            .await?;
+        Ok(first_value(check(res)?))
+    }
+
+    pub async fn hdel(&mut self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let res = self.execute(CommandRequest::new_hdel(table, key)).await?;
+        Ok(first_value(check(res)?))
+    }
+
+    // 所有节点上的 key-value，按 key 排序
+    pub async fn hgetall(&mut self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        let res = self.execute(CommandRequest::new_hgetall(table)).await?;
+        Ok(check(res)?.pairs)
+    }
+
+    pub async fn hmget(&mut self, table: &str, keys: Vec<String>) -> Result<Vec<Value>, KvError> {
+        let res = self.execute(CommandRequest::new_hmget(table, keys)).await?;
+        Ok(check(res)?.values)
+    }
+
+    pub async fn hmset(&mut self, table: &str, pairs: Vec<Kvpair>) -> Result<Vec<Value>, KvError> {
+        let res = self
+            .execute(CommandRequest::new_hmset(table, pairs))
+            .await?;
+        Ok(check(res)?.values)
+    }
+
+    async fn send(
+        &mut self,
+        node: Option<String>,
+        cmd: CommandRequest,
+    ) -> Result<CommandResponse, KvError> {
+        match node.and_then(|node| self.nodes.get_mut(&node)) {
+            Some(client) => client.execute(cmd).await,
+            None => Err(no_node()),
+        }
+    }
+
+    // 按节点拆分 key，并发执行后按原来的顺序合并每个 key 的结果
+    // 部分节点失败时返回第一个失败的状态，message 中列出已经执行成功的节点，
+    // values 中保留这些节点上 key 的结果，失败节点上的 key 是空值
+    async fn split(&mut self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        let keys = keys(&cmd).unwrap_or_default();
+        let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, (table, key)) in keys.iter().enumerate() {
+            let node = self.node_for(table, key).ok_or_else(no_node)?;
+            groups.entry(node.into()).or_default().push(i);
+        }
+        let count = keys.len();
+
+        let requests = self.nodes.iter_mut().filter_map(|(name, client)| {
+            let indexes = groups.remove(name)?;
+            let cmd = subset(&cmd, &indexes);
+            Some(async move { (name.as_str(), indexes, client.execute(cmd).await) })
+        });
+        let mut values = vec![Value::default(); count];
+        let mut succeeded = Vec::new();
+        let mut failed: Option<CommandResponse> = None;
+        for (name, indexes, res) in join_all(requests).await {
+            let res = match res {
+                Ok(res) if res.status == StatusCode::OK.as_u16() as u32 => res,
+                Ok(res) => {
+                    failed.get_or_insert(res);
+                    continue;
+                }
+                Err(e) => {
+                    failed.get_or_insert(e.into());
+                    continue;
+                }
+            };
+            succeeded.push(name.to_string());
+            for (i, value) in indexes.into_iter().zip(res.values) {
+                values[i] = value;
+            }
+        }
+        let Some(mut res) = failed else {
+            return Ok(values.into());
+        };
+        if !succeeded.is_empty() {
+            succeeded.sort();
+            res.message = format!(
+                "{}, partially succeeded on {}",
+                res.message,
+                succeeded.join(", ")
+            );
+            res.values = values;
+        }
+        Ok(res)
+    }
+
+    async fn fan_out(&mut self, cmd: &CommandRequest) -> Result<Vec<CommandResponse>, KvError> {
+        if self.nodes.is_empty() {
+            return Err(no_node());
+        }
+        let requests = self
+            .nodes
+            .values_mut()
+            .map(|client| client.execute(cmd.clone()));
+        join_all(requests).await.into_iter().collect()
+    }
+}
+
+fn no_node() -> KvError {
+    KvError::Internal("no kv server in the ring".into())
+}
+
+// 命令涉及的 (table, key)，需要访问所有节点的命令返回 None
+fn keys(cmd: &CommandRequest) -> Option<Vec<(&str, &str)>> {
+    let keys = match &cmd.request_data {
+        Some(RequestData::Hget(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Hdel(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Hexists(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Expire(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Ttl(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Persist(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Hincrby(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Hincrbyfloat(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Cas(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Lpush(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Rpush(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Lpop(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Rpop(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Lrange(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Sadd(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Srem(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Smembers(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Zadd(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Zrem(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Zrange(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Zrangebyscore(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        Some(RequestData::Zrank(p)) => vec![(p.table.as_str(), p.key.as_str())],
+        // 集合的交集需要所有的 key 在同一个节点上
+        Some(RequestData::Sinter(p)) => p
+            .keys
+            .iter()
+            .map(|k| (p.table.as_str(), k.as_str()))
+            .collect(),
+        Some(RequestData::Hset(p)) => {
+            vec![(p.table.as_str(), p.pair.as_ref().map_or("", |p| &p.key))]
+        }
+        Some(RequestData::Hsetnx(p)) => {
+            vec![(p.table.as_str(), p.pair.as_ref().map_or("", |p| &p.key))]
+        }
+        Some(RequestData::Hmget(p)) => p
+            .keys
+            .iter()
+            .map(|k| (p.table.as_str(), k.as_str()))
+            .collect(),
+        Some(RequestData::Hmdel(p)) => p
+            .keys
+            .iter()
+            .map(|k| (p.table.as_str(), k.as_str()))
+            .collect(),
+        Some(RequestData::Hmexists(p)) => p
+            .keys
+            .iter()
+            .map(|k| (p.table.as_str(), k.as_str()))
+            .collect(),
+        Some(RequestData::Hmset(p)) => p
+            .pairs
+            .iter()
+            .map(|pair| (p.table.as_str(), pair.key.as_str()))
+            .collect(),
+        Some(RequestData::Transaction(tx)) => {
+            let mut keys: Vec<_> = tx
+                .watches
+                .iter()
+                .map(|w| (w.table.as_str(), w.key.as_str()))
+                .collect();
+            for cmd in &tx.commands {
+                keys.extend(keys_of(cmd)?);
+            }
+            keys
+        }
+        _ => return None,
+    };
+    Some(keys)
+}
+
+// 事务中的命令不能是事务
+fn keys_of(cmd: &CommandRequest) -> Option<Vec<(&str, &str)>> {
+    match cmd.request_data {
+        Some(RequestData::Transaction(_)) => None,
+        _ => keys(cmd),
+    }
+}
+
+// 只保留 indexes 中的 key
+fn subset(cmd: &CommandRequest, indexes: &[usize]) -> CommandRequest {
+    let mut cmd = cmd.clone();
+    match &mut cmd.request_data {
+        Some(RequestData::Hmget(p)) => p.keys = pick(&p.keys, indexes),
+        Some(RequestData::Hmdel(p)) => p.keys = pick(&p.keys, indexes),
+        Some(RequestData::Hmexists(p)) => p.keys = pick(&p.keys, indexes),
+        Some(RequestData::Hmset(p)) => p.pairs = pick(&p.pairs, indexes),
+        _ => {}
+    }
+    cmd
+}
+
+fn pick<T: Clone>(items: &[T], indexes: &[usize]) -> Vec<T> {
+    indexes.iter().map(|i| items[*i].clone()).collect()
+}
+
+// 合并所有节点的响应，有节点失败时返回第一个失败的响应
+fn merge(cmd: &CommandRequest, responses: Vec<CommandResponse>) -> CommandResponse {
+    let ok = StatusCode::OK.as_u16() as u32;
+    // 表只存在于部分节点上时，其它节点返回的 404 可以忽略
+    let (found, not_found): (Vec<_>, Vec<_>) = responses
+        .into_iter()
+        .partition(|res| res.status != StatusCode::NOT_FOUND.as_u16() as u32);
+    if let Some(res) = found.iter().find(|res| res.status != ok) {
+        return res.clone();
+    }
+    if found.is_empty() {
+        return not_found.into_iter().next().unwrap_or_default();
+    }
+
+    match &cmd.request_data {
+        Some(RequestData::Hgetall(_)) => {
+            let mut pairs: Vec<Kvpair> = found.into_iter().flat_map(|res| res.pairs).collect();
+            pairs.sort_by(|a, b| a.key.cmp(&b.key));
+            pairs.into()
+        }
+        // 每个节点返回下一页之前的一段 key，只返回所有节点都已经读到的部分，最多 limit 个，
+        // 游标是返回的最后一个 key，所有节点都读完并且没有截断时为空
+        Some(RequestData::Hscan(p)) => {
+            let limit = match p.limit as usize {
+                0 => DEFAULT_SCAN_LIMIT,
+                limit => limit.min(MAX_SCAN_LIMIT),
+            };
+            let cursor = found
+                .iter()
+                .map(|res| res.cursor.as_str())
+                .filter(|cursor| !cursor.is_empty())
+                .min()
+                .map(String::from);
+            let mut pairs: Vec<Kvpair> = found.into_iter().flat_map(|res| res.pairs).collect();
+            pairs.sort_by(|a, b| a.key.cmp(&b.key));
+            if let Some(cursor) = cursor.as_ref() {
+                pairs.retain(|pair| pair.key <= *cursor);
+            }
+            let more = cursor.is_some() || pairs.len() > limit;
+            pairs.truncate(limit);
+            let cursor = match pairs.last() {
+                Some(pair) if more => pair.key.clone(),
+                _ => String::new(),
+            };
+            CommandResponse {
+                cursor,
+                ..pairs.into()
+            }
+        }
+        Some(RequestData::ListTables(_)) => {
+            let tables: BTreeSet<String> = found
+                .into_iter()
+                .flat_map(|res| res.values)
+                .filter_map(|v| match v.value {
+                    Some(value::Value::StringValue(s)) => Some(s),
+                    _ => None,
+                })
+                .collect();
+            tables
+                .into_iter()
+                .map(Value::from)
+                .collect::<Vec<_>>()
+                .into()
+        }
+        Some(RequestData::DropTable(_)) => {
+            let count: i64 = found
+                .iter()
+                .flat_map(|res| res.values.first())
+                .filter_map(|v| i64::try_from(v).ok())
+                .sum();
+            Value::from(count).into()
+        }
+        Some(RequestData::TableInfo(_)) => {
+            let mut stats: BTreeMap<String, i64> = BTreeMap::new();
+            for pair in found.into_iter().flat_map(|res| res.pairs) {
+                let n = pair.value.as_ref().and_then(|v| i64::try_from(v).ok());
+                *stats.entry(pair.key).or_default() += n.unwrap_or_default();
+            }
+            // 保持 keys、bytes 的顺序
+            ["keys", "bytes"]
+                .into_iter()
+                .filter_map(|key| stats.get(key).map(|n| Kvpair::new(key, (*n).into())))
+                .collect::<Vec<_>>()
+                .into()
+        }
+        _ => found.into_iter().next().unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use anyhow::Result;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::{BlockingStorage, KvServerStream, MemTable, Service, ServiceInner};
+
+    #[test]
+    fn hash_ring_should_distribute_and_remap_minimally() {
+        let mut ring = HashRing::default();
+        for node in ["a", "b", "c"] {
+            ring.add(node);
+        }
+        assert_eq!(ring.nodes(), vec!["a", "b", "c"]);
+
+        let keys: Vec<Vec<u8>> = (0..10000)
+            .map(|i| shard_key("t1", &format!("key{}", i)))
+            .collect();
+        let before: Vec<String> = keys.iter().map(|k| ring.get(k).unwrap().into()).collect();
+        for node in ["a", "b", "c"] {
+            let count = before.iter().filter(|n| *n == node).count();
+            assert!(
+                (2000..4700).contains(&count),
+                "{} owns {} keys",
+                node,
+                count
+            );
+        }
+
+        // 新节点只接管一部分 key，其它 key 的归属不变
+        ring.add("d");
+        let mut moved = 0;
+        for (key, old) in keys.iter().zip(&before) {
+            let node = ring.get(key).unwrap();
+            if node != old {
+                assert_eq!(node, "d");
+                moved += 1;
+            }
+        }
+        assert!((1500..3500).contains(&moved), "{} keys moved", moved);
+
+        // 删除节点后恢复原来的归属
+        ring.remove("d");
+        for (key, old) in keys.iter().zip(&before) {
+            assert_eq!(ring.get(key).unwrap(), old);
+        }
+    }
+
+    #[tokio::test]
+    async fn sharded_client_should_work() -> Result<()> {
+        let mut client = ShardedClient::new();
+        let mut nodes = Vec::new();
+        for _ in 0..3 {
+            let (addr, service) = start_server().await?;
+            client.add_node(
+                addr.to_string(),
+                KvClient::new(TcpStream::connect(addr).await?),
+            );
+            nodes.push((addr.to_string(), service));
+        }
+
+        let pairs: Vec<Kvpair> = (0..100)
+            .map(|i| Kvpair::new(&format!("key{:03}", i), Value::from(i as i64)))
+            .collect();
+        let old = client.hmset("t1", pairs.clone()).await?;
+        assert_eq!(old, vec![Value::default(); 100]);
+
+        // 每个 key 只写到负责它的节点上
+        for (name, service) in &nodes {
+            let res = service.exec(CommandRequest::new_hgetall("t1")).await;
+            assert!(!res.pairs.is_empty());
+            for pair in &res.pairs {
+                assert_eq!(client.node_for("t1", &pair.key), Some(name.as_str()));
+            }
+        }
+
+        let keys = vec!["key099".into(), "nope".into(), "key000".into()];
+        let values = client.hmget("t1", keys).await?;
+        assert_eq!(values, vec![99.into(), Value::default(), 0.into()]);
+        assert_eq!(client.hgetall("t1").await?, pairs);
+
+        assert_eq!(client.hset("t1", "key000", "v0").await?, Some(0.into()));
+        assert_eq!(client.hget("t1", "key000").await?, Some("v0".into()));
+        assert_eq!(client.hdel("t1", "key000").await?, Some("v0".into()));
+        assert_eq!(client.hget("t1", "key000").await?, None);
+
+        // 分页遍历所有节点上的 key，每个节点都读完时合并的结果也不超过 limit
+        for limit in [30, 50] {
+            let mut scanned = Vec::new();
+            let mut cursor = String::new();
+            loop {
+                let res = client
+                    .execute(CommandRequest::new_hscan("t1", &cursor, limit))
+                    .await?;
+                let res = check(res)?;
+                assert!(res.pairs.len() <= limit as usize);
+                scanned.extend(res.pairs);
+                if res.cursor.is_empty() {
+                    break;
+                }
+                cursor = res.cursor;
+            }
+            assert_eq!(scanned, pairs[1..]);
+        }
+
+        let res = client.execute(CommandRequest::new_list_tables()).await?;
+        assert_eq!(res.values, vec![Value::from("t1")]);
+        let res = client.execute(CommandRequest::new_table_info("t1")).await?;
+        assert_eq!(res.pairs[0], Kvpair::new("keys", 99.into()));
+        let res = client.execute(CommandRequest::new_table_info("t2")).await?;
+        assert_eq!(res.status, StatusCode::NOT_FOUND.as_u16() as u32);
+
+        // 跨节点的事务被拒绝，单个节点上的事务正常执行
+        let other = key_on_other_node(&client, "key001");
+        let cmd = CommandRequest::new_transaction(
+            vec![
+                CommandRequest::new_hdel("t1", "key001"),
+                CommandRequest::new_hdel("t1", &other),
+            ],
+            vec![],
+        );
+        assert!(matches!(
+            client.execute(cmd).await,
+            Err(KvError::InvalidCommand(_))
+        ));
+        let cmd = CommandRequest::new_transaction(
+            vec![CommandRequest::new_hincrby("t1", "n", 2)],
+            vec![],
+        );
+        assert_eq!(client.execute(cmd).await?.status, 200);
+
+        let res = client.execute(CommandRequest::new_drop_table("t1")).await?;
+        assert_eq!(res.values, vec![Value::from(100)]);
+        assert!(client.hgetall("t1").await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sharded_client_should_add_and_remove_nodes() -> Result<()> {
+        let mut client = ShardedClient::new();
+        assert!(client.hget("t1", "k1").await.is_err());
+
+        let (addr1, _) = start_server().await?;
+        client.add_node("n1", KvClient::new(TcpStream::connect(addr1).await?));
+        client.hset("t1", "k1", "v1").await?;
+
+        let (addr2, _) = start_server().await?;
+        client.add_node("n2", KvClient::new(TcpStream::connect(addr2).await?));
+        assert_eq!(client.nodes(), vec!["n1", "n2"]);
+        // 归属变化的 key 在新节点上读不到，需要调用方自行迁移
+        let owner = client.node_for("t1", "k1").unwrap().to_string();
+        let expected = (owner == "n1").then(|| "v1".into());
+        assert_eq!(client.hget("t1", "k1").await?, expected);
+
+        assert!(client.remove_node("n2").is_some());
+        assert_eq!(client.nodes(), vec!["n1"]);
+        assert_eq!(client.hget("t1", "k1").await?, Some("v1".into()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn split_should_report_partial_failure() -> Result<()> {
+        let mut client = ShardedClient::new();
+        let (addr1, _) = start_server().await?;
+        client.add_node("n1", KvClient::new(TcpStream::connect(addr1).await?));
+        // n2 拒绝所有的写入
+        let service: Service = ServiceInner::new(BlockingStorage::new(MemTable::new()))
+            .fn_intercept(|cmd| {
+                cmd.is_write()
+                    .then(|| KvError::Internal("disk is full".into()).into())
+            })
+            .into();
+        let (addr2, _) = start_server_with(service).await?;
+        client.add_node("n2", KvClient::new(TcpStream::connect(addr2).await?));
+
+        let key1 = (0..)
+            .map(|i| format!("key{}", i))
+            .find(|key| client.node_for("t1", key) == Some("n1"))
+            .unwrap();
+        let key2 = key_on_other_node(&client, &key1);
+        let pairs = vec![
+            Kvpair::new(&key1, "v1".into()),
+            Kvpair::new(&key2, "v2".into()),
+        ];
+        let res = client
+            .execute(CommandRequest::new_hmset("t1", pairs))
+            .await?;
+        assert_eq!(res.status, 500);
+        assert!(res.message.contains("partially succeeded on n1"));
+        assert_eq!(client.hget("t1", &key1).await?, Some("v1".into()));
+        assert_eq!(client.hget("t1", &key2).await?, None);
+
+        // 所有节点都失败时直接返回失败的响应
+        let res = client
+            .execute(CommandRequest::new_hmdel("t1", vec![key2.clone()]))
+            .await?;
+        assert_eq!(res.status, 500);
+        assert!(!res.message.contains("partially"));
+        Ok(())
+    }
+
+    // 与 key 不在同一个节点上的另一个 key
+    fn key_on_other_node<S>(client: &ShardedClient<S>, key: &str) -> String
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let node = client.node_for("t1", key);
+        (0..)
+            .map(|i| format!("other{}", i))
+            .find(|other| client.node_for("t1", other) != node)
+            .unwrap()
+    }
+
+    async fn start_server() -> Result<(SocketAddr, Service)> {
+        start_server_with(ServiceInner::new(BlockingStorage::new(MemTable::new())).into()).await
+    }
+
+    async fn start_server_with(service: Service) -> Result<(SocketAddr, Service)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let svc = service.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let stream = KvServerStream::new(stream, svc.clone());
+                tokio::spawn(stream.process());
+            }
+        });
+
+        Ok((addr, service))
+    }
+}
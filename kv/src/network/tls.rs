@@ -12,9 +12,13 @@ use tokio_rustls::{
     server::TlsStream as ServerTlsStream,
 };
 
+use x509_parser::prelude::{FromDer, X509Certificate};
+
 use crate::error::KvError;
 
 const ALPN_KV: &str = "kv";
+// 协商到这个协议时，连接上运行 yamux 多路复用
+pub const ALPN_KV_MUX: &str = "kv/yamux";
 #[derive(Clone)]
 #[allow(unused)]
 pub struct TlsServerAcceptor {
@@ -68,6 +72,14 @@ impl TlsClientConnector {
             .await?;
         Ok(stream)
     }
+
+    // 通过 ALPN 请求多路复用，服务端不支持时回退到普通连接
+    pub fn with_multiplex(mut self) -> Self {
+        let mut config = (*self.config).clone();
+        config.alpn_protocols = vec![ALPN_KV_MUX.as_bytes().to_vec(), ALPN_KV.as_bytes().to_vec()];
+        self.config = Arc::new(config);
+        self
+    }
 }
 #[allow(unused)]
 impl TlsServerAcceptor {
@@ -93,7 +105,7 @@ impl TlsServerAcceptor {
                     .with_single_cert(certs.clone(), key)?
             }
         };
-        config.alpn_protocols = vec![ALPN_KV.as_bytes().to_vec()];
+        config.alpn_protocols = vec![ALPN_KV_MUX.as_bytes().to_vec(), ALPN_KV.as_bytes().to_vec()];
 
         Ok(Self {
             inner: Arc::new(config),
@@ -108,11 +120,44 @@ impl TlsServerAcceptor {
         Ok(stream)
     }
 }
+
+// 客户端和服务端是否协商了多路复用
+pub fn is_multiplex_server<S>(stream: &ServerTlsStream<S>) -> bool {
+    stream.get_ref().1.alpn_protocol() == Some(ALPN_KV_MUX.as_bytes())
+}
+
+pub fn is_multiplex_client<S>(stream: &ClientTlsStream<S>) -> bool {
+    stream.get_ref().1.alpn_protocol() == Some(ALPN_KV_MUX.as_bytes())
+}
+// 客户端证书 subject 中的 CN，开启双向认证时作为授权的身份
+// 没有客户端证书时返回 None；有证书但无法解析出 CN 时返回错误，不能当作没有身份按默认权限处理
+pub fn peer_identity<S>(stream: &ServerTlsStream<S>) -> Result<Option<String>, KvError> {
+    match stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|c| c.first())
+    {
+        Some(cert) => subject_common_name(cert).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn subject_common_name(der: &[u8]) -> Result<String, KvError> {
+    let invalid = |e: String| KvError::CertParseError("client certificate".into(), e);
+    let (_, cert) = X509Certificate::from_der(der).map_err(|e| invalid(e.to_string()))?;
+    let cn = cert
+        .subject()
+        .iter_common_name()
+        .next()
+        .ok_or_else(|| invalid("subject has no common name".into()))?;
+    Ok(cn.as_str().map_err(|e| invalid(e.to_string()))?.into())
+}
+
 #[allow(unused)]
 fn load_certs(key: &str) -> Result<Vec<CertificateDer<'static>>, KvError> {
-    println!("load_certs: {}", key);
     let certs = CertificateDer::pem_file_iter(key)
-        .unwrap()
+        .map_err(|e| KvError::CertParseError(key.to_string(), e.to_string()))?
         .map(|c| c.map_err(|e| KvError::CertParseError(key.to_string(), e.to_string())))
         .collect::<Result<Vec<_>, KvError>>()?;
     Ok(certs)
@@ -120,7 +165,8 @@ fn load_certs(key: &str) -> Result<Vec<CertificateDer<'static>>, KvError> {
 
 #[allow(unused)]
 fn load_key(key: &str) -> Result<PrivateKeyDer<'static>, KvError> {
-    let key = PrivateKeyDer::from_pem_file(key).unwrap();
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| KvError::CertParseError(key.to_string(), e.to_string()))?;
     match key {
         PrivateKeyDer::Pkcs8(keys) => Ok(PrivateKeyDer::Pkcs8(keys)),
         PrivateKeyDer::Sec1(keys) => Ok(PrivateKeyDer::Sec1(keys)),
@@ -176,7 +222,7 @@ mod tests {
         let addr = start_server(ca.clone()).await?;
 
         let connector =
-            TlsClientConnector::new("kvserver.kevin.inc", client_identity, Some(SERVER_CERT))?;
+            TlsClientConnector::new("kvserver.kevin.inc", client_identity, Some(CA_CERT))?;
         let stream = TcpStream::connect(addr).await?;
         let mut stream = connector.connect(stream).await?;
         stream.write_all(b"hello world!").await?;
@@ -191,16 +237,78 @@ mod tests {
     async fn tls_with_bad_domain_should_not_work() -> Result<()> {
         let addr = start_server(Some(CA_CERT)).await?;
 
-        // let connector = TlsClientConnector::new("kvserver1.acme.inc", None, Some(SERVER_CERT))?;
-        let connector = TlsClientConnector::new("kvserver.kevin.inc", None, Some(SERVER_CERT))?;
+        // let connector = TlsClientConnector::new("kvserver1.acme.inc", None, Some(CA_CERT))?;
+        let connector = TlsClientConnector::new("kvserver.kevin.inc", None, Some(CA_CERT))?;
         let stream = TcpStream::connect(addr).await?;
         let result = connector.connect(stream).await;
-        println!("result: {:?}", result);
+        info!("result: {:?}", result);
         assert!(result.is_ok());
 
         Ok(())
     }
 
+    #[tokio::test]
+    async fn tls_should_negotiate_multiplex_with_alpn() -> Result<()> {
+        let acceptor = TlsServerAcceptor::new(SERVER_CERT, SERVER_KEY, None)?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            let mut results = vec![];
+            for _ in 0..3 {
+                let (stream, _) = listener.accept().await.unwrap();
+                let stream = acceptor.accept(stream).await.unwrap();
+                let alpn = stream.get_ref().1.alpn_protocol().map(|p| p.to_vec());
+                results.push((is_multiplex_server(&stream), alpn));
+            }
+            results
+        });
+
+        let connector = TlsClientConnector::new("kvserver.kevin.inc", None, Some(CA_CERT))?;
+        // 请求多路复用时协商到 ALPN_KV_MUX
+        let mux = connector.clone().with_multiplex();
+        let stream = mux.connect(TcpStream::connect(addr).await?).await?;
+        assert!(is_multiplex_client(&stream));
+
+        // 只支持 ALPN_KV 的客户端使用普通连接
+        let mut config = (*connector.config).clone();
+        config.alpn_protocols = vec![ALPN_KV.as_bytes().to_vec()];
+        let plain = TlsClientConnector {
+            config: Arc::new(config),
+            domain: connector.domain.clone(),
+        };
+        let stream = plain.connect(TcpStream::connect(addr).await?).await?;
+        assert!(!is_multiplex_client(&stream));
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(ALPN_KV.as_bytes()));
+
+        // 没有 ALPN 的客户端同样使用普通连接
+        let stream = connector.connect(TcpStream::connect(addr).await?).await?;
+        assert!(!is_multiplex_client(&stream));
+
+        assert_eq!(
+            server.await?,
+            vec![
+                (true, Some(ALPN_KV_MUX.as_bytes().to_vec())),
+                (false, Some(ALPN_KV.as_bytes().to_vec())),
+                (false, None),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn subject_common_name_should_work() -> Result<()> {
+        let cert = load_certs(CLIENT_CERT)?.remove(0);
+        assert_eq!(subject_common_name(&cert)?, "awesome-client");
+        let cert = load_certs(SERVER_CERT)?.remove(0);
+        assert_eq!(subject_common_name(&cert)?, "awesome-server");
+
+        // 截断或者无效的数据返回错误，不会 panic
+        assert!(subject_common_name(&cert[..cert.len() / 2]).is_err());
+        assert!(subject_common_name(b"\x30\x84\xff\xff\xff\xff").is_err());
+        assert!(subject_common_name(b"").is_err());
+        Ok(())
+    }
+
     async fn start_server(ca: Option<&str>) -> Result<SocketAddr> {
         let acceptor = TlsServerAcceptor::new(SERVER_CERT, SERVER_KEY, ca)?;
 
@@ -1,9 +1,12 @@
 // This file is @generated by prost-build.
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct CommandRequest {
+    /// 请求 id，服务端在对应的响应中原样带回，用于匹配乱序返回的响应
+    #[prost(uint32, tag = "100")]
+    pub id: u32,
     #[prost(
         oneof = "command_request::RequestData",
-        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9"
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39"
     )]
     pub request_data: ::core::option::Option<command_request::RequestData>,
 }
@@ -29,6 +32,66 @@ pub mod command_request {
         Hexists(super::Hexists),
         #[prost(message, tag = "9")]
         Hmexists(super::Hmexists),
+        #[prost(message, tag = "10")]
+        Subscribe(super::Subscribe),
+        #[prost(message, tag = "11")]
+        Unsubscribe(super::Unsubscribe),
+        #[prost(message, tag = "12")]
+        Publish(super::Publish),
+        #[prost(message, tag = "13")]
+        Expire(super::Expire),
+        #[prost(message, tag = "14")]
+        Ttl(super::Ttl),
+        #[prost(message, tag = "15")]
+        Persist(super::Persist),
+        #[prost(message, tag = "16")]
+        Transaction(super::Transaction),
+        #[prost(message, tag = "17")]
+        Hincrby(super::Hincrby),
+        #[prost(message, tag = "18")]
+        Hincrbyfloat(super::Hincrbyfloat),
+        #[prost(message, tag = "19")]
+        Hsetnx(super::Hsetnx),
+        #[prost(message, tag = "20")]
+        Cas(super::Cas),
+        #[prost(message, tag = "21")]
+        Hscan(super::Hscan),
+        #[prost(message, tag = "22")]
+        ListTables(super::ListTables),
+        #[prost(message, tag = "23")]
+        DropTable(super::DropTable),
+        #[prost(message, tag = "24")]
+        TableInfo(super::TableInfo),
+        #[prost(message, tag = "25")]
+        Replicate(super::Replicate),
+        #[prost(message, tag = "26")]
+        Lpush(super::Lpush),
+        #[prost(message, tag = "27")]
+        Rpush(super::Rpush),
+        #[prost(message, tag = "28")]
+        Lpop(super::Lpop),
+        #[prost(message, tag = "29")]
+        Rpop(super::Rpop),
+        #[prost(message, tag = "30")]
+        Lrange(super::Lrange),
+        #[prost(message, tag = "31")]
+        Sadd(super::Sadd),
+        #[prost(message, tag = "32")]
+        Srem(super::Srem),
+        #[prost(message, tag = "33")]
+        Smembers(super::Smembers),
+        #[prost(message, tag = "34")]
+        Sinter(super::Sinter),
+        #[prost(message, tag = "35")]
+        Zadd(super::Zadd),
+        #[prost(message, tag = "36")]
+        Zrem(super::Zrem),
+        #[prost(message, tag = "37")]
+        Zrange(super::Zrange),
+        #[prost(message, tag = "38")]
+        Zrangebyscore(super::Zrangebyscore),
+        #[prost(message, tag = "39")]
+        Zrank(super::Zrank),
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -41,6 +104,18 @@ pub struct CommandResponse {
     pub values: ::prost::alloc::vec::Vec<Value>,
     #[prost(message, repeated, tag = "4")]
     pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
+    /// 对应请求的 id
+    #[prost(uint32, tag = "5")]
+    pub id: u32,
+    /// Transaction 中每个命令的响应
+    #[prost(message, repeated, tag = "6")]
+    pub responses: ::prost::alloc::vec::Vec<CommandResponse>,
+    /// Hscan 下一页的游标，为空表示已经遍历完
+    #[prost(string, tag = "7")]
+    pub cursor: ::prost::alloc::string::String,
+    /// 复制流中 follower 需要依次执行的命令
+    #[prost(message, repeated, tag = "8")]
+    pub commands: ::prost::alloc::vec::Vec<CommandRequest>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hget {
@@ -67,10 +142,13 @@ pub struct Hmset {
     pub table: ::prost::alloc::string::String,
     #[prost(message, repeated, tag = "2")]
     pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
+    /// 过期时间，单位毫秒，0 表示不过期
+    #[prost(uint64, tag = "3")]
+    pub ttl: u64,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Value {
-    #[prost(oneof = "value::Value", tags = "1, 2, 3, 4, 5")]
+    #[prost(oneof = "value::Value", tags = "1, 2, 3, 4, 5, 6, 7, 8")]
     pub value: ::core::option::Option<value::Value>,
 }
 /// Nested message and enum types in `Value`.
@@ -87,8 +165,39 @@ pub mod value {
         DoubleValue(f64),
         #[prost(bool, tag = "5")]
         BoolValue(bool),
+        #[prost(message, tag = "6")]
+        ListValue(super::ListValue),
+        #[prost(message, tag = "7")]
+        SetValue(super::SetValue),
+        #[prost(message, tag = "8")]
+        ZsetValue(super::ZsetValue),
     }
 }
+/// 列表，元素按插入的位置排列
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListValue {
+    #[prost(message, repeated, tag = "1")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 集合，成员按字典序排列并且不重复
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SetValue {
+    #[prost(string, repeated, tag = "1")]
+    pub members: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 有序集合，成员按分数排列，分数相同时按成员的字典序排列
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ZsetValue {
+    #[prost(message, repeated, tag = "1")]
+    pub members: ::prost::alloc::vec::Vec<ScoredMember>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ScoredMember {
+    #[prost(string, tag = "1")]
+    pub member: ::prost::alloc::string::String,
+    #[prost(double, tag = "2")]
+    pub score: f64,
+}
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Kvpair {
     #[prost(string, tag = "1")]
@@ -102,6 +211,12 @@ pub struct Hset {
     pub table: ::prost::alloc::string::String,
     #[prost(message, optional, tag = "2")]
     pub pair: ::core::option::Option<Kvpair>,
+    /// 过期时间，单位毫秒，0 表示不过期
+    #[prost(uint64, tag = "3")]
+    pub ttl: u64,
+    /// 过期的时间点（毫秒时间戳），不为 0 时代替 ttl，leader 同步给 follower 时使用
+    #[prost(uint64, tag = "4")]
+    pub expire_at: u64,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hmdel {
@@ -131,3 +246,291 @@ pub struct Hmexists {
     #[prost(string, repeated, tag = "2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
+/// 订阅一个主题，返回的第一个响应中包含订阅 id，之后持续返回发布到该主题的数据
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Subscribe {
+    #[prost(string, tag = "1")]
+    pub topic: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Unsubscribe {
+    #[prost(string, tag = "1")]
+    pub topic: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "2")]
+    pub id: u32,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Publish {
+    #[prost(string, tag = "1")]
+    pub topic: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "2")]
+    pub data: ::prost::alloc::vec::Vec<Value>,
+}
+/// 设置 key 的过期时间，单位毫秒
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Expire {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub ttl: u64,
+}
+/// 查询 key 剩余的过期时间，单位毫秒，-1 表示不过期，-2 表示 key 不存在
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Ttl {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 移除 key 的过期时间
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Persist {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 事务：命令要么全部执行成功，要么全部回滚
+/// 执行前检查所有 watch 条件，任何一个不满足时放弃执行
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Transaction {
+    #[prost(message, repeated, tag = "1")]
+    pub commands: ::prost::alloc::vec::Vec<CommandRequest>,
+    #[prost(message, repeated, tag = "2")]
+    pub watches: ::prost::alloc::vec::Vec<Watch>,
+}
+/// 乐观锁条件：key 当前的值必须等于 value，value 为空表示 key 不存在
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Watch {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "3")]
+    pub value: ::core::option::Option<Value>,
+}
+/// 把 key 的整数值原子地加上 delta，key 不存在时视为 0，返回新值
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hincrby {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub delta: i64,
+}
+/// 把 key 的浮点数值原子地加上 delta，整数会被转换成浮点数，返回新值
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hincrbyfloat {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(double, tag = "3")]
+    pub delta: f64,
+}
+/// key 不存在时才写入，返回是否写入
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hsetnx {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub pair: ::core::option::Option<Kvpair>,
+}
+/// key 当前的值等于 expected 时写入 value，expected 为空表示 key 不存在
+/// 返回是否写入以及写入后 key 的值
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Cas {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "3")]
+    pub expected: ::core::option::Option<Value>,
+    #[prost(message, optional, tag = "4")]
+    pub value: ::core::option::Option<Value>,
+}
+/// 按 key 的字典序分页遍历表，返回的 cursor 用于获取下一页
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hscan {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    /// 从大于 cursor 的 key 开始，为空表示从头开始
+    #[prost(string, tag = "2")]
+    pub cursor: ::prost::alloc::string::String,
+    /// 只返回以 prefix 开头的 key
+    #[prost(string, tag = "3")]
+    pub prefix: ::prost::alloc::string::String,
+    /// 只返回 \[start, end) 范围内的 key，为空表示不限制
+    #[prost(string, tag = "4")]
+    pub start: ::prost::alloc::string::String,
+    #[prost(string, tag = "5")]
+    pub end: ::prost::alloc::string::String,
+    /// 每页最多返回的数量，0 表示使用默认值
+    #[prost(uint32, tag = "6")]
+    pub limit: u32,
+}
+/// 列出所有的表，按名称排序返回表名
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct ListTables {}
+/// 删除整个表，返回删除的 key 的数量
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DropTable {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// 获取表的 key 数量和大致占用的字节数，以 keys、bytes 两个 pair 返回
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TableInfo {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// follower 请求复制：先收到所有数据的快照，之后持续收到 leader 上执行成功的修改命令
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct Replicate {}
+/// 把 values 依次插入到列表的头部，key 不存在时创建列表，返回列表的长度
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lpush {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "3")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 把 values 依次插入到列表的尾部，key 不存在时创建列表，返回列表的长度
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Rpush {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "3")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 从列表的头部弹出最多 count 个元素，count 为 0 时弹出一个
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lpop {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "3")]
+    pub count: u32,
+}
+/// 从列表的尾部弹出最多 count 个元素，count 为 0 时弹出一个
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Rpop {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "3")]
+    pub count: u32,
+}
+/// 返回列表中 \[start, stop\] 范围内的元素，负数表示从尾部开始计算，-1 是最后一个元素
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lrange {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub start: i64,
+    #[prost(int64, tag = "4")]
+    pub stop: i64,
+}
+/// 向集合中添加成员，返回新添加的成员数量
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Sadd {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "3")]
+    pub members: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 从集合中移除成员，返回移除的成员数量
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Srem {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "3")]
+    pub members: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 按字典序返回集合的所有成员
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Smembers {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 返回表中多个集合的交集，任何一个 key 不存在时交集为空
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Sinter {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 向有序集合中添加成员，已经存在的成员更新分数，返回新添加的成员数量
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zadd {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "3")]
+    pub members: ::prost::alloc::vec::Vec<ScoredMember>,
+}
+/// 从有序集合中移除成员，返回移除的成员数量
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrem {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "3")]
+    pub members: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 按排名返回 \[start, stop\] 范围内的成员，负数表示从尾部开始计算，成员和分数以 pair 返回
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrange {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub start: i64,
+    #[prost(int64, tag = "4")]
+    pub stop: i64,
+}
+/// 按分数返回 \[min, max\] 范围内的成员，limit 为 0 表示不限制数量，成员和分数以 pair 返回
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrangebyscore {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(double, tag = "3")]
+    pub min: f64,
+    #[prost(double, tag = "4")]
+    pub max: f64,
+    #[prost(uint32, tag = "5")]
+    pub limit: u32,
+}
+/// 返回成员按分数从小到大的排名，从 0 开始，成员不存在时返回 404
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrank {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub member: ::prost::alloc::string::String,
+}
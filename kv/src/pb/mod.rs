@@ -2,19 +2,42 @@ use http::StatusCode;
 use prost::Message;
 
 use crate::{
-    CommandRequest, CommandResponse, Hget, Hgetall, Hset, Kvpair, Value,
-    command_request::RequestData, error::KvError, value,
+    Cas, CommandRequest, CommandResponse, DropTable, Expire, Hdel, Hexists, Hget, Hgetall, Hincrby,
+    Hincrbyfloat, Hmdel, Hmexists, Hmget, Hmset, Hscan, Hset, Hsetnx, Kvpair, ListTables,
+    ListValue, Lpop, Lpush, Lrange, Persist, Publish, Replicate, Rpop, Rpush, Sadd, ScoredMember,
+    SetValue, Sinter, Smembers, Srem, Subscribe, TableInfo, Transaction, Ttl, Unsubscribe, Value,
+    Watch, Zadd, Zrange, Zrangebyscore, Zrank, Zrem, ZsetValue, command_request::RequestData,
+    error::KvError, value,
 };
 
 pub mod abi;
 
 impl CommandRequest {
     pub fn new_hset(table: &str, key: &str, value: Value) -> Self {
+        Self::new_hset_with_ttl(table, key, value, 0)
+    }
+    // ttl 单位为毫秒，0 表示不过期
+    pub fn new_hset_with_ttl(table: &str, key: &str, value: Value, ttl: u64) -> Self {
         Self {
             request_data: Some(RequestData::Hset(Hset {
                 table: table.into(),
                 pair: Some(Kvpair::new(key, value)),
+                ttl,
+                expire_at: 0,
             })),
+            ..Default::default()
+        }
+    }
+    // expire_at 为过期的时间点（毫秒时间戳），已经过去时删除这个 key
+    pub fn new_hset_with_deadline(table: &str, key: &str, value: Value, expire_at: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hset(Hset {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
+                ttl: 0,
+                expire_at,
+            })),
+            ..Default::default()
         }
     }
     pub fn new_hget(table: &str, key: &str) -> Self {
@@ -23,6 +46,7 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
         }
     }
     pub fn new_hgetall(table: &str) -> Self {
@@ -30,6 +54,499 @@ impl CommandRequest {
             request_data: Some(RequestData::Hgetall(Hgetall {
                 table: table.into(),
             })),
+            ..Default::default()
+        }
+    }
+    pub fn new_hmget(table: &str, keys: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmget(Hmget {
+                table: table.into(),
+                keys,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_hmset(table: &str, pairs: Vec<Kvpair>) -> Self {
+        Self::new_hmset_with_ttl(table, pairs, 0)
+    }
+    pub fn new_hmset_with_ttl(table: &str, pairs: Vec<Kvpair>, ttl: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmset(Hmset {
+                table: table.into(),
+                pairs,
+                ttl,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_hdel(table: &str, key: &str) -> Self {
+        Self {
+            request_data: Some(RequestData::Hdel(Hdel {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_hmdel(table: &str, keys: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmdel(Hmdel {
+                table: table.into(),
+                keys,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_hexists(table: &str, key: &str) -> Self {
+        Self {
+            request_data: Some(RequestData::Hexists(Hexists {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_hmexists(table: &str, keys: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmexists(Hmexists {
+                table: table.into(),
+                keys,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_subscribe(topic: &str) -> Self {
+        Self {
+            request_data: Some(RequestData::Subscribe(Subscribe {
+                topic: topic.into(),
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_unsubscribe(topic: &str, id: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Unsubscribe(Unsubscribe {
+                topic: topic.into(),
+                id,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_publish(topic: &str, data: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Publish(Publish {
+                topic: topic.into(),
+                data,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_expire(table: &str, key: &str, ttl: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Expire(Expire {
+                table: table.into(),
+                key: key.into(),
+                ttl,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_ttl(table: &str, key: &str) -> Self {
+        Self {
+            request_data: Some(RequestData::Ttl(Ttl {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_persist(table: &str, key: &str) -> Self {
+        Self {
+            request_data: Some(RequestData::Persist(Persist {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_hincrby(table: &str, key: &str, delta: i64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hincrby(Hincrby {
+                table: table.into(),
+                key: key.into(),
+                delta,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_hincrbyfloat(table: &str, key: &str, delta: f64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hincrbyfloat(Hincrbyfloat {
+                table: table.into(),
+                key: key.into(),
+                delta,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_hsetnx(table: &str, key: &str, value: Value) -> Self {
+        Self {
+            request_data: Some(RequestData::Hsetnx(Hsetnx {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
+            })),
+            ..Default::default()
+        }
+    }
+    // expected 为 None 表示要求 key 不存在
+    pub fn new_cas(table: &str, key: &str, expected: Option<Value>, value: Value) -> Self {
+        Self {
+            request_data: Some(RequestData::Cas(Cas {
+                table: table.into(),
+                key: key.into(),
+                expected,
+                value: Some(value),
+            })),
+            ..Default::default()
+        }
+    }
+    // cursor 为上一次返回的游标，第一页传空字符串；limit 为 0 时使用默认值
+    pub fn new_hscan(table: &str, cursor: &str, limit: u32) -> Self {
+        Self::new_hscan_prefix(table, "", cursor, limit)
+    }
+    pub fn new_hscan_prefix(table: &str, prefix: &str, cursor: &str, limit: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Hscan(Hscan {
+                table: table.into(),
+                cursor: cursor.into(),
+                prefix: prefix.into(),
+                limit,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+    // 返回 [start, end) 范围内的 key，为空表示不限制
+    pub fn new_hscan_range(table: &str, start: &str, end: &str, cursor: &str, limit: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Hscan(Hscan {
+                table: table.into(),
+                cursor: cursor.into(),
+                start: start.into(),
+                end: end.into(),
+                limit,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_list_tables() -> Self {
+        Self {
+            request_data: Some(RequestData::ListTables(ListTables {})),
+            ..Default::default()
+        }
+    }
+    pub fn new_drop_table(table: &str) -> Self {
+        Self {
+            request_data: Some(RequestData::DropTable(DropTable {
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_table_info(table: &str) -> Self {
+        Self {
+            request_data: Some(RequestData::TableInfo(TableInfo {
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_replicate() -> Self {
+        Self {
+            request_data: Some(RequestData::Replicate(Replicate {})),
+            ..Default::default()
+        }
+    }
+    pub fn new_lpush(table: &str, key: &str, values: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Lpush(Lpush {
+                table: table.into(),
+                key: key.into(),
+                values,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_rpush(table: &str, key: &str, values: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Rpush(Rpush {
+                table: table.into(),
+                key: key.into(),
+                values,
+            })),
+            ..Default::default()
+        }
+    }
+    // count 为 0 时弹出一个元素
+    pub fn new_lpop(table: &str, key: &str, count: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Lpop(Lpop {
+                table: table.into(),
+                key: key.into(),
+                count,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_rpop(table: &str, key: &str, count: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Rpop(Rpop {
+                table: table.into(),
+                key: key.into(),
+                count,
+            })),
+            ..Default::default()
+        }
+    }
+    // start 和 stop 都包含在内，负数表示从尾部开始计算
+    pub fn new_lrange(table: &str, key: &str, start: i64, stop: i64) -> Self {
+        Self {
+            request_data: Some(RequestData::Lrange(Lrange {
+                table: table.into(),
+                key: key.into(),
+                start,
+                stop,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_sadd(table: &str, key: &str, members: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Sadd(Sadd {
+                table: table.into(),
+                key: key.into(),
+                members,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_srem(table: &str, key: &str, members: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Srem(Srem {
+                table: table.into(),
+                key: key.into(),
+                members,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_smembers(table: &str, key: &str) -> Self {
+        Self {
+            request_data: Some(RequestData::Smembers(Smembers {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_sinter(table: &str, keys: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Sinter(Sinter {
+                table: table.into(),
+                keys,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_zadd(table: &str, key: &str, members: Vec<ScoredMember>) -> Self {
+        Self {
+            request_data: Some(RequestData::Zadd(Zadd {
+                table: table.into(),
+                key: key.into(),
+                members,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_zrem(table: &str, key: &str, members: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrem(Zrem {
+                table: table.into(),
+                key: key.into(),
+                members,
+            })),
+            ..Default::default()
+        }
+    }
+    // 按排名返回，start 和 stop 都包含在内，负数表示从尾部开始计算
+    pub fn new_zrange(table: &str, key: &str, start: i64, stop: i64) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrange(Zrange {
+                table: table.into(),
+                key: key.into(),
+                start,
+                stop,
+            })),
+            ..Default::default()
+        }
+    }
+    // 返回分数在 [min, max] 范围内的成员，limit 为 0 时不限制数量
+    pub fn new_zrangebyscore(table: &str, key: &str, min: f64, max: f64, limit: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrangebyscore(Zrangebyscore {
+                table: table.into(),
+                key: key.into(),
+                min,
+                max,
+                limit,
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_zrank(table: &str, key: &str, member: &str) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrank(Zrank {
+                table: table.into(),
+                key: key.into(),
+                member: member.into(),
+            })),
+            ..Default::default()
+        }
+    }
+    pub fn new_transaction(commands: Vec<CommandRequest>, watches: Vec<Watch>) -> Self {
+        Self {
+            request_data: Some(RequestData::Transaction(Transaction { commands, watches })),
+            ..Default::default()
+        }
+    }
+
+    // 命令名，用于日志和监控指标
+    pub fn command_name(&self) -> &'static str {
+        match &self.request_data {
+            Some(RequestData::Hget(_)) => "hget",
+            Some(RequestData::Hgetall(_)) => "hgetall",
+            Some(RequestData::Hmget(_)) => "hmget",
+            Some(RequestData::Hset(_)) => "hset",
+            Some(RequestData::Hmset(_)) => "hmset",
+            Some(RequestData::Hdel(_)) => "hdel",
+            Some(RequestData::Hmdel(_)) => "hmdel",
+            Some(RequestData::Hexists(_)) => "hexists",
+            Some(RequestData::Hmexists(_)) => "hmexists",
+            Some(RequestData::Subscribe(_)) => "subscribe",
+            Some(RequestData::Unsubscribe(_)) => "unsubscribe",
+            Some(RequestData::Publish(_)) => "publish",
+            Some(RequestData::Expire(_)) => "expire",
+            Some(RequestData::Ttl(_)) => "ttl",
+            Some(RequestData::Persist(_)) => "persist",
+            Some(RequestData::Transaction(_)) => "transaction",
+            Some(RequestData::Hincrby(_)) => "hincrby",
+            Some(RequestData::Hincrbyfloat(_)) => "hincrbyfloat",
+            Some(RequestData::Hsetnx(_)) => "hsetnx",
+            Some(RequestData::Cas(_)) => "cas",
+            Some(RequestData::Hscan(_)) => "hscan",
+            Some(RequestData::ListTables(_)) => "list_tables",
+            Some(RequestData::DropTable(_)) => "drop_table",
+            Some(RequestData::TableInfo(_)) => "table_info",
+            Some(RequestData::Replicate(_)) => "replicate",
+            Some(RequestData::Lpush(_)) => "lpush",
+            Some(RequestData::Rpush(_)) => "rpush",
+            Some(RequestData::Lpop(_)) => "lpop",
+            Some(RequestData::Rpop(_)) => "rpop",
+            Some(RequestData::Lrange(_)) => "lrange",
+            Some(RequestData::Sadd(_)) => "sadd",
+            Some(RequestData::Srem(_)) => "srem",
+            Some(RequestData::Smembers(_)) => "smembers",
+            Some(RequestData::Sinter(_)) => "sinter",
+            Some(RequestData::Zadd(_)) => "zadd",
+            Some(RequestData::Zrem(_)) => "zrem",
+            Some(RequestData::Zrange(_)) => "zrange",
+            Some(RequestData::Zrangebyscore(_)) => "zrangebyscore",
+            Some(RequestData::Zrank(_)) => "zrank",
+            None => "unknown",
+        }
+    }
+
+    // 是否修改数据，事务中有一个修改命令就算修改
+    pub fn is_write(&self) -> bool {
+        match &self.request_data {
+            Some(
+                RequestData::Hset(_)
+                | RequestData::Hmset(_)
+                | RequestData::Hdel(_)
+                | RequestData::Hmdel(_)
+                | RequestData::Expire(_)
+                | RequestData::Persist(_)
+                | RequestData::Hincrby(_)
+                | RequestData::Hincrbyfloat(_)
+                | RequestData::Hsetnx(_)
+                | RequestData::Cas(_)
+                | RequestData::DropTable(_)
+                | RequestData::Lpush(_)
+                | RequestData::Rpush(_)
+                | RequestData::Lpop(_)
+                | RequestData::Rpop(_)
+                | RequestData::Sadd(_)
+                | RequestData::Srem(_)
+                | RequestData::Zadd(_)
+                | RequestData::Zrem(_),
+            ) => true,
+            Some(RequestData::Transaction(tx)) => tx.commands.iter().any(|cmd| cmd.is_write()),
+            _ => false,
+        }
+    }
+
+    // 修改命令可能修改的 (表, key)，key 为 None 表示整个表，leader 据此记录修改后的结果
+    pub(crate) fn touched(&self) -> Vec<(&str, Option<&str>)> {
+        match &self.request_data {
+            Some(RequestData::Hset(p)) => match p.pair.as_ref() {
+                Some(pair) => vec![(p.table.as_str(), Some(pair.key.as_str()))],
+                None => vec![],
+            },
+            Some(RequestData::Hsetnx(p)) => match p.pair.as_ref() {
+                Some(pair) => vec![(p.table.as_str(), Some(pair.key.as_str()))],
+                None => vec![],
+            },
+            Some(RequestData::Hmset(p)) => p
+                .pairs
+                .iter()
+                .map(|pair| (p.table.as_str(), Some(pair.key.as_str())))
+                .collect(),
+            Some(RequestData::Hmdel(p)) => p
+                .keys
+                .iter()
+                .map(|key| (p.table.as_str(), Some(key.as_str())))
+                .collect(),
+            Some(RequestData::Hdel(p)) => vec![(p.table.as_str(), Some(p.key.as_str()))],
+            Some(RequestData::Expire(p)) => vec![(p.table.as_str(), Some(p.key.as_str()))],
+            Some(RequestData::Persist(p)) => vec![(p.table.as_str(), Some(p.key.as_str()))],
+            Some(RequestData::Hincrby(p)) => vec![(p.table.as_str(), Some(p.key.as_str()))],
+            Some(RequestData::Hincrbyfloat(p)) => vec![(p.table.as_str(), Some(p.key.as_str()))],
+            Some(RequestData::Cas(p)) => vec![(p.table.as_str(), Some(p.key.as_str()))],
+            Some(RequestData::Lpush(p)) => vec![(p.table.as_str(), Some(p.key.as_str()))],
+            Some(RequestData::Rpush(p)) => vec![(p.table.as_str(), Some(p.key.as_str()))],
+            Some(RequestData::Lpop(p)) => vec![(p.table.as_str(), Some(p.key.as_str()))],
+            Some(RequestData::Rpop(p)) => vec![(p.table.as_str(), Some(p.key.as_str()))],
+            Some(RequestData::Sadd(p)) => vec![(p.table.as_str(), Some(p.key.as_str()))],
+            Some(RequestData::Srem(p)) => vec![(p.table.as_str(), Some(p.key.as_str()))],
+            Some(RequestData::Zadd(p)) => vec![(p.table.as_str(), Some(p.key.as_str()))],
+            Some(RequestData::Zrem(p)) => vec![(p.table.as_str(), Some(p.key.as_str()))],
+            Some(RequestData::DropTable(p)) => vec![(p.table.as_str(), None)],
+            Some(RequestData::Transaction(tx)) => {
+                tx.commands.iter().flat_map(|cmd| cmd.touched()).collect()
+            }
+            _ => vec![],
+        }
+    }
+}
+
+impl Watch {
+    // value 为 None 表示要求 key 不存在
+    pub fn new(table: &str, key: &str, value: Option<Value>) -> Self {
+        Self {
+            table: table.into(),
+            key: key.into(),
+            value,
         }
     }
 }
@@ -43,6 +560,65 @@ impl Kvpair {
     }
 }
 
+impl ScoredMember {
+    pub fn new(member: &str, score: f64) -> Self {
+        Self {
+            member: member.into(),
+            score,
+        }
+    }
+}
+
+impl Value {
+    // 值的类型名，用于类型错误的提示
+    pub fn is_collection(&self) -> bool {
+        matches!(
+            self.value,
+            Some(
+                value::Value::ListValue(_) | value::Value::SetValue(_) | value::Value::ZsetValue(_)
+            )
+        )
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self.value {
+            Some(value::Value::StringValue(_)) => "string",
+            Some(value::Value::BytesValue(_)) => "bytes",
+            Some(value::Value::Int64Value(_)) => "int64",
+            Some(value::Value::DoubleValue(_)) => "double",
+            Some(value::Value::BoolValue(_)) => "bool",
+            Some(value::Value::ListValue(_)) => "list",
+            Some(value::Value::SetValue(_)) => "set",
+            Some(value::Value::ZsetValue(_)) => "zset",
+            None => "empty",
+        }
+    }
+}
+
+impl From<ListValue> for Value {
+    fn from(value: ListValue) -> Self {
+        Self {
+            value: Some(value::Value::ListValue(value)),
+        }
+    }
+}
+
+impl From<SetValue> for Value {
+    fn from(value: SetValue) -> Self {
+        Self {
+            value: Some(value::Value::SetValue(value)),
+        }
+    }
+}
+
+impl From<ZsetValue> for Value {
+    fn from(value: ZsetValue) -> Self {
+        Self {
+            value: Some(value::Value::ZsetValue(value)),
+        }
+    }
+}
+
 impl From<String> for Value {
     fn from(value: String) -> Self {
         Self {
@@ -70,7 +646,8 @@ impl From<Value> for CommandResponse {
     fn from(value: Value) -> Self {
         Self {
             status: StatusCode::OK.as_u16() as _,
-            values: vec![value.into()],
+            message: "success".to_string(),
+            values: vec![value],
             ..Default::default()
         }
     }
@@ -84,12 +661,53 @@ impl From<KvError> for CommandResponse {
                 message: "not found".to_string(),
                 values: vec![],
                 pairs: vec![],
+                ..Default::default()
+            },
+            KvError::NotFound(..) | KvError::TableNotFound(..) => Self {
+                status: StatusCode::NOT_FOUND.as_u16() as _,
+                message: error.to_string(),
+                values: vec![],
+                pairs: vec![],
+                ..Default::default()
+            },
+            KvError::PermissionDenied(..) => Self {
+                status: StatusCode::FORBIDDEN.as_u16() as _,
+                message: error.to_string(),
+                values: vec![],
+                pairs: vec![],
+                ..Default::default()
+            },
+            // 客户端应该把写请求发到 message 中的 leader 地址
+            KvError::Redirect(..) => Self {
+                status: StatusCode::TEMPORARY_REDIRECT.as_u16() as _,
+                message: error.to_string(),
+                values: vec![],
+                pairs: vec![],
+                ..Default::default()
+            },
+            // 对错误类型的 key 执行了命令，例如对字符串执行列表命令，递增后的结果超出范围，或者参数不合法
+            KvError::WrongType(..) | KvError::OutOfRange(..) | KvError::InvalidArgument(..) => {
+                Self {
+                    status: StatusCode::BAD_REQUEST.as_u16() as _,
+                    message: error.to_string(),
+                    values: vec![],
+                    pairs: vec![],
+                    ..Default::default()
+                }
+            }
+            KvError::TransactionAborted(..) => Self {
+                status: StatusCode::CONFLICT.as_u16() as _,
+                message: error.to_string(),
+                values: vec![],
+                pairs: vec![],
+                ..Default::default()
             },
             _ => Self {
                 status: StatusCode::INTERNAL_SERVER_ERROR.as_u16() as _,
                 message: error.to_string(),
                 values: vec![],
                 pairs: vec![],
+                ..Default::default()
             },
         }
     }
@@ -102,6 +720,7 @@ impl From<Vec<Kvpair>> for CommandResponse {
             message: "success".to_string(),
             values: vec![],
             pairs: value,
+            ..Default::default()
         }
     }
 }
@@ -113,6 +732,7 @@ impl From<Vec<Value>> for CommandResponse {
             message: "success".to_string(),
             values,
             pairs: vec![],
+            ..Default::default()
         }
     }
 }
@@ -145,6 +765,28 @@ impl Value {
         Value::decode(value).map_err(KvError::ProstError)
     }
 }
+impl TryFrom<&Value> for i64 {
+    type Error = KvError;
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value.value {
+            Some(value::Value::Int64Value(v)) => Ok(v),
+            _ => Err(KvError::ConvertError(format!("{:?}", value), "Int64Value")),
+        }
+    }
+}
+
+// 整数也可以当作浮点数使用
+impl TryFrom<&Value> for f64 {
+    type Error = KvError;
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value.value {
+            Some(value::Value::DoubleValue(v)) => Ok(v),
+            Some(value::Value::Int64Value(v)) => Ok(v as f64),
+            _ => Err(KvError::ConvertError(format!("{:?}", value), "DoubleValue")),
+        }
+    }
+}
+
 impl TryFrom<Value> for Vec<u8> {
     type Error = KvError;
     fn try_from(value: Value) -> Result<Self, Self::Error> {
@@ -0,0 +1,544 @@
+use std::{future::Future, ops::Bound, sync::Arc, time::Duration};
+
+use futures::{
+    StreamExt,
+    future::ready,
+    stream::{self, BoxStream},
+};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    Kvpair, Value,
+    error::KvError,
+    storage::storage::{ScanRange, Storage, TableStats},
+};
+
+// 异步的存储接口，Service 通过它访问存储，远程存储等非阻塞的后端直接实现这个 trait
+// 同步的后端用 BlockingStorage 包装，不要求 Send + Sync，需要跨线程共享的地方自行约束
+pub trait AsyncStorage {
+    //获取一个key 的value
+    fn get(
+        &self,
+        table: &str,
+        key: &str,
+    ) -> impl Future<Output = Result<Option<Value>, KvError>> + Send;
+    //设置一个key 的value，返回旧值
+    fn set(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+    ) -> impl Future<Output = Result<Option<Value>, KvError>> + Send;
+    //删除一个key，返回被删除的值
+    fn delete(
+        &self,
+        table: &str,
+        key: &str,
+    ) -> impl Future<Output = Result<Option<Value>, KvError>> + Send;
+    //判断一个key 是否存在
+    fn contains(
+        &self,
+        table: &str,
+        key: &str,
+    ) -> impl Future<Output = Result<bool, KvError>> + Send;
+    //获取一个表的所有key-value
+    fn get_all(&self, table: &str) -> impl Future<Output = Result<Vec<Kvpair>, KvError>> + Send;
+    //以 stream 的方式遍历一个表，用于生成快照
+    fn get_iter(
+        &self,
+        table: &str,
+    ) -> impl Future<Output = Result<BoxStream<'static, Kvpair>, KvError>> + Send;
+    //批量设置多个key 的value，返回每个key 的旧值
+    fn set_many(
+        &self,
+        table: &str,
+        pairs: Vec<Kvpair>,
+    ) -> impl Future<Output = Result<Vec<Option<Value>>, KvError>> + Send;
+    //批量删除多个key，返回每个key 被删除的值
+    fn delete_many(
+        &self,
+        table: &str,
+        keys: &[String],
+    ) -> impl Future<Output = Result<Vec<Option<Value>>, KvError>> + Send;
+    //设置一个key 的过期时间，key 不存在时返回 false
+    fn expire(
+        &self,
+        table: &str,
+        key: &str,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<bool, KvError>> + Send;
+    //写入一个key 的value 并设置过期时间，两者同时生效，返回旧值
+    fn set_with_ttl(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<Option<Value>, KvError>> + Send;
+    //批量写入多个key 的value 并设置相同的过期时间，返回每个key 的旧值
+    fn set_many_with_ttl(
+        &self,
+        table: &str,
+        pairs: Vec<Kvpair>,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<Vec<Option<Value>>, KvError>> + Send;
+    //获取一个key 剩余的过期时间，没有过期时间时返回 None
+    fn ttl(
+        &self,
+        table: &str,
+        key: &str,
+    ) -> impl Future<Output = Result<Option<Duration>, KvError>> + Send;
+    //清除一个key 的过期时间
+    fn persist(&self, table: &str, key: &str)
+    -> impl Future<Output = Result<bool, KvError>> + Send;
+    //清理所有已经过期的key，返回清理的数量
+    fn reap_expired(&self) -> impl Future<Output = Result<usize, KvError>> + Send;
+    //原子地读取-修改-写入一个key，返回旧值
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: BoxedUpdateFn,
+    ) -> impl Future<Output = Result<Option<Value>, KvError>> + Send;
+    //按key 的顺序返回表中以 prefix 开头并且在 range 范围内的key-value，最多 limit 个
+    fn scan(
+        &self,
+        table: &str,
+        prefix: &str,
+        range: ScanRange<'_>,
+        limit: usize,
+    ) -> impl Future<Output = Result<Vec<Kvpair>, KvError>> + Send;
+    //列出所有的表
+    fn list_tables(&self) -> impl Future<Output = Result<Vec<String>, KvError>> + Send;
+    //删除整个表，返回删除的key 的数量
+    fn drop_table(&self, table: &str) -> impl Future<Output = Result<usize, KvError>> + Send;
+    //获取表的统计信息，表不存在时返回 None
+    fn table_info(
+        &self,
+        table: &str,
+    ) -> impl Future<Output = Result<Option<TableStats>, KvError>> + Send;
+    //在事务中执行 f，事务中的操作通过同步的存储视图完成
+    fn transaction(&self, f: BoxedTxFn) -> impl Future<Output = Result<(), KvError>> + Send;
+}
+
+// 异步存储的闭包可能被移到其它线程执行，因此需要 Send + 'static
+pub type BoxedUpdateFn = Box<dyn FnMut(Option<&Value>) -> Result<Option<Value>, KvError> + Send>;
+pub type BoxedTxFn = Box<dyn FnMut(&dyn Storage) -> Result<(), KvError> + Send>;
+
+// get_iter 在阻塞线程中遍历时，缓冲的 key-value 数量
+const ITER_BUFFER: usize = 1024;
+
+// 把同步的存储包装成异步存储，每个操作都在 spawn_blocking 的线程池中执行，不阻塞 tokio 的工作线程
+#[derive(Debug, Default)]
+pub struct BlockingStorage<S> {
+    inner: Arc<S>,
+}
+
+impl<S> BlockingStorage<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            inner: Arc::new(store),
+        }
+    }
+}
+
+impl<S> Clone for BlockingStorage<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<S: Storage + Send + Sync + 'static> BlockingStorage<S> {
+    async fn run<T, F>(&self, f: F) -> Result<T, KvError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&S) -> Result<T, KvError> + Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        tokio::task::spawn_blocking(move || f(&inner))
+            .await
+            .map_err(|e| KvError::Internal(e.to_string()))?
+    }
+}
+
+impl<S: Storage + Send + Sync + 'static> AsyncStorage for BlockingStorage<S> {
+    async fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let (table, key) = (table.to_owned(), key.to_owned());
+        self.run(move |store| store.get(&table, &key)).await
+    }
+
+    async fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
+        let (table, key) = (table.to_owned(), key.to_owned());
+        self.run(move |store| store.set(&table, &key, value)).await
+    }
+
+    async fn delete(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let (table, key) = (table.to_owned(), key.to_owned());
+        self.run(move |store| store.delete(&table, &key)).await
+    }
+
+    async fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        let (table, key) = (table.to_owned(), key.to_owned());
+        self.run(move |store| store.contains(&table, &key)).await
+    }
+
+    async fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        let table = table.to_owned();
+        self.run(move |store| store.get_all(&table)).await
+    }
+
+    // 在阻塞线程中遍历，通过 channel 逐个发送，stream 被释放后遍历随之停止
+    async fn get_iter(&self, table: &str) -> Result<BoxStream<'static, Kvpair>, KvError> {
+        let (table, inner) = (table.to_owned(), Arc::clone(&self.inner));
+        let (tx, rx) = mpsc::channel(ITER_BUFFER);
+        let (ready_tx, ready_rx) = oneshot::channel();
+        tokio::task::spawn_blocking(move || {
+            let iter = match inner.get_iter(&table) {
+                Ok(iter) => iter,
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                    return;
+                }
+            };
+            let _ = ready_tx.send(Ok(()));
+            for pair in iter {
+                if tx.blocking_send(pair).is_err() {
+                    break;
+                }
+            }
+        });
+        ready_rx
+            .await
+            .map_err(|e| KvError::Internal(e.to_string()))??;
+        Ok(Box::pin(stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|pair| (pair, rx))
+        })))
+    }
+
+    async fn set_many(
+        &self,
+        table: &str,
+        pairs: Vec<Kvpair>,
+    ) -> Result<Vec<Option<Value>>, KvError> {
+        let table = table.to_owned();
+        self.run(move |store| store.set_many(&table, pairs)).await
+    }
+
+    async fn delete_many(
+        &self,
+        table: &str,
+        keys: &[String],
+    ) -> Result<Vec<Option<Value>>, KvError> {
+        let (table, keys) = (table.to_owned(), keys.to_vec());
+        self.run(move |store| store.delete_many(&table, &keys))
+            .await
+    }
+
+    async fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        let (table, key) = (table.to_owned(), key.to_owned());
+        self.run(move |store| store.expire(&table, &key, ttl)).await
+    }
+
+    async fn set_with_ttl(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        let (table, key) = (table.to_owned(), key.to_owned());
+        self.run(move |store| store.set_with_ttl(&table, &key, value, ttl))
+            .await
+    }
+
+    async fn set_many_with_ttl(
+        &self,
+        table: &str,
+        pairs: Vec<Kvpair>,
+        ttl: Duration,
+    ) -> Result<Vec<Option<Value>>, KvError> {
+        let table = table.to_owned();
+        self.run(move |store| store.set_many_with_ttl(&table, pairs, ttl))
+            .await
+    }
+
+    async fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        let (table, key) = (table.to_owned(), key.to_owned());
+        self.run(move |store| store.ttl(&table, &key)).await
+    }
+
+    async fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        let (table, key) = (table.to_owned(), key.to_owned());
+        self.run(move |store| store.persist(&table, &key)).await
+    }
+
+    async fn reap_expired(&self) -> Result<usize, KvError> {
+        self.run(|store| store.reap_expired()).await
+    }
+
+    async fn update(
+        &self,
+        table: &str,
+        key: &str,
+        mut f: BoxedUpdateFn,
+    ) -> Result<Option<Value>, KvError> {
+        let (table, key) = (table.to_owned(), key.to_owned());
+        self.run(move |store| store.update(&table, &key, &mut *f))
+            .await
+    }
+
+    async fn scan(
+        &self,
+        table: &str,
+        prefix: &str,
+        range: ScanRange<'_>,
+        limit: usize,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let (table, prefix) = (table.to_owned(), prefix.to_owned());
+        let (start, end) = (range.0.map(str::to_owned), range.1.map(str::to_owned));
+        self.run(move |store| {
+            let range = (as_str_bound(&start), as_str_bound(&end));
+            store.scan(&table, &prefix, range, limit)
+        })
+        .await
+    }
+
+    async fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        self.run(|store| store.list_tables()).await
+    }
+
+    async fn drop_table(&self, table: &str) -> Result<usize, KvError> {
+        let table = table.to_owned();
+        self.run(move |store| store.drop_table(&table)).await
+    }
+
+    async fn table_info(&self, table: &str) -> Result<Option<TableStats>, KvError> {
+        let table = table.to_owned();
+        self.run(move |store| store.table_info(&table)).await
+    }
+
+    async fn transaction(&self, mut f: BoxedTxFn) -> Result<(), KvError> {
+        self.run(move |store| store.transaction(&mut *f)).await
+    }
+}
+
+fn as_str_bound(bound: &Bound<String>) -> Bound<&str> {
+    bound.as_ref().map(String::as_str)
+}
+
+// 事务中的同步存储视图，操作立即完成，用来在事务里执行异步的命令
+pub(crate) struct StorageView<'a>(pub &'a dyn Storage);
+
+impl AsyncStorage for StorageView<'_> {
+    fn get(
+        &self,
+        table: &str,
+        key: &str,
+    ) -> impl Future<Output = Result<Option<Value>, KvError>> + Send {
+        ready(self.0.get(table, key))
+    }
+
+    fn set(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+    ) -> impl Future<Output = Result<Option<Value>, KvError>> + Send {
+        ready(self.0.set(table, key, value))
+    }
+
+    fn delete(
+        &self,
+        table: &str,
+        key: &str,
+    ) -> impl Future<Output = Result<Option<Value>, KvError>> + Send {
+        ready(self.0.delete(table, key))
+    }
+
+    fn contains(
+        &self,
+        table: &str,
+        key: &str,
+    ) -> impl Future<Output = Result<bool, KvError>> + Send {
+        ready(self.0.contains(table, key))
+    }
+
+    fn get_all(&self, table: &str) -> impl Future<Output = Result<Vec<Kvpair>, KvError>> + Send {
+        ready(self.0.get_all(table))
+    }
+
+    // 同步的迭代器不一定是 Send 的，先收集起来
+    fn get_iter(
+        &self,
+        table: &str,
+    ) -> impl Future<Output = Result<BoxStream<'static, Kvpair>, KvError>> + Send {
+        let pairs = self.0.get_iter(table).map(Iterator::collect::<Vec<_>>);
+        ready(pairs.map(|pairs| stream::iter(pairs).boxed()))
+    }
+
+    fn set_many(
+        &self,
+        table: &str,
+        pairs: Vec<Kvpair>,
+    ) -> impl Future<Output = Result<Vec<Option<Value>>, KvError>> + Send {
+        ready(self.0.set_many(table, pairs))
+    }
+
+    fn delete_many(
+        &self,
+        table: &str,
+        keys: &[String],
+    ) -> impl Future<Output = Result<Vec<Option<Value>>, KvError>> + Send {
+        ready(self.0.delete_many(table, keys))
+    }
+
+    fn expire(
+        &self,
+        table: &str,
+        key: &str,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<bool, KvError>> + Send {
+        ready(self.0.expire(table, key, ttl))
+    }
+
+    fn set_with_ttl(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<Option<Value>, KvError>> + Send {
+        ready(self.0.set_with_ttl(table, key, value, ttl))
+    }
+
+    fn set_many_with_ttl(
+        &self,
+        table: &str,
+        pairs: Vec<Kvpair>,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<Vec<Option<Value>>, KvError>> + Send {
+        ready(self.0.set_many_with_ttl(table, pairs, ttl))
+    }
+
+    fn ttl(
+        &self,
+        table: &str,
+        key: &str,
+    ) -> impl Future<Output = Result<Option<Duration>, KvError>> + Send {
+        ready(self.0.ttl(table, key))
+    }
+
+    fn persist(
+        &self,
+        table: &str,
+        key: &str,
+    ) -> impl Future<Output = Result<bool, KvError>> + Send {
+        ready(self.0.persist(table, key))
+    }
+
+    fn reap_expired(&self) -> impl Future<Output = Result<usize, KvError>> + Send {
+        ready(self.0.reap_expired())
+    }
+
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        mut f: BoxedUpdateFn,
+    ) -> impl Future<Output = Result<Option<Value>, KvError>> + Send {
+        ready(self.0.update(table, key, &mut *f))
+    }
+
+    fn scan(
+        &self,
+        table: &str,
+        prefix: &str,
+        range: ScanRange<'_>,
+        limit: usize,
+    ) -> impl Future<Output = Result<Vec<Kvpair>, KvError>> + Send {
+        ready(self.0.scan(table, prefix, range, limit))
+    }
+
+    fn list_tables(&self) -> impl Future<Output = Result<Vec<String>, KvError>> + Send {
+        ready(self.0.list_tables())
+    }
+
+    fn drop_table(&self, table: &str) -> impl Future<Output = Result<usize, KvError>> + Send {
+        ready(self.0.drop_table(table))
+    }
+
+    fn table_info(
+        &self,
+        table: &str,
+    ) -> impl Future<Output = Result<Option<TableStats>, KvError>> + Send {
+        ready(self.0.table_info(table))
+    }
+
+    fn transaction(&self, mut f: BoxedTxFn) -> impl Future<Output = Result<(), KvError>> + Send {
+        ready(self.0.transaction(&mut *f))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use tempfile::tempdir;
+
+    use crate::{CommandRequest, MemTable, command::dispatch, sleddb::SledDb};
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn memtable_blocking_storage_should_work() {
+        test_blocking_storage(BlockingStorage::new(MemTable::new())).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn sleddb_blocking_storage_should_work() {
+        let dir = tempdir().unwrap();
+        test_blocking_storage(BlockingStorage::new(SledDb::new(dir))).await;
+    }
+
+    async fn test_blocking_storage<S: Storage + Send + Sync + 'static>(store: BlockingStorage<S>) {
+        assert_eq!(store.set("t1", "k1", "v1".into()).await, Ok(None));
+        assert_eq!(store.get("t1", "k1").await, Ok(Some("v1".into())));
+        assert_eq!(store.contains("t1", "k2").await, Ok(false));
+        let range = (Bound::Included("k"), Bound::Unbounded);
+        let pairs = store.scan("t1", "", range, 10).await.unwrap();
+        assert_eq!(pairs, vec![Kvpair::new("k1", "v1".into())]);
+
+        // 并发的原子更新在阻塞线程池中执行，不会丢失
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    for _ in 0..25 {
+                        let cmd = CommandRequest::new_hincrby("t1", "counter", 1);
+                        assert_eq!(dispatch(cmd, &store).await.status, 200);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(store.get("t1", "counter").await, Ok(Some(100.into())));
+
+        // 事务在同一个阻塞任务中完成
+        let cmd = CommandRequest::new_transaction(
+            vec![
+                CommandRequest::new_hdel("t1", "k1"),
+                CommandRequest::new_hset("t2", "k1", "v1".into()),
+            ],
+            vec![],
+        );
+        let res = dispatch(cmd, &store).await;
+        assert_eq!(res.status, 200);
+        assert_eq!(res.responses.len(), 2);
+        assert_eq!(store.get("t1", "k1").await, Ok(None));
+        assert_eq!(store.drop_table("t2").await, Ok(1));
+    }
+}
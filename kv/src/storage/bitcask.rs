@@ -0,0 +1,1153 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    thread,
+    time::Duration,
+};
+
+use tracing::{debug, info, warn};
+
+use crate::{
+    Kvpair, Value, deadline_ms,
+    error::KvError,
+    now_ms,
+    storage::{
+        record::{HEADER_LEN, RECORD_PUT, Record, invalid_data, scan_records},
+        storage::{Storage, TableStats, UpdateFn},
+    },
+};
+
+// hint 格式：crc(4) | 过期时间(8) | offset(8) | 记录长度(4) | table 长度(4) | key 长度(4) | table | key
+const HINT_HEADER_LEN: usize = 32;
+
+const DATA_EXT: &str = "data";
+const HINT_EXT: &str = "hint";
+// 压缩中写入的文件先使用临时的扩展名，写完后再改名
+const TMP_EXT: &str = "tmp";
+
+#[derive(Debug, Clone)]
+pub struct BitcaskOptions {
+    // 活跃段文件超过这个大小后切换到新的段文件
+    pub max_segment_size: u64,
+    // 每次写入后是否 fsync
+    pub sync: bool,
+    // 后台检查是否需要压缩的间隔，None 表示不在后台压缩
+    pub compact_interval: Option<Duration>,
+    // 无效数据占比达到这个值时压缩
+    pub compact_ratio: f64,
+}
+
+impl Default for BitcaskOptions {
+    fn default() -> Self {
+        Self {
+            max_segment_size: 64 * 1024 * 1024,
+            sync: false,
+            compact_interval: Some(Duration::from_secs(60)),
+            compact_ratio: 0.5,
+        }
+    }
+}
+
+// 追加写的日志存储：数据写入段文件，内存中的 keydir 记录每个 key 最新记录的位置
+pub struct Bitcask {
+    inner: Arc<RwLock<Inner>>,
+}
+
+impl Bitcask {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self::try_new(path).unwrap()
+    }
+
+    pub fn try_new(path: impl AsRef<Path>) -> Result<Self, KvError> {
+        Self::open(path, BitcaskOptions::default())
+    }
+
+    // 打开目录并从段文件（有 hint 文件时使用 hint 文件）重建 keydir
+    pub fn open(path: impl AsRef<Path>, opts: BitcaskOptions) -> Result<Self, KvError> {
+        let interval = opts.compact_interval;
+        let inner = Arc::new(RwLock::new(Inner::open(path.as_ref(), opts)?));
+        if let Some(interval) = interval {
+            start_compactor(Arc::downgrade(&inner), interval);
+        }
+        Ok(Self { inner })
+    }
+
+    // 合并所有段文件，只保留每个 key 最新的未过期记录
+    pub fn compact(&self) -> Result<(), KvError> {
+        compact(&self.inner)
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Inner> {
+        self.inner.read().expect("bitcask lock poisoned")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Inner> {
+        self.inner.write().expect("bitcask lock poisoned")
+    }
+}
+
+// Bitcask 被释放后后台线程随之退出
+fn start_compactor(inner: std::sync::Weak<RwLock<Inner>>, interval: Duration) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+            let Some(inner) = inner.upgrade() else {
+                break;
+            };
+            let should_compact = inner
+                .read()
+                .expect("bitcask lock poisoned")
+                .should_compact();
+            if should_compact && let Err(e) = compact(&inner) {
+                warn!("Failed to compact bitcask: {}", e);
+            }
+        }
+    });
+}
+
+// 一条记录在段文件中的位置
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    file_id: u32,
+    offset: u64,
+    len: u64,
+    // 过期时间（毫秒时间戳），0 表示不过期
+    expire_at: u64,
+}
+
+impl Entry {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expire_at != 0 && self.expire_at <= now
+    }
+}
+
+// table -> key -> 最新记录的位置，同时统计无效数据的大小用于决定是否压缩
+#[derive(Default)]
+struct Keydir {
+    tables: HashMap<String, BTreeMap<String, Entry>>,
+    total_bytes: u64,
+    dead_bytes: u64,
+}
+
+impl Keydir {
+    fn get(&self, table: &str, key: &str) -> Option<Entry> {
+        self.tables.get(table).and_then(|t| t.get(key)).copied()
+    }
+
+    fn get_live(&self, table: &str, key: &str, now: u64) -> Option<Entry> {
+        self.get(table, key).filter(|e| !e.is_expired(now))
+    }
+
+    fn put(&mut self, table: &str, key: &str, entry: Entry) {
+        self.total_bytes += entry.len;
+        let old = self
+            .tables
+            .entry(table.into())
+            .or_default()
+            .insert(key.into(), entry);
+        if let Some(old) = old {
+            self.dead_bytes += old.len;
+        }
+    }
+
+    // 删除记录本身也是无效数据
+    fn remove(&mut self, table: &str, key: &str, len: u64) -> Option<Entry> {
+        self.total_bytes += len;
+        self.dead_bytes += len;
+        self.evict(table, key)
+    }
+
+    fn evict(&mut self, table: &str, key: &str) -> Option<Entry> {
+        let t = self.tables.get_mut(table)?;
+        let old = t.remove(key)?;
+        if t.is_empty() {
+            self.tables.remove(table);
+        }
+        self.dead_bytes += old.len;
+        Some(old)
+    }
+
+    fn expired_keys(&self, now: u64) -> Vec<(String, String)> {
+        self.tables
+            .iter()
+            .flat_map(|(table, t)| {
+                t.iter()
+                    .filter(|(_, e)| e.is_expired(now))
+                    .map(|(key, _)| (table.clone(), key.clone()))
+            })
+            .collect()
+    }
+}
+
+fn encode_hint(table: &str, key: &str, entry: &Entry) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HINT_HEADER_LEN + table.len() + key.len());
+    buf.extend_from_slice(&[0; 4]);
+    buf.extend_from_slice(&entry.expire_at.to_be_bytes());
+    buf.extend_from_slice(&entry.offset.to_be_bytes());
+    buf.extend_from_slice(&(entry.len as u32).to_be_bytes());
+    buf.extend_from_slice(&(table.len() as u32).to_be_bytes());
+    buf.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    buf.extend_from_slice(table.as_bytes());
+    buf.extend_from_slice(key.as_bytes());
+    let crc = crc32fast::hash(&buf[4..]);
+    buf[..4].copy_from_slice(&crc.to_be_bytes());
+    buf
+}
+
+// hint 文件只由压缩生成，任何错误都退回到扫描数据文件
+fn load_hint(path: &Path, file_id: u32) -> io::Result<Vec<(String, String, Entry)>> {
+    let data = fs::read(path)?;
+    let mut buf = data.as_slice();
+    let mut entries = Vec::new();
+    while !buf.is_empty() {
+        if buf.len() < HINT_HEADER_LEN {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let (header, rest) = buf.split_at(HINT_HEADER_LEN);
+        let table_len = u32::from_be_bytes(header[24..28].try_into().unwrap()) as usize;
+        let key_len = u32::from_be_bytes(header[28..32].try_into().unwrap()) as usize;
+        if rest.len() < table_len + key_len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let (body, rest) = rest.split_at(table_len + key_len);
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&header[4..]);
+        hasher.update(body);
+        if hasher.finalize() != u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            return Err(invalid_data("hint checksum mismatch"));
+        }
+        let entry = Entry {
+            file_id,
+            expire_at: u64::from_be_bytes(header[4..12].try_into().unwrap()),
+            offset: u64::from_be_bytes(header[12..20].try_into().unwrap()),
+            len: u32::from_be_bytes(header[20..24].try_into().unwrap()) as u64,
+        };
+        let table = String::from_utf8(body[..table_len].to_vec())
+            .map_err(|_| invalid_data("invalid table"))?;
+        let key = String::from_utf8(body[table_len..].to_vec())
+            .map_err(|_| invalid_data("invalid key"))?;
+        entries.push((table, key, entry));
+        buf = rest;
+    }
+    Ok(entries)
+}
+
+fn segment_path(dir: &Path, id: u32, ext: &str) -> PathBuf {
+    dir.join(format!("{:09}.{}", id, ext))
+}
+
+fn tmp_path(dir: &Path, id: u32, ext: &str) -> PathBuf {
+    dir.join(format!("{:09}.{}.{}", id, ext, TMP_EXT))
+}
+
+// 压缩中途崩溃时留下的临时文件
+fn remove_tmp_files(dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) == Some(TMP_EXT) {
+            warn!("Removing unfinished compaction file {}", path.display());
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+// 按 id 从小到大返回所有段文件
+fn list_segments(dir: &Path) -> io::Result<Vec<u32>> {
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(DATA_EXT) {
+            continue;
+        }
+        if let Some(id) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse().ok())
+        {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+fn open_active(dir: &Path, id: u32) -> io::Result<(File, File)> {
+    let path = segment_path(dir, id, DATA_EXT);
+    let writer = OpenOptions::new().create(true).append(true).open(&path)?;
+    let reader = File::open(&path)?;
+    Ok((writer, reader))
+}
+
+fn remove_segment(dir: &Path, id: u32) {
+    let paths = [DATA_EXT, HINT_EXT]
+        .into_iter()
+        .flat_map(|ext| [segment_path(dir, id, ext), tmp_path(dir, id, ext)]);
+    for path in paths {
+        if let Err(e) = fs::remove_file(&path)
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            warn!("Failed to remove {}: {}", path.display(), e);
+        }
+    }
+}
+
+// 压缩时写入的新段文件，数据写完后再写 hint 文件
+struct SegmentWriter {
+    id: u32,
+    data: BufWriter<File>,
+    hint: Vec<u8>,
+    size: u64,
+}
+
+impl SegmentWriter {
+    fn create(dir: &Path, id: u32) -> io::Result<Self> {
+        let data = File::create(tmp_path(dir, id, DATA_EXT))?;
+        Ok(Self {
+            id,
+            data: BufWriter::new(data),
+            hint: Vec::new(),
+            size: 0,
+        })
+    }
+
+    fn write(
+        &mut self,
+        table: &str,
+        key: &str,
+        record: &[u8],
+        expire_at: u64,
+    ) -> io::Result<Entry> {
+        self.data.write_all(record)?;
+        let entry = Entry {
+            file_id: self.id,
+            offset: self.size,
+            len: record.len() as u64,
+            expire_at,
+        };
+        self.hint.extend(encode_hint(table, key, &entry));
+        self.size += entry.len;
+        Ok(entry)
+    }
+
+    // 数据和 hint 都先写到临时文件再改名，段文件存在即代表完整
+    fn finish(mut self, dir: &Path) -> io::Result<()> {
+        self.data.flush()?;
+        self.data.get_ref().sync_all()?;
+        fs::rename(
+            tmp_path(dir, self.id, DATA_EXT),
+            segment_path(dir, self.id, DATA_EXT),
+        )?;
+        let tmp = tmp_path(dir, self.id, HINT_EXT);
+        let mut hint = File::create(&tmp)?;
+        hint.write_all(&self.hint)?;
+        hint.sync_all()?;
+        fs::rename(tmp, segment_path(dir, self.id, HINT_EXT))
+    }
+}
+
+struct Inner {
+    dir: PathBuf,
+    opts: BitcaskOptions,
+    keydir: Keydir,
+    // 每个段文件的读句柄，包括活跃段
+    readers: BTreeMap<u32, Mutex<File>>,
+    active: File,
+    active_id: u32,
+    active_size: u64,
+    // 压缩在锁外复制数据，同一时间只运行一个
+    compacting: bool,
+}
+
+impl Inner {
+    fn open(dir: &Path, opts: BitcaskOptions) -> Result<Self, KvError> {
+        fs::create_dir_all(dir)?;
+        remove_tmp_files(dir)?;
+        let now = now_ms();
+        let ids = list_segments(dir)?;
+        let mut keydir = Keydir::default();
+        let mut readers = BTreeMap::new();
+
+        for &id in &ids {
+            let hint_path = segment_path(dir, id, HINT_EXT);
+            let hint = match hint_path.exists() {
+                true => load_hint(&hint_path, id)
+                    .inspect_err(|e| warn!("Ignoring broken hint {}: {}", hint_path.display(), e))
+                    .ok(),
+                false => None,
+            };
+            match hint {
+                Some(entries) => {
+                    for (table, key, entry) in entries {
+                        if entry.is_expired(now) {
+                            keydir.remove(&table, &key, entry.len);
+                        } else {
+                            keydir.put(&table, &key, entry);
+                        }
+                    }
+                }
+                None => {
+                    // 只有最后一个段文件可能有不完整的记录
+                    let path = segment_path(dir, id, DATA_EXT);
+                    let active = ids.last() == Some(&id);
+                    scan_records(&path, active, |record, offset, len| {
+                        let entry = Entry {
+                            file_id: id,
+                            offset,
+                            len,
+                            expire_at: record.expire_at,
+                        };
+                        // 删除记录和已经过期的记录都会移除之前的值
+                        if record.kind == RECORD_PUT && !entry.is_expired(now) {
+                            keydir.put(&record.table, &record.key, entry);
+                        } else {
+                            keydir.remove(&record.table, &record.key, entry.len);
+                        }
+                    })?;
+                }
+            }
+            readers.insert(id, Mutex::new(File::open(segment_path(dir, id, DATA_EXT))?));
+        }
+
+        // 最后一个段文件没有满并且不是压缩生成的，继续追加写入
+        let reusable = ids.last().copied().filter(|&id| {
+            !segment_path(dir, id, HINT_EXT).exists()
+                && fs::metadata(segment_path(dir, id, DATA_EXT))
+                    .is_ok_and(|m| m.len() < opts.max_segment_size)
+        });
+        let active_id = reusable.unwrap_or_else(|| ids.last().map_or(1, |id| id + 1));
+        let (active, reader) = open_active(dir, active_id)?;
+        let active_size = active.metadata()?.len();
+        readers.insert(active_id, Mutex::new(reader));
+
+        info!(
+            "Bitcask opened at {} with {} segments, active segment {}",
+            dir.display(),
+            readers.len(),
+            active_id
+        );
+        Ok(Self {
+            dir: dir.into(),
+            opts,
+            keydir,
+            readers,
+            active,
+            active_id,
+            active_size,
+            compacting: false,
+        })
+    }
+
+    fn read_raw(&self, entry: &Entry) -> Result<Vec<u8>, KvError> {
+        let reader = self.readers.get(&entry.file_id).ok_or_else(|| {
+            KvError::Internal(format!("bitcask segment {} not found", entry.file_id))
+        })?;
+        let mut file = reader.lock().expect("bitcask lock poisoned");
+        file.seek(SeekFrom::Start(entry.offset))?;
+        let mut buf = vec![0; entry.len as usize];
+        file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn read_value(&self, entry: &Entry) -> Result<Value, KvError> {
+        let buf = self.read_raw(entry)?;
+        match Record::read_from(&mut buf.as_slice()) {
+            Ok(Some((record, _))) => Value::try_from(record.value.as_slice()),
+            _ => Err(KvError::Internal(format!(
+                "corrupted record in bitcask segment {} at offset {}",
+                entry.file_id, entry.offset
+            ))),
+        }
+    }
+
+    fn read_live(&self, table: &str, key: &str, now: u64) -> Result<Option<Value>, KvError> {
+        match self.keydir.get_live(table, key, now) {
+            Some(entry) => Ok(Some(self.read_value(&entry)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn append(&mut self, record: &Record) -> Result<Entry, KvError> {
+        if self.active_size >= self.opts.max_segment_size {
+            self.rotate()?;
+        }
+        let buf = record.encode();
+        self.active.write_all(&buf)?;
+        if self.opts.sync {
+            self.active.sync_data()?;
+        }
+        let entry = Entry {
+            file_id: self.active_id,
+            offset: self.active_size,
+            len: buf.len() as u64,
+            expire_at: record.expire_at,
+        };
+        self.active_size += entry.len;
+        Ok(entry)
+    }
+
+    fn put(&mut self, table: &str, key: &str, value: Value, expire_at: u64) -> Result<(), KvError> {
+        let value: Vec<u8> = value.try_into()?;
+        let entry = self.append(&Record::put(table, key, value, expire_at))?;
+        self.keydir.put(table, key, entry);
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), KvError> {
+        self.rotate_to(self.active_id + 1)
+    }
+
+    fn rotate_to(&mut self, id: u32) -> Result<(), KvError> {
+        self.active.sync_all()?;
+        let (active, reader) = open_active(&self.dir, id)?;
+        self.readers.insert(id, Mutex::new(reader));
+        self.active = active;
+        self.active_id = id;
+        self.active_size = 0;
+        debug!("Bitcask rotated to segment {}", id);
+        Ok(())
+    }
+
+    fn should_compact(&self) -> bool {
+        self.keydir.dead_bytes > 0
+            && self.keydir.dead_bytes as f64
+                >= self.keydir.total_bytes as f64 * self.opts.compact_ratio
+    }
+
+    // 已经在压缩时返回 None
+    fn plan_compaction(&mut self) -> Result<Option<Compaction>, KvError> {
+        if self.compacting {
+            return Ok(None);
+        }
+        let now = now_ms();
+        let live: Vec<(String, String, Entry)> = self
+            .keydir
+            .tables
+            .iter()
+            .flat_map(|(table, t)| {
+                t.iter()
+                    .filter(|(_, entry)| !entry.is_expired(now))
+                    .map(|(key, entry)| (table.clone(), key.clone(), *entry))
+            })
+            .collect();
+        let mut segments = Vec::with_capacity(self.readers.len());
+        for &id in self.readers.keys() {
+            segments.push((id, File::open(segment_path(&self.dir, id, DATA_EXT))?));
+        }
+        // 每个新段文件至少写满 max_segment_size 才切换，据此预留新段文件的 id
+        let live_bytes: u64 = live.iter().map(|(_, _, entry)| entry.len).sum();
+        let reserved = (live_bytes / self.opts.max_segment_size.max(1) + 2) as u32;
+        let first_id = self.active_id + 1;
+        self.rotate_to(first_id + reserved)?;
+        self.compacting = true;
+        Ok(Some(Compaction {
+            dir: self.dir.clone(),
+            max_segment_size: self.opts.max_segment_size,
+            live,
+            segments,
+            ids: first_id..self.active_id,
+        }))
+    }
+
+    // 旧段文件中的 key 在复制之后没有被修改时指向新的位置，没有被复制的是已经过期的 key
+    fn finish_compaction(&mut self, plan: &Compaction, copied: Copied) -> Result<(), KvError> {
+        for &id in &copied.ids {
+            let reader = File::open(segment_path(&self.dir, id, DATA_EXT))?;
+            self.readers.insert(id, Mutex::new(reader));
+        }
+        let mut compacted_bytes = 0;
+        for (id, file) in &plan.segments {
+            self.readers.remove(id);
+            compacted_bytes += file.metadata().map_or(0, |m| m.len());
+        }
+
+        let old = std::mem::take(&mut self.keydir);
+        let mut entries = copied.entries;
+        for (table, t) in old.tables {
+            for (key, entry) in t {
+                if entry.file_id >= plan.ids.start {
+                    self.keydir.put(&table, &key, entry);
+                } else if let Some(entry) = entries.remove(&(table.clone(), key.clone())) {
+                    self.keydir.put(&table, &key, entry);
+                }
+            }
+        }
+        // 活跃段中被覆盖的记录仍然是无效数据
+        let live_bytes = self.keydir.total_bytes;
+        let total = (old.total_bytes + copied.bytes).saturating_sub(compacted_bytes);
+        self.keydir.total_bytes = total.max(live_bytes);
+        self.keydir.dead_bytes = self.keydir.total_bytes - live_bytes;
+
+        for (id, _) in &plan.segments {
+            remove_segment(&self.dir, *id);
+        }
+        info!(
+            "Bitcask compacted {} segments into {}, {} keys",
+            plan.segments.len(),
+            copied.ids.len(),
+            plan.live.len()
+        );
+        Ok(())
+    }
+}
+
+// 压缩分三步，只有开始和结束时短暂持有写锁，复制数据时读写不受影响：
+// 1. 切换到新的活跃段，记录此时所有有效的记录，之前的段文件不再被修改
+// 2. 在锁外把这些记录复制到新的段文件，新段文件的 id 在旧段和新的活跃段之间
+// 3. 重新加锁，没有被再次修改的 key 指向新的位置，删除旧的段文件
+// 压缩中途崩溃时旧的段文件仍然存在，重建 keydir 得到的结果不变
+fn compact(inner: &RwLock<Inner>) -> Result<(), KvError> {
+    let Some(plan) = inner
+        .write()
+        .expect("bitcask lock poisoned")
+        .plan_compaction()?
+    else {
+        return Ok(());
+    };
+    let result = plan.copy_live();
+
+    let mut inner = inner.write().expect("bitcask lock poisoned");
+    inner.compacting = false;
+    let copied = match result {
+        Ok(copied) => copied,
+        Err(e) => {
+            for id in plan.ids.clone() {
+                remove_segment(&plan.dir, id);
+            }
+            return Err(e);
+        }
+    };
+    inner.finish_compaction(&plan, copied)
+}
+
+// 压缩开始时记录的旧段文件和有效的记录
+struct Compaction {
+    dir: PathBuf,
+    max_segment_size: u64,
+    live: Vec<(String, String, Entry)>,
+    // 旧段文件的 id 和独立的读句柄
+    segments: Vec<(u32, File)>,
+    // 可以使用的新段文件的 id
+    ids: Range<u32>,
+}
+
+// 复制后用到的新段文件、每个 key 新的位置和写入的字节数
+struct Copied {
+    ids: Vec<u32>,
+    entries: HashMap<(String, String), Entry>,
+    bytes: u64,
+}
+
+impl Compaction {
+    fn copy_live(&self) -> Result<Copied, KvError> {
+        let mut ids = self.ids.clone();
+        let mut copied = Copied {
+            ids: Vec::new(),
+            entries: HashMap::with_capacity(self.live.len()),
+            bytes: 0,
+        };
+        let mut writer: Option<SegmentWriter> = None;
+        for (table, key, entry) in &self.live {
+            let mut file = self
+                .segments
+                .iter()
+                .find(|(id, _)| *id == entry.file_id)
+                .map(|(_, file)| file)
+                .ok_or_else(|| {
+                    KvError::Internal(format!("bitcask segment {} not found", entry.file_id))
+                })?;
+            file.seek(SeekFrom::Start(entry.offset))?;
+            let mut record = vec![0; entry.len as usize];
+            file.read_exact(&mut record)?;
+
+            if writer
+                .as_ref()
+                .is_none_or(|w| w.size >= self.max_segment_size)
+            {
+                if let Some(w) = writer.take() {
+                    copied.bytes += w.size;
+                    w.finish(&self.dir)?;
+                }
+                let id = ids.next().ok_or_else(|| {
+                    KvError::Internal("bitcask compaction ran out of segment ids".into())
+                })?;
+                copied.ids.push(id);
+                writer = Some(SegmentWriter::create(&self.dir, id)?);
+            }
+            let w = writer.as_mut().unwrap();
+            let entry = w.write(table, key, &record, entry.expire_at)?;
+            copied.entries.insert((table.clone(), key.clone()), entry);
+        }
+        if let Some(w) = writer {
+            copied.bytes += w.size;
+            w.finish(&self.dir)?;
+        }
+        Ok(copied)
+    }
+}
+
+impl Storage for Bitcask {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.read().read_live(table, key, now_ms())
+    }
+
+    fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
+        let mut result = self.set_many(table, vec![Kvpair::new(key, value)])?;
+        Ok(result.pop().flatten())
+    }
+
+    fn delete(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let mut result = self.delete_many(table, &[key.to_string()])?;
+        Ok(result.pop().flatten())
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        Ok(self.read().keydir.get_live(table, key, now_ms()).is_some())
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        let inner = self.read();
+        let now = now_ms();
+        let Some(t) = inner.keydir.tables.get(table) else {
+            return Ok(vec![]);
+        };
+        t.iter()
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| Ok(Kvpair::new(key, inner.read_value(entry)?)))
+            .collect()
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        Ok(Box::new(self.get_all(table)?.into_iter()))
+    }
+
+    fn set_many(&self, table: &str, pairs: Vec<Kvpair>) -> Result<Vec<Option<Value>>, KvError> {
+        let mut inner = self.write();
+        let now = now_ms();
+        pairs
+            .into_iter()
+            .map(|pair| {
+                let old = inner.read_live(table, &pair.key, now)?;
+                inner.put(table, &pair.key, pair.value.unwrap_or_default(), 0)?;
+                Ok(old)
+            })
+            .collect()
+    }
+
+    fn set_many_with_ttl(
+        &self,
+        table: &str,
+        pairs: Vec<Kvpair>,
+        ttl: Duration,
+    ) -> Result<Vec<Option<Value>>, KvError> {
+        let mut inner = self.write();
+        let now = now_ms();
+        let deadline = deadline_ms(ttl);
+        pairs
+            .into_iter()
+            .map(|pair| {
+                let old = inner.read_live(table, &pair.key, now)?;
+                inner.put(table, &pair.key, pair.value.unwrap_or_default(), deadline)?;
+                Ok(old)
+            })
+            .collect()
+    }
+
+    fn delete_many(&self, table: &str, keys: &[String]) -> Result<Vec<Option<Value>>, KvError> {
+        let mut inner = self.write();
+        let now = now_ms();
+        keys.iter()
+            .map(|key| {
+                if inner.keydir.get(table, key).is_none() {
+                    return Ok(None);
+                }
+                let old = inner.read_live(table, key, now)?;
+                let entry = inner.append(&Record::delete(table, key))?;
+                inner.keydir.remove(table, key, entry.len);
+                Ok(old)
+            })
+            .collect()
+    }
+
+    // 值和过期时间写在同一条记录里
+    fn set_with_ttl(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        let mut inner = self.write();
+        let old = inner.read_live(table, key, now_ms())?;
+        inner.put(table, key, value, deadline_ms(ttl))?;
+        Ok(old)
+    }
+
+    // 用新的过期时间重写一条记录
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        let mut inner = self.write();
+        match inner.read_live(table, key, now_ms())? {
+            Some(value) => {
+                inner.put(table, key, value, deadline_ms(ttl))?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        let now = now_ms();
+        Ok(self
+            .read()
+            .keydir
+            .get_live(table, key, now)
+            .filter(|entry| entry.expire_at != 0)
+            .map(|entry| Duration::from_millis(entry.expire_at - now)))
+    }
+
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        let mut inner = self.write();
+        let now = now_ms();
+        match inner.keydir.get_live(table, key, now) {
+            Some(entry) if entry.expire_at != 0 => {
+                let value = inner.read_value(&entry)?;
+                inner.put(table, key, value, 0)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    // 持有写锁完成读-改-写，保留原来的过期时间
+    fn update(&self, table: &str, key: &str, f: UpdateFn) -> Result<Option<Value>, KvError> {
+        let mut inner = self.write();
+        let entry = inner.keydir.get_live(table, key, now_ms());
+        let old = match &entry {
+            Some(entry) => Some(inner.read_value(entry)?),
+            None => None,
+        };
+        if let Some(value) = f(old.as_ref())? {
+            let expire_at = entry.map_or(0, |entry| entry.expire_at);
+            inner.put(table, key, value, expire_at)?;
+        }
+        Ok(old)
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        let now = now_ms();
+        let inner = self.read();
+        let mut tables: Vec<String> = inner
+            .keydir
+            .tables
+            .iter()
+            .filter(|(_, t)| t.values().any(|entry| !entry.is_expired(now)))
+            .map(|(table, _)| table.clone())
+            .collect();
+        tables.sort_unstable();
+        Ok(tables)
+    }
+
+    // 为每个 key 写入删除记录，不需要读取 value
+    fn drop_table(&self, table: &str) -> Result<usize, KvError> {
+        let mut inner = self.write();
+        let now = now_ms();
+        let Some(t) = inner.keydir.tables.get(table) else {
+            return Ok(0);
+        };
+        let keys: Vec<(String, bool)> = t
+            .iter()
+            .map(|(key, entry)| (key.clone(), !entry.is_expired(now)))
+            .collect();
+        let mut count = 0;
+        for (key, live) in keys {
+            let entry = inner.append(&Record::delete(table, &key))?;
+            inner.keydir.remove(table, &key, entry.len);
+            count += live as usize;
+        }
+        Ok(count)
+    }
+
+    // 记录长度减去头部和 table 的长度就是 key 和 value 的大小
+    fn table_info(&self, table: &str) -> Result<Option<TableStats>, KvError> {
+        let now = now_ms();
+        let inner = self.read();
+        let mut stats = TableStats::default();
+        if let Some(t) = inner.keydir.tables.get(table) {
+            for entry in t.values().filter(|entry| !entry.is_expired(now)) {
+                stats.add(0, entry.len as usize - HEADER_LEN - table.len());
+            }
+        }
+        Ok((stats.keys > 0).then_some(stats))
+    }
+
+    // 过期时间保存在记录中，重启后过期的记录不会被加载，只需要从 keydir 中移除
+    fn reap_expired(&self) -> Result<usize, KvError> {
+        let mut inner = self.write();
+        let keys = inner.keydir.expired_keys(now_ms());
+        for (table, key) in &keys {
+            inner.keydir.evict(table, key);
+        }
+        Ok(keys.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn options() -> BitcaskOptions {
+        BitcaskOptions {
+            compact_interval: None,
+            ..Default::default()
+        }
+    }
+
+    fn last_segment(dir: &Path) -> PathBuf {
+        let id = *list_segments(dir).unwrap().last().unwrap();
+        segment_path(dir, id, DATA_EXT)
+    }
+
+    #[test]
+    fn bitcask_should_recover_after_reopen() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::open(dir.path(), options()).unwrap();
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store.set("t1", "k2", 2.into()).unwrap();
+            store.set("t1", "k1", "v11".into()).unwrap();
+            store.delete("t1", "k2").unwrap();
+            store.set("t2", "k1", true.into()).unwrap();
+        }
+        let store = Bitcask::open(dir.path(), options()).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v11".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+        assert_eq!(store.get("t2", "k1"), Ok(Some(true.into())));
+        assert_eq!(store.get_all("t1").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn truncated_last_record_should_be_dropped() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::open(dir.path(), options()).unwrap();
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store.set("t1", "k2", "v2".into()).unwrap();
+        }
+        // 模拟写入最后一条记录时崩溃
+        let path = last_segment(dir.path());
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        {
+            let store = Bitcask::open(dir.path(), options()).unwrap();
+            assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+            assert_eq!(store.get("t1", "k2"), Ok(None));
+            // 截断后可以继续写入
+            store.set("t1", "k3", "v3".into()).unwrap();
+        }
+        let store = Bitcask::open(dir.path(), options()).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t1", "k3"), Ok(Some("v3".into())));
+        assert_eq!(store.get_all("t1").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn truncated_header_should_be_dropped() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::open(dir.path(), options()).unwrap();
+            store.set("t1", "k1", "v1".into()).unwrap();
+        }
+        let path = last_segment(dir.path());
+        let len = fs::metadata(&path).unwrap().len();
+        // 只写入了下一条记录的一部分头部
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[1, 2, 3, 4, 5]).unwrap();
+
+        let store = Bitcask::open(dir.path(), options()).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+    }
+
+    #[test]
+    fn corrupted_last_record_should_be_dropped() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::open(dir.path(), options()).unwrap();
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store.set("t1", "k2", "v2".into()).unwrap();
+        }
+        let path = last_segment(dir.path());
+        let mut data = fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(&path, data).unwrap();
+
+        let store = Bitcask::open(dir.path(), options()).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+    }
+
+    #[test]
+    fn corrupted_sealed_segment_should_fail_to_open() {
+        let dir = tempdir().unwrap();
+        let opts = BitcaskOptions {
+            max_segment_size: 128,
+            ..options()
+        };
+        {
+            let store = Bitcask::open(dir.path(), opts.clone()).unwrap();
+            for i in 0..20 {
+                store.set("t1", &format!("k{}", i), i.into()).unwrap();
+            }
+        }
+        // 已经写满的段文件损坏时不能截断，否则会丢失之后的记录
+        let path = segment_path(dir.path(), list_segments(dir.path()).unwrap()[0], DATA_EXT);
+        let mut data = fs::read(&path).unwrap();
+        let len = data.len();
+        data[len / 2] ^= 0xff;
+        fs::write(&path, data).unwrap();
+
+        assert!(Bitcask::open(dir.path(), opts).is_err());
+        assert_eq!(fs::metadata(&path).unwrap().len(), len as u64);
+    }
+
+    #[test]
+    fn bitcask_should_rotate_segments() {
+        let dir = tempdir().unwrap();
+        let opts = BitcaskOptions {
+            max_segment_size: 128,
+            ..options()
+        };
+        {
+            let store = Bitcask::open(dir.path(), opts.clone()).unwrap();
+            for i in 0..20 {
+                store.set("t1", &format!("k{}", i), i.into()).unwrap();
+            }
+        }
+        assert!(list_segments(dir.path()).unwrap().len() > 1);
+        let store = Bitcask::open(dir.path(), opts).unwrap();
+        for i in 0..20 {
+            assert_eq!(store.get("t1", &format!("k{}", i)), Ok(Some(i.into())));
+        }
+    }
+
+    #[test]
+    fn compact_should_keep_live_records_only() {
+        let dir = tempdir().unwrap();
+        let opts = BitcaskOptions {
+            max_segment_size: 256,
+            ..options()
+        };
+        {
+            let store = Bitcask::open(dir.path(), opts.clone()).unwrap();
+            for i in 0..50 {
+                store.set("t1", &format!("k{}", i % 5), i.into()).unwrap();
+            }
+            store.delete("t1", "k0").unwrap();
+            store.set("t1", "gone", "v".into()).unwrap();
+            store
+                .expire("t1", "gone", Duration::from_millis(1))
+                .unwrap();
+            thread::sleep(Duration::from_millis(5));
+            assert!(store.read().should_compact());
+
+            store.compact().unwrap();
+            assert!(!store.read().should_compact());
+            assert_eq!(store.read().keydir.dead_bytes, 0);
+            assert_eq!(store.get("t1", "k4"), Ok(Some(49.into())));
+
+            // 压缩后继续写入
+            store.set("t1", "k5", 5.into()).unwrap();
+        }
+
+        let ids = list_segments(dir.path()).unwrap();
+        assert!(
+            ids.iter()
+                .take(ids.len() - 1)
+                .all(|id| segment_path(dir.path(), *id, HINT_EXT).exists())
+        );
+
+        // 重启时从 hint 文件加载
+        let store = Bitcask::open(dir.path(), opts).unwrap();
+        let mut pairs = store.get_all("t1").unwrap();
+        pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(
+            pairs,
+            vec![
+                Kvpair::new("k1", 46.into()),
+                Kvpair::new("k2", 47.into()),
+                Kvpair::new("k3", 48.into()),
+                Kvpair::new("k4", 49.into()),
+                Kvpair::new("k5", 5.into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_during_compaction_should_be_kept() {
+        let dir = tempdir().unwrap();
+        let opts = BitcaskOptions {
+            max_segment_size: 256,
+            ..options()
+        };
+        {
+            let store = Bitcask::open(dir.path(), opts.clone()).unwrap();
+            for i in 0..20 {
+                store.set("t1", &format!("k{}", i % 5), i.into()).unwrap();
+            }
+            let plan = store.write().plan_compaction().unwrap().unwrap();
+            // 复制数据时不持有锁，新的写入进入新的活跃段
+            store.set("t1", "k1", "new".into()).unwrap();
+            store.delete("t1", "k2").unwrap();
+            store.set("t1", "k9", 9.into()).unwrap();
+            assert!(store.write().plan_compaction().unwrap().is_none());
+
+            let copied = plan.copy_live().unwrap();
+            let mut inner = store.write();
+            inner.compacting = false;
+            inner.finish_compaction(&plan, copied).unwrap();
+        }
+
+        let store = Bitcask::open(dir.path(), opts).unwrap();
+        let mut pairs = store.get_all("t1").unwrap();
+        pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(
+            pairs,
+            vec![
+                Kvpair::new("k0", 15.into()),
+                Kvpair::new("k1", "new".into()),
+                Kvpair::new("k3", 18.into()),
+                Kvpair::new("k4", 19.into()),
+                Kvpair::new("k9", 9.into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn background_compaction_should_work() {
+        let dir = tempdir().unwrap();
+        let opts = BitcaskOptions {
+            compact_interval: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let store = Bitcask::open(dir.path(), opts).unwrap();
+        for i in 0..10 {
+            store.set("t1", "k1", i.into()).unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(store.read().keydir.dead_bytes, 0);
+        assert_eq!(store.get("t1", "k1"), Ok(Some(9.into())));
+    }
+}
@@ -1,20 +1,68 @@
-use dashmap::{DashMap, mapref::one::Ref};
+use std::{
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use crate::{Kvpair, Value, error::KvError, storage::storage::Storage};
+use dashmap::{
+    DashMap,
+    mapref::{entry::Entry, one::Ref},
+};
+use prost::Message;
 
+use crate::{
+    Kvpair, Value,
+    error::KvError,
+    storage::{
+        record::Record,
+        storage::{Storage, TableStats, TxFn, UpdateFn},
+        transaction::MemTableTx,
+        wal::Wal,
+    },
+};
+
+mod asyncstorage;
+pub mod bitcask;
+mod record;
 pub mod sleddb;
 pub mod storage;
+mod transaction;
+mod wal;
+
+pub(crate) use asyncstorage::StorageView;
+pub use asyncstorage::{AsyncStorage, BlockingStorage, BoxedTxFn, BoxedUpdateFn};
+pub use wal::{FsyncPolicy, WalOptions};
 
+// clone 出来的 MemTable 共享同一份数据
 #[derive(Debug, Clone, Default)]
 pub struct MemTable {
-    table: DashMap<String, DashMap<String, Value>>,
+    table: Arc<DashMap<String, DashMap<String, Value>>>,
+    // table -> key -> 过期时间（毫秒时间戳）
+    expires: Arc<DashMap<String, DashMap<String, u64>>>,
+    // 开启持久化时，修改先写入 WAL
+    wal: Option<Arc<Wal>>,
+    // 事务持有写锁独占整个 MemTable，其它操作持有读锁
+    tx_lock: Arc<RwLock<()>>,
+    // 事务内部使用的 MemTable 已经持有写锁，不再加锁
+    in_tx: bool,
+}
+
+// 当前时间的毫秒时间戳，用于计算过期时间
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+pub(crate) fn deadline_ms(ttl: Duration) -> u64 {
+    now_ms().saturating_add(ttl.as_millis() as u64)
 }
 
 impl MemTable {
     pub fn new() -> Self {
         Self::default()
     }
-    // 获取或创建表
+    // 获取或创建表，只在写入时使用，读取不存在的表不会创建它
     fn get_or_create_table(&self, table: &str) -> Ref<String, DashMap<String, Value>> {
         match self.table.get(table) {
             Some(table) => table,
@@ -24,37 +72,347 @@ impl MemTable {
             }
         }
     }
+
+    fn shared(&self) -> Option<RwLockReadGuard<'_, ()>> {
+        (!self.in_tx).then(|| self.tx_lock.read().expect("tx lock poisoned"))
+    }
+
+    fn exclusive(&self) -> Option<RwLockWriteGuard<'_, ()>> {
+        (!self.in_tx).then(|| self.tx_lock.write().expect("tx lock poisoned"))
+    }
+
+    fn is_expired(&self, table: &str, key: &str) -> bool {
+        let now = now_ms();
+        self.expires
+            .get(table)
+            .and_then(|t| t.get(key).map(|deadline| *deadline <= now))
+            .unwrap_or(false)
+    }
+
+    // 清除过期时间，返回之前是否已经过期
+    fn take_expire(&self, table: &str, key: &str) -> bool {
+        let now = now_ms();
+        self.expires
+            .get(table)
+            .and_then(|t| t.remove(key))
+            .is_some_and(|(_, deadline)| deadline <= now)
+    }
+
+    fn expired_keys(&self, table: &str) -> Vec<String> {
+        let now = now_ms();
+        match self.expires.get(table) {
+            Some(t) => t
+                .iter()
+                .filter(|kv| *kv.value() <= now)
+                .map(|kv| kv.key().clone())
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    // 没有开启持久化时直接修改内存
+    fn logged<T>(
+        &self,
+        record: impl FnOnce() -> Record,
+        apply: impl FnOnce() -> T,
+    ) -> Result<T, KvError> {
+        match &self.wal {
+            Some(wal) => wal.append(&record(), apply),
+            None => Ok(apply()),
+        }
+    }
+
+    fn set_raw(&self, table: &str, key: &str, value: Value) -> Option<Value> {
+        // 先清除过期时间再写入，避免 reaper 删除新写入的值
+        let expired = self.take_expire(table, key);
+        let table = self.get_or_create_table(table);
+        let v = table.insert(key.into(), value);
+        v.filter(|_| !expired)
+    }
+
+    fn delete_raw(&self, table: &str, key: &str) -> Option<Value> {
+        let expired = self.take_expire(table, key);
+        let table = self.table.get(table)?;
+        table.remove(key).map(|(_, v)| v).filter(|_| !expired)
+    }
+
+    // 只检查 key 是否存在，不检查是否已经过期
+    fn expire_raw(&self, table: &str, key: &str, deadline: u64) -> bool {
+        let exists = self.table.get(table).is_some_and(|t| t.contains_key(key));
+        if exists {
+            self.expires
+                .entry(table.into())
+                .or_default()
+                .insert(key.into(), deadline);
+        }
+        exists
+    }
+
+    fn persist_raw(&self, table: &str, key: &str) -> bool {
+        self.expires
+            .get(table)
+            .and_then(|t| t.remove(key))
+            .is_some()
+    }
+
+    // 移除一个已经过期的 key，调用者需要独占 MemTable
+    fn evict_expired(&self, table: &str, key: &str) {
+        let now = now_ms();
+        let removed = self
+            .expires
+            .get(table)
+            .and_then(|t| t.remove_if(key, |_, deadline| *deadline <= now));
+        if removed.is_some()
+            && let Some(t) = self.table.get(table)
+        {
+            t.remove(key);
+        }
+    }
+
+    fn contains_raw(&self, table: &str, key: &str) -> bool {
+        let expired = self.is_expired(table, key);
+        self.table.get(table).is_some_and(|t| t.contains_key(key)) && !expired
+    }
+
+    // 返回删除的没有过期的key 的数量，调用者需要独占 MemTable
+    fn drop_table_raw(&self, table: &str) -> usize {
+        let expired = self.expired_keys(table);
+        self.expires.remove(table);
+        self.table
+            .remove(table)
+            .map(|(_, t)| t.iter().filter(|kv| !expired.contains(kv.key())).count())
+            .unwrap_or(0)
+    }
+
+    fn ttl_raw(&self, table: &str, key: &str) -> Option<Duration> {
+        let now = now_ms();
+        let deadline = self
+            .expires
+            .get(table)
+            .and_then(|t| t.get(key).map(|deadline| *deadline));
+        deadline
+            .filter(|deadline| *deadline > now)
+            .map(|deadline| Duration::from_millis(deadline - now))
+    }
 }
 impl Storage for MemTable {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.get(key).map(|v| v.clone()))
+        let _guard = self.shared();
+        if self.is_expired(table, key) {
+            return Ok(None);
+        }
+        Ok(self
+            .table
+            .get(table)
+            .and_then(|t| t.get(key).map(|v| v.clone())))
     }
     fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        let v = table.insert(key.into(), value);
-        Ok(v)
+        let _guard = self.shared();
+        let Some(wal) = &self.wal else {
+            return Ok(self.set_raw(table, key, value));
+        };
+        let record = Record::put(table, key, value.clone().try_into()?, 0);
+        wal.append(&record, || self.set_raw(table, key, value))
     }
     fn delete(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.remove(key).map(|(_, v)| v))
+        let _guard = self.shared();
+        self.logged(
+            || Record::delete(table, key),
+            || self.delete_raw(table, key),
+        )
     }
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.contains_key(key))
+        let _guard = self.shared();
+        Ok(self.contains_raw(table, key))
     }
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
-        let table = self.get_or_create_table(table);
+        let _guard = self.shared();
+        let expired = self.expired_keys(table);
+        let Some(table) = self.table.get(table) else {
+            return Ok(vec![]);
+        };
         Ok(table
             .iter()
+            .filter(|kv| !expired.contains(kv.key()))
             .map(|kv| Kvpair::new(kv.key(), kv.value().clone()))
             .collect())
     }
     fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
-        let table = self.get_or_create_table(table).clone();
-        let pairs = StorageIter::new(table.into_iter());
+        let _guard = self.shared();
+        let expired = self.expired_keys(table);
+        let table = self.table.get(table).map(|t| t.clone()).unwrap_or_default();
+        let pairs = StorageIter::new(
+            table
+                .into_iter()
+                .filter(move |(key, _)| !expired.contains(key)),
+        );
         Ok(Box::new(pairs))
     }
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        let _guard = self.shared();
+        if !self.contains_raw(table, key) {
+            return Ok(false);
+        }
+        let deadline = deadline_ms(ttl);
+        self.logged(
+            || Record::expire(table, key, deadline),
+            || self.expire_raw(table, key, deadline),
+        )
+    }
+    // 值和过期时间写在同一条记录里
+    fn set_with_ttl(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        let _guard = self.shared();
+        let deadline = deadline_ms(ttl);
+        let apply = |value| {
+            let old = self.set_raw(table, key, value);
+            self.expire_raw(table, key, deadline);
+            old
+        };
+        let Some(wal) = &self.wal else {
+            return Ok(apply(value));
+        };
+        let record = Record::put(table, key, value.clone().try_into()?, deadline);
+        wal.append(&record, || apply(value))
+    }
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        let _guard = self.shared();
+        Ok(self.ttl_raw(table, key))
+    }
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        let _guard = self.shared();
+        if self.ttl_raw(table, key).is_none() {
+            return Ok(false);
+        }
+        self.logged(
+            || Record::persist(table, key),
+            || self.persist_raw(table, key),
+        )
+    }
+    fn reap_expired(&self) -> Result<usize, KvError> {
+        let _guard = self.shared();
+        let now = now_ms();
+        let mut count = 0;
+        for expires in self.expires.iter() {
+            let Some(table) = self.table.get(expires.key()) else {
+                continue;
+            };
+            let keys: Vec<String> = expires
+                .iter()
+                .filter(|kv| *kv.value() <= now)
+                .map(|kv| kv.key().clone())
+                .collect();
+            for key in keys {
+                // 删除前再次确认过期时间没有被 set/persist 清除
+                let removed = table.remove_if(&key, |_, _| {
+                    expires.get(&key).is_some_and(|deadline| *deadline <= now)
+                });
+                expires.remove_if(&key, |_, deadline| *deadline <= now);
+                if removed.is_some() {
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+    // 通过 DashMap 的 entry 持有 key 所在分片的写锁完成读-改-写
+    fn update(&self, table: &str, key: &str, f: UpdateFn) -> Result<Option<Value>, KvError> {
+        // 过期的 key 先在独占的情况下移除，避免在持有 entry 时再修改过期时间
+        while self.is_expired(table, key) {
+            let _guard = self.exclusive();
+            self.evict_expired(table, key);
+        }
+
+        let _guard = self.shared();
+        // 与 set 一致，先持有 WAL 的锁再修改内存
+        let mut wal = self.wal.as_ref().map(|wal| wal.lock());
+        let deadline = self
+            .expires
+            .get(table)
+            .and_then(|t| t.get(key).map(|deadline| *deadline))
+            .unwrap_or(0);
+        let t = self.get_or_create_table(table);
+        let entry = t.entry(key.into());
+        let old = match &entry {
+            Entry::Occupied(e) => Some(e.get().clone()),
+            Entry::Vacant(_) => None,
+        };
+        let Some(value) = f(old.as_ref())? else {
+            return Ok(old);
+        };
+        if let Some(wal) = wal.as_mut() {
+            wal.write(&Record::put(
+                table,
+                key,
+                value.clone().try_into()?,
+                deadline,
+            ))?;
+        }
+        entry.insert(value);
+        Ok(old)
+    }
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        let _guard = self.shared();
+        // 先取出所有表名，避免同时持有 table 和 expires 的分片锁
+        let names: Vec<String> = self.table.iter().map(|t| t.key().clone()).collect();
+        let mut tables: Vec<String> = names
+            .into_iter()
+            .filter(|table| {
+                let expired = self.expired_keys(table);
+                self.table
+                    .get(table)
+                    .is_some_and(|t| t.iter().any(|kv| !expired.contains(kv.key())))
+            })
+            .collect();
+        tables.sort_unstable();
+        Ok(tables)
+    }
+    // 独占 MemTable，避免删除时有并发的写入
+    fn drop_table(&self, table: &str) -> Result<usize, KvError> {
+        let _guard = self.exclusive();
+        self.logged(|| Record::drop_table(table), || self.drop_table_raw(table))
+    }
+    fn table_info(&self, table: &str) -> Result<Option<TableStats>, KvError> {
+        let _guard = self.shared();
+        let expired = self.expired_keys(table);
+        let Some(t) = self.table.get(table) else {
+            return Ok(None);
+        };
+        let mut stats = TableStats::default();
+        for kv in t.iter().filter(|kv| !expired.contains(kv.key())) {
+            stats.add(kv.key().len(), kv.value().encoded_len());
+        }
+        Ok((stats.keys > 0).then_some(stats))
+    }
+    // 持有写锁执行，失败时用 undo 日志恢复修改前的值
+    // 修改在提交时作为带提交标记的一批记录写入 WAL，崩溃时不会只留下一部分
+    fn transaction(&self, f: TxFn) -> Result<(), KvError> {
+        let _guard = self.exclusive();
+        let tx = MemTableTx::new(MemTable {
+            wal: None,
+            in_tx: true,
+            ..self.clone()
+        });
+        let result = f(&tx).and_then(|_| match &self.wal {
+            Some(wal) => {
+                let records = tx.records()?;
+                if records.is_empty() {
+                    return Ok(());
+                }
+                wal.lock().write_batch(&records)
+            }
+            None => Ok(()),
+        });
+        if result.is_err() {
+            tx.rollback()?;
+        }
+        result
+    }
 }
 
 impl From<(String, Value)> for Kvpair {
@@ -0,0 +1,196 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+use tracing::warn;
+
+// 磁盘上的一条日志记录，Bitcask 的段文件和 MemTable 的 WAL/快照共用
+// 格式：crc(4) | 类型(1) | 过期时间(8) | table 长度(4) | key 长度(4) | value 长度(4) | table | key | value
+// crc 覆盖 crc 之后的所有字节
+pub(crate) const HEADER_LEN: usize = 25;
+
+// 写入 value，过期时间为 0 表示不过期
+pub(crate) const RECORD_PUT: u8 = 0;
+pub(crate) const RECORD_DELETE: u8 = 1;
+// 只修改过期时间
+pub(crate) const RECORD_EXPIRE: u8 = 2;
+pub(crate) const RECORD_PERSIST: u8 = 3;
+// 删除整个表，只在 MemTable 的 WAL 中使用
+pub(crate) const RECORD_DROP_TABLE: u8 = 4;
+// 事务的开始和提交标记，只在 MemTable 的 WAL 中使用
+// 开始标记的过期时间字段保存这一批记录的数量，没有提交标记的一批记录在重放时丢弃
+pub(crate) const RECORD_BATCH_BEGIN: u8 = 5;
+pub(crate) const RECORD_BATCH_COMMIT: u8 = 6;
+
+pub(crate) struct Record {
+    pub kind: u8,
+    pub expire_at: u64,
+    pub table: String,
+    pub key: String,
+    pub value: Vec<u8>,
+}
+
+impl Record {
+    pub fn put(table: &str, key: &str, value: Vec<u8>, expire_at: u64) -> Self {
+        Self::new(RECORD_PUT, table, key, value, expire_at)
+    }
+
+    pub fn delete(table: &str, key: &str) -> Self {
+        Self::new(RECORD_DELETE, table, key, vec![], 0)
+    }
+
+    pub fn expire(table: &str, key: &str, expire_at: u64) -> Self {
+        Self::new(RECORD_EXPIRE, table, key, vec![], expire_at)
+    }
+
+    pub fn persist(table: &str, key: &str) -> Self {
+        Self::new(RECORD_PERSIST, table, key, vec![], 0)
+    }
+
+    pub fn drop_table(table: &str) -> Self {
+        Self::new(RECORD_DROP_TABLE, table, "", vec![], 0)
+    }
+
+    pub fn batch_begin(count: usize) -> Self {
+        Self::new(RECORD_BATCH_BEGIN, "", "", vec![], count as u64)
+    }
+
+    pub fn batch_commit() -> Self {
+        Self::new(RECORD_BATCH_COMMIT, "", "", vec![], 0)
+    }
+
+    fn new(kind: u8, table: &str, key: &str, value: Vec<u8>, expire_at: u64) -> Self {
+        Self {
+            kind,
+            expire_at,
+            table: table.into(),
+            key: key.into(),
+            value,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf =
+            Vec::with_capacity(HEADER_LEN + self.table.len() + self.key.len() + self.value.len());
+        buf.extend_from_slice(&[0; 4]);
+        buf.push(self.kind);
+        buf.extend_from_slice(&self.expire_at.to_be_bytes());
+        buf.extend_from_slice(&(self.table.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&(self.key.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&(self.value.len() as u32).to_be_bytes());
+        buf.extend_from_slice(self.table.as_bytes());
+        buf.extend_from_slice(self.key.as_bytes());
+        buf.extend_from_slice(&self.value);
+        let crc = crc32fast::hash(&buf[4..]);
+        buf[..4].copy_from_slice(&crc.to_be_bytes());
+        buf
+    }
+
+    // 读取一条记录，正好读到文件末尾时返回 None
+    // 记录不完整时返回 UnexpectedEof，校验失败时返回 InvalidData
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Option<(Self, u64)>> {
+        let mut header = [0u8; HEADER_LEN];
+        match read_full(reader, &mut header)? {
+            0 => return Ok(None),
+            HEADER_LEN => {}
+            _ => return Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+        let kind = header[4];
+        let expire_at = u64::from_be_bytes(header[5..13].try_into().unwrap());
+        let table_len = u32::from_be_bytes(header[13..17].try_into().unwrap()) as u64;
+        let key_len = u32::from_be_bytes(header[17..21].try_into().unwrap()) as u64;
+        let value_len = u32::from_be_bytes(header[21..25].try_into().unwrap()) as u64;
+        let body_len = table_len + key_len + value_len;
+
+        // 用 take 读取，长度字段损坏时不会一次分配过大的内存
+        let mut body = Vec::new();
+        reader.take(body_len).read_to_end(&mut body)?;
+        if (body.len() as u64) < body_len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&header[4..]);
+        hasher.update(&body);
+        let crc = u32::from_be_bytes(header[..4].try_into().unwrap());
+        if hasher.finalize() != crc || kind > RECORD_BATCH_COMMIT {
+            return Err(invalid_data("record checksum mismatch"));
+        }
+
+        let value = body.split_off((table_len + key_len) as usize);
+        let key = body.split_off(table_len as usize);
+        let record = Self {
+            kind,
+            expire_at,
+            table: String::from_utf8(body).map_err(|_| invalid_data("invalid table"))?,
+            key: String::from_utf8(key).map_err(|_| invalid_data("invalid key"))?,
+            value,
+        };
+        Ok(Some((record, HEADER_LEN as u64 + body_len)))
+    }
+}
+
+// 顺序扫描日志文件，f 的参数为记录、偏移和长度
+// 只有最后一个（正在写入的）文件可能因为崩溃留下不完整的记录，active 为 true 时把它截断到最后一条完整记录之后
+// 其它文件写完后已经 fsync，出现损坏时返回错误，不能静默丢弃后面的数据
+pub(crate) fn scan_records(
+    path: &Path,
+    active: bool,
+    mut f: impl FnMut(Record, u64, u64),
+) -> io::Result<()> {
+    let file = OpenOptions::new().read(true).write(active).open(path)?;
+    let mut reader = BufReader::new(&file);
+    let mut offset = 0;
+    loop {
+        match Record::read_from(&mut reader) {
+            Ok(Some((record, len))) => {
+                f(record, offset, len);
+                offset += len;
+            }
+            Ok(None) => break,
+            Err(e)
+                if e.kind() == io::ErrorKind::UnexpectedEof
+                    || e.kind() == io::ErrorKind::InvalidData =>
+            {
+                if !active {
+                    return Err(invalid_data(&format!(
+                        "broken record in sealed file {} at offset {}: {}",
+                        path.display(),
+                        offset,
+                        e
+                    )));
+                }
+                warn!(
+                    "Truncating {} at offset {} after a broken record: {}",
+                    path.display(),
+                    offset,
+                    e
+                );
+                file.set_len(offset)?;
+                file.sync_all()?;
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
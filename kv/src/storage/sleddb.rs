@@ -1,94 +1,783 @@
-use std::path::Path;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeSet, HashMap},
+    ops::Bound,
+    path::Path,
+    time::Duration,
+};
 
-use sled::{Db, IVec};
+use sled::{
+    Batch, Db, IVec, Transactional, Tree,
+    transaction::{
+        ConflictableTransactionError, TransactionError, TransactionalTree,
+        UnabortableTransactionError,
+    },
+};
 
-use crate::{Kvpair, StorageIter, Value, error::KvError, storage::storage::Storage};
+use tracing::{info, warn};
 
-pub struct SledDb(Db);
+use crate::{
+    Kvpair, StorageIter, Value, deadline_ms,
+    error::KvError,
+    now_ms,
+    storage::storage::{ScanRange, Storage, TableStats, TxFn, UpdateFn},
+};
+
+// 所有表的数据保存在同一个 tree 中，这样跨表的事务也可以映射到 sled 事务
+const DATA_TREE: &str = "data";
+// 保存过期时间的 tree，key 与数据 key 相同，value 为大端序的毫秒时间戳
+const EXPIRES_TREE: &str = "expires";
+// 旧版本的数据保存在默认的 tree 中，过期时间保存在这个 tree 中
+const LEGACY_EXPIRES_TREE: &str = "__expires";
+
+pub struct SledDb {
+    data: Tree,
+    expires: Tree,
+}
 
 impl SledDb {
     pub fn new(path: impl AsRef<Path>) -> Self {
-        let db = sled::open(path).unwrap();
-        Self(db)
+        Self::try_new(path).unwrap()
+    }
+
+    pub fn try_new(path: impl AsRef<Path>) -> Result<Self, KvError> {
+        let db = sled::open(path)?;
+        let data = db.open_tree(DATA_TREE)?;
+        let expires = db.open_tree(EXPIRES_TREE)?;
+        migrate(&db, &data, &expires)?;
+        Ok(Self { data, expires })
+    }
+
+    // key 的编码：table 长度（4 字节大端序）| table | key
+    // table 和 key 可以包含任意字符，同一个表的 key 连续存放并按 key 的字节序排列
+    fn get_full_key(table: &str, key: &str) -> Vec<u8> {
+        let mut full_key = SledDb::get_table_perfix(table);
+        full_key.extend_from_slice(key.as_bytes());
+        full_key
+    }
+
+    fn get_table_perfix(table: &str) -> Vec<u8> {
+        let mut prefix = Vec::with_capacity(4 + table.len());
+        prefix.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        prefix.extend_from_slice(table.as_bytes());
+        prefix
+    }
+
+    fn is_expired(&self, full_key: &[u8]) -> Result<bool, KvError> {
+        is_expired(&self.expires, full_key, now_ms())
+    }
+
+    fn has_live_key(&self, prefix: &[u8], now: u64) -> Result<bool, KvError> {
+        for k in self.data.scan_prefix(prefix).keys() {
+            if !is_expired(&self.expires, &k?, now)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+// 返回 (table, key)
+fn decode_key(full_key: &[u8]) -> Result<(&str, &str), KvError> {
+    let invalid = || KvError::Internal(format!("invalid sled key: {:?}", full_key));
+    let len = full_key
+        .get(..4)
+        .and_then(|len| <[u8; 4]>::try_from(len).ok())
+        .map(|len| u32::from_be_bytes(len) as usize)
+        .ok_or_else(invalid)?;
+    let (table, key) = full_key[4..].split_at_checked(len).ok_or_else(invalid)?;
+    match (str::from_utf8(table), str::from_utf8(key)) {
+        (Ok(table), Ok(key)) => Ok((table, key)),
+        _ => Err(invalid()),
     }
+}
 
-    fn get_full_key(table: &str, key: &str) -> String {
-        format!("{}:{}", table, key)
+// 大于所有以 prefix 开头的 key 的最小的 key，不存在时返回 None
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
     }
+    None
+}
 
-    fn get_table_perfix(table: &str) -> String {
-        format!("{}:", table)
+// 旧版本把 key 保存为 "table:key"，table 或 key 中包含 ':' 时无法正确解析
+// 打开时把旧的数据迁移到新的 tree 中，全部写入后才清除旧的数据，中途失败时可以重新执行
+fn migrate(db: &Db, data: &Tree, expires: &Tree) -> Result<(), KvError> {
+    if db.is_empty() {
+        db.drop_tree(LEGACY_EXPIRES_TREE)?;
+        return Ok(());
     }
+    let legacy_expires = db.open_tree(LEGACY_EXPIRES_TREE)?;
+    let mut data_batch = Batch::default();
+    let mut expires_batch = Batch::default();
+    let mut count = 0;
+    for kv in db.iter() {
+        let (k, v) = kv?;
+        // 旧版本的 key 以第一个 ':' 分隔 table 和 key
+        let Some((table, key)) = str::from_utf8(&k).ok().and_then(|k| k.split_once(':')) else {
+            warn!("Skipping invalid legacy sled key {:?}", k);
+            continue;
+        };
+        let full_key = SledDb::get_full_key(table, key);
+        if let Some(deadline) = legacy_expires.get(&k)? {
+            expires_batch.insert(full_key.as_slice(), deadline);
+        }
+        data_batch.insert(full_key, v);
+        count += 1;
+    }
+    data.apply_batch(data_batch)?;
+    expires.apply_batch(expires_batch)?;
+    db.flush()?;
+    db.clear()?;
+    db.drop_tree(LEGACY_EXPIRES_TREE)?;
+    db.flush()?;
+    info!("Migrated {} keys from the legacy sled layout", count);
+    Ok(())
 }
 
 fn flip<T, E>(x: Option<Result<T, E>>) -> Result<Option<T>, E> {
     x.map_or(Ok(None), |x| x.map(Some))
 }
 
+fn decode_all(values: Vec<Option<IVec>>) -> Result<Vec<Option<Value>>, KvError> {
+    values
+        .into_iter()
+        .map(|v| flip(v.map(|v| Value::try_from(v.as_ref()))))
+        .collect()
+}
+
+fn decode_deadline(v: &[u8]) -> u64 {
+    // 无法解析的过期时间视为永不过期
+    <[u8; 8]>::try_from(v)
+        .map(u64::from_be_bytes)
+        .unwrap_or(u64::MAX)
+}
+
+fn is_expired(expires: &Tree, key: &[u8], now: u64) -> Result<bool, KvError> {
+    Ok(expires
+        .get(key)?
+        .is_some_and(|v| decode_deadline(&v) <= now))
+}
+
+fn tx_error(cmd: &'static str, table: &str, e: TransactionError) -> KvError {
+    KvError::StorageError(cmd, table.into(), "".into(), e.to_string())
+}
+
+// 写入或删除时同时清除过期时间，已经过期的旧值视为不存在
+fn take_unexpired(old: Option<IVec>, deadline: Option<IVec>, now: u64) -> Option<IVec> {
+    let expired = deadline.is_some_and(|v| decode_deadline(&v) <= now);
+    old.filter(|_| !expired)
+}
+
 impl Storage for SledDb {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
         let full_key = SledDb::get_full_key(table, key);
+        if self.is_expired(full_key.as_slice())? {
+            return Ok(None);
+        }
         let value = self
-            .0
-            .get(full_key.as_bytes())?
+            .data
+            .get(full_key.as_slice())?
             .map(|v| Value::try_from(v.as_ref()));
         flip(value)
     }
     fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
-        let full_key = SledDb::get_full_key(table, key);
-        let value: Vec<u8> = value.try_into()?;
-        let result = self
-            .0
-            .insert(full_key, value)?
-            .map(|v| Value::try_from(v.as_ref()));
-        flip(result)
+        let mut result = self.set_many(table, vec![Kvpair::new(key, value)])?;
+        Ok(result.pop().flatten())
     }
     fn delete(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let full_key = SledDb::get_full_key(table, key);
-        let result = self
-            .0
-            .remove(full_key.as_bytes())?
-            .map(|v| Value::try_from(v.as_ref()));
-        flip(result)
+        let mut result = self.delete_many(table, &[key.to_string()])?;
+        Ok(result.pop().flatten())
     }
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
         let full_key = SledDb::get_full_key(table, key);
-        let result = self.0.contains_key(full_key.as_bytes())?;
-        Ok(result)
+        let result = self.data.contains_key(full_key.as_slice())?;
+        Ok(result && !self.is_expired(full_key.as_slice())?)
     }
 
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
-        let prefix = SledDb::get_table_perfix(table);
-        let result = self
-            .0
-            .scan_prefix(prefix.as_bytes())
-            .map(|v| v.into())
-            .collect();
-        Ok(result)
+        Ok(self.get_iter(table)?.collect())
     }
 
     fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
         let prefix = SledDb::get_table_perfix(table);
-        let result = StorageIter::new(self.0.scan_prefix(prefix.as_bytes()));
+        let expires = self.expires.clone();
+        let now = now_ms();
+        let iter = self.data.scan_prefix(prefix).filter(move |kv| match kv {
+            Ok((k, _)) => !is_expired(&expires, k, now).unwrap_or(false),
+            Err(_) => true,
+        });
+        let result = StorageIter::new(iter);
         Ok(Box::new(result))
     }
+
+    // sled 的 key 按字节序排列，直接按范围读取，不需要遍历整个表
+    fn scan(
+        &self,
+        table: &str,
+        prefix: &str,
+        range: ScanRange,
+        limit: usize,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let scan_prefix = SledDb::get_full_key(table, prefix);
+        let to_full_key = |bound: Bound<&str>| bound.map(|k| SledDb::get_full_key(table, k));
+        // 下界至少从前缀开始，上界由前缀截止
+        let start = match to_full_key(range.0) {
+            Bound::Included(k) | Bound::Excluded(k) if k < scan_prefix => {
+                Bound::Included(scan_prefix.clone())
+            }
+            Bound::Unbounded => Bound::Included(scan_prefix.clone()),
+            bound => bound,
+        };
+        let end = to_full_key(range.1);
+
+        let now = now_ms();
+        let mut pairs = vec![];
+        for kv in self.data.range((start, end)) {
+            if pairs.len() >= limit {
+                break;
+            }
+            let (k, v) = kv?;
+            if !k.starts_with(&scan_prefix) {
+                break;
+            }
+            if is_expired(&self.expires, &k, now)? {
+                continue;
+            }
+            let (_, key) = decode_key(&k)?;
+            pairs.push(Kvpair::new(key, Value::try_from(v.as_ref())?));
+        }
+        Ok(pairs)
+    }
+
+    // 在同一个 sled 事务中写入，保证多个 key 要么全部成功要么全部失败
+    fn set_many(&self, table: &str, pairs: Vec<Kvpair>) -> Result<Vec<Option<Value>>, KvError> {
+        let entries = pairs
+            .into_iter()
+            .map(|pair| {
+                let value: Vec<u8> = pair.value.unwrap_or_default().try_into()?;
+                Ok((SledDb::get_full_key(table, &pair.key), value))
+            })
+            .collect::<Result<Vec<_>, KvError>>()?;
+        let now = now_ms();
+        let result = (&self.data, &self.expires)
+            .transaction(|(db, expires)| {
+                let mut olds = Vec::with_capacity(entries.len());
+                for (key, value) in &entries {
+                    let old = db.insert(key.as_slice(), value.as_slice())?;
+                    let deadline = expires.remove(key.as_slice())?;
+                    olds.push(take_unexpired(old, deadline, now));
+                }
+                Ok(olds)
+            })
+            .map_err(|e: TransactionError| tx_error("hmset", table, e))?;
+        decode_all(result)
+    }
+
+    // 所有 key 的值和过期时间在同一个 sled 事务中写入
+    fn set_many_with_ttl(
+        &self,
+        table: &str,
+        pairs: Vec<Kvpair>,
+        ttl: Duration,
+    ) -> Result<Vec<Option<Value>>, KvError> {
+        let entries = pairs
+            .into_iter()
+            .map(|pair| {
+                let value: Vec<u8> = pair.value.unwrap_or_default().try_into()?;
+                Ok((SledDb::get_full_key(table, &pair.key), value))
+            })
+            .collect::<Result<Vec<_>, KvError>>()?;
+        let now = now_ms();
+        let deadline = deadline_ms(ttl).to_be_bytes();
+        let result = (&self.data, &self.expires)
+            .transaction(|(db, expires)| {
+                let mut olds = Vec::with_capacity(entries.len());
+                for (key, value) in &entries {
+                    let old = db.insert(key.as_slice(), value.as_slice())?;
+                    let old_deadline = expires.insert(key.as_slice(), &deadline)?;
+                    olds.push(take_unexpired(old, old_deadline, now));
+                }
+                Ok(olds)
+            })
+            .map_err(|e: TransactionError| tx_error("hmset", table, e))?;
+        decode_all(result)
+    }
+
+    fn delete_many(&self, table: &str, keys: &[String]) -> Result<Vec<Option<Value>>, KvError> {
+        let full_keys: Vec<Vec<u8>> = keys
+            .iter()
+            .map(|key| SledDb::get_full_key(table, key))
+            .collect();
+        let now = now_ms();
+        let result = (&self.data, &self.expires)
+            .transaction(|(db, expires)| {
+                let mut olds = Vec::with_capacity(full_keys.len());
+                for key in &full_keys {
+                    let old = db.remove(key.as_slice())?;
+                    let deadline = expires.remove(key.as_slice())?;
+                    olds.push(take_unexpired(old, deadline, now));
+                }
+                Ok(olds)
+            })
+            .map_err(|e: TransactionError| tx_error("hmdel", table, e))?;
+        decode_all(result)
+    }
+
+    // 在同一个 sled 事务中写入值和过期时间
+    fn set_with_ttl(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        let full_key = SledDb::get_full_key(table, key);
+        let value: Vec<u8> = value.try_into()?;
+        let now = now_ms();
+        let deadline = deadline_ms(ttl).to_be_bytes();
+        let old = (&self.data, &self.expires)
+            .transaction(|(db, expires)| {
+                let old = db.insert(full_key.as_slice(), value.as_slice())?;
+                let old_deadline = expires.insert(full_key.as_slice(), &deadline)?;
+                Ok(take_unexpired(old, old_deadline, now))
+            })
+            .map_err(|e: TransactionError| tx_error("hset", table, e))?;
+        flip(old.map(|v| Value::try_from(v.as_ref())))
+    }
+
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        let full_key = SledDb::get_full_key(table, key);
+        let now = now_ms();
+        let deadline = deadline_ms(ttl).to_be_bytes();
+        (&self.data, &self.expires)
+            .transaction(|(db, expires)| {
+                let expired = expires
+                    .get(full_key.as_slice())?
+                    .is_some_and(|v| decode_deadline(&v) <= now);
+                if expired || db.get(full_key.as_slice())?.is_none() {
+                    return Ok(false);
+                }
+                expires.insert(full_key.as_slice(), &deadline)?;
+                Ok(true)
+            })
+            .map_err(|e: TransactionError| tx_error("expire", table, e))
+    }
+
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        let full_key = SledDb::get_full_key(table, key);
+        let now = now_ms();
+        let deadline = self
+            .expires
+            .get(full_key.as_slice())?
+            .map(|v| decode_deadline(&v));
+        Ok(deadline
+            .filter(|deadline| *deadline > now)
+            .map(|deadline| Duration::from_millis(deadline - now)))
+    }
+
+    // 同一个表的 key 连续存放，找到一个表后直接跳到下一个表的第一个 key
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        let now = now_ms();
+        let mut tables = vec![];
+        let mut start = vec![];
+        while let Some(k) = self
+            .data
+            .range(start.as_slice()..)
+            .keys()
+            .next()
+            .transpose()?
+        {
+            let (table, _) = decode_key(&k)?;
+            let prefix = SledDb::get_table_perfix(table);
+            if self.has_live_key(&prefix, now)? {
+                tables.push(table.to_string());
+            }
+            match prefix_end(&prefix) {
+                Some(end) => start = end,
+                None => break,
+            }
+        }
+        // key 先按 table 的长度排列，需要重新按名称排序
+        tables.sort_unstable();
+        Ok(tables)
+    }
+
+    // 数据和过期时间分别批量删除，过期时间残留时会在下次写入时被清除
+    // sled 的事务中不能遍历，先找出表中的 key，再在同一个事务中删除值和过期时间，
+    // 避免并发写入的 key 只剩下值或者过期时间
+    fn drop_table(&self, table: &str) -> Result<usize, KvError> {
+        let keys = self
+            .data
+            .scan_prefix(SledDb::get_table_perfix(table))
+            .keys()
+            .collect::<Result<Vec<_>, _>>()?;
+        let now = now_ms();
+        (&self.data, &self.expires)
+            .transaction(|(db, expires)| {
+                let mut count = 0;
+                for key in &keys {
+                    let old = db.remove(key)?;
+                    let deadline = expires.remove(key)?;
+                    if take_unexpired(old, deadline, now).is_some() {
+                        count += 1;
+                    }
+                }
+                Ok(count)
+            })
+            .map_err(|e: TransactionError| tx_error("droptable", table, e))
+    }
+
+    // 直接使用编码后的 value 的长度，不需要解码
+    fn table_info(&self, table: &str) -> Result<Option<TableStats>, KvError> {
+        let prefix = SledDb::get_table_perfix(table);
+        let now = now_ms();
+        let mut stats = TableStats::default();
+        for kv in self.data.scan_prefix(&prefix) {
+            let (k, v) = kv?;
+            if !is_expired(&self.expires, &k, now)? {
+                stats.add(k.len() - prefix.len(), v.len());
+            }
+        }
+        Ok((stats.keys > 0).then_some(stats))
+    }
+
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        let full_key = SledDb::get_full_key(table, key);
+        let now = now_ms();
+        self.expires
+            .transaction(|expires| match expires.get(full_key.as_slice())? {
+                Some(v) if decode_deadline(&v) > now => {
+                    expires.remove(full_key.as_slice())?;
+                    Ok(true)
+                }
+                _ => Ok(false),
+            })
+            .map_err(|e: TransactionError| tx_error("persist", table, e))
+    }
+
+    fn reap_expired(&self) -> Result<usize, KvError> {
+        let now = now_ms();
+        let mut count = 0;
+        for kv in self.expires.iter() {
+            let (key, deadline) = kv?;
+            if decode_deadline(&deadline) > now {
+                continue;
+            }
+            // 删除前在事务中再次确认过期时间没有被 set/persist 清除
+            let removed = (&self.data, &self.expires)
+                .transaction(|(db, expires)| match expires.get(&key)? {
+                    Some(v) if decode_deadline(&v) <= now => {
+                        expires.remove(&key)?;
+                        Ok(db.remove(&key)?.is_some())
+                    }
+                    _ => Ok::<_, ConflictableTransactionError>(false),
+                })
+                .map_err(|e: TransactionError| tx_error("reap", "", e))?;
+            if removed {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    // 值和过期时间在同一个 sled 事务中读取和修改，发生冲突时由 sled 重试
+    fn update(&self, table: &str, key: &str, f: UpdateFn) -> Result<Option<Value>, KvError> {
+        let full_key = SledDb::get_full_key(table, key);
+        let f = RefCell::new(f);
+        (&self.data, &self.expires)
+            .transaction(|(db, expires)| {
+                let now = now_ms();
+                let deadline = expires.get(full_key.as_slice())?;
+                let expired = deadline.as_ref().is_some_and(|v| decode_deadline(v) <= now);
+                let old = match db.get(full_key.as_slice())? {
+                    Some(v) if !expired => Some(
+                        Value::try_from(v.as_ref()).map_err(ConflictableTransactionError::Abort)?,
+                    ),
+                    _ => None,
+                };
+                let value = (f.borrow_mut())(old.as_ref())
+                    .and_then(|value| value.map(Vec::<u8>::try_from).transpose())
+                    .map_err(ConflictableTransactionError::Abort)?;
+                let Some(value) = value else {
+                    return Ok(old);
+                };
+                db.insert(full_key.as_slice(), value)?;
+                // 旧值已经过期时同时清除过期时间
+                if expired {
+                    expires.remove(full_key.as_slice())?;
+                }
+                Ok(old)
+            })
+            .map_err(|e| match e {
+                TransactionError::Abort(e) => e,
+                TransactionError::Storage(e) => e.into(),
+            })
+    }
+
+    // 映射到 sled 事务：f 返回错误时回滚，冲突时由 sled 重试
+    // sled 的事务中不能遍历 tree，f 需要遍历某个表时中止事务，在事务外读取表中的 key 后重新执行
+    fn transaction(&self, f: TxFn) -> Result<(), KvError> {
+        let f = RefCell::new(f);
+        let mut tables = HashMap::new();
+        loop {
+            let missing = RefCell::new(None);
+            let result = (&self.data, &self.expires).transaction(|(data, expires)| {
+                let tx = SledTx {
+                    data,
+                    expires,
+                    tables: &tables,
+                    missing: &missing,
+                    written: RefCell::default(),
+                    now: now_ms(),
+                    conflict: Cell::new(false),
+                };
+                let result = (f.borrow_mut())(&tx);
+                if missing.borrow().is_some() {
+                    return Err(ConflictableTransactionError::Abort(KvError::Internal(
+                        "table is not loaded".into(),
+                    )));
+                }
+                match result {
+                    Ok(()) => Ok(()),
+                    Err(_) if tx.conflict.get() => Err(ConflictableTransactionError::Conflict),
+                    Err(e) => Err(ConflictableTransactionError::Abort(e)),
+                }
+            });
+            if let Some(table) = missing.take() {
+                let prefix = SledDb::get_table_perfix(&table);
+                let keys = self
+                    .data
+                    .scan_prefix(prefix)
+                    .keys()
+                    .collect::<Result<BTreeSet<_>, _>>()?;
+                tables.insert(table, keys);
+                continue;
+            }
+            return result.map_err(|e| match e {
+                TransactionError::Abort(e) => e,
+                TransactionError::Storage(e) => e.into(),
+            });
+        }
+    }
+}
+
+// sled 事务中的视图，所有读写都在同一个事务中完成
+struct SledTx<'a> {
+    data: &'a TransactionalTree,
+    expires: &'a TransactionalTree,
+    // 在事务外读取的表中的 key，以及事务中需要遍历但还没有读取的表
+    tables: &'a HashMap<String, BTreeSet<IVec>>,
+    missing: &'a RefCell<Option<String>>,
+    // 事务中写入的 key，遍历时与已经提交的 key 合并
+    written: RefCell<BTreeSet<Vec<u8>>>,
+    now: u64,
+    // 发生冲突时需要让 sled 重试整个事务，而不是当作普通错误返回
+    conflict: Cell<bool>,
+}
+
+impl SledTx<'_> {
+    fn check<T>(&self, result: Result<T, UnabortableTransactionError>) -> Result<T, KvError> {
+        result.map_err(|e| match e {
+            UnabortableTransactionError::Conflict => {
+                self.conflict.set(true);
+                KvError::Internal("transaction conflict".into())
+            }
+            UnabortableTransactionError::Storage(e) => e.into(),
+        })
+    }
+
+    fn deadline(&self, full_key: &[u8]) -> Result<Option<u64>, KvError> {
+        let deadline = self.check(self.expires.get(full_key))?;
+        Ok(deadline.map(|v| decode_deadline(&v)))
+    }
+
+    fn is_expired(&self, full_key: &[u8]) -> Result<bool, KvError> {
+        Ok(self
+            .deadline(full_key)?
+            .is_some_and(|deadline| deadline <= self.now))
+    }
+}
+
+impl Storage for SledTx<'_> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let full_key = SledDb::get_full_key(table, key);
+        if self.is_expired(full_key.as_slice())? {
+            return Ok(None);
+        }
+        let value = self.check(self.data.get(full_key.as_slice()))?;
+        flip(value.map(|v| Value::try_from(v.as_ref())))
+    }
+    fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
+        let full_key = SledDb::get_full_key(table, key);
+        let value: Vec<u8> = value.try_into()?;
+        let old = self.check(self.data.insert(full_key.as_slice(), value))?;
+        let deadline = self.check(self.expires.remove(full_key.as_slice()))?;
+        self.written.borrow_mut().insert(full_key);
+        let old = take_unexpired(old, deadline, self.now);
+        flip(old.map(|v| Value::try_from(v.as_ref())))
+    }
+    fn delete(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let full_key = SledDb::get_full_key(table, key);
+        let old = self.check(self.data.remove(full_key.as_slice()))?;
+        let deadline = self.check(self.expires.remove(full_key.as_slice()))?;
+        let old = take_unexpired(old, deadline, self.now);
+        flip(old.map(|v| Value::try_from(v.as_ref())))
+    }
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        Ok(self.get(table, key)?.is_some())
+    }
+    // 已经提交的 key 加上事务中写入的 key，再逐个在事务中读取
+    // 在事务外读取 key 之后其它连接新写入的 key 不会出现在结果中
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        let Some(committed) = self.tables.get(table) else {
+            *self.missing.borrow_mut() = Some(table.into());
+            return Err(KvError::Internal("table is not loaded".into()));
+        };
+        let prefix = SledDb::get_table_perfix(table);
+        let mut keys: BTreeSet<&[u8]> = committed.iter().map(|k| k.as_ref()).collect();
+        let written = self.written.borrow();
+        keys.extend(
+            written
+                .iter()
+                .filter(|k| k.starts_with(&prefix))
+                .map(|k| k.as_slice()),
+        );
+        let mut pairs = Vec::new();
+        for full_key in keys {
+            let (_, key) = decode_key(full_key)?;
+            if let Some(value) = self.get(table, key)? {
+                pairs.push(Kvpair::new(key, value));
+            }
+        }
+        Ok(pairs)
+    }
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        Ok(Box::new(self.get_all(table)?.into_iter()))
+    }
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        if !self.contains(table, key)? {
+            return Ok(false);
+        }
+        let full_key = SledDb::get_full_key(table, key);
+        let deadline = self.now.saturating_add(ttl.as_millis() as u64);
+        self.check(
+            self.expires
+                .insert(full_key.as_slice(), &deadline.to_be_bytes()),
+        )?;
+        Ok(true)
+    }
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        let deadline = self.deadline(&SledDb::get_full_key(table, key))?;
+        Ok(deadline
+            .filter(|deadline| *deadline > self.now)
+            .map(|deadline| Duration::from_millis(deadline - self.now)))
+    }
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        if self.ttl(table, key)?.is_none() {
+            return Ok(false);
+        }
+        let full_key = SledDb::get_full_key(table, key);
+        self.check(self.expires.remove(full_key.as_slice()))?;
+        Ok(true)
+    }
 }
 
 impl From<Result<(IVec, IVec), sled::Error>> for Kvpair {
     fn from(value: Result<(IVec, IVec), sled::Error>) -> Self {
         match value {
-            Ok((k, v)) => match v.as_ref().try_into() {
-                Ok(v) => Kvpair::new(ivec_to_str(k.as_ref()), v),
-                Err(_) => Kvpair::default(),
+            // 使用 Value::try_from 解码，而不是把编码后的字节当作 BytesValue
+            Ok((k, v)) => match (decode_key(&k), Value::try_from(v.as_ref())) {
+                (Ok((_, key)), Ok(v)) => Kvpair::new(key, v),
+                _ => Kvpair::default(),
             },
             _ => Kvpair::default(),
         }
     }
 }
 
-fn ivec_to_str(ivec: &[u8]) -> &str {
-    let s = str::from_utf8(ivec).unwrap();
-    let mut iter = s.split(":");
-    iter.next();
-    iter.next().unwrap()
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn sleddb_should_round_trip_any_key() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir.path());
+        store.set("a", "b:c", "v1".into()).unwrap();
+        store.set("a:b", "c", "v2".into()).unwrap();
+        store.set("表", "键:😀", 3.into()).unwrap();
+        store.set("", "", true.into()).unwrap();
+
+        assert_eq!(store.get("a", "b:c"), Ok(Some("v1".into())));
+        assert_eq!(store.get("a:b", "c"), Ok(Some("v2".into())));
+        assert_eq!(
+            store.get_all("a"),
+            Ok(vec![Kvpair::new("b:c", "v1".into())])
+        );
+        assert_eq!(
+            store.get_all("a:b"),
+            Ok(vec![Kvpair::new("c", "v2".into())])
+        );
+        assert_eq!(
+            store.get_all("表"),
+            Ok(vec![Kvpair::new("键:😀", 3.into())])
+        );
+        assert_eq!(store.get_all(""), Ok(vec![Kvpair::new("", true.into())]));
+        assert_eq!(
+            store.list_tables(),
+            Ok(vec!["".into(), "a".into(), "a:b".into(), "表".into()])
+        );
+    }
+
+    #[test]
+    fn decode_key_should_reject_invalid_keys() {
+        let full_key = SledDb::get_full_key("t1", "k1");
+        assert_eq!(decode_key(&full_key), Ok(("t1", "k1")));
+        assert!(decode_key(b"t1").is_err());
+        assert!(decode_key(&[0, 0, 0, 9, b't']).is_err());
+        assert_eq!(prefix_end(&[1, 2]), Some(vec![1, 3]));
+        assert_eq!(prefix_end(&[1, 255]), Some(vec![2]));
+        assert_eq!(prefix_end(&[255]), None);
+    }
+
+    #[test]
+    fn legacy_layout_should_be_migrated() {
+        let dir = tempdir().unwrap();
+        {
+            let db = sled::open(dir.path()).unwrap();
+            let expires = db.open_tree(LEGACY_EXPIRES_TREE).unwrap();
+            let value: Vec<u8> = Value::from("v1").try_into().unwrap();
+            db.insert("t1:k1", value.clone()).unwrap();
+            db.insert("t1:k2:x", value.clone()).unwrap();
+            db.insert("t2:k1", value).unwrap();
+            let deadline = deadline_ms(Duration::from_secs(60));
+            expires.insert("t2:k1", &deadline.to_be_bytes()).unwrap();
+            db.flush().unwrap();
+        }
+
+        for _ in 0..2 {
+            // 等待上一个 sled 实例释放目录的锁
+            thread::sleep(Duration::from_millis(80));
+            let store = SledDb::new(dir.path());
+            assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+            assert_eq!(store.get("t1", "k2:x"), Ok(Some("v1".into())));
+            assert_eq!(store.get_all("t1").unwrap().len(), 2);
+            assert!(store.ttl("t2", "k1").unwrap().unwrap() > Duration::from_secs(50));
+            assert_eq!(store.list_tables(), Ok(vec!["t1".into(), "t2".into()]));
+        }
+
+        thread::sleep(Duration::from_millis(80));
+        let db = sled::open(dir.path()).unwrap();
+        assert!(db.is_empty());
+        assert!(!db.tree_names().contains(&LEGACY_EXPIRES_TREE.into()));
+    }
 }
@@ -1,6 +1,14 @@
+use std::{
+    ops::{Bound, RangeBounds},
+    time::Duration,
+};
+
+use prost::Message;
+
 use crate::{Kvpair, Value, error::KvError};
 
-pub trait Storage: Send + Sync + 'static {
+// 事务中的存储视图也实现了这个 trait，因此不要求 Send + Sync，需要跨线程共享的地方自行约束
+pub trait Storage {
     //获取一个key 的value
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
     //设置一个key 的value
@@ -13,14 +21,153 @@ pub trait Storage: Send + Sync + 'static {
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError>;
     //获取一个表的迭代器
     fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError>;
+    //批量设置多个key 的value，返回每个key 的旧值
+    fn set_many(&self, table: &str, pairs: Vec<Kvpair>) -> Result<Vec<Option<Value>>, KvError> {
+        pairs
+            .into_iter()
+            .map(|pair| self.set(table, &pair.key, pair.value.unwrap_or_default()))
+            .collect()
+    }
+    //批量删除多个key，返回每个key 被删除的值
+    fn delete_many(&self, table: &str, keys: &[String]) -> Result<Vec<Option<Value>>, KvError> {
+        keys.iter().map(|key| self.delete(table, key)).collect()
+    }
+    //设置一个key 的过期时间，key 不存在时返回 false；set 会清除之前的过期时间
+    fn expire(&self, _table: &str, _key: &str, _ttl: Duration) -> Result<bool, KvError> {
+        Err(KvError::Internal(
+            "ttl is not supported by this storage".into(),
+        ))
+    }
+    //写入一个key 的value 并设置过期时间，两者同时生效，返回旧值
+    //默认实现不是原子的，只适用于事务视图这类已经隔离的场景，存储引擎需要自己实现
+    fn set_with_ttl(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        let old = self.set(table, key, value)?;
+        self.expire(table, key, ttl)?;
+        Ok(old)
+    }
+    //批量写入多个key 的value 并设置相同的过期时间，返回每个key 的旧值
+    //默认实现逐个调用 set_with_ttl，多个key 之间不是原子的
+    fn set_many_with_ttl(
+        &self,
+        table: &str,
+        pairs: Vec<Kvpair>,
+        ttl: Duration,
+    ) -> Result<Vec<Option<Value>>, KvError> {
+        pairs
+            .into_iter()
+            .map(|pair| self.set_with_ttl(table, &pair.key, pair.value.unwrap_or_default(), ttl))
+            .collect()
+    }
+    //获取一个key 剩余的过期时间，key 不存在或没有过期时间时返回 None
+    fn ttl(&self, _table: &str, _key: &str) -> Result<Option<Duration>, KvError> {
+        Ok(None)
+    }
+    //移除一个key 的过期时间，返回之前是否设置了过期时间
+    fn persist(&self, _table: &str, _key: &str) -> Result<bool, KvError> {
+        Ok(false)
+    }
+    //清理所有已经过期的key，返回清理的数量
+    fn reap_expired(&self) -> Result<usize, KvError> {
+        Ok(0)
+    }
+    //原子地读取-修改-写入一个key：f 收到当前的值（不存在或已经过期时为 None），返回新值，返回 None 时不写入
+    //返回修改前的值；没有过期的key 保留原来的过期时间
+    //默认实现不是原子的，只适用于事务视图这类已经隔离的场景，存储引擎需要自己实现
+    fn update(&self, table: &str, key: &str, f: UpdateFn) -> Result<Option<Value>, KvError> {
+        let old = self.get(table, key)?;
+        if let Some(value) = f(old.as_ref())? {
+            let ttl = self.ttl(table, key)?;
+            self.set(table, key, value)?;
+            if let Some(ttl) = ttl {
+                self.expire(table, key, ttl)?;
+            }
+        }
+        Ok(old)
+    }
+    //按 key 的字典序返回 range 范围内以 prefix 开头的最多 limit 个key-value
+    //默认实现遍历整个表后排序，有序的存储引擎可以直接按范围读取
+    fn scan(
+        &self,
+        table: &str,
+        prefix: &str,
+        range: ScanRange,
+        limit: usize,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let mut pairs: Vec<Kvpair> = self
+            .get_iter(table)?
+            .filter(|pair| {
+                pair.key.starts_with(prefix)
+                    && RangeBounds::<str>::contains(&range, pair.key.as_str())
+            })
+            .collect();
+        pairs.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        pairs.truncate(limit);
+        Ok(pairs)
+    }
+    //列出所有至少有一个key 的表，按名称排序
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        Err(KvError::Internal(
+            "list tables is not supported by this storage".into(),
+        ))
+    }
+    //删除整个表，返回删除的key 的数量
+    fn drop_table(&self, table: &str) -> Result<usize, KvError> {
+        let keys: Vec<String> = self.get_iter(table)?.map(|pair| pair.key).collect();
+        let removed = self.delete_many(table, &keys)?;
+        Ok(removed.iter().filter(|v| v.is_some()).count())
+    }
+    //获取一个表的统计信息，表中没有key 时返回 None
+    fn table_info(&self, table: &str) -> Result<Option<TableStats>, KvError> {
+        let mut stats = TableStats::default();
+        for pair in self.get_iter(table)? {
+            stats.add(pair.key.len(), pair.value.map_or(0, |v| v.encoded_len()));
+        }
+        Ok((stats.keys > 0).then_some(stats))
+    }
+    //在事务中执行 f，f 通过传入的视图读写，返回错误时所有修改回滚
+    //发生冲突时 f 可能被重试，不应该有视图以外的副作用
+    fn transaction(&self, _f: TxFn) -> Result<(), KvError> {
+        Err(KvError::Internal(
+            "transaction is not supported by this storage".into(),
+        ))
+    }
+}
+
+// bytes 是 key 与编码后的 value 的大小之和，不包括存储引擎额外的开销
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableStats {
+    pub keys: u64,
+    pub bytes: u64,
 }
 
+impl TableStats {
+    pub(crate) fn add(&mut self, key_len: usize, value_len: usize) {
+        self.keys += 1;
+        self.bytes += (key_len + value_len) as u64;
+    }
+}
+
+pub type ScanRange<'a> = (Bound<&'a str>, Bound<&'a str>);
+
+pub type TxFn<'a> = &'a mut dyn FnMut(&dyn Storage) -> Result<(), KvError>;
+
+//update 可能因为并发修改被重试，不应该有副作用
+pub type UpdateFn<'a> = &'a mut dyn FnMut(Option<&Value>) -> Result<Option<Value>, KvError>;
+
 #[cfg(test)]
 mod tests {
 
+    use std::thread;
+
     use tempfile::tempdir;
 
-    use crate::{MemTable, sleddb::SledDb};
+    use crate::{MemTable, WalOptions, bitcask::Bitcask, sleddb::SledDb, value};
 
     use super::*;
 
@@ -52,6 +199,42 @@ mod tests {
         test_basic_interface(storage);
     }
 
+    #[test]
+    fn bitcask_basic_interface_should_work() {
+        let dir = tempdir().unwrap();
+        test_basic_interface(Bitcask::new(dir.path()));
+    }
+
+    #[test]
+    fn bitcask_batch_interface_should_work() {
+        let dir = tempdir().unwrap();
+        test_batch_interface(Bitcask::new(dir.path()));
+    }
+
+    #[test]
+    fn bitcask_ttl_should_work() {
+        let dir = tempdir().unwrap();
+        test_ttl(Bitcask::new(dir.path()));
+    }
+
+    #[test]
+    fn bitcask_ttl_should_survive_restart() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::new(dir.path());
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store.set("t1", "k2", "v2".into()).unwrap();
+            store.expire("t1", "k1", Duration::from_millis(50)).unwrap();
+            store.expire("t1", "k2", Duration::from_secs(60)).unwrap();
+        }
+        thread::sleep(Duration::from_millis(80));
+        let store = Bitcask::new(dir.path());
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert!(store.ttl("t1", "k2").unwrap().unwrap() > Duration::from_secs(50));
+        // 重启时过期的记录不会被加载
+        assert_eq!(store.reap_expired(), Ok(0));
+    }
+
     fn test_basic_interface(storage: impl Storage) {
         let v = storage.set("t1", "k1", "v1".into());
         assert!(v.unwrap().is_none());
@@ -81,4 +264,450 @@ mod tests {
         let vd = storage.delete("t1", "k2");
         assert_eq!(vd, Ok(Some("v1".into())));
     }
+
+    #[test]
+    fn sleddb_batch_interface_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_batch_interface(store);
+    }
+
+    #[test]
+    fn memtable_batch_interface_should_work() {
+        let storage = MemTable::new();
+        test_batch_interface(storage);
+    }
+
+    #[test]
+    fn sleddb_ttl_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_ttl(store);
+    }
+
+    #[test]
+    fn memtable_ttl_should_work() {
+        let storage = MemTable::new();
+        test_ttl(storage);
+    }
+
+    #[test]
+    fn sleddb_ttl_should_survive_restart() {
+        let dir = tempdir().unwrap();
+        {
+            let store = SledDb::new(dir.path());
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store.set("t1", "k2", "v2".into()).unwrap();
+            store.expire("t1", "k1", Duration::from_millis(50)).unwrap();
+            store.expire("t1", "k2", Duration::from_secs(60)).unwrap();
+        }
+        thread::sleep(Duration::from_millis(80));
+        let store = SledDb::new(dir.path());
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert!(store.ttl("t1", "k2").unwrap().unwrap() > Duration::from_secs(50));
+        assert_eq!(store.reap_expired(), Ok(1));
+    }
+
+    #[test]
+    fn memtable_scan_should_work() {
+        test_scan(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_scan_should_work() {
+        let dir = tempdir().unwrap();
+        test_scan(SledDb::new(dir));
+    }
+
+    #[test]
+    fn bitcask_scan_should_work() {
+        let dir = tempdir().unwrap();
+        test_scan(Bitcask::new(dir.path()));
+    }
+
+    #[test]
+    fn memtable_table_management_should_work() {
+        test_table_management(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_table_management_should_work() {
+        let dir = tempdir().unwrap();
+        test_table_management(SledDb::new(dir));
+    }
+
+    #[test]
+    fn bitcask_table_management_should_work() {
+        let dir = tempdir().unwrap();
+        test_table_management(Bitcask::new(dir.path()));
+    }
+
+    #[test]
+    fn memtable_read_should_not_create_table() {
+        let store = MemTable::new();
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert_eq!(store.contains("t2", "k1"), Ok(false));
+        assert_eq!(store.get_all("t3"), Ok(vec![]));
+        assert_eq!(store.get_iter("t4").unwrap().count(), 0);
+        assert_eq!(store.delete("t5", "k1"), Ok(None));
+        assert!(store.table.is_empty());
+    }
+
+    #[test]
+    fn memtable_transaction_should_work() {
+        test_transaction(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_transaction_should_work() {
+        let dir = tempdir().unwrap();
+        test_transaction(SledDb::new(dir));
+    }
+
+    #[test]
+    fn memtable_transaction_should_be_isolated() {
+        test_concurrent_transaction(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_transaction_should_be_isolated() {
+        let dir = tempdir().unwrap();
+        test_concurrent_transaction(SledDb::new(dir));
+    }
+
+    #[test]
+    fn memtable_update_should_be_atomic() {
+        test_update(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_update_should_be_atomic() {
+        let dir = tempdir().unwrap();
+        test_update(SledDb::new(dir));
+    }
+
+    #[test]
+    fn bitcask_update_should_be_atomic() {
+        let dir = tempdir().unwrap();
+        test_update(Bitcask::new(dir.path()));
+    }
+
+    #[test]
+    fn persistent_memtable_update_should_survive_restart() {
+        let dir = tempdir().unwrap();
+        let opts = WalOptions {
+            snapshot_interval: None,
+            ..Default::default()
+        };
+        {
+            let store = MemTable::open(dir.path(), opts.clone()).unwrap();
+            test_update(store);
+        }
+        let store = MemTable::open(dir.path(), opts).unwrap();
+        assert_eq!(store.get("t1", "counter"), Ok(Some(200.into())));
+        assert!(store.ttl("t1", "counter").unwrap().is_some());
+    }
+
+    // 多个线程并发递增同一个计数器，不会丢失更新，也不会清除过期时间
+    fn test_update(storage: impl Storage + Send + Sync + 'static) {
+        let storage = std::sync::Arc::new(storage);
+        storage.set("t1", "counter", 0.into()).unwrap();
+        storage
+            .expire("t1", "counter", Duration::from_secs(60))
+            .unwrap();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let storage = storage.clone();
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        storage
+                            .update("t1", "counter", &mut |old| {
+                                let n = old.map(i64::try_from).transpose()?.unwrap_or(0);
+                                Ok(Some((n + 1).into()))
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(storage.get("t1", "counter"), Ok(Some(200.into())));
+        assert!(storage.ttl("t1", "counter").unwrap().is_some());
+
+        // 返回 None 时不写入
+        let old = storage.update("t1", "counter", &mut |_| Ok(None));
+        assert_eq!(old, Ok(Some(200.into())));
+        assert_eq!(storage.update("t1", "nope", &mut |_| Ok(None)), Ok(None));
+        assert_eq!(storage.get("t1", "nope"), Ok(None));
+    }
+
+    fn test_scan(storage: impl Storage) {
+        for key in ["b2", "a1", "c1", "b1", "a2", "b10"] {
+            storage.set("t1", key, key.into()).unwrap();
+        }
+        storage.set("t0", "a0", "a0".into()).unwrap();
+        storage.set("t2", "a3", "a3".into()).unwrap();
+        storage.set("t1", "b3", "b3".into()).unwrap();
+        storage.expire("t1", "b3", Duration::ZERO).unwrap();
+        let keys = |pairs: Vec<Kvpair>| pairs.into_iter().map(|p| p.key).collect::<Vec<_>>();
+
+        // 所有存储引擎都按 key 的字典序返回，不包含其它表和已经过期的 key
+        let all = storage
+            .scan("t1", "", (Bound::Unbounded, Bound::Unbounded), 100)
+            .unwrap();
+        assert!(all.iter().all(|p| p.value == Some(p.key.as_str().into())));
+        assert_eq!(keys(all), ["a1", "a2", "b1", "b10", "b2", "c1"]);
+
+        let page = storage
+            .scan("t1", "", (Bound::Excluded("a2"), Bound::Unbounded), 2)
+            .unwrap();
+        assert_eq!(keys(page), ["b1", "b10"]);
+
+        let prefix = storage
+            .scan("t1", "b", (Bound::Excluded("b1"), Bound::Unbounded), 100)
+            .unwrap();
+        assert_eq!(keys(prefix), ["b10", "b2"]);
+
+        let range = storage
+            .scan(
+                "t1",
+                "",
+                (Bound::Included("a2"), Bound::Excluded("b2")),
+                100,
+            )
+            .unwrap();
+        assert_eq!(keys(range), ["a2", "b1", "b10"]);
+
+        let empty = storage
+            .scan("t1", "", (Bound::Included("c"), Bound::Excluded("b")), 100)
+            .unwrap();
+        assert!(empty.is_empty());
+        assert!(
+            storage
+                .scan("t3", "", (Bound::Unbounded, Bound::Unbounded), 100)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    fn test_table_management(storage: impl Storage) {
+        assert_eq!(storage.list_tables(), Ok(vec![]));
+        storage.set("users", "alice", "a".into()).unwrap();
+        storage.set("users", "bob", 10.into()).unwrap();
+        storage.set("orders", "o1", true.into()).unwrap();
+        storage.set("sessions", "s1", "x".into()).unwrap();
+        storage.expire("sessions", "s1", Duration::ZERO).unwrap();
+        // 读取不存在的表不会创建它
+        storage.get("missing", "k1").unwrap();
+
+        // 只剩已经过期的 key 的表不会被列出
+        assert_eq!(
+            storage.list_tables(),
+            Ok(vec!["orders".to_string(), "users".to_string()])
+        );
+
+        let value: Value = "a".into();
+        let stats = storage.table_info("users").unwrap().unwrap();
+        assert_eq!(stats.keys, 2);
+        assert_eq!(
+            stats.bytes as usize,
+            "alice".len() + value.encoded_len() + "bob".len() + Value::from(10).encoded_len()
+        );
+        assert_eq!(storage.table_info("sessions"), Ok(None));
+        assert_eq!(storage.table_info("missing"), Ok(None));
+
+        assert_eq!(storage.drop_table("users"), Ok(2));
+        assert_eq!(storage.drop_table("missing"), Ok(0));
+        assert_eq!(storage.get("users", "alice"), Ok(None));
+        assert_eq!(storage.table_info("users"), Ok(None));
+        assert_eq!(storage.list_tables(), Ok(vec!["orders".to_string()]));
+
+        // 删除后可以重新写入
+        storage.set("users", "carol", "c".into()).unwrap();
+        assert_eq!(storage.get_all("users").unwrap().len(), 1);
+
+        // 过期时间和值一起删除，已经过期的 key 不计数
+        let ttl = Duration::from_secs(60);
+        storage.set_with_ttl("orders", "o2", 2.into(), ttl).unwrap();
+        assert_eq!(storage.drop_table("orders"), Ok(2));
+        assert_eq!(storage.drop_table("sessions"), Ok(0));
+        storage.set("orders", "o2", 2.into()).unwrap();
+        assert_eq!(storage.ttl("orders", "o2"), Ok(None));
+    }
+
+    fn test_transaction(storage: impl Storage) {
+        storage.set("t1", "k1", 1.into()).unwrap();
+        storage.set("t2", "k1", "v1".into()).unwrap();
+        storage.expire("t2", "k1", Duration::from_secs(60)).unwrap();
+
+        storage
+            .transaction(&mut |tx| {
+                assert_eq!(tx.get("t1", "k1")?, Some(1.into()));
+                tx.set("t1", "k1", 2.into())?;
+                tx.set("t1", "k2", 3.into())?;
+                assert_eq!(tx.get("t1", "k1")?, Some(2.into()));
+                assert_eq!(tx.get_all("t1")?.len(), 2);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(storage.get("t1", "k1"), Ok(Some(2.into())));
+        assert_eq!(storage.get("t1", "k2"), Ok(Some(3.into())));
+
+        // 返回错误时所有修改回滚，包括过期时间
+        let res = storage.transaction(&mut |tx| {
+            tx.set("t1", "k1", 10.into())?;
+            tx.delete("t1", "k2")?;
+            tx.set("t1", "k3", "new".into())?;
+            let mut keys: Vec<_> = tx.get_all("t1")?.into_iter().map(|p| p.key).collect();
+            keys.sort();
+            assert_eq!(keys, vec!["k1", "k3"]);
+            tx.persist("t2", "k1")?;
+            tx.delete("t2", "k1")?;
+            Err(KvError::Internal("abort".into()))
+        });
+        assert_eq!(res, Err(KvError::Internal("abort".into())));
+        assert_eq!(storage.get("t1", "k1"), Ok(Some(2.into())));
+        assert_eq!(storage.get("t1", "k2"), Ok(Some(3.into())));
+        assert_eq!(storage.get("t1", "k3"), Ok(None));
+        assert_eq!(storage.get("t2", "k1"), Ok(Some("v1".into())));
+        assert!(storage.ttl("t2", "k1").unwrap().unwrap() > Duration::from_secs(50));
+    }
+
+    // 多个线程在事务中读-改-写同一个计数器，不会丢失更新
+    fn test_concurrent_transaction(storage: impl Storage + Send + Sync + 'static) {
+        let storage = std::sync::Arc::new(storage);
+        storage.set("t1", "counter", 0.into()).unwrap();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let storage = storage.clone();
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        storage
+                            .transaction(&mut |tx| {
+                                let n = match tx.get("t1", "counter")?.and_then(|v| v.value) {
+                                    Some(value::Value::Int64Value(n)) => n,
+                                    _ => 0,
+                                };
+                                tx.set("t1", "counter", (n + 1).into())?;
+                                Ok(())
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(storage.get("t1", "counter"), Ok(Some(200.into())));
+    }
+
+    fn test_ttl(storage: impl Storage) {
+        storage.set("t1", "k1", "v1".into()).unwrap();
+        storage.set("t1", "k2", "v2".into()).unwrap();
+        storage.set("t1", "k3", "v3".into()).unwrap();
+
+        assert_eq!(
+            storage.expire("t1", "k1", Duration::from_millis(50)),
+            Ok(true)
+        );
+        assert_eq!(
+            storage.expire("t1", "k2", Duration::from_millis(50)),
+            Ok(true)
+        );
+        assert_eq!(
+            storage.expire("t1", "nope", Duration::from_millis(50)),
+            Ok(false)
+        );
+        assert!(storage.ttl("t1", "k1").unwrap().unwrap() <= Duration::from_millis(50));
+        assert_eq!(storage.ttl("t1", "k3"), Ok(None));
+
+        // persist 之后不再过期
+        assert_eq!(storage.persist("t1", "k2"), Ok(true));
+        assert_eq!(storage.persist("t1", "k2"), Ok(false));
+        assert_eq!(storage.ttl("t1", "k2"), Ok(None));
+
+        thread::sleep(Duration::from_millis(80));
+
+        // 过期的 key 立即不可见
+        assert_eq!(storage.get("t1", "k1"), Ok(None));
+        assert_eq!(storage.contains("t1", "k1"), Ok(false));
+        assert_eq!(storage.ttl("t1", "k1"), Ok(None));
+        let mut keys: Vec<_> = storage
+            .get_all("t1")
+            .unwrap()
+            .into_iter()
+            .map(|p| p.key)
+            .collect();
+        keys.sort();
+        assert_eq!(keys, vec!["k2", "k3"]);
+        assert_eq!(storage.get_iter("t1").unwrap().count(), 2);
+
+        assert_eq!(storage.reap_expired(), Ok(1));
+        assert_eq!(storage.reap_expired(), Ok(0));
+
+        // 重新 set 会清除过期时间，过期的旧值不会返回
+        storage
+            .expire("t1", "k3", Duration::from_millis(10))
+            .unwrap();
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(storage.set("t1", "k3", "v33".into()), Ok(None));
+        assert_eq!(storage.ttl("t1", "k3"), Ok(None));
+        assert_eq!(storage.reap_expired(), Ok(0));
+        assert_eq!(storage.get("t1", "k3"), Ok(Some("v33".into())));
+
+        // set_with_ttl 同时写入值和过期时间，过期的旧值不会返回
+        let ttl = Duration::from_secs(60);
+        assert_eq!(storage.set_with_ttl("t1", "k4", "v4".into(), ttl), Ok(None));
+        assert!(storage.ttl("t1", "k4").unwrap().unwrap() > Duration::from_secs(50));
+        let ttl = Duration::from_millis(10);
+        let old = storage.set_with_ttl("t1", "k4", "v44".into(), ttl);
+        assert_eq!(old, Ok(Some("v4".into())));
+        assert!(storage.ttl("t1", "k4").unwrap().unwrap() <= ttl);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(storage.get("t1", "k4"), Ok(None));
+        assert_eq!(storage.set_with_ttl("t1", "k4", "v4".into(), ttl), Ok(None));
+    }
+
+    fn test_batch_interface(storage: impl Storage) {
+        storage.set("t1", "k1", "v1".into()).unwrap();
+
+        let pairs = vec![Kvpair::new("k1", "v11".into()), Kvpair::new("k2", 2.into())];
+        let v = storage.set_many("t1", pairs);
+        assert_eq!(v, Ok(vec![Some("v1".into()), None]));
+        assert_eq!(storage.get("t1", "k1"), Ok(Some("v11".into())));
+        assert_eq!(storage.get("t1", "k2"), Ok(Some(2.into())));
+
+        let keys = vec!["k1".to_string(), "k3".to_string()];
+        let v = storage.delete_many("t1", &keys);
+        assert_eq!(v, Ok(vec![Some("v11".into()), None]));
+        assert_eq!(storage.contains("t1", "k1"), Ok(false));
+        assert_eq!(storage.contains("t1", "k2"), Ok(true));
+
+        // 所有 key 的值和过期时间同时写入
+        let ttl = Duration::from_secs(60);
+        let pairs = vec![Kvpair::new("k2", 22.into()), Kvpair::new("k3", 3.into())];
+        let v = storage.set_many_with_ttl("t1", pairs, ttl);
+        assert_eq!(v, Ok(vec![Some(2.into()), None]));
+        for key in ["k2", "k3"] {
+            assert!(storage.ttl("t1", key).unwrap().unwrap() > Duration::from_secs(50));
+        }
+        assert_eq!(storage.get("t1", "k3"), Ok(Some(3.into())));
+        // 过期的旧值不会返回，set_many 清除过期时间
+        let ttl = Duration::from_millis(10);
+        let pairs = vec![Kvpair::new("k3", 33.into())];
+        assert_eq!(
+            storage.set_many_with_ttl("t1", pairs, ttl),
+            Ok(vec![Some(3.into())])
+        );
+        thread::sleep(Duration::from_millis(20));
+        let pairs = vec![Kvpair::new("k3", 3.into()), Kvpair::new("k2", 2.into())];
+        let v = storage.set_many_with_ttl("t1", pairs, Duration::from_secs(60));
+        assert_eq!(v, Ok(vec![None, Some(22.into())]));
+        let pairs = vec![Kvpair::new("k2", 2.into())];
+        storage.set_many("t1", pairs).unwrap();
+        assert_eq!(storage.ttl("t1", "k2"), Ok(None));
+    }
 }
@@ -0,0 +1,142 @@
+use std::{cell::RefCell, collections::HashSet, time::Duration};
+
+use crate::{
+    Kvpair, MemTable, Value,
+    error::KvError,
+    storage::{
+        record::Record,
+        storage::{Storage, TableStats},
+    },
+};
+
+// MemTable 事务中的视图：修改前记录旧值和过期时间，回滚时按相反的顺序恢复
+// 视图只修改内存，提交时由 MemTable::transaction 把修改一次写入 WAL
+pub(crate) struct MemTableTx {
+    store: MemTable,
+    undo: RefCell<Vec<Undo>>,
+}
+
+struct Undo {
+    table: String,
+    key: String,
+    value: Option<Value>,
+    deadline: Option<u64>,
+}
+
+impl MemTableTx {
+    pub(crate) fn new(store: MemTable) -> Self {
+        Self {
+            store,
+            undo: RefCell::default(),
+        }
+    }
+
+    fn save(&self, table: &str, key: &str) {
+        let value = self
+            .store
+            .table
+            .get(table)
+            .and_then(|t| t.get(key).map(|v| v.clone()));
+        let deadline = self
+            .store
+            .expires
+            .get(table)
+            .and_then(|t| t.get(key).map(|deadline| *deadline));
+        self.undo.borrow_mut().push(Undo {
+            table: table.into(),
+            key: key.into(),
+            value,
+            deadline,
+        });
+    }
+
+    // 事务中修改过的 key 的最终状态，提交时作为一批记录写入 WAL
+    pub(crate) fn records(&self) -> Result<Vec<Record>, KvError> {
+        let mut seen = HashSet::new();
+        let mut records = Vec::new();
+        for undo in self.undo.borrow().iter() {
+            let (table, key) = (undo.table.as_str(), undo.key.as_str());
+            if !seen.insert((table, key)) {
+                continue;
+            }
+            let value = self
+                .store
+                .table
+                .get(table)
+                .and_then(|t| t.get(key).map(|v| v.clone()));
+            let record = match value {
+                Some(value) => {
+                    let deadline = self
+                        .store
+                        .expires
+                        .get(table)
+                        .and_then(|t| t.get(key).map(|deadline| *deadline))
+                        .unwrap_or(0);
+                    Record::put(table, key, value.try_into()?, deadline)
+                }
+                None => Record::delete(table, key),
+            };
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    // 事务视图不写 WAL，只需要恢复内存中的值
+    pub(crate) fn rollback(&self) -> Result<(), KvError> {
+        for undo in self.undo.take().into_iter().rev() {
+            let (table, key) = (undo.table.as_str(), undo.key.as_str());
+            match undo.value {
+                Some(value) => {
+                    self.store.set(table, key, value)?;
+                    if let Some(deadline) = undo.deadline {
+                        self.store.expire_raw(table, key, deadline);
+                    }
+                }
+                None => {
+                    self.store.delete(table, key)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Storage for MemTableTx {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.store.get(table, key)
+    }
+    fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
+        self.save(table, key);
+        self.store.set(table, key, value)
+    }
+    fn delete(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.save(table, key);
+        self.store.delete(table, key)
+    }
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.store.contains(table, key)
+    }
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.store.get_all(table)
+    }
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        self.store.get_iter(table)
+    }
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        self.save(table, key);
+        self.store.expire(table, key, ttl)
+    }
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        self.store.ttl(table, key)
+    }
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.save(table, key);
+        self.store.persist(table, key)
+    }
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        self.store.list_tables()
+    }
+    fn table_info(&self, table: &str) -> Result<Option<TableStats>, KvError> {
+        self.store.table_info(table)
+    }
+}
@@ -0,0 +1,620 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::{
+    MemTable, Value,
+    error::KvError,
+    storage::record::{
+        RECORD_BATCH_BEGIN, RECORD_BATCH_COMMIT, RECORD_DELETE, RECORD_DROP_TABLE, RECORD_EXPIRE,
+        RECORD_PUT, Record, scan_records,
+    },
+};
+
+const WAL_EXT: &str = "wal";
+const SNAPSHOT_EXT: &str = "snapshot";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FsyncPolicy {
+    // 每次写入后 fsync
+    Always,
+    // 每隔 N 毫秒 fsync 一次
+    Interval(u64),
+    // 由操作系统决定何时落盘
+    Never,
+}
+
+impl Default for FsyncPolicy {
+    fn default() -> Self {
+        FsyncPolicy::Interval(1000)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WalOptions {
+    pub fsync: FsyncPolicy,
+    // 定期生成快照的间隔，None 表示只能手动调用 MemTable::snapshot
+    pub snapshot_interval: Option<Duration>,
+}
+
+impl Default for WalOptions {
+    fn default() -> Self {
+        Self {
+            fsync: FsyncPolicy::default(),
+            snapshot_interval: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+// 目录中的文件：{seq}.wal 为修改日志，{seq}.snapshot 为快照
+// 序号为 seq 的快照包含了所有序号小于 seq 的 WAL 中的修改
+#[derive(Debug)]
+pub(crate) struct Wal {
+    dir: PathBuf,
+    fsync: FsyncPolicy,
+    writer: Mutex<WalWriter>,
+}
+
+#[derive(Debug)]
+struct WalWriter {
+    file: File,
+    seq: u64,
+    dirty: bool,
+}
+
+impl Wal {
+    fn open(dir: &Path, seq: u64, fsync: FsyncPolicy) -> io::Result<Self> {
+        Ok(Self {
+            dir: dir.into(),
+            fsync,
+            writer: Mutex::new(WalWriter {
+                file: open_wal(dir, seq)?,
+                seq,
+                dirty: false,
+            }),
+        })
+    }
+
+    // 先写日志再修改内存，两者在同一把锁内完成，切换 WAL 时内存已经包含旧 WAL 中的所有修改
+    pub(crate) fn append<T>(
+        &self,
+        record: &Record,
+        apply: impl FnOnce() -> T,
+    ) -> Result<T, KvError> {
+        let mut guard = self.lock();
+        guard.write(record)?;
+        Ok(apply())
+    }
+
+    // 需要先读取当前值再决定写入内容时，在整个过程中持有 WAL 的锁
+    pub(crate) fn lock(&self) -> WalGuard<'_> {
+        WalGuard {
+            writer: self.writer.lock().expect("wal lock poisoned"),
+            fsync: self.fsync,
+        }
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        let mut writer = self.writer.lock().expect("wal lock poisoned");
+        if writer.dirty {
+            writer.file.sync_data()?;
+            writer.dirty = false;
+        }
+        Ok(())
+    }
+
+    // 切换到新的 WAL 文件，返回新文件的序号
+    fn rotate(&self) -> io::Result<u64> {
+        let mut writer = self.writer.lock().expect("wal lock poisoned");
+        let seq = writer.seq + 1;
+        let file = open_wal(&self.dir, seq)?;
+        writer.file.sync_data()?;
+        writer.file = file;
+        writer.seq = seq;
+        writer.dirty = false;
+        Ok(seq)
+    }
+}
+
+pub(crate) struct WalGuard<'a> {
+    writer: MutexGuard<'a, WalWriter>,
+    fsync: FsyncPolicy,
+}
+
+impl WalGuard<'_> {
+    pub(crate) fn write(&mut self, record: &Record) -> Result<(), KvError> {
+        self.write_bytes(&record.encode())
+    }
+
+    // 一批记录加上开始和提交标记后一次写入
+    pub(crate) fn write_batch(&mut self, records: &[Record]) -> Result<(), KvError> {
+        let mut buf = Record::batch_begin(records.len()).encode();
+        for record in records {
+            buf.extend_from_slice(&record.encode());
+        }
+        buf.extend_from_slice(&Record::batch_commit().encode());
+        self.write_bytes(&buf)
+    }
+
+    fn write_bytes(&mut self, buf: &[u8]) -> Result<(), KvError> {
+        self.writer.file.write_all(buf)?;
+        match self.fsync {
+            FsyncPolicy::Always => self.writer.file.sync_data()?,
+            _ => self.writer.dirty = true,
+        }
+        Ok(())
+    }
+}
+
+impl MemTable {
+    // 开启持久化：加载最新的快照，重放之后的 WAL，之后的每次修改都会写入 WAL
+    pub fn open(path: impl AsRef<Path>, opts: WalOptions) -> Result<Self, KvError> {
+        let dir = path.as_ref();
+        fs::create_dir_all(dir)?;
+        let mut store = MemTable::default();
+
+        let base = list_files(dir, SNAPSHOT_EXT)?.last().copied();
+        if let Some(seq) = base {
+            // 快照先写到临时文件再改名，不会不完整
+            store.load(&file_path(dir, seq, SNAPSHOT_EXT), false)?;
+        }
+        let base = base.unwrap_or(0);
+        let wals: Vec<u64> = list_files(dir, WAL_EXT)?
+            .into_iter()
+            .filter(|seq| *seq >= base)
+            .collect();
+        // 只有最后一个 WAL 还在写入，之前的在切换时已经 fsync
+        for (i, seq) in wals.iter().enumerate() {
+            store.load(&file_path(dir, *seq, WAL_EXT), i + 1 == wals.len())?;
+        }
+        remove_before(dir, base);
+
+        let seq = wals.last().copied().unwrap_or(base);
+        let wal = Arc::new(Wal::open(dir, seq, opts.fsync)?);
+        if let FsyncPolicy::Interval(ms) = opts.fsync {
+            // 间隔为 0 时 syncer 会空转占满一个核
+            start_syncer(Arc::downgrade(&wal), Duration::from_millis(ms.max(1)));
+        }
+        store.wal = Some(wal);
+        if let Some(interval) = opts.snapshot_interval {
+            start_snapshotter(&store, interval);
+        }
+        info!(
+            "MemTable opened at {} from snapshot {} and {} wal files",
+            dir.display(),
+            base,
+            wals.len()
+        );
+        Ok(store)
+    }
+
+    // 生成快照并删除快照之前的 WAL
+    pub fn snapshot(&self) -> Result<(), KvError> {
+        let Some(wal) = self.wal.as_ref() else {
+            return Err(KvError::Internal(
+                "memtable persistence is not enabled".into(),
+            ));
+        };
+        // 事务持有写锁，快照中不会包含还没有提交的修改
+        let _guard = self.shared();
+        let seq = wal.rotate()?;
+        let tmp = wal.dir.join(format!("{:09}.{}.tmp", seq, SNAPSHOT_EXT));
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        let mut count = 0;
+        for table in self.table.iter() {
+            let expires = self.expires.get(table.key()).map(|t| t.clone());
+            for kv in table.iter() {
+                let expire_at = expires
+                    .as_ref()
+                    .and_then(|t| t.get(kv.key()).map(|deadline| *deadline))
+                    .unwrap_or(0);
+                let value: Vec<u8> = kv.value().clone().try_into()?;
+                let record = Record::put(table.key(), kv.key(), value, expire_at);
+                writer.write_all(&record.encode())?;
+                count += 1;
+            }
+        }
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&tmp, file_path(&wal.dir, seq, SNAPSHOT_EXT))?;
+        remove_before(&wal.dir, seq);
+        debug!("Snapshot {} written with {} keys", seq, count);
+        Ok(())
+    }
+
+    fn load(&self, path: &Path, active: bool) -> Result<(), KvError> {
+        // 正在读取的一批记录、开始标记中的数量和开始标记的偏移
+        let mut batch: Option<(Vec<Record>, u64, u64)> = None;
+        scan_records(path, active, |record, offset, _| {
+            match (record.kind, batch.as_mut()) {
+                (RECORD_BATCH_BEGIN, _) => {
+                    if batch.is_some() {
+                        warn!("Discarding uncommitted batch in {}", path.display());
+                    }
+                    batch = Some((vec![], record.expire_at, offset));
+                }
+                (RECORD_BATCH_COMMIT, Some((records, count, _)))
+                    if records.len() as u64 == *count =>
+                {
+                    for record in batch.take().unwrap().0 {
+                        self.replay_logged(path, record);
+                    }
+                }
+                (RECORD_BATCH_COMMIT, _) => {
+                    warn!("Discarding incomplete batch in {}", path.display());
+                    batch = None;
+                }
+                (_, Some((records, _, _))) => records.push(record),
+                (_, None) => self.replay_logged(path, record),
+            }
+        })?;
+        // 崩溃时没有写完的一批记录在最后一个 WAL 的末尾，截断后之后的写入不会被当作这一批中的记录
+        if let Some((_, _, offset)) = batch {
+            warn!(
+                "Discarding uncommitted batch at offset {} of {}",
+                offset,
+                path.display()
+            );
+            if active {
+                let file = OpenOptions::new().write(true).open(path)?;
+                file.set_len(offset)?;
+                file.sync_all()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn replay_logged(&self, path: &Path, record: Record) {
+        if let Err(e) = self.replay(record) {
+            warn!("Skipping broken record in {}: {}", path.display(), e);
+        }
+    }
+
+    // 重放时不检查过期时间，保证结果与写入时一致
+    fn replay(&self, record: Record) -> Result<(), KvError> {
+        let (table, key) = (record.table.as_str(), record.key.as_str());
+        match record.kind {
+            RECORD_PUT => {
+                let value = Value::try_from(record.value.as_slice())?;
+                self.set_raw(table, key, value);
+                if record.expire_at != 0 {
+                    self.expire_raw(table, key, record.expire_at);
+                }
+            }
+            RECORD_DELETE => {
+                self.delete_raw(table, key);
+            }
+            RECORD_EXPIRE => {
+                self.expire_raw(table, key, record.expire_at);
+            }
+            RECORD_DROP_TABLE => {
+                self.drop_table_raw(table);
+            }
+            _ => {
+                self.persist_raw(table, key);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn start_syncer(wal: std::sync::Weak<Wal>, interval: Duration) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+            let Some(wal) = wal.upgrade() else {
+                break;
+            };
+            if let Err(e) = wal.sync() {
+                warn!("Failed to sync wal: {}", e);
+            }
+        }
+    });
+}
+
+// 后台线程只持有弱引用，MemTable 被释放后退出
+fn start_snapshotter(store: &MemTable, interval: Duration) {
+    let table = Arc::downgrade(&store.table);
+    let expires = Arc::downgrade(&store.expires);
+    let wal = store.wal.as_ref().map(Arc::downgrade);
+    // 与原来的 MemTable 共用事务锁，生成快照时不会有进行中的事务
+    let tx_lock = Arc::downgrade(&store.tx_lock);
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+            let (Some(table), Some(expires), Some(wal), Some(tx_lock)) = (
+                table.upgrade(),
+                expires.upgrade(),
+                wal.as_ref().and_then(|wal| wal.upgrade()),
+                tx_lock.upgrade(),
+            ) else {
+                break;
+            };
+            let store = MemTable {
+                table,
+                expires,
+                wal: Some(wal),
+                tx_lock,
+                ..Default::default()
+            };
+            if let Err(e) = store.snapshot() {
+                warn!("Failed to write snapshot: {}", e);
+            }
+        }
+    });
+}
+
+fn open_wal(dir: &Path, seq: u64) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file_path(dir, seq, WAL_EXT))
+}
+
+fn file_path(dir: &Path, seq: u64, ext: &str) -> PathBuf {
+    dir.join(format!("{:09}.{}", seq, ext))
+}
+
+fn list_files(dir: &Path, ext: &str) -> io::Result<Vec<u64>> {
+    let mut seqs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(ext) {
+            continue;
+        }
+        if let Some(seq) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse().ok())
+        {
+            seqs.push(seq);
+        }
+    }
+    seqs.sort_unstable();
+    Ok(seqs)
+}
+
+// 删除序号小于 seq 的快照和 WAL，以及未完成的临时快照
+fn remove_before(dir: &Path, seq: u64) {
+    let mut paths = Vec::new();
+    for ext in [WAL_EXT, SNAPSHOT_EXT] {
+        if let Ok(seqs) = list_files(dir, ext) {
+            paths.extend(
+                seqs.into_iter()
+                    .filter(|s| *s < seq)
+                    .map(|s| file_path(dir, s, ext)),
+            );
+        }
+    }
+    if let Ok(entries) = fs::read_dir(dir) {
+        paths.extend(
+            entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("tmp")),
+        );
+    }
+    for path in paths {
+        if let Err(e) = fs::remove_file(&path) {
+            warn!("Failed to remove {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::storage::storage::Storage;
+
+    fn options(fsync: FsyncPolicy) -> WalOptions {
+        WalOptions {
+            fsync,
+            snapshot_interval: None,
+        }
+    }
+
+    #[test]
+    fn memtable_should_replay_wal_after_restart() {
+        let dir = tempdir().unwrap();
+        {
+            let store = MemTable::open(dir.path(), options(FsyncPolicy::Always)).unwrap();
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store.set("t1", "k2", 2.into()).unwrap();
+            store.set("t1", "k1", "v11".into()).unwrap();
+            store.delete("t1", "k2").unwrap();
+            store.set("t2", "k1", true.into()).unwrap();
+            store.expire("t2", "k1", Duration::from_secs(60)).unwrap();
+            store
+                .set_with_ttl("t2", "k2", 2.into(), Duration::from_secs(60))
+                .unwrap();
+        }
+        let store = MemTable::open(dir.path(), options(FsyncPolicy::Always)).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v11".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+        assert_eq!(store.get("t2", "k1"), Ok(Some(true.into())));
+        assert!(store.ttl("t2", "k1").unwrap().unwrap() > Duration::from_secs(50));
+        assert_eq!(store.get("t2", "k2"), Ok(Some(2.into())));
+        assert!(store.ttl("t2", "k2").unwrap().unwrap() > Duration::from_secs(50));
+    }
+
+    #[test]
+    fn memtable_should_load_snapshot_and_wal_tail() {
+        let dir = tempdir().unwrap();
+        {
+            let store = MemTable::open(dir.path(), options(FsyncPolicy::Never)).unwrap();
+            for i in 0..10 {
+                store.set("t1", &format!("k{}", i), i.into()).unwrap();
+            }
+            store.expire("t1", "k0", Duration::from_secs(60)).unwrap();
+            store.snapshot().unwrap();
+            // 快照之后的修改在 WAL 中
+            store.set("t1", "k1", "new".into()).unwrap();
+            store.delete("t1", "k2").unwrap();
+            store.persist("t1", "k0").unwrap();
+        }
+        assert_eq!(list_files(dir.path(), SNAPSHOT_EXT).unwrap(), vec![1]);
+        assert_eq!(list_files(dir.path(), WAL_EXT).unwrap(), vec![1]);
+
+        let store = MemTable::open(dir.path(), options(FsyncPolicy::Never)).unwrap();
+        assert_eq!(store.get_all("t1").unwrap().len(), 9);
+        assert_eq!(store.get("t1", "k1"), Ok(Some("new".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+        assert_eq!(store.get("t1", "k9"), Ok(Some(9.into())));
+        assert_eq!(store.ttl("t1", "k0"), Ok(None));
+    }
+
+    #[test]
+    fn drop_table_should_survive_restart() {
+        let dir = tempdir().unwrap();
+        {
+            let store = MemTable::open(dir.path(), options(FsyncPolicy::Always)).unwrap();
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store.set("t2", "k1", "v1".into()).unwrap();
+            assert_eq!(store.drop_table("t1"), Ok(1));
+            store.set("t2", "k2", "v2".into()).unwrap();
+        }
+        let store = MemTable::open(dir.path(), options(FsyncPolicy::Always)).unwrap();
+        assert_eq!(store.list_tables(), Ok(vec!["t2".to_string()]));
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert_eq!(store.get_all("t2").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn replay_should_not_depend_on_current_time() {
+        let dir = tempdir().unwrap();
+        {
+            let store = MemTable::open(dir.path(), options(FsyncPolicy::Never)).unwrap();
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store.set("t1", "k2", "v2".into()).unwrap();
+            store.expire("t1", "k1", Duration::from_millis(20)).unwrap();
+            store.persist("t1", "k1").unwrap();
+            store.expire("t1", "k2", Duration::from_millis(20)).unwrap();
+            store.expire("t1", "k2", Duration::from_secs(60)).unwrap();
+        }
+        thread::sleep(Duration::from_millis(40));
+        let store = MemTable::open(dir.path(), options(FsyncPolicy::Never)).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(Some("v2".into())));
+    }
+
+    #[test]
+    fn truncated_wal_tail_should_be_ignored() {
+        let dir = tempdir().unwrap();
+        {
+            let store = MemTable::open(dir.path(), options(FsyncPolicy::Always)).unwrap();
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store.set("t1", "k2", "v2".into()).unwrap();
+        }
+        let path = file_path(dir.path(), 0, WAL_EXT);
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 2)
+            .unwrap();
+
+        {
+            let store = MemTable::open(dir.path(), options(FsyncPolicy::Always)).unwrap();
+            assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+            assert_eq!(store.get("t1", "k2"), Ok(None));
+            store.set("t1", "k3", "v3".into()).unwrap();
+        }
+        let store = MemTable::open(dir.path(), options(FsyncPolicy::Always)).unwrap();
+        assert_eq!(store.get_all("t1").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn uncommitted_transaction_should_be_discarded() {
+        let dir = tempdir().unwrap();
+        {
+            let store = MemTable::open(dir.path(), options(FsyncPolicy::Always)).unwrap();
+            store.set("t1", "k1", 1.into()).unwrap();
+            store
+                .transaction(&mut |tx| {
+                    tx.set("t1", "k1", 2.into())?;
+                    tx.set("t1", "k2", 2.into())?;
+                    Ok(())
+                })
+                .unwrap();
+            // 回滚的事务不写 WAL
+            let len = fs::metadata(file_path(dir.path(), 0, WAL_EXT))
+                .unwrap()
+                .len();
+            let res = store.transaction(&mut |tx| {
+                tx.delete("t1", "k1")?;
+                Err(KvError::Internal("abort".into()))
+            });
+            assert!(res.is_err());
+            let path = file_path(dir.path(), 0, WAL_EXT);
+            assert_eq!(fs::metadata(&path).unwrap().len(), len);
+
+            store
+                .transaction(&mut |tx| {
+                    tx.set("t1", "k1", 3.into())?;
+                    tx.set("t1", "k3", 3.into())?;
+                    Ok(())
+                })
+                .unwrap();
+        }
+        {
+            let store = MemTable::open(dir.path(), options(FsyncPolicy::Always)).unwrap();
+            assert_eq!(store.get("t1", "k1"), Ok(Some(3.into())));
+            assert_eq!(store.get_all("t1").unwrap().len(), 3);
+        }
+
+        // 去掉最后一个事务的提交标记，事务中的修改都不会被重放
+        let path = file_path(dir.path(), 0, WAL_EXT);
+        let len = fs::metadata(&path).unwrap().len();
+        let commit_len = Record::batch_commit().encode().len() as u64;
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - commit_len)
+            .unwrap();
+        {
+            let store = MemTable::open(dir.path(), options(FsyncPolicy::Always)).unwrap();
+            assert_eq!(store.get("t1", "k1"), Ok(Some(2.into())));
+            assert_eq!(store.get("t1", "k3"), Ok(None));
+            // 之后的写入追加在没有提交的记录后面
+            store.set("t1", "k4", 4.into()).unwrap();
+        }
+        let store = MemTable::open(dir.path(), options(FsyncPolicy::Always)).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some(2.into())));
+        assert_eq!(store.get("t1", "k3"), Ok(None));
+        assert_eq!(store.get("t1", "k4"), Ok(Some(4.into())));
+    }
+
+    #[test]
+    fn background_snapshot_should_work() {
+        let dir = tempdir().unwrap();
+        let opts = WalOptions {
+            fsync: FsyncPolicy::Interval(10),
+            snapshot_interval: Some(Duration::from_millis(20)),
+        };
+        let store = MemTable::open(dir.path(), opts).unwrap();
+        store.set("t1", "k1", "v1".into()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(!list_files(dir.path(), SNAPSHOT_EXT).unwrap().is_empty());
+        drop(store);
+
+        let store = MemTable::open(dir.path(), options(FsyncPolicy::Never)).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+    }
+
+    #[test]
+    fn snapshot_without_persistence_should_fail() {
+        assert!(MemTable::new().snapshot().is_err());
+    }
+}